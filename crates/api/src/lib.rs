@@ -1,3 +1,6 @@
 pub mod web;
 pub mod outlook;
 pub mod mail;
+pub mod search;
+pub mod thread;
+pub mod mime;
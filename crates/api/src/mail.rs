@@ -1,32 +1,411 @@
 use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use reqwest::StatusCode;
-use serde::{Deserialize};
+use serde::{Serialize, Deserialize};
+use crate::search::SearchQuery;
+
+/// A new id for a mailbox, unique across accounts and providers.
+pub fn new_mailbox_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
 
 #[async_trait::async_trait]
 pub trait Mailbox {
     fn get_id(&self) -> &str;
     async fn fetch_unread(&self) -> Result<Vec<Message>, Box<dyn Error>>;
-    async fn set_as_read(self, message_id: String) -> Result<(), SetReadError>;
+    /// Fetches a message by id from any folder, or `None` if the mailbox has no such message.
+    async fn fetch_message(&self, message_id: &str) -> Result<Option<Message>, Box<dyn Error>>;
+    /// Searches every folder of the mailbox on the server.
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<Message>, Box<dyn Error>>;
+    /// Streams the attachment's content into `writer`, returning the number of bytes written.
+    async fn download_attachment(
+        &self,
+        message_id: &str,
+        attachment_id: &str,
+        writer: &mut (dyn Write + Send),
+    ) -> Result<u64, Box<dyn Error>>;
+    /// Streams the message as raw RFC 5322 into `writer`, returning the number of bytes
+    /// written, such as to forward it as an attachment.
+    async fn download_message(
+        &self,
+        message_id: &str,
+        writer: &mut (dyn Write + Send),
+    ) -> Result<u64, Box<dyn Error>>;
+    /// Total attachment size in bytes accepted by [`Mailbox::send`].
+    fn max_attachments_size(&self) -> u64;
+    /// Whether the server holds a message with [`OutgoingMessage::send_at`] set until then,
+    /// so that it is delivered on time whether or not this client is running.
+    fn can_defer_sending(&self) -> bool {
+        false
+    }
+    /// Sends the message, along with the draft it was saved as, if any.
+    async fn send(&self, message: OutgoingMessage) -> Result<(), Box<dyn Error>>;
+    /// Saves the message as a draft, replacing [`OutgoingMessage::draft_id`] if set, and
+    /// returns the draft's id. Attachments are only added when the message is sent. IMAP
+    /// would append it to the Drafts folder with the `\Draft` flag.
+    async fn save_draft(&self, message: &OutgoingMessage) -> Result<String, Box<dyn Error>>;
+    /// Lists the drafts, most recently changed first, with plain-text bodies to edit.
+    async fn fetch_drafts(&self) -> Result<Vec<Message>, Box<dyn Error>>;
+    /// Deletes the message for good, rather than moving it to the deleted items.
+    async fn delete_message(&self, message_id: &str) -> Result<(), Box<dyn Error>>;
+    /// Applies every field set in `update` to the message, leaving the rest untouched.
+    async fn update_flags(self, message_id: String, update: FlagUpdate) -> Result<(), UpdateError>;
+    /// Moves the message to the folder with this name, returning its id in that folder.
+    async fn move_message(&self, message_id: &str, folder: &str) -> Result<String, Box<dyn Error>>;
+    /// Takes the message out of the inbox until [`Mailbox::wake`], returning its new id. The
+    /// wake time is kept by the caller: Outlook moves the message to a Snoozed folder, and
+    /// IMAP would tag it with a keyword.
+    async fn snooze(&self, message_id: &str) -> Result<String, Box<dyn Error>>;
+    /// Puts a snoozed message back in the inbox as unread, returning its new id.
+    async fn wake(&self, message_id: &str) -> Result<String, Box<dyn Error>>;
+
+    async fn set_as_read(self, message_id: String) -> Result<(), UpdateError>
+        where Self: Sized
+    {
+        self.update_flags(message_id, FlagUpdate {
+            is_read: Some(true),
+            ..Default::default()
+        }).await
+    }
 }
 
-#[derive(Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Message {
     pub id: String,
     pub mailbox_id: String,
     pub subject: String,
     pub body: String,
+    pub body_type: BodyType,
     pub from: Recipient,
     pub to: Vec<Recipient>,
+    pub cc: Vec<Recipient>,
     pub date: u64,
+    pub is_read: bool,
+    pub flagged: Flag,
+    /// Outlook categories, Gmail labels or IMAP keywords.
+    pub categories: Vec<String>,
+    /// The `Message-ID` header.
+    pub internet_message_id: Option<String>,
+    pub in_reply_to: Option<String>,
+    pub references: Vec<String>,
+    /// Messages sharing a thread id belong to the same conversation.
+    /// See [`crate::thread::assign_thread_ids`].
+    pub thread_id: Option<String>,
+    pub attachments: Vec<Attachment>,
+}
+
+/// A message to build tests on, from `sender@example.com` to `me@example.com`, with setters
+/// for the fields a test cares about.
+#[cfg(any(test, feature = "test-util"))]
+impl Message {
+    pub fn test(id: &str) -> Message {
+        Message {
+            id: id.to_string(),
+            mailbox_id: "mailbox".to_string(),
+            subject: String::new(),
+            body: String::new(),
+            body_type: BodyType::Text,
+            from: Recipient { address: "sender@example.com".to_string(), name: String::new() },
+            to: vec![Recipient { address: "me@example.com".to_string(), name: String::new() }],
+            cc: vec![],
+            date: 0,
+            is_read: false,
+            flagged: Flag::NotFlagged,
+            categories: vec![],
+            internet_message_id: Some(format!("<{}@example.com>", id)),
+            in_reply_to: None,
+            references: vec![],
+            thread_id: None,
+            attachments: vec![],
+        }
+    }
+
+    pub fn with_mailbox(mut self, mailbox_id: &str) -> Message {
+        self.mailbox_id = mailbox_id.to_string();
+        self
+    }
+
+    pub fn with_subject(mut self, subject: &str) -> Message {
+        self.subject = subject.to_string();
+        self
+    }
+
+    pub fn with_body(mut self, body: &str) -> Message {
+        self.body = body.to_string();
+        self
+    }
+
+    pub fn with_from(mut self, name: &str, address: &str) -> Message {
+        self.from = Recipient { address: address.to_string(), name: name.to_string() };
+        self
+    }
+
+    pub fn with_to(mut self, to: &str) -> Message {
+        self.to = Recipient::parse_list(to);
+        self
+    }
+
+    pub fn with_cc(mut self, cc: &str) -> Message {
+        self.cc = Recipient::parse_list(cc);
+        self
+    }
+
+    pub fn with_date(mut self, date: u64) -> Message {
+        self.date = date;
+        self
+    }
+
+    pub fn with_categories(mut self, categories: &[&str]) -> Message {
+        self.categories = categories.iter().map(|category| category.to_string()).collect();
+        self
+    }
+
+    /// Sets `In-Reply-To` and `References`, which may each be left empty.
+    pub fn with_parents(mut self, in_reply_to: Option<&str>, references: &[&str]) -> Message {
+        self.in_reply_to = in_reply_to.map(|id| id.to_string());
+        self.references = references.iter().map(|id| id.to_string()).collect();
+        self
+    }
 }
 
-#[derive(Deserialize, Clone)]
+/// Attachment metadata. The content is only fetched on demand with
+/// [`Mailbox::download_attachment`].
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Attachment {
+    pub id: String,
+    pub name: String,
+    /// Size in bytes.
+    pub size: u64,
+    pub content_type: String,
+    /// Inline attachments are referenced from the body, e.g. images in HTML mail.
+    pub is_inline: bool,
+    /// The Content-ID that the body refers to with a `cid:` URL, without angle brackets.
+    pub content_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Recipient {
     pub address: String,
     pub name: String,
 }
 
-pub enum SetReadError {
+impl Recipient {
+    /// Parses a comma separated list such as `"Doe, Jane" <jane@example.com>, bob@example.com`.
+    pub fn parse_list(list: &str) -> Vec<Recipient> {
+        let mut recipients: Vec<&str> = vec![];
+        let mut start = 0;
+        let mut is_quoted = false;
+        for (i, c) in list.char_indices() {
+            match c {
+                '"' => is_quoted = !is_quoted,
+                ',' if !is_quoted => {
+                    recipients.push(&list[start..i]);
+                    start = i + 1;
+                }
+                _ => (),
+            }
+        }
+        recipients.push(&list[start..]);
+        recipients.into_iter()
+            .map(|recipient| recipient.trim())
+            .filter(|recipient| !recipient.is_empty())
+            .map(|recipient| match (recipient.rfind('<'), recipient.rfind('>')) {
+                (Some(start), Some(end)) if start < end => Recipient {
+                    address: recipient[start + 1..end].trim().to_string(),
+                    name: recipient[..start].trim().trim_matches('"').to_string(),
+                },
+                _ => Recipient {
+                    address: recipient.to_string(),
+                    name: "".to_string(),
+                },
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for Recipient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "{}", self.address)
+        } else {
+            write!(f, "{} <{}>", self.name, self.address)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BodyType {
+    Text,
+    Html,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub enum Flag {
+    NotFlagged,
+    /// Flagged for follow-up, with an optional due date timestamp.
+    Flagged(Option<u64>),
+    Complete,
+}
+
+impl Flag {
+    pub fn is_flagged(&self) -> bool {
+        matches!(self, Flag::Flagged(_))
+    }
+}
+
+/// A partial update of a message's flags. Fields set to `None` are not changed.
+#[derive(Clone, Default)]
+pub struct FlagUpdate {
+    pub is_read: Option<bool>,
+    pub flagged: Option<Flag>,
+    pub categories: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub enum UpdateError {
     NoResponse,
     NonOkCode(StatusCode),
-}
\ No newline at end of file
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpdateError::NoResponse => write!(f, "no response from the server"),
+            UpdateError::NonOkCode(status) => write!(f, "the server answered {}", status),
+        }
+    }
+}
+
+impl Error for UpdateError {}
+
+/// A plain-text message to be sent.
+#[derive(Serialize, Deserialize, Clone, Default)]
+pub struct OutgoingMessage {
+    pub to: Vec<Recipient>,
+    pub cc: Vec<Recipient>,
+    pub subject: String,
+    pub body: String,
+    pub attachments: Vec<OutgoingAttachment>,
+    /// When the server should deliver the message, in seconds since the Unix epoch, rather
+    /// than straight away.
+    #[serde(default)]
+    pub send_at: Option<u64>,
+    /// The draft the message is saved as on the server, replaced when it is saved again.
+    #[serde(default)]
+    pub draft_id: Option<String>,
+    /// The `Message-ID` of the message this replies to.
+    #[serde(default)]
+    pub in_reply_to: Option<String>,
+    /// The `Message-ID`s of the thread so far, oldest first, ending with `in_reply_to`.
+    #[serde(default)]
+    pub references: Vec<String>,
+}
+
+/// A local file to attach. The content is read from disk only when the message is sent.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OutgoingAttachment {
+    pub path: PathBuf,
+    pub name: String,
+    pub size: u64,
+    pub content_type: String,
+}
+
+#[derive(Debug)]
+pub enum AttachmentError {
+    NotAFile(PathBuf),
+    Io(io::Error),
+    /// The attachments would exceed the mailbox's limit, in bytes.
+    TooLarge { size: u64, limit: u64 },
+}
+
+impl fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachmentError::NotAFile(path) => write!(f, "{} is not a file", path.display()),
+            AttachmentError::Io(error) => write!(f, "{}", error),
+            AttachmentError::TooLarge { size, limit } => write!(
+                f,
+                "attachments would total {} bytes, over the {} byte limit",
+                size,
+                limit
+            ),
+        }
+    }
+}
+
+impl Error for AttachmentError {}
+
+impl From<io::Error> for AttachmentError {
+    fn from(error: io::Error) -> Self {
+        AttachmentError::Io(error)
+    }
+}
+
+impl OutgoingMessage {
+    /// Attaches the file at `path` if the total attachment size stays within `size_limit`,
+    /// usually [`Mailbox::max_attachments_size`].
+    pub fn attach(&mut self, path: &Path, size_limit: u64) -> Result<(), AttachmentError> {
+        let attachment = OutgoingAttachment::from_path(path)?;
+        let size = self.attachments.iter()
+            .map(|attachment| attachment.size)
+            .sum::<u64>() + attachment.size;
+        if size > size_limit {
+            return Err(AttachmentError::TooLarge { size, limit: size_limit });
+        }
+        self.attachments.push(attachment);
+        Ok(())
+    }
+}
+
+impl OutgoingAttachment {
+    pub fn from_path(path: &Path) -> Result<OutgoingAttachment, AttachmentError> {
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(AttachmentError::NotAFile(path.to_path_buf()));
+        }
+        let name = path.file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| "attachment".to_string());
+        let content_type = match mime_guess::from_path(path).first() {
+            Some(mime) => mime.essence_str().to_string(),
+            None => sniff_content_type(path)?.to_string(),
+        };
+        Ok(OutgoingAttachment {
+            path: path.to_path_buf(),
+            name,
+            size: metadata.len(),
+            content_type,
+        })
+    }
+}
+
+/// Detects the content type of files without a known extension from their first bytes.
+fn sniff_content_type(path: &Path) -> io::Result<&'static str> {
+    let mut header = [0u8; 8];
+    let length = fs::File::open(path)?.read(&mut header)?;
+    let header = &header[..length];
+    let signatures: [(&[u8], &str); 6] = [
+        (b"%PDF", "application/pdf"),
+        (b"\x89PNG", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF8", "image/gif"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+    let content_type = signatures.iter()
+        .find(|(signature, _)| header.starts_with(signature))
+        .map(|(_, content_type)| *content_type);
+    if let Some(content_type) = content_type {
+        return Ok(content_type);
+    }
+    match std::str::from_utf8(header) {
+        Ok(_) => return Ok("text/plain"),
+        // The header may end in the middle of a multi-byte character.
+        Err(error) if error.error_len().is_none() => return Ok("text/plain"),
+        Err(_) => {}
+    }
+    Ok("application/octet-stream")
+}
@@ -0,0 +1,20 @@
+//! Reading and writing raw RFC 5322 messages. Graph hands over messages as JSON with their
+//! bodies already decoded, so the Outlook backend only uses [`BodyPreference`]; the parser
+//! and writer are for backends that fetch and send raw MIME, such as IMAP and SMTP.
+
+use serde::{Serialize, Deserialize};
+
+mod parse;
+mod write;
+
+pub use parse::{parse, percent_decode, to_message, Disposition, Part};
+pub use write::write_message;
+
+/// Which alternative to show when a message has both a plain-text and an HTML body.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum BodyPreference {
+    Plain,
+    #[default]
+    Html,
+}
@@ -0,0 +1,613 @@
+use std::collections::HashMap;
+use base64::Engine;
+use chrono::DateTime;
+use encoding_rs::Encoding;
+use crate::mail::{Attachment, BodyType, Flag, Message, Recipient};
+use crate::mime::BodyPreference;
+
+/// A node of a MIME message. Multipart nodes have children, others have a body.
+pub struct Part {
+    /// Position in the tree such as `1.2`, used as the attachment id. The root is empty.
+    pub id: String,
+    /// Headers with RFC 2047 encoded words decoded, in their original order.
+    pub headers: Vec<(String, String)>,
+    /// Lowercase content type without parameters, e.g. `text/plain`.
+    pub mime_type: String,
+    /// Content type parameters keyed by lowercase name, with RFC 2231 values decoded.
+    pub parameters: HashMap<String, String>,
+    /// The content with its transfer encoding (base64, quoted-printable) removed.
+    pub body: Vec<u8>,
+    pub children: Vec<Part>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Disposition {
+    Inline,
+    Attachment,
+}
+
+/// Parses a raw RFC 5322 message into its MIME part tree. Parsing is lenient: malformed
+/// input yields a best-effort tree rather than an error, as mail clients are expected to.
+pub fn parse(raw: &[u8]) -> Part {
+    parse_part(raw, "".to_string(), "text/plain")
+}
+
+fn parse_part(raw: &[u8], id: String, default_mime_type: &str) -> Part {
+    let (header_bytes, body) = split_header(raw);
+    let headers = parse_headers(header_bytes);
+    let (mime_type, parameters) = match find_header(&headers, "content-type") {
+        Some(content_type) => parse_header_parameters(content_type),
+        None => (default_mime_type.to_string(), HashMap::new()),
+    };
+    let mut part = Part {
+        id,
+        headers,
+        mime_type,
+        parameters,
+        body: vec![],
+        children: vec![],
+    };
+    if part.is_multipart() {
+        if let Some(boundary) = part.parameters.get("boundary").cloned() {
+            // Parts of multipart/digest default to message/rfc822 instead of text/plain.
+            let default_mime_type = if part.mime_type == "multipart/digest" {
+                "message/rfc822"
+            } else {
+                "text/plain"
+            };
+            part.children = split_multipart(body, &boundary).into_iter()
+                .enumerate()
+                .map(|(i, child)| {
+                    let child_id = if part.id.is_empty() {
+                        format!("{}", i + 1)
+                    } else {
+                        format!("{}.{}", part.id, i + 1)
+                    };
+                    parse_part(child, child_id, default_mime_type)
+                })
+                .collect();
+            return part;
+        }
+    }
+    let encoding = part.header("content-transfer-encoding")
+        .unwrap_or("7bit")
+        .trim()
+        .to_lowercase();
+    part.body = match encoding.as_str() {
+        "base64" => decode_base64(body),
+        "quoted-printable" => decode_quoted_printable(body),
+        _ => body.to_vec(),
+    };
+    if part.mime_type == "message/rfc822" {
+        let child_id = if part.id.is_empty() { "1".to_string() } else { format!("{}.1", part.id) };
+        part.children = vec![parse_part(&part.body, child_id, "text/plain")];
+    }
+    part
+}
+
+impl Part {
+    /// Returns the first header with the given case-insensitive name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        find_header(&self.headers, name)
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type.starts_with("multipart/")
+    }
+
+    pub fn disposition(&self) -> Disposition {
+        match self.header("content-disposition") {
+            Some(disposition) if parse_header_parameters(disposition).0 == "attachment" => {
+                Disposition::Attachment
+            }
+            _ => Disposition::Inline,
+        }
+    }
+
+    /// The file name from Content-Disposition, falling back to the Content-Type `name`.
+    pub fn filename(&self) -> Option<String> {
+        self.header("content-disposition")
+            .and_then(|disposition| parse_header_parameters(disposition).1.remove("filename"))
+            .or_else(|| self.parameters.get("name").cloned())
+    }
+
+    /// The Content-ID without angle brackets, as referenced by `cid:` URLs.
+    pub fn content_id(&self) -> Option<String> {
+        self.header("content-id")
+            .map(|content_id| content_id.trim().trim_start_matches('<').trim_end_matches('>'))
+            .map(|content_id| content_id.to_string())
+    }
+
+    /// Decodes the body from its charset, defaulting to US-ASCII (read as Windows-1252, which
+    /// is also how mislabelled Latin-1 mail is best read).
+    pub fn text(&self) -> String {
+        let label = self.parameters.get("charset").map(|charset| charset.as_str()).unwrap_or("");
+        let encoding = Encoding::for_label(label.trim().as_bytes())
+            .unwrap_or(encoding_rs::WINDOWS_1252);
+        // Unlabelled ASCII is valid UTF-8, so it skips the Windows-1252 decoder.
+        if encoding == encoding_rs::UTF_8 || (label.is_empty() && self.body.is_ascii()) {
+            return String::from_utf8_lossy(&self.body).to_string();
+        }
+        encoding.decode_without_bom_handling(&self.body).0.to_string()
+    }
+
+    fn is_text_body(&self) -> bool {
+        (self.mime_type == "text/plain" || self.mime_type == "text/html")
+            && self.disposition() == Disposition::Inline
+            && self.filename().is_none()
+    }
+
+    /// Finds the part to display as the message body.
+    pub fn body_part(&self, preference: BodyPreference) -> Option<&Part> {
+        if !self.is_multipart() {
+            return if self.is_text_body() { Some(self) } else { None };
+        }
+        match self.mime_type.as_str() {
+            "multipart/alternative" => {
+                let preferred_type = match preference {
+                    BodyPreference::Plain => "text/plain",
+                    BodyPreference::Html => "text/html",
+                };
+                let candidates: Vec<&Part> = self.children.iter()
+                    .filter_map(|child| child.body_part(preference))
+                    .collect();
+                // Alternatives are ordered from plainest to richest.
+                candidates.iter()
+                    .rev()
+                    .find(|candidate| candidate.mime_type == preferred_type)
+                    .or_else(|| candidates.last())
+                    .cloned()
+            }
+            "multipart/related" => {
+                let start = self.parameters.get("start")
+                    .map(|start| start.trim_start_matches('<').trim_end_matches('>'));
+                let root = start
+                    .and_then(|start| self.children.iter()
+                        .find(|child| child.content_id().as_deref() == Some(start)))
+                    .or_else(|| self.children.first());
+                root.and_then(|root| root.body_part(preference))
+            }
+            _ => self.children.iter().find_map(|child| child.body_part(preference)),
+        }
+    }
+
+    /// Returns the body as text with its type, preferring the given alternative.
+    pub fn body_text(&self, preference: BodyPreference) -> Option<(BodyType, String)> {
+        self.body_part(preference).map(|part| {
+            let body_type = if part.mime_type == "text/html" {
+                BodyType::Html
+            } else {
+                BodyType::Text
+            };
+            (body_type, part.text())
+        })
+    }
+
+    /// Returns every part that is not a displayable body: attachments, and inline parts such
+    /// as the images of an HTML body. Forwarded messages are returned whole.
+    pub fn attachments(&self) -> Vec<&Part> {
+        let mut attachments = vec![];
+        self.collect_attachments(&mut attachments);
+        attachments
+    }
+
+    fn collect_attachments<'a>(&'a self, attachments: &mut Vec<&'a Part>) {
+        if self.is_multipart() {
+            for child in &self.children {
+                child.collect_attachments(attachments);
+            }
+            return;
+        }
+        if !self.is_text_body() && !self.id.is_empty() {
+            attachments.push(self);
+        }
+    }
+
+    /// Finds a part by its id.
+    pub fn find(&self, id: &str) -> Option<&Part> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter().find_map(|child| child.find(id))
+    }
+}
+
+/// Builds a [`Message`] from a raw RFC 5322 message, for backends which fetch raw MIME.
+pub fn to_message(raw: &[u8], id: &str, mailbox_id: &str, preference: BodyPreference) -> Message {
+    let part = parse(raw);
+    let (body_type, body) = part.body_text(preference)
+        .unwrap_or((BodyType::Text, "".to_string()));
+    let message_ids = |name: &str| part.header(name)
+        .map(parse_message_ids)
+        .unwrap_or_default();
+    Message {
+        id: id.to_string(),
+        mailbox_id: mailbox_id.to_string(),
+        subject: part.header("subject").unwrap_or_default().to_string(),
+        body,
+        body_type,
+        from: part.header("from")
+            .and_then(|from| Recipient::parse_list(from).into_iter().next())
+            .unwrap_or(Recipient {
+                address: "".to_string(),
+                name: "".to_string(),
+            }),
+        to: Recipient::parse_list(part.header("to").unwrap_or_default()),
+        cc: Recipient::parse_list(part.header("cc").unwrap_or_default()),
+        date: part.header("date").and_then(parse_date).unwrap_or(0),
+        is_read: false,
+        flagged: Flag::NotFlagged,
+        categories: vec![],
+        internet_message_id: message_ids("message-id").into_iter().next(),
+        in_reply_to: message_ids("in-reply-to").into_iter().next(),
+        references: message_ids("references"),
+        thread_id: None,
+        attachments: part.attachments().into_iter()
+            .map(|attachment| Attachment {
+                id: attachment.id.clone(),
+                name: attachment.filename()
+                    .unwrap_or_else(|| format!("part-{}", attachment.id)),
+                size: attachment.body.len() as u64,
+                content_type: attachment.mime_type.clone(),
+                is_inline: attachment.disposition() == Disposition::Inline,
+                content_id: attachment.content_id(),
+            })
+            .collect(),
+    }
+}
+
+fn split_header(raw: &[u8]) -> (&[u8], &[u8]) {
+    // A message starting with a blank line has no headers.
+    if raw.starts_with(b"\r\n") {
+        return (&[], &raw[2..]);
+    }
+    if raw.starts_with(b"\n") {
+        return (&[], &raw[1..]);
+    }
+    for i in 0..raw.len() {
+        if raw[i..].starts_with(b"\r\n\r\n") {
+            return (&raw[..i], &raw[i + 4..]);
+        }
+        if raw[i..].starts_with(b"\n\n") {
+            return (&raw[..i], &raw[i + 2..]);
+        }
+    }
+    (raw, &[])
+}
+
+fn parse_headers(raw: &[u8]) -> Vec<(String, String)> {
+    let text = String::from_utf8_lossy(raw);
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in text.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.starts_with(' ') || line.starts_with('\t') {
+            // Continuation of a folded header.
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim_start());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers.into_iter()
+        .map(|(name, value)| {
+            let value = decode_encoded_words(&value);
+            (name, value)
+        })
+        .collect()
+}
+
+fn find_header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers.iter()
+        .find(|(header_name, _)| header_name.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Parses a header such as `text/plain; charset="utf-8"` into its lowercase value and
+/// parameters, joining RFC 2231 continuations and decoding their charset.
+fn parse_header_parameters(header: &str) -> (String, HashMap<String, String>) {
+    let mut segments = split_unquoted(header, ';').into_iter();
+    let value = segments.next().unwrap_or_default().trim().to_lowercase();
+    let mut parameters: HashMap<String, String> = HashMap::new();
+    // RFC 2231 sections such as `filename*0*=` and `filename*1=`, keyed by name.
+    let mut sections: HashMap<String, Vec<(usize, bool, String)>> = HashMap::new();
+    for segment in segments {
+        let (name, value) = match segment.split_once('=') {
+            Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+        let value = value.strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+            .map(|value| value.replace("\\\"", "\"").replace("\\\\", "\\"))
+            .unwrap_or_else(|| value.to_string());
+        let is_extended = name.ends_with('*');
+        let name = name.trim_end_matches('*');
+        match name.split_once('*') {
+            Some((name, index)) => {
+                let index = index.parse::<usize>().unwrap_or(0);
+                sections.entry(name.to_string()).or_default().push((index, is_extended, value));
+            }
+            None if is_extended => {
+                parameters.insert(name.to_string(), decode_extended_value(&value, None));
+            }
+            None => {
+                parameters.insert(name.to_string(), value);
+            }
+        }
+    }
+    for (name, mut name_sections) in sections {
+        name_sections.sort_by_key(|(index, _, _)| *index);
+        // Only the first section carries the charset.
+        let mut charset: Option<String> = None;
+        let mut bytes: Vec<u8> = vec![];
+        for (index, is_extended, value) in name_sections {
+            if !is_extended {
+                bytes.extend(value.as_bytes());
+                continue;
+            }
+            let value = if index == 0 {
+                let mut fields = value.splitn(3, '\'');
+                match (fields.next(), fields.next(), fields.next()) {
+                    (Some(value_charset), Some(_language), Some(rest)) => {
+                        charset = Some(value_charset.to_string());
+                        rest.to_string()
+                    }
+                    _ => value,
+                }
+            } else {
+                value
+            };
+            bytes.extend(percent_decode(&value));
+        }
+        parameters.insert(name, decode_charset(&bytes, charset.as_deref().unwrap_or("utf-8")));
+    }
+    (value, parameters)
+}
+
+/// Decodes an RFC 2231 value such as `utf-8'en'na%C3%AFve.txt`.
+fn decode_extended_value(value: &str, charset: Option<&str>) -> String {
+    let mut fields = value.splitn(3, '\'');
+    match (fields.next(), fields.next(), fields.next()) {
+        (Some(value_charset), Some(_language), Some(rest)) => {
+            decode_charset(&percent_decode(rest), value_charset)
+        }
+        _ => decode_charset(&percent_decode(value), charset.unwrap_or("utf-8")),
+    }
+}
+
+/// Decodes `%XX` escapes, as used in RFC 2231 values and URLs, keeping invalid escapes as
+/// they are.
+pub fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match bytes[i] {
+            b'%' => bytes.get(i + 1..i + 3).and_then(decode_hex),
+            _ => None,
+        };
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Decodes two hex digits. Unlike `u8::from_str_radix`, a sign is not accepted.
+fn decode_hex(hex: &[u8]) -> Option<u8> {
+    if hex.len() != 2 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()
+}
+
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut segments = vec![];
+    let mut start = 0;
+    let mut is_quoted = false;
+    let mut is_escaped = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if is_escaped => is_escaped = false,
+            '\\' if is_quoted => is_escaped = true,
+            '"' => is_quoted = !is_quoted,
+            c if c == separator && !is_quoted => {
+                segments.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => (),
+        }
+    }
+    segments.push(&value[start..]);
+    segments
+}
+
+/// Splits a multipart body on its boundary lines, dropping the preamble and epilogue.
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{}", boundary);
+    let delimiter = delimiter.as_bytes();
+    let mut parts = vec![];
+    let mut part_start: Option<usize> = None;
+    let mut line_start = 0;
+    while line_start < body.len() {
+        let line_end = body[line_start..].iter()
+            .position(|byte| *byte == b'\n')
+            .map(|position| line_start + position + 1)
+            .unwrap_or(body.len());
+        let line = &body[line_start..line_end];
+        if line.starts_with(delimiter) {
+            let rest = &line[delimiter.len()..];
+            let is_close = rest.starts_with(b"--");
+            let rest = if is_close { &rest[2..] } else { rest };
+            // The boundary may only be followed by whitespace.
+            if rest.iter().all(|byte| byte.is_ascii_whitespace()) {
+                if let Some(start) = part_start {
+                    parts.push(trim_line_break(&body[start..line_start]));
+                }
+                if is_close {
+                    return parts;
+                }
+                part_start = Some(line_end);
+            }
+        }
+        line_start = line_end;
+    }
+    // Missing close delimiter: keep what was read.
+    if let Some(start) = part_start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+/// Removes the line break before a boundary, which belongs to the boundary.
+fn trim_line_break(part: &[u8]) -> &[u8] {
+    let part = part.strip_suffix(b"\n").unwrap_or(part);
+    part.strip_suffix(b"\r").unwrap_or(part)
+}
+
+fn decode_base64(body: &[u8]) -> Vec<u8> {
+    let mut clean: Vec<u8> = body.iter()
+        .cloned()
+        .filter(|byte| byte.is_ascii_alphanumeric() || *byte == b'+' || *byte == b'/')
+        .collect();
+    // Trailing partial groups are padded rather than dropped.
+    while !clean.len().is_multiple_of(4) {
+        if clean.len() % 4 == 1 {
+            clean.pop();
+        } else {
+            clean.push(b'=');
+        }
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(&clean)
+        .unwrap_or_default()
+}
+
+fn decode_quoted_printable(body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(body.len());
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] != b'=' {
+            decoded.push(body[i]);
+            i += 1;
+            continue;
+        }
+        // Soft line break, with or without trailing whitespace.
+        let rest = &body[i + 1..];
+        let whitespace = rest.iter().take_while(|byte| **byte == b' ' || **byte == b'\t').count();
+        let after_whitespace = &rest[whitespace..];
+        if after_whitespace.starts_with(b"\r\n") {
+            i += 1 + whitespace + 2;
+            continue;
+        }
+        if after_whitespace.starts_with(b"\n") {
+            i += 1 + whitespace + 1;
+            continue;
+        }
+        match rest.get(..2).and_then(decode_hex) {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                // Invalid escape: keep it literally.
+                decoded.push(b'=');
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    let encoding = Encoding::for_label(charset.trim().as_bytes())
+        .unwrap_or(encoding_rs::WINDOWS_1252);
+    encoding.decode_without_bom_handling(bytes).0.to_string()
+}
+
+/// Decodes RFC 2047 encoded words such as `=?ISO-8859-1?Q?caf=E9?=`. Whitespace between
+/// adjacent encoded words is dropped, as the standard requires.
+fn decode_encoded_words(value: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = value;
+    let mut pending_whitespace = String::new();
+    let mut previous_was_encoded = false;
+    while !rest.is_empty() {
+        let start = match rest.find("=?") {
+            Some(start) => start,
+            None => break,
+        };
+        let word = rest[start + 2..].splitn(3, '?').collect::<Vec<&str>>();
+        let end = match word.get(2).and_then(|text| text.find("?=")) {
+            Some(end) if word.len() == 3 => end,
+            _ => {
+                decoded.push_str(&pending_whitespace);
+                decoded.push_str(&rest[..start + 2]);
+                pending_whitespace.clear();
+                previous_was_encoded = false;
+                rest = &rest[start + 2..];
+                continue;
+            }
+        };
+        let (charset, encoding, text) = (word[0], word[1], &word[2][..end]);
+        let between = &rest[..start];
+        let bytes = match encoding.to_ascii_lowercase().as_str() {
+            "b" => Some(decode_base64(text.as_bytes())),
+            "q" => Some(decode_quoted_printable(text.replace('_', " ").as_bytes())),
+            _ => None,
+        };
+        let word_length = start + 2 + charset.len() + 1 + encoding.len() + 1 + end + 2;
+        match bytes {
+            Some(bytes) => {
+                let is_whitespace = between.chars().all(char::is_whitespace);
+                if !(previous_was_encoded && is_whitespace) {
+                    decoded.push_str(&pending_whitespace);
+                    decoded.push_str(between);
+                }
+                // Drop an RFC 2231 language suffix such as `UTF-8*en`.
+                let charset = charset.split('*').next().unwrap_or(charset);
+                decoded.push_str(&decode_charset(&bytes, charset));
+                previous_was_encoded = true;
+            }
+            None => {
+                decoded.push_str(&pending_whitespace);
+                decoded.push_str(&rest[..word_length]);
+                previous_was_encoded = false;
+            }
+        }
+        pending_whitespace.clear();
+        rest = &rest[word_length..];
+    }
+    decoded.push_str(&pending_whitespace);
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Extracts every `<id>` from a Message-ID, In-Reply-To or References header.
+fn parse_message_ids(value: &str) -> Vec<String> {
+    value.split('<')
+        .skip(1)
+        .filter_map(|id| id.split_once('>').map(|(id, _)| format!("<{}>", id.trim())))
+        .collect()
+}
+
+fn parse_date(date: &str) -> Option<u64> {
+    // Drop trailing comments such as "(UTC)", which chrono does not accept.
+    let date = match date.find('(') {
+        Some(index) => date[..index].trim(),
+        None => date.trim(),
+    };
+    DateTime::parse_from_rfc2822(date)
+        .ok()
+        .map(|date| date.timestamp().max(0) as u64)
+}
@@ -0,0 +1,141 @@
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use base64::Engine;
+use chrono::Utc;
+use crate::mail::{OutgoingAttachment, OutgoingMessage, Recipient};
+
+/// Bytes of input per base64 line, giving the 76 character lines required by RFC 2045.
+const BASE64_LINE_INPUT: usize = 57;
+
+static UNIQUE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Writes the message in RFC 5322 format, as `multipart/mixed` when it has attachments,
+/// for transports such as SMTP which take raw MIME. Attachments are streamed from disk.
+pub fn write_message(
+    message: &OutgoingMessage,
+    from: &Recipient,
+    writer: &mut impl Write,
+) -> io::Result<()> {
+    let domain = from.address.rsplit('@').next().unwrap_or("localhost");
+    write!(writer, "From: {}\r\n", encode_recipients(std::slice::from_ref(from)))?;
+    write!(writer, "To: {}\r\n", encode_recipients(&message.to))?;
+    if !message.cc.is_empty() {
+        write!(writer, "Cc: {}\r\n", encode_recipients(&message.cc))?;
+    }
+    write!(writer, "Subject: {}\r\n", encode_header_value(&message.subject))?;
+    write!(writer, "Date: {}\r\n", Utc::now().to_rfc2822())?;
+    write!(writer, "Message-ID: <{}@{}>\r\n", unique_id(), domain)?;
+    if let Some(in_reply_to) = &message.in_reply_to {
+        write!(writer, "In-Reply-To: {}\r\n", in_reply_to)?;
+    }
+    if !message.references.is_empty() {
+        write!(writer, "References: {}\r\n", message.references.join(" "))?;
+    }
+    write!(writer, "MIME-Version: 1.0\r\n")?;
+    if message.attachments.is_empty() {
+        write_text_part(&message.body, writer)?;
+        return Ok(());
+    }
+    let boundary = format!("----=_dashboard_{}", unique_id());
+    write!(writer, "Content-Type: multipart/mixed; boundary=\"{}\"\r\n\r\n", boundary)?;
+    write!(writer, "This is a multi-part message in MIME format.\r\n")?;
+    write!(writer, "--{}\r\n", boundary)?;
+    write_text_part(&message.body, writer)?;
+    for attachment in &message.attachments {
+        write!(writer, "\r\n--{}\r\n", boundary)?;
+        write_attachment_part(attachment, writer)?;
+    }
+    write!(writer, "\r\n--{}--\r\n", boundary)?;
+    Ok(())
+}
+
+fn write_text_part(body: &str, writer: &mut impl Write) -> io::Result<()> {
+    write!(writer, "Content-Type: text/plain; charset=utf-8\r\n")?;
+    let is_7bit = body.is_ascii() && body.lines().all(|line| line.len() <= 998);
+    if is_7bit {
+        write!(writer, "Content-Transfer-Encoding: 7bit\r\n\r\n")?;
+        for line in body.lines() {
+            write!(writer, "{}\r\n", line)?;
+        }
+        return Ok(());
+    }
+    write!(writer, "Content-Transfer-Encoding: base64\r\n\r\n")?;
+    write_base64(&mut body.as_bytes(), writer)
+}
+
+fn write_attachment_part(attachment: &OutgoingAttachment, writer: &mut impl Write) -> io::Result<()> {
+    let name = encode_header_value(&attachment.name).replace('"', "\\\"");
+    write!(writer, "Content-Type: {}; name=\"{}\"\r\n", attachment.content_type, name)?;
+    write!(writer, "Content-Disposition: attachment; filename=\"{}\"\r\n", name)?;
+    write!(writer, "Content-Transfer-Encoding: base64\r\n\r\n")?;
+    write_base64(&mut File::open(&attachment.path)?, writer)
+}
+
+/// Encodes the reader's content in base64 lines without reading it all into memory.
+fn write_base64(reader: &mut impl Read, writer: &mut impl Write) -> io::Result<()> {
+    let mut buffer = [0u8; BASE64_LINE_INPUT];
+    loop {
+        let mut length = 0;
+        while length < buffer.len() {
+            let read = reader.read(&mut buffer[length..])?;
+            if read == 0 {
+                break;
+            }
+            length += read;
+        }
+        if length == 0 {
+            return Ok(());
+        }
+        let line = base64::engine::general_purpose::STANDARD.encode(&buffer[..length]);
+        write!(writer, "{}\r\n", line)?;
+        if length < buffer.len() {
+            return Ok(());
+        }
+    }
+}
+
+fn encode_recipients(recipients: &[Recipient]) -> String {
+    recipients.iter()
+        .map(|recipient| if recipient.name.is_empty() {
+            recipient.address.clone()
+        } else {
+            format!("{} <{}>", encode_header_value(&recipient.name), recipient.address)
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Encodes non-ASCII header values as RFC 2047 encoded words, which may be at most 75
+/// characters long each, folding the header between them.
+fn encode_header_value(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    let mut words: Vec<String> = vec![];
+    let mut chunk = String::new();
+    for c in value.chars() {
+        // 45 bytes encode to 60 base64 characters, plus 12 for the =?UTF-8?B?...?= wrapper.
+        if chunk.len() + c.len_utf8() > 45 {
+            words.push(std::mem::take(&mut chunk));
+        }
+        chunk.push(c);
+    }
+    words.push(chunk);
+    let engine = base64::engine::general_purpose::STANDARD;
+    words.iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", engine.encode(word)))
+        .collect::<Vec<String>>()
+        .join("\r\n ")
+}
+
+fn unique_id() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!(
+        "{}.{}.{}",
+        now.as_micros(),
+        std::process::id(),
+        UNIQUE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
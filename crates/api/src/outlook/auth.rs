@@ -1,28 +1,23 @@
+use std::error::Error;
 use std::str::FromStr;
 use serde::{Serialize, Deserialize};
-use crate::outlook::auth::AccessTokenRequestType::{AuthorizationCode, RefreshToken};
 
-const SCOPE_STR: &'static str = "\
-    offline_access \
-    user.read \
-    mail.readwrite \
-    calendars.readwrite";
-const REDIRECT_URI: &'static str = "http://localhost:6767";
-const API_HOST: &'static str = "https://login.microsoftonline.com";
+const DEFAULT_SCOPES: [&str; 5] = [
+    "offline_access",
+    "user.read",
+    "mail.readwrite",
+    "calendars.readwrite",
+    // Inbox rules are mailbox settings.
+    "mailboxsettings.readwrite",
+];
+const DEFAULT_REDIRECT_PORT: u16 = 6767;
+const API_HOST: &str = "https://login.microsoftonline.com";
 
 /*
     Azure app client id:
     5d2e90a4-2356-4edc-ae81-80fcd4641575
 */
 
-#[derive(Serialize)]
-struct AuthorisationCodeRequest {
-    client_id: String,
-    response_type: String,
-    redirect_uri: String,
-    scope: String
-}
-
 #[derive(Serialize)]
 struct AccessTokenRequest {
     client_id: String,
@@ -34,6 +29,61 @@ struct AccessTokenRequest {
     grant_type: String,
 }
 
+/// How the authorisation flow is run, which must match the registered Azure app.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuthOptions {
+    /// Port of the local server that receives the redirect after signing in.
+    pub redirect_port: u16,
+    pub scopes: Vec<String>,
+}
+
+impl Default for AuthOptions {
+    fn default() -> AuthOptions {
+        AuthOptions {
+            redirect_port: DEFAULT_REDIRECT_PORT,
+            scopes: DEFAULT_SCOPES.iter().map(|scope| scope.to_string()).collect(),
+        }
+    }
+}
+
+impl AuthOptions {
+    fn redirect_uri(&self) -> String {
+        format!("http://localhost:{}", self.redirect_port)
+    }
+
+    fn scope(&self) -> String {
+        self.scopes.join(" ")
+    }
+
+    /// The options to refresh an access token with, asking for the scopes the account was
+    /// granted rather than those configured: a scope added since it signed in fails the
+    /// refresh until it signs in again.
+    pub fn for_refresh(&self, granted_scope: &str) -> AuthOptions {
+        if granted_scope.trim().is_empty() {
+            return self.clone();
+        }
+        let mut scopes: Vec<String> = granted_scope.split_whitespace()
+            .map(|scope| scope.to_string())
+            .collect();
+        // The token response leaves it out, but without it there is no new refresh token.
+        if !has_scope(granted_scope, "offline_access") {
+            scopes.insert(0, "offline_access".to_string());
+        }
+        AuthOptions {
+            redirect_port: self.redirect_port,
+            scopes,
+        }
+    }
+}
+
+/// Whether the scopes of a token response include the scope, which they may give with the
+/// Graph resource in front, as in `https://graph.microsoft.com/Mail.ReadWrite`.
+pub fn has_scope(granted_scope: &str, scope: &str) -> bool {
+    granted_scope.split_whitespace()
+        .map(|granted| granted.rsplit('/').next().unwrap_or(granted))
+        .any(|granted| granted.eq_ignore_ascii_case(scope))
+}
+
 pub enum AccessTokenRequestType {
     AuthorizationCode(String),
     RefreshToken(String)
@@ -48,7 +98,7 @@ pub struct AccessTokenResponse {
     pub refresh_token: String,
 }
 
-pub fn get_authorisation_code_request_url(client_id: &str) -> String {
+pub fn get_authorisation_code_request_url(client_id: &str, options: &AuthOptions) -> String {
     let api_endpoint = "/common/oauth2/v2.0/authorize";
     let auth_url = format!(
         "{}{}?\
@@ -59,35 +109,41 @@ pub fn get_authorisation_code_request_url(client_id: &str) -> String {
         API_HOST,
         api_endpoint,
         client_id,
-        REDIRECT_URI,
-        SCOPE_STR,
+        options.redirect_uri(),
+        options.scope(),
     );
     reqwest::Url::from_str(&auth_url).unwrap().to_string()
 }
 
-pub fn get_authorisation_code() -> String {
-    let mut redirect_request = crate::web::get_request();
-    // Redirect request should be in the format GET /?code={} HTTP/
-    let code: String = {
-        let mut split = redirect_request.split("HTTP/")
-            .next().unwrap().chars().as_str().split("?code=");
-        if split.clone().count() != 2 {
-            panic!("Invalid redirect URL. \
-                It should be in the format https://localhost:port?code={}");
-        }
-        split.next();
-        let mut chars = split.next().unwrap().chars();
-        chars.next_back();
-        let front_capped = chars.as_str().to_owned();
-        front_capped.split("&").next().unwrap().to_owned()
-    };
-    code
+/// Waits for the browser to be redirected back after signing in, and returns the code it
+/// carries.
+pub async fn get_authorisation_code(options: &AuthOptions) -> Result<String, Box<dyn Error>> {
+    let redirect_request = crate::web::get_request(options.redirect_port).await?;
+    // The request line is in the format GET /?code=<code>&... HTTP/1.1
+    let query = redirect_request.lines()
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|path| path.split_once('?'))
+        .map(|(_, query)| query)
+        .unwrap_or_default();
+    let parameter = |name: &str| query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string());
+    if let Some(code) = parameter("code") {
+        return Ok(code);
+    }
+    let description = parameter("error_description")
+        .map(|description| description.replace('+', " "))
+        .unwrap_or_else(|| "the redirect carried no code".to_string());
+    Err(format!("sign-in failed: {}", description).into())
 }
 
 pub async fn get_access_token(
     client_id: &str,
     request_type: AccessTokenRequestType,
-) -> AccessTokenResponse {
+    options: &AuthOptions,
+) -> Result<AccessTokenResponse, Box<dyn Error>> {
     let api_endpoint = "/common/oauth2/v2.0/token";
     let request = AccessTokenRequest {
         client_id: client_id.to_string(),
@@ -97,8 +153,8 @@ pub async fn get_access_token(
                 _ => None
             }
         },
-        redirect_uri: "http://localhost:6767".to_string(),
-        scope: SCOPE_STR.to_string(),
+        redirect_uri: options.redirect_uri(),
+        scope: options.scope(),
         code: {
             match &request_type {
                 AccessTokenRequestType::AuthorizationCode(code) => { Some(code.clone()) }
@@ -126,16 +182,46 @@ pub async fn get_access_token(
             }
         }.to_string(),
     };
-    let access_token_response: AccessTokenResponse = {
-        let response = reqwest::Client::new()
-            .post(format!("{}{}", API_HOST, api_endpoint))
-            .header("Content-Type", "application/x-www-form-urlencoded")
-            .form(&request)
-            .send()
-            .await
-            .unwrap();
-        let str = response.text().await.unwrap();
-        serde_json::from_str(str.as_str()).unwrap()
-    };
-    access_token_response
+    #[derive(Deserialize)]
+    struct ErrorResponse {
+        error_description: String,
+    }
+    let response = reqwest::Client::new()
+        .post(format!("{}{}", API_HOST, api_endpoint))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .form(&request)
+        .send()
+        .await?;
+    let text = response.text().await?;
+    if let Ok(error) = serde_json::from_str::<ErrorResponse>(&text) {
+        return Err(format!("could not get an access token: {}", error.error_description).into());
+    }
+    Ok(serde_json::from_str(&text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refreshes_with_the_granted_scopes() {
+        let options = AuthOptions::default();
+        let granted = "https://graph.microsoft.com/Mail.ReadWrite \
+            https://graph.microsoft.com/User.Read";
+        assert_eq!(
+            options.for_refresh(granted).scope(),
+            format!("offline_access {}", granted)
+        );
+        let granted = "offline_access Mail.Read";
+        assert_eq!(options.for_refresh(granted).scope(), granted);
+        assert_eq!(options.for_refresh(""), options);
+    }
+
+    #[test]
+    fn finds_scopes_with_or_without_the_resource() {
+        let granted = "openid https://graph.microsoft.com/MailboxSettings.ReadWrite";
+        assert!(has_scope(granted, "mailboxsettings.readwrite"));
+        assert!(has_scope(granted, "openid"));
+        assert!(!has_scope(granted, "mail.readwrite"));
+    }
 }
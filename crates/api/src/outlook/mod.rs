@@ -1,38 +1,117 @@
 use std::error::Error;
-use std::future::Future;
-use std::io;
-use std::os::unix::fs::chroot;
+use std::io::{Read, Write};
+use base64::Engine;
 use std::time::{SystemTime, UNIX_EPOCH};
-use chrono::{NaiveDateTime};
+use chrono::{NaiveDateTime, TimeZone, Utc};
 use reqwest::StatusCode;
 use serde::{Serialize, Deserialize};
-use crate::mail::{Mailbox, Message};
-use crate::mail::SetReadError;
-use crate::outlook::auth::{AccessTokenRequestType, AccessTokenResponse};
+use crate::mail::{Attachment, Flag, FlagUpdate, Mailbox, Message, OutgoingAttachment, OutgoingMessage};
+use crate::mail::{BodyType, UpdateError};
+use crate::mime::BodyPreference;
+use crate::search::SearchQuery;
+use crate::outlook::auth::{AccessTokenRequestType, AccessTokenResponse, AuthOptions};
 
 pub mod auth;
+pub mod rules;
 
-const API_HOST: &'static str = "https://graph.microsoft.com";
+const API_HOST: &str = "https://graph.microsoft.com";
+/// Attachments at or above this size must be uploaded through an upload session.
+const UPLOAD_SESSION_THRESHOLD: u64 = 3 * 1024 * 1024;
+/// Upload session chunks must be a multiple of 320 KiB.
+const UPLOAD_CHUNK_SIZE: usize = 320 * 1024 * 12;
+/// The largest attachment Graph accepts through an upload session.
+const MAX_ATTACHMENTS_SIZE: u64 = 150 * 1024 * 1024;
+/// Where snoozed messages wait, created the first time a message is snoozed.
+const SNOOZED_FOLDER: &str = "Snoozed";
+/// Folders Graph knows by name in every mailbox, whatever their display name.
+const WELL_KNOWN_FOLDERS: [&str; 6] =
+    ["inbox", "archive", "deleteditems", "junkemail", "drafts", "sentitems"];
+/// Expands attachment metadata only, as content is downloaded on demand. contentId only
+/// exists on file attachments, so it is selected through a cast. In-Reply-To and References
+/// are only exposed as the MAPI properties below.
+const MESSAGE_EXPAND: &str = "attachments($select=id,name,size,contentType,isInline,\
+    microsoft.graph.fileAttachment/contentId),\
+    singleValueExtendedProperties($filter=id eq 'String 0x1042' or id eq 'String 0x1039')";
+/// PidTagInReplyToId, the `In-Reply-To` header.
+const IN_REPLY_TO_PROPERTY: &str = "String 0x1042";
+/// PidTagInternetReferences, the `References` header.
+const REFERENCES_PROPERTY: &str = "String 0x1039";
+/// PidTagDeferredSendTime: the server keeps the message in the Outbox until then.
+const DEFERRED_SEND_TIME_PROPERTY: &str = "SystemTime 0x3FEF";
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct OutlookMailbox {
+    /// Stable identity of the account. Several accounts may sign in with the same app, so
+    /// the client ID cannot tell them apart. Empty in storage written before ids existed.
+    #[serde(default)]
+    pub id: String,
+    /// The account's own address, looked up after signing in.
+    #[serde(default)]
+    pub address: Option<String>,
     /// Last update timestamp.
     pub timestamp: u64,
     pub client_id: String,
-    pub auth: AccessTokenResponse
+    pub auth: AccessTokenResponse,
+    /// Shown instead of the id, when set.
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Colour of the tag on the account's messages, as a colour name or `#rrggbb`.
+    #[serde(default)]
+    pub color: Option<String>,
+    /// Disabled mailboxes are kept, but not synced.
+    #[serde(default)]
+    pub is_disabled: bool,
+    /// Set from the config on load rather than saved per mailbox.
+    #[serde(skip)]
+    pub body_preference: BodyPreference,
+    /// Set from the config on load, and used when refreshing the access token.
+    #[serde(skip)]
+    pub auth_options: AuthOptions,
 }
 
 #[derive(Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct OutlookMessage {
     id: String,
-    sent_date_time: String,
-    has_attachments: bool,
+    /// Not set on drafts.
+    sent_date_time: Option<String>,
+    #[serde(default)]
+    last_modified_date_time: Option<String>,
     subject: String,
     body: OutlookMessageBody,
-    body_preview: String,
-    from: Recipient,
-    to_recipients: Vec<Recipient>
+    /// Not set on drafts.
+    from: Option<Recipient>,
+    to_recipients: Vec<Recipient>,
+    #[serde(default)]
+    cc_recipients: Vec<Recipient>,
+    is_read: bool,
+    flag: OutlookFlag,
+    categories: Vec<String>,
+    conversation_id: String,
+    internet_message_id: Option<String>,
+    #[serde(default)]
+    attachments: Vec<OutlookAttachment>,
+    #[serde(default)]
+    single_value_extended_properties: Vec<ExtendedProperty>,
+}
+
+/// A MAPI property Graph has no field for.
+#[derive(Serialize, Deserialize, Clone)]
+struct ExtendedProperty {
+    id: String,
+    value: String,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct OutlookAttachment {
+    id: String,
+    name: String,
+    size: u64,
+    content_type: Option<String>,
+    is_inline: bool,
+    #[serde(default)]
+    content_id: Option<String>,
 }
 
 #[derive(Deserialize, Clone)]
@@ -42,7 +121,24 @@ struct OutlookMessageBody {
     content: String,
 }
 
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct OutlookFlag {
+    flag_status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    start_date_time: Option<DateTimeTimeZone>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    due_date_time: Option<DateTimeTimeZone>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+struct DateTimeTimeZone {
+    date_time: String,
+    time_zone: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
 struct Recipient {
     email_address: crate::mail::Recipient
@@ -54,92 +150,776 @@ impl OutlookMailbox {
         auth: AccessTokenResponse
     ) -> Self {
         Self {
+            id: crate::mail::new_mailbox_id(),
+            address: None,
             timestamp: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs(),
             client_id: client_id.to_string(),
             auth,
+            name: None,
+            color: None,
+            is_disabled: false,
+            body_preference: BodyPreference::default(),
+            auth_options: AuthOptions::default(),
+        }
+    }
+
+    /// Replaces the tokens with those of a new sign-in.
+    pub fn set_auth(&mut self, auth: AccessTokenResponse) {
+        self.auth = auth;
+        self.timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    }
+
+    /// Looks up the address of the signed in user.
+    pub async fn fetch_address(&self) -> Result<String, Box<dyn Error>> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Response {
+            mail: Option<String>,
+            /// The sign-in name, which is the address of personal accounts without `mail`.
+            user_principal_name: String,
+        }
+        let api_endpoint = "/v1.0/me";
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", API_HOST, api_endpoint))
+            .query(&[("$select", "mail,userPrincipalName")])
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(format!("failed to look up address: {}", response.text().await?).into());
         }
+        let response: Response = serde_json::from_str(&response.text().await?)?;
+        Ok(response.mail.unwrap_or(response.user_principal_name))
+    }
+
+    /// When the access token expires, in seconds since the Unix epoch. It is refreshed on the
+    /// next request after that.
+    pub fn access_token_expiry(&self) -> u64 {
+        self.timestamp + self.auth.expires_in as u64
     }
 
-    pub async fn try_refresh_access_token(&mut self) -> bool {
+    /// Gets a new access token if the current one has expired, returning whether it did.
+    pub async fn try_refresh_access_token(&mut self) -> Result<bool, Box<dyn Error>> {
         let is_expired: bool = {
             let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
             let elapsed = now - self.timestamp;
             elapsed > self.auth.expires_in as u64
         };
         if !is_expired {
-            return false;
+            return Ok(false);
         }
         let access_token = crate::outlook::auth::get_access_token(
             self.client_id.as_str(),
-            AccessTokenRequestType::RefreshToken(self.auth.refresh_token.clone())
-        ).await;
-        self.auth = access_token;
-        return true;
+            AccessTokenRequestType::RefreshToken(self.auth.refresh_token.clone()),
+            &self.auth_options.for_refresh(&self.auth.scope),
+        ).await?;
+        self.set_auth(access_token);
+        Ok(true)
     }
-}
 
-#[async_trait::async_trait]
-impl Mailbox for OutlookMailbox {
-    fn get_id(&self) -> &str {
-        self.client_id.as_str()
+    /// Requests messages with their bodies in the given format.
+    async fn get_messages(
+        &self,
+        api_endpoint: &str,
+        query: &[(&str, String)],
+        body_preference: BodyPreference,
+    ) -> reqwest::Result<reqwest::Response> {
+        // Graph returns HTML bodies unless asked to convert them.
+        let body_content_type = match body_preference {
+            BodyPreference::Plain => "text",
+            BodyPreference::Html => "html",
+        };
+        reqwest::Client::new()
+            .get(format!("{}{}", API_HOST, api_endpoint))
+            .query(query)
+            .header("Authorization", &self.auth.access_token)
+            .header("Prefer", format!("outlook.body-content-type=\"{}\"", body_content_type))
+            .send()
+            .await
     }
 
-    async fn fetch_unread(&self) -> Result<Vec<Message>, Box<dyn Error>> {
+    async fn fetch_messages(
+        &self,
+        api_endpoint: &str,
+        query: &[(&str, String)],
+        body_preference: BodyPreference,
+    ) -> Result<Vec<Message>, Box<dyn Error>> {
         #[derive(Deserialize)]
         struct Response {
             value: Vec<OutlookMessage>,
         }
-        let api_endpoint = "/v1.0/me/mailFolders/Inbox/messages?$filter=isRead ne true&$top=1000";
         let response: Response = {
-            let response = reqwest::Client::new()
-                .get(format!("{}{}", API_HOST, api_endpoint))
-                .header("Authorization", &self.auth.access_token)
-                .send()
-                .await?;
+            let response = self.get_messages(api_endpoint, query, body_preference).await?;
             if response.status() != StatusCode::OK {
-                // todo: handle
-                panic!("failed to fetch unread email");
+                return Err(format!(
+                    "failed to fetch messages from {}: {}",
+                    api_endpoint,
+                    response.status()
+                ).into());
             }
             serde_json::from_str(response.text().await?.as_str())?
         };
-        let messages: Vec<Message> = response.value.iter().map(|outlook_message|
-            Message {
-                id: outlook_message.id.clone(),
-                mailbox_id: self.get_id().to_string(),
-                from: outlook_message.from.email_address.clone(),
-                to: outlook_message.to_recipients.iter()
-                    .map(|recipient| recipient.email_address.clone()).collect(),
-                subject: outlook_message.subject.clone(),
-                body: outlook_message.body.content.clone(),
-                date: NaiveDateTime
-                    ::parse_from_str(&outlook_message.sent_date_time, "%Y-%m-%dT%H:%M:%S%Z")
-                    .unwrap()
-                    .timestamp() as u64,
-            }
-        ).collect();
+        let messages: Vec<Message> = response.value.iter()
+            .map(|outlook_message| self.to_message(outlook_message))
+            .collect();
         Ok(messages)
     }
 
-    async fn set_as_read(self, message_id: String) -> Result<(), SetReadError> {
+    /// Finds the id of a folder by its display name, or the name itself for well-known folders.
+    async fn find_folder_id(&self, name: &str) -> Result<Option<String>, Box<dyn Error>> {
+        if WELL_KNOWN_FOLDERS.contains(&name.to_lowercase().as_str()) {
+            return Ok(Some(name.to_lowercase()));
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            value: Vec<Folder>,
+        }
+        #[derive(Deserialize)]
+        struct Folder {
+            id: String,
+        }
+        let api_endpoint = "/v1.0/me/mailFolders";
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", API_HOST, api_endpoint))
+            .query(&[
+                // Quotes are escaped by doubling them in OData string literals.
+                ("$filter", format!("displayName eq '{}'", name.replace('\'', "''"))),
+                ("$select", "id".to_string()),
+            ])
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(format!("failed to look up folder {}: {}", name, response.status()).into());
+        }
+        let response: Response = serde_json::from_str(&response.text().await?)?;
+        Ok(response.value.into_iter().next().map(|folder| folder.id))
+    }
+
+    /// Creates a top-level folder and returns its id.
+    async fn create_folder(&self, name: &str) -> Result<String, Box<dyn Error>> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Request<'a> {
+            display_name: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            id: String,
+        }
+        let api_endpoint = "/v1.0/me/mailFolders";
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&Request { display_name: name })?)
+            .send()
+            .await?;
+        if response.status() != StatusCode::CREATED {
+            return Err(format!("failed to create folder {}: {}", name, response.status()).into());
+        }
+        let response: Response = serde_json::from_str(&response.text().await?)?;
+        Ok(response.id)
+    }
+
+    /// Moves the message to the folder with this id, returning its id in that folder.
+    async fn move_to_folder(
+        &self,
+        message_id: &str,
+        folder_id: String,
+    ) -> Result<String, Box<dyn Error>> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Request {
+            destination_id: String,
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            id: String,
+        }
+        let api_endpoint = format!("/v1.0/me/messages/{}/move", message_id);
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&Request { destination_id: folder_id }).unwrap())
+            .send()
+            .await?;
+        if response.status() != StatusCode::CREATED {
+            return Err(format!("failed to move message: {}", response.status()).into());
+        }
+        let response: Response = serde_json::from_str(&response.text().await?)?;
+        Ok(response.id)
+    }
+
+    /// Adds the attachments to a draft and sends it.
+    async fn send_draft(
+        &self,
+        message_id: &str,
+        attachments: &[OutgoingAttachment],
+    ) -> Result<(), Box<dyn Error>> {
+        for attachment in attachments {
+            self.add_attachment(message_id, attachment).await?;
+        }
+        let api_endpoint = format!("/v1.0/me/messages/{}/send", message_id);
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Length", 0)
+            .send()
+            .await?;
+        if response.status() != StatusCode::ACCEPTED {
+            return Err(format!("failed to send message: {}", response.status()).into());
+        }
+        Ok(())
+    }
+
+    async fn add_attachment(
+        &self,
+        message_id: &str,
+        attachment: &OutgoingAttachment,
+    ) -> Result<(), Box<dyn Error>> {
+        if attachment.size >= UPLOAD_SESSION_THRESHOLD {
+            return self.upload_attachment(message_id, attachment).await;
+        }
+        let api_endpoint = format!("/v1.0/me/messages/{}/attachments", message_id);
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Request<'a> {
+            #[serde(rename = "@odata.type")]
+            odata_type: &'a str,
+            name: &'a str,
+            content_type: &'a str,
+            content_bytes: String,
+        }
+        let content = std::fs::read(&attachment.path)?;
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&Request {
+                odata_type: "#microsoft.graph.fileAttachment",
+                name: &attachment.name,
+                content_type: &attachment.content_type,
+                content_bytes: base64::engine::general_purpose::STANDARD.encode(content),
+            })?)
+            .send()
+            .await?;
+        if response.status() != StatusCode::CREATED {
+            return Err(format!(
+                "failed to attach {}: {}",
+                attachment.name,
+                response.status()
+            ).into());
+        }
+        Ok(())
+    }
+
+    /// Uploads a large attachment in chunks through an upload session.
+    async fn upload_attachment(
+        &self,
+        message_id: &str,
+        attachment: &OutgoingAttachment,
+    ) -> Result<(), Box<dyn Error>> {
+        let api_endpoint = format!(
+            "/v1.0/me/messages/{}/attachments/createUploadSession",
+            message_id
+        );
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Request<'a> {
+            attachment_item: AttachmentItem<'a>,
+        }
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct AttachmentItem<'a> {
+            attachment_type: &'a str,
+            name: &'a str,
+            size: u64,
+            content_type: &'a str,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Response {
+            upload_url: String,
+        }
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(&Request {
+                attachment_item: AttachmentItem {
+                    attachment_type: "file",
+                    name: &attachment.name,
+                    size: attachment.size,
+                    content_type: &attachment.content_type,
+                },
+            })?)
+            .send()
+            .await?;
+        if response.status() != StatusCode::CREATED {
+            return Err(format!(
+                "failed to create upload session for {}: {}",
+                attachment.name,
+                response.status()
+            ).into());
+        }
+        let upload_url = serde_json::from_str::<Response>(response.text().await?.as_str())?
+            .upload_url;
+        let mut file = std::fs::File::open(&attachment.path)?;
+        let mut buffer = vec![0u8; UPLOAD_CHUNK_SIZE];
+        let mut offset: u64 = 0;
+        while offset < attachment.size {
+            let length = read_chunk(&mut file, &mut buffer)?;
+            if length == 0 {
+                return Err(format!("{} changed while uploading", attachment.name).into());
+            }
+            // The upload URL is pre-authenticated, and rejects an Authorization header.
+            let response = reqwest::Client::new()
+                .put(&upload_url)
+                .header("Content-Length", length)
+                .header("Content-Range", format!(
+                    "bytes {}-{}/{}",
+                    offset,
+                    offset + length as u64 - 1,
+                    attachment.size
+                ))
+                .body(buffer[..length].to_vec())
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                return Err(format!(
+                    "failed to upload {}: {}",
+                    attachment.name,
+                    response.status()
+                ).into());
+            }
+            offset += length as u64;
+        }
+        Ok(())
+    }
+
+    fn to_message(&self, outlook_message: &OutlookMessage) -> Message {
+        // Graph names properties with the type in lowercase, whatever case was asked for.
+        let extended_property = |id: &str| outlook_message.single_value_extended_properties
+            .iter()
+            .find(|property| property.id.eq_ignore_ascii_case(id))
+            .map(|property| property.value.as_str());
+        Message {
+            id: outlook_message.id.clone(),
+            mailbox_id: self.get_id().to_string(),
+            from: match &outlook_message.from {
+                Some(from) => from.email_address.clone(),
+                None => crate::mail::Recipient {
+                    address: self.address.clone().unwrap_or_default(),
+                    name: "".to_string(),
+                },
+            },
+            to: outlook_message.to_recipients.iter()
+                .map(|recipient| recipient.email_address.clone()).collect(),
+            cc: outlook_message.cc_recipients.iter()
+                .map(|recipient| recipient.email_address.clone()).collect(),
+            subject: outlook_message.subject.clone(),
+            body: outlook_message.body.content.clone(),
+            body_type: match outlook_message.body.content_type.to_lowercase().as_str() {
+                "html" => BodyType::Html,
+                _ => BodyType::Text,
+            },
+            date: outlook_message.sent_date_time.as_ref()
+                .or(outlook_message.last_modified_date_time.as_ref())
+                .and_then(|date| NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S%Z").ok())
+                .map(|date| date.and_utc().timestamp() as u64)
+                .unwrap_or(0),
+            is_read: outlook_message.is_read,
+            flagged: Flag::from(&outlook_message.flag),
+            categories: outlook_message.categories.clone(),
+            internet_message_id: outlook_message.internet_message_id.clone(),
+            in_reply_to: extended_property(IN_REPLY_TO_PROPERTY).map(|id| id.to_string()),
+            references: extended_property(REFERENCES_PROPERTY)
+                .map(|ids| ids.split_whitespace().map(|id| id.to_string()).collect())
+                .unwrap_or_default(),
+            thread_id: Some(outlook_message.conversation_id.clone()),
+            attachments: outlook_message.attachments.iter()
+                .map(|attachment| Attachment {
+                    id: attachment.id.clone(),
+                    name: attachment.name.clone(),
+                    size: attachment.size,
+                    content_type: attachment.content_type.clone()
+                        .unwrap_or_else(|| "application/octet-stream".to_string()),
+                    is_inline: attachment.is_inline,
+                    content_id: attachment.content_id.as_ref()
+                        .map(|content_id| content_id.trim_start_matches('<').trim_end_matches('>'))
+                        .map(|content_id| content_id.to_string()),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Fills the buffer unless the end of the file is reached first.
+fn read_chunk(file: &mut std::fs::File, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut length = 0;
+    while length < buffer.len() {
+        let read = file.read(&mut buffer[length..])?;
+        if read == 0 {
+            break;
+        }
+        length += read;
+    }
+    Ok(length)
+}
+
+/// Converts a query into the Keyword Query Language understood by Graph's `$search`.
+fn to_kql(query: &SearchQuery) -> String {
+    fn quote(value: &str) -> String {
+        if value.contains(char::is_whitespace) {
+            format!("\\\"{}\\\"", value)
+        } else {
+            value.to_string()
+        }
+    }
+    fn date(timestamp: u64) -> String {
+        Utc.timestamp_opt(timestamp as i64, 0).unwrap().format("%Y-%m-%d").to_string()
+    }
+    let mut terms: Vec<String> = vec![];
+    terms.extend(query.from.iter().map(|from| format!("from:{}", quote(from))));
+    terms.extend(query.to.iter().map(|to| format!("to:{}", quote(to))));
+    terms.extend(query.subject.iter().map(|subject| format!("subject:{}", quote(subject))));
+    if let Some(before) = query.before {
+        terms.push(format!("received<{}", date(before)));
+    }
+    if let Some(after) = query.after {
+        terms.push(format!("received>={}", date(after)));
+    }
+    terms.extend(query.text.iter().map(|text| quote(text)));
+    terms.join(" AND ")
+}
+
+impl DateTimeTimeZone {
+    fn from_timestamp(timestamp: u64) -> Self {
+        Self {
+            date_time: Utc.timestamp_opt(timestamp as i64, 0).unwrap()
+                .format("%Y-%m-%dT%H:%M:%S").to_string(),
+            time_zone: "UTC".to_string(),
+        }
+    }
+
+    /// Graph returns flag dates in UTC unless a Prefer: outlook.timezone header is sent.
+    fn to_timestamp(&self) -> Option<u64> {
+        NaiveDateTime::parse_from_str(&self.date_time, "%Y-%m-%dT%H:%M:%S%.f")
+            .ok()
+            .map(|date_time| date_time.and_utc().timestamp() as u64)
+    }
+}
+
+impl From<&OutlookFlag> for Flag {
+    fn from(flag: &OutlookFlag) -> Self {
+        match flag.flag_status.as_str() {
+            "flagged" => Flag::Flagged(
+                flag.due_date_time.as_ref().and_then(|due| due.to_timestamp())
+            ),
+            "complete" => Flag::Complete,
+            _ => Flag::NotFlagged,
+        }
+    }
+}
+
+impl From<&Flag> for OutlookFlag {
+    fn from(flag: &Flag) -> Self {
+        match flag {
+            Flag::NotFlagged => OutlookFlag {
+                flag_status: "notFlagged".to_string(),
+                start_date_time: None,
+                due_date_time: None,
+            },
+            Flag::Flagged(due) => OutlookFlag {
+                flag_status: "flagged".to_string(),
+                // Graph rejects a due date without a start date.
+                start_date_time: due.map(|_| DateTimeTimeZone::from_timestamp(
+                    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+                )),
+                due_date_time: due.map(DateTimeTimeZone::from_timestamp),
+            },
+            Flag::Complete => OutlookFlag {
+                flag_status: "complete".to_string(),
+                start_date_time: None,
+                due_date_time: None,
+            },
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl Mailbox for OutlookMailbox {
+    fn get_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn fetch_unread(&self) -> Result<Vec<Message>, Box<dyn Error>> {
+        let api_endpoint = "/v1.0/me/mailFolders/Inbox/messages";
+        self.fetch_messages(api_endpoint, &[
+            ("$filter", "isRead ne true".to_string()),
+            ("$top", "1000".to_string()),
+            ("$expand", MESSAGE_EXPAND.to_string()),
+        ], self.body_preference).await
+    }
+
+    async fn fetch_message(&self, message_id: &str) -> Result<Option<Message>, Box<dyn Error>> {
+        let api_endpoint = format!("/v1.0/me/messages/{}", message_id);
+        let response = self.get_messages(&api_endpoint, &[
+            ("$expand", MESSAGE_EXPAND.to_string()),
+        ], self.body_preference).await?;
+        // Graph answers 400 rather than 404 for ids that are not even well formed.
+        if response.status() == StatusCode::NOT_FOUND
+            || response.status() == StatusCode::BAD_REQUEST {
+            return Ok(None);
+        }
+        if response.status() != StatusCode::OK {
+            return Err(format!("failed to fetch message: {}", response.status()).into());
+        }
+        let message: OutlookMessage = serde_json::from_str(response.text().await?.as_str())?;
+        Ok(Some(self.to_message(&message)))
+    }
+
+    async fn search(&self, query: &SearchQuery) -> Result<Vec<Message>, Box<dyn Error>> {
+        // $search cannot be combined with $filter, so dates are expressed in KQL too.
+        let api_endpoint = "/v1.0/me/messages";
+        self.fetch_messages(api_endpoint, &[
+            ("$search", format!("\"{}\"", to_kql(query))),
+            ("$top", "250".to_string()),
+            ("$expand", MESSAGE_EXPAND.to_string()),
+        ], self.body_preference).await
+    }
+
+    async fn download_attachment(
+        &self,
+        message_id: &str,
+        attachment_id: &str,
+        writer: &mut (dyn Write + Send),
+    ) -> Result<u64, Box<dyn Error>> {
+        let api_endpoint = format!(
+            "/v1.0/me/messages/{}/attachments/{}/$value",
+            message_id,
+            attachment_id
+        );
+        let mut response = reqwest::Client::new()
+            .get(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(format!("failed to download attachment: {}", response.status()).into());
+        }
+        // Write chunk by chunk so large files are never fully buffered in memory.
+        let mut length: u64 = 0;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk)?;
+            length += chunk.len() as u64;
+        }
+        writer.flush()?;
+        Ok(length)
+    }
+
+    async fn download_message(
+        &self,
+        message_id: &str,
+        writer: &mut (dyn Write + Send),
+    ) -> Result<u64, Box<dyn Error>> {
+        let api_endpoint = format!("/v1.0/me/messages/{}/$value", message_id);
+        let mut response = reqwest::Client::new()
+            .get(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(format!("failed to download message: {}", response.status()).into());
+        }
+        let mut length: u64 = 0;
+        while let Some(chunk) = response.chunk().await? {
+            writer.write_all(&chunk)?;
+            length += chunk.len() as u64;
+        }
+        writer.flush()?;
+        Ok(length)
+    }
+
+    fn max_attachments_size(&self) -> u64 {
+        MAX_ATTACHMENTS_SIZE
+    }
+
+    fn can_defer_sending(&self) -> bool {
+        true
+    }
+
+    async fn send(&self, message: OutgoingMessage) -> Result<(), Box<dyn Error>> {
+        // Attachments over 3 MB can only be added to an existing message, so every message
+        // is sent as a draft first.
+        let message_id = self.save_draft(&message).await?;
+        // Kept as text, as the error has to be held while the draft is deleted.
+        let result = self.send_draft(&message_id, &message.attachments).await
+            .map_err(|error| error.to_string());
+        if result.is_err() && message.draft_id.is_none() {
+            // The draft was only made to send the message, and would otherwise be left behind
+            // in the Drafts folder. A draft the user saved is kept for them.
+            let _ = self.delete_message(&message_id).await;
+        }
+        Ok(result?)
+    }
+
+    async fn save_draft(&self, message: &OutgoingMessage) -> Result<String, Box<dyn Error>> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Request<'a> {
+            subject: &'a str,
+            body: Body<'a>,
+            to_recipients: Vec<Recipient>,
+            cc_recipients: Vec<Recipient>,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            single_value_extended_properties: Vec<ExtendedProperty>,
+        }
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Body<'a> {
+            content_type: &'a str,
+            content: &'a str,
+        }
+        #[derive(Deserialize)]
+        struct Response {
+            id: String,
+        }
+        fn to_recipients(recipients: &[crate::mail::Recipient]) -> Vec<Recipient> {
+            recipients.iter()
+                .map(|recipient| Recipient { email_address: recipient.clone() })
+                .collect()
+        }
+        let mut single_value_extended_properties: Vec<ExtendedProperty> = message.send_at.iter()
+            .filter_map(|send_at| Utc.timestamp_opt(*send_at as i64, 0).single())
+            .map(|send_at| ExtendedProperty {
+                id: DEFERRED_SEND_TIME_PROPERTY.to_string(),
+                value: send_at.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            })
+            .collect();
+        if let Some(in_reply_to) = &message.in_reply_to {
+            single_value_extended_properties.push(ExtendedProperty {
+                id: IN_REPLY_TO_PROPERTY.to_string(),
+                value: in_reply_to.clone(),
+            });
+        }
+        if !message.references.is_empty() {
+            single_value_extended_properties.push(ExtendedProperty {
+                id: REFERENCES_PROPERTY.to_string(),
+                value: message.references.join(" "),
+            });
+        }
+        let request_body = serde_json::to_string(&Request {
+            subject: &message.subject,
+            body: Body {
+                content_type: "Text",
+                content: &message.body,
+            },
+            to_recipients: to_recipients(&message.to),
+            cc_recipients: to_recipients(&message.cc),
+            single_value_extended_properties,
+        })?;
+        let client = reqwest::Client::new();
+        let (request, expected_status) = match &message.draft_id {
+            Some(draft_id) => (
+                client.patch(format!("{}/v1.0/me/messages/{}", API_HOST, draft_id)),
+                StatusCode::OK,
+            ),
+            None => (
+                client.post(format!("{}/v1.0/me/messages", API_HOST)),
+                StatusCode::CREATED,
+            ),
+        };
+        let response = request
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Type", "application/json")
+            .body(request_body)
+            .send()
+            .await?;
+        if response.status() != expected_status {
+            return Err(format!("failed to save draft: {}", response.status()).into());
+        }
+        let response: Response = serde_json::from_str(response.text().await?.as_str())?;
+        Ok(response.id)
+    }
+
+    async fn fetch_drafts(&self) -> Result<Vec<Message>, Box<dyn Error>> {
+        let api_endpoint = "/v1.0/me/mailFolders/drafts/messages";
+        self.fetch_messages(api_endpoint, &[
+            ("$orderby", "lastModifiedDateTime desc".to_string()),
+            ("$top", "250".to_string()),
+            ("$expand", MESSAGE_EXPAND.to_string()),
+        ], BodyPreference::Plain).await
+    }
+
+    async fn delete_message(&self, message_id: &str) -> Result<(), Box<dyn Error>> {
+        let api_endpoint = format!("/v1.0/me/messages/{}", message_id);
+        let response = reqwest::Client::new()
+            .delete(format!("{}{}", API_HOST, api_endpoint))
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::NO_CONTENT {
+            return Err(format!("failed to delete message: {}", response.status()).into());
+        }
+        Ok(())
+    }
+
+    async fn update_flags(self, message_id: String, update: FlagUpdate) -> Result<(), UpdateError> {
         let api_endpoint = format!("/v1.0/me/messages/{}", message_id);
         #[derive(Serialize)]
         #[serde(rename_all = "camelCase")]
         struct Request {
-            is_read: bool,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            is_read: Option<bool>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            flag: Option<OutlookFlag>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            categories: Option<Vec<String>>,
         }
         let response = reqwest::Client::new()
             .patch(format!("{}{}", API_HOST, api_endpoint))
             .header("Authorization", &self.auth.access_token)
             .header("Content-Type", "application/json")
             .body(serde_json::to_string(&Request {
-                is_read: true,
+                is_read: update.is_read,
+                flag: update.flagged.as_ref().map(OutlookFlag::from),
+                categories: update.categories,
             }).unwrap())
             .send()
             .await
-            .map_err(|_| SetReadError::NoResponse)?;
+            .map_err(|_| UpdateError::NoResponse)?;
         if response.status() != StatusCode::OK {
-            return Err(SetReadError::NonOkCode(response.status()));
+            return Err(UpdateError::NonOkCode(response.status()));
         }
         Ok(())
     }
+
+    async fn move_message(&self, message_id: &str, folder: &str) -> Result<String, Box<dyn Error>> {
+        let folder_id = self.find_folder_id(folder).await?;
+        match folder_id {
+            Some(folder_id) => self.move_to_folder(message_id, folder_id).await,
+            None => Err(format!("no folder named {}", folder).into()),
+        }
+    }
+
+    async fn snooze(&self, message_id: &str) -> Result<String, Box<dyn Error>> {
+        let folder_id = self.find_folder_id(SNOOZED_FOLDER).await?;
+        let folder_id = match folder_id {
+            Some(folder_id) => folder_id,
+            None => self.create_folder(SNOOZED_FOLDER).await?,
+        };
+        self.move_to_folder(message_id, folder_id).await
+    }
+
+    async fn wake(&self, message_id: &str) -> Result<String, Box<dyn Error>> {
+        let message_id = self.move_to_folder(message_id, "inbox".to_string()).await?;
+        self.clone().update_flags(message_id.clone(), FlagUpdate {
+            is_read: Some(false),
+            ..Default::default()
+        }).await?;
+        Ok(message_id)
+    }
 }
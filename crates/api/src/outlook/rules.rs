@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::error::Error;
+use reqwest::StatusCode;
+use serde::{Serialize, Deserialize, Deserializer};
+use crate::outlook::{OutlookMailbox, API_HOST};
+use crate::outlook::auth::has_scope;
+
+/// A rule run by the server on messages arriving in the inbox.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageRule {
+    /// Set by the server once the rule is created.
+    #[serde(default, skip_serializing)]
+    pub id: String,
+    pub display_name: String,
+    /// Rules run in ascending order of sequence.
+    pub sequence: u32,
+    pub is_enabled: bool,
+    /// What a message must match for the actions to be taken. Every condition set must match.
+    #[serde(default, deserialize_with = "nullable")]
+    pub conditions: RulePredicates,
+    #[serde(default, deserialize_with = "nullable")]
+    pub actions: RuleActions,
+    /// Messages matching any of these are left alone.
+    #[serde(default, deserialize_with = "nullable")]
+    pub exceptions: RulePredicates,
+    /// Whether the server could not run the rule, such as after its folder was deleted.
+    #[serde(default, skip_serializing)]
+    pub has_error: bool,
+    /// Rules created by other clients that Graph cannot change.
+    #[serde(default, skip_serializing)]
+    pub is_read_only: bool,
+}
+
+/// The conditions of a rule. Those Graph has beyond these are kept as they are in `other`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RulePredicates {
+    /// Exact sender addresses.
+    #[serde(default, with = "addresses", skip_serializing_if = "Vec::is_empty")]
+    pub from_addresses: Vec<String>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Vec::is_empty")]
+    pub sender_contains: Vec<String>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Vec::is_empty")]
+    pub recipient_contains: Vec<String>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Vec::is_empty")]
+    pub subject_contains: Vec<String>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Vec::is_empty")]
+    pub body_or_subject_contains: Vec<String>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Vec::is_empty")]
+    pub categories: Vec<String>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub has_attachments: Option<bool>,
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleActions {
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub mark_as_read: Option<bool>,
+    /// The id of the folder to move messages to.
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub move_to_folder: Option<String>,
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Vec::is_empty")]
+    pub assign_categories: Vec<String>,
+    #[serde(default, with = "addresses", skip_serializing_if = "Vec::is_empty")]
+    pub forward_to: Vec<String>,
+    /// Moves messages to Deleted Items.
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub delete: Option<bool>,
+    /// Stops later rules from running on the messages this one matched.
+    #[serde(default, deserialize_with = "nullable", skip_serializing_if = "Option::is_none")]
+    pub stop_processing_rules: Option<bool>,
+    /// Actions Graph has beyond these, kept as they are.
+    #[serde(flatten)]
+    pub other: HashMap<String, serde_json::Value>,
+}
+
+impl RulePredicates {
+    pub fn is_empty(&self) -> bool {
+        self.from_addresses.is_empty()
+            && self.sender_contains.is_empty()
+            && self.recipient_contains.is_empty()
+            && self.subject_contains.is_empty()
+            && self.body_or_subject_contains.is_empty()
+            && self.categories.is_empty()
+            && self.has_attachments.is_none()
+            && self.other.values().all(|value| value.is_null())
+    }
+}
+
+/// Graph sends unset conditions and actions as `null`.
+fn nullable<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + Default,
+{
+    Ok(Option::<T>::deserialize(deserializer)?.unwrap_or_default())
+}
+
+/// Graph lists addresses as recipients, of which only the address matters to a rule.
+mod addresses {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Recipient {
+        email_address: EmailAddress,
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct EmailAddress {
+        address: String,
+    }
+
+    pub fn serialize<S: Serializer>(
+        addresses: &[String],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        addresses.iter()
+            .map(|address| Recipient { email_address: EmailAddress { address: address.clone() } })
+            .collect::<Vec<Recipient>>()
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Vec<String>, D::Error> {
+        let recipients = Option::<Vec<Recipient>>::deserialize(deserializer)?.unwrap_or_default();
+        Ok(recipients.into_iter().map(|recipient| recipient.email_address.address).collect())
+    }
+}
+
+/// A mail folder, for picking where a rule moves messages.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MailFolder {
+    pub id: String,
+    pub display_name: String,
+}
+
+const RULES_ENDPOINT: &str = "/v1.0/me/mailFolders/inbox/messageRules";
+
+/// Rules need the `MailboxSettings.ReadWrite` scope, which accounts added before it was asked
+/// for do not have until they sign in again.
+fn rules_error(action: &str, status: StatusCode) -> Box<dyn Error> {
+    if status == StatusCode::FORBIDDEN {
+        return format!("failed to {}: {} (sign in again to allow rules)", action, status).into();
+    }
+    format!("failed to {}: {}", action, status).into()
+}
+
+impl OutlookMailbox {
+    /// Whether the account was granted the scope rules need, which it asks for on sign-in.
+    pub fn can_manage_rules(&self) -> bool {
+        has_scope(&self.auth.scope, "mailboxsettings.readwrite")
+    }
+
+    /// Lists the inbox rules in the order they run.
+    pub async fn list_rules(&self) -> Result<Vec<MessageRule>, Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct Response {
+            value: Vec<MessageRule>,
+        }
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", API_HOST, RULES_ENDPOINT))
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(rules_error("list rules", response.status()));
+        }
+        let response: Response = serde_json::from_str(&response.text().await?)?;
+        let mut rules = response.value;
+        rules.sort_by_key(|rule| rule.sequence);
+        Ok(rules)
+    }
+
+    /// Creates the rule, returning it as saved by the server.
+    pub async fn create_rule(&self, rule: &MessageRule) -> Result<MessageRule, Box<dyn Error>> {
+        let response = reqwest::Client::new()
+            .post(format!("{}{}", API_HOST, RULES_ENDPOINT))
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Type", "application/json")
+            .body(serde_json::to_string(rule)?)
+            .send()
+            .await?;
+        if response.status() != StatusCode::CREATED {
+            return Err(rules_error("create rule", response.status()));
+        }
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+
+    /// Replaces the rule with this id by `rule`, returning it as saved by the server.
+    pub async fn update_rule(
+        &self,
+        rule_id: &str,
+        rule: &MessageRule,
+    ) -> Result<MessageRule, Box<dyn Error>> {
+        self.patch_rule(rule_id, serde_json::to_string(rule)?).await
+    }
+
+    pub async fn set_rule_enabled(
+        &self,
+        rule_id: &str,
+        is_enabled: bool,
+    ) -> Result<MessageRule, Box<dyn Error>> {
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Request {
+            is_enabled: bool,
+        }
+        self.patch_rule(rule_id, serde_json::to_string(&Request { is_enabled })?).await
+    }
+
+    async fn patch_rule(&self, rule_id: &str, body: String) -> Result<MessageRule, Box<dyn Error>> {
+        let response = reqwest::Client::new()
+            .patch(format!("{}{}/{}", API_HOST, RULES_ENDPOINT, rule_id))
+            .header("Authorization", &self.auth.access_token)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(rules_error("update rule", response.status()));
+        }
+        Ok(serde_json::from_str(&response.text().await?)?)
+    }
+
+    pub async fn delete_rule(&self, rule_id: &str) -> Result<(), Box<dyn Error>> {
+        let response = reqwest::Client::new()
+            .delete(format!("{}{}/{}", API_HOST, RULES_ENDPOINT, rule_id))
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::NO_CONTENT {
+            return Err(rules_error("delete rule", response.status()));
+        }
+        Ok(())
+    }
+
+    /// Lists the top-level mail folders.
+    pub async fn list_folders(&self) -> Result<Vec<MailFolder>, Box<dyn Error>> {
+        #[derive(Deserialize)]
+        struct Response {
+            value: Vec<MailFolder>,
+        }
+        let api_endpoint = "/v1.0/me/mailFolders";
+        let response = reqwest::Client::new()
+            .get(format!("{}{}", API_HOST, api_endpoint))
+            .query(&[("$select", "id,displayName"), ("$top", "250")])
+            .header("Authorization", &self.auth.access_token)
+            .send()
+            .await?;
+        if response.status() != StatusCode::OK {
+            return Err(format!("failed to list folders: {}", response.status()).into());
+        }
+        let response: Response = serde_json::from_str(&response.text().await?)?;
+        Ok(response.value)
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use chrono::NaiveDate;
+
+/// A parsed search query, e.g. `from:alice subject:"weekly report" after:2022-01-01 budget`.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct SearchQuery {
+    /// Free-text terms matched against every indexed field.
+    pub text: Vec<String>,
+    pub from: Vec<String>,
+    pub to: Vec<String>,
+    pub subject: Vec<String>,
+    /// Only match messages sent before this timestamp.
+    pub before: Option<u64>,
+    /// Only match messages sent on or after this timestamp.
+    pub after: Option<u64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum QueryError {
+    Empty,
+    MissingValue(String),
+    InvalidDate(String),
+    UnterminatedQuote,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Empty => write!(f, "search query is empty"),
+            QueryError::MissingValue(operator) => write!(f, "{}: is missing a value", operator),
+            QueryError::InvalidDate(date) => {
+                write!(f, "invalid date \"{}\", expected YYYY-MM-DD", date)
+            }
+            QueryError::UnterminatedQuote => write!(f, "unterminated quote"),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl SearchQuery {
+    pub fn parse(query: &str) -> Result<SearchQuery, QueryError> {
+        let mut search_query = SearchQuery::default();
+        for token in tokenize(query)? {
+            let (operator, value) = match token.split_once(':') {
+                Some((operator, value)) => (operator.to_lowercase(), value.to_string()),
+                None => {
+                    search_query.text.push(token);
+                    continue;
+                }
+            };
+            let is_operator = matches!(
+                operator.as_str(),
+                "from" | "to" | "subject" | "before" | "after"
+            );
+            if !is_operator {
+                // Not an operator, e.g. a time or URL: keep it as free text.
+                search_query.text.push(token);
+                continue;
+            }
+            if value.is_empty() {
+                return Err(QueryError::MissingValue(operator));
+            }
+            match operator.as_str() {
+                "from" => search_query.from.push(value),
+                "to" => search_query.to.push(value),
+                "subject" => search_query.subject.push(value),
+                "before" => search_query.before = Some(parse_date(&value)?),
+                "after" => search_query.after = Some(parse_date(&value)?),
+                _ => unreachable!(),
+            }
+        }
+        if search_query.is_empty() {
+            return Err(QueryError::Empty);
+        }
+        Ok(search_query)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+            && self.from.is_empty()
+            && self.to.is_empty()
+            && self.subject.is_empty()
+            && self.before.is_none()
+            && self.after.is_none()
+    }
+}
+
+/// Splits on whitespace, keeping double-quoted sections (including `key:"a b"`) together.
+fn tokenize(query: &str) -> Result<Vec<String>, QueryError> {
+    let mut tokens = vec![];
+    let mut token = String::new();
+    let mut is_quoted = false;
+    for c in query.chars() {
+        match c {
+            '"' => is_quoted = !is_quoted,
+            c if c.is_whitespace() && !is_quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+            }
+            c => token.push(c),
+        }
+    }
+    if is_quoted {
+        return Err(QueryError::UnterminatedQuote);
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+fn parse_date(date: &str) -> Result<u64, QueryError> {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp() as u64)
+        .map_err(|_| QueryError::InvalidDate(date.to_string()))
+}
@@ -0,0 +1,254 @@
+use std::collections::{HashMap, HashSet};
+use crate::mail::Message;
+
+/// A node of the thread tree. Containers without a message stand for messages that are
+/// referenced but were not fetched, such as a read parent of an unread reply.
+struct Container {
+    id: String,
+    message: Option<usize>,
+    parent: Option<usize>,
+}
+
+/// Groups messages into threads with the JWZ algorithm (https://www.jwz.org/doc/threading.html)
+/// over their `Message-ID`, `In-Reply-To` and `References` headers, setting `thread_id` to the
+/// id of the thread's root. Messages which already have a `thread_id`, such as those carrying
+/// an Outlook `conversationId`, are left untouched.
+pub fn assign_thread_ids(messages: &mut [Message]) {
+    let mut containers: Vec<Container> = vec![];
+    let mut id_table: HashMap<String, usize> = HashMap::new();
+    fn get_container(
+        containers: &mut Vec<Container>,
+        id_table: &mut HashMap<String, usize>,
+        id: &str,
+    ) -> usize {
+        *id_table.entry(id.to_string()).or_insert_with(|| {
+            containers.push(Container {
+                id: id.to_string(),
+                message: None,
+                parent: None,
+            });
+            containers.len() - 1
+        })
+    }
+    fn is_ancestor(containers: &[Container], ancestor: usize, container: usize) -> bool {
+        let mut current = Some(container);
+        while let Some(index) = current {
+            if index == ancestor {
+                return true;
+            }
+            current = containers[index].parent;
+        }
+        false
+    }
+    fn set_parent(containers: &mut [Container], child: usize, parent: usize) {
+        // Linking would create a loop, so the existing structure wins.
+        if is_ancestor(containers, child, parent) {
+            return;
+        }
+        containers[child].parent = Some(parent);
+    }
+
+    // 1. Build the containers and link each message to the chain of its references.
+    for (i, message) in messages.iter().enumerate() {
+        if message.thread_id.is_some() {
+            continue;
+        }
+        let message_id = message.internet_message_id.clone()
+            // Messages without a Message-ID still need a container of their own.
+            .unwrap_or_else(|| format!("<{}@{}>", message.id, message.mailbox_id));
+        let mut container = get_container(&mut containers, &mut id_table, &message_id);
+        if containers[container].message.is_some() {
+            // Duplicate Message-ID: thread the copy separately.
+            let duplicate_id = format!("{}#{}", message_id, i);
+            container = get_container(&mut containers, &mut id_table, &duplicate_id);
+        }
+        containers[container].message = Some(i);
+        let mut references = message.references.clone();
+        if let Some(in_reply_to) = &message.in_reply_to {
+            if references.last() != Some(in_reply_to) {
+                references.push(in_reply_to.clone());
+            }
+        }
+        let mut previous: Option<usize> = None;
+        for reference in &references {
+            let reference_container = get_container(&mut containers, &mut id_table, reference);
+            if let Some(previous) = previous {
+                if containers[reference_container].parent.is_none() {
+                    set_parent(&mut containers, reference_container, previous);
+                }
+            }
+            previous = Some(reference_container);
+        }
+        // The message's own references take precedence over what others implied.
+        containers[container].parent = None;
+        if let Some(parent) = previous {
+            set_parent(&mut containers, container, parent);
+        }
+    }
+
+    // 2. Find the root of every container.
+    let roots: Vec<usize> = (0..containers.len())
+        .map(|mut index| {
+            while let Some(parent) = containers[index].parent {
+                index = parent;
+            }
+            index
+        })
+        .collect();
+
+    // 3. Merge root threads which share a subject, as JWZ does: a root that is a reply, or that
+    // stands for a message that was not fetched, joins the thread it answers. Roots that are
+    // neither, such as two "Build passed" mails, only share a subject and stay apart.
+    let mut root_subjects: Vec<(usize, String, bool)> = vec![];
+    let mut seen_roots: HashSet<usize> = HashSet::new();
+    for (index, container) in containers.iter().enumerate() {
+        let message = match container.message {
+            Some(message) => &messages[message],
+            None => continue,
+        };
+        let root = roots[index];
+        if !seen_roots.insert(root) {
+            continue;
+        }
+        let (subject, can_merge) = match containers[root].message {
+            Some(root_message) => {
+                let subject = &messages[root_message].subject;
+                (normalise_subject(subject), is_reply(subject))
+            }
+            // An empty root takes the subject of the first message under it.
+            None => (normalise_subject(&message.subject), true),
+        };
+        if !subject.is_empty() {
+            root_subjects.push((root, subject, can_merge));
+        }
+    }
+    // Replies join the first original with their subject, or else the first reply.
+    let mut root_by_subject: HashMap<&str, usize> = HashMap::new();
+    for (root, subject, _) in root_subjects.iter().filter(|(_, _, can_merge)| !can_merge) {
+        root_by_subject.entry(subject).or_insert(*root);
+    }
+    let mut merged_roots: HashMap<usize, usize> = HashMap::new();
+    for (root, subject, _) in root_subjects.iter().filter(|(_, _, can_merge)| *can_merge) {
+        let subject_root = *root_by_subject.entry(subject).or_insert(*root);
+        merged_roots.insert(*root, subject_root);
+    }
+
+    // 4. Every message's thread is identified by its (merged) root's id.
+    for (index, container) in containers.iter().enumerate() {
+        if let Some(message) = container.message {
+            let root = merged_roots.get(&roots[index]).cloned().unwrap_or(roots[index]);
+            messages[message].thread_id = Some(containers[root].id.clone());
+        }
+    }
+}
+
+/// Whether the subject starts with a reply or forward prefix.
+fn is_reply(subject: &str) -> bool {
+    normalise_subject(subject) != subject.trim()
+}
+
+/// Strips reply and forward prefixes such as `Re:`, `RE[2]:` and `Fwd:`.
+pub fn normalise_subject(subject: &str) -> String {
+    let mut subject = subject.trim();
+    loop {
+        let rest = ["fwd", "re", "fw", "aw", "sv"].iter()
+            .filter_map(|prefix| {
+                let start = subject.get(..prefix.len())?;
+                if !start.eq_ignore_ascii_case(prefix) {
+                    return None;
+                }
+                let rest = &subject[prefix.len()..];
+                // Allow a counter such as "Re[2]:".
+                let rest = match rest.strip_prefix('[') {
+                    Some(counter) => counter
+                        .trim_start_matches(|c: char| c.is_ascii_digit())
+                        .strip_prefix(']')?,
+                    None => rest,
+                };
+                rest.strip_prefix(':')
+            })
+            .next();
+        match rest {
+            Some(rest) => subject = rest.trim_start(),
+            None => return subject.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mail::Message;
+    use super::*;
+
+    fn thread_ids(messages: &mut [Message]) -> Vec<String> {
+        assign_thread_ids(messages);
+        messages.iter().map(|message| message.thread_id.clone().unwrap()).collect()
+    }
+
+    #[test]
+    fn links_replies_by_references_and_in_reply_to() {
+        let mut messages = [
+            Message::test("c").with_subject("Re: Plans")
+                .with_parents(None, &["<a@example.com>", "<b@example.com>"]),
+            Message::test("a").with_subject("Plans"),
+            Message::test("b").with_subject("Re: Plans")
+                .with_parents(Some("<a@example.com>"), &[]),
+            Message::test("d").with_subject("Something else"),
+        ];
+        let ids = thread_ids(&mut messages);
+        assert_eq!(ids[..3], ["<a@example.com>"; 3]);
+        assert_eq!(ids[3], "<d@example.com>");
+    }
+
+    #[test]
+    fn threads_replies_to_a_missing_parent_under_it() {
+        let mut messages = [
+            Message::test("b").with_subject("Re: Plans")
+                .with_parents(Some("<a@example.com>"), &[]),
+            Message::test("c").with_subject("Re: Plans")
+                .with_parents(None, &["<a@example.com>"]),
+        ];
+        assert_eq!(thread_ids(&mut messages), ["<a@example.com>"; 2]);
+    }
+
+    #[test]
+    fn falls_back_to_the_subject_for_replies() {
+        let mut messages = [
+            Message::test("b").with_subject("RE[2]: Fwd: Plans"),
+            Message::test("a").with_subject("Plans"),
+            Message::test("c").with_subject("Re: Plans"),
+        ];
+        assert_eq!(thread_ids(&mut messages), ["<a@example.com>"; 3]);
+    }
+
+    #[test]
+    fn keeps_unrelated_messages_with_the_same_subject_apart() {
+        let mut messages = [
+            Message::test("a").with_subject("Build passed"),
+            Message::test("b").with_subject("Build passed"),
+            Message::test("c").with_subject("Re: Build passed"),
+        ];
+        assert_eq!(
+            thread_ids(&mut messages),
+            ["<a@example.com>", "<b@example.com>", "<a@example.com>"]
+        );
+    }
+
+    #[test]
+    fn leaves_existing_thread_ids_alone() {
+        let mut messages = [
+            Message::test("a").with_subject("Plans"),
+            Message::test("b").with_subject("Re: Plans")
+                .with_parents(Some("<a@example.com>"), &[]),
+        ];
+        messages[1].thread_id = Some("conversation".to_string());
+        assert_eq!(thread_ids(&mut messages), ["<a@example.com>", "conversation"]);
+    }
+
+    #[test]
+    fn normalises_reply_and_forward_prefixes() {
+        assert_eq!(normalise_subject("  RE[2]: Fwd: AW: Plans "), "Plans");
+        assert_eq!(normalise_subject("Review: plans"), "Review: plans");
+        assert_eq!(normalise_subject("Re:"), "");
+    }
+}
@@ -1,28 +1,22 @@
-use std::io::prelude::*;
-use std::net::TcpListener;
-use std::net::TcpStream;
-use std::fs;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
 
-/// Starts a web server and returns first request.
-pub fn get_request() -> String {
-    let listener = TcpListener::bind("127.0.0.1:6767").unwrap();
-    for stream in listener.incoming() {
-        let mut stream = stream.unwrap();
-        let mut buffer = [0; 1024];
-        stream.read(&mut buffer).unwrap();
-        let contents = get_html_response();
-        let response = format!(
-            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
-            contents.len(),
-            contents
-        );
-        stream.write(response.as_bytes()).unwrap();
-        stream.flush().unwrap();
-        let x = String::from_utf8_lossy(&buffer[..]);
-        let string = x.to_string();
-        return string;
-    }
-    panic!("Failed to return request");
+/// Starts a web server on the port and returns the first request.
+pub async fn get_request(port: u16) -> io::Result<String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+    let (mut stream, _) = listener.accept().await?;
+    let mut buffer = [0; 1024];
+    let length = stream.read(&mut buffer).await?;
+    let contents = get_html_response();
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        contents.len(),
+        contents
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.flush().await?;
+    Ok(String::from_utf8_lossy(&buffer[..length]).to_string())
 }
 
 fn get_html_response() -> String {
@@ -0,0 +1,121 @@
+use api::mail::{BodyType, Message};
+use api::mime::{self, BodyPreference, Disposition};
+
+fn fixture(name: &str) -> Vec<u8> {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read(&path).unwrap_or_else(|error| panic!("could not read {}: {}", path, error))
+}
+
+fn message(name: &str, preference: BodyPreference) -> Message {
+    mime::to_message(&fixture(name), "id", "mailbox", preference)
+}
+
+#[test]
+fn multipart_alternative_follows_the_body_preference() {
+    let html = message("multipart_alternative.eml", BodyPreference::Html);
+    assert_eq!(html.body_type, BodyType::Html);
+    assert_eq!(html.body, "<p>Noon at the <b>café</b>?</p>");
+    let plain = message("multipart_alternative.eml", BodyPreference::Plain);
+    assert_eq!(plain.body_type, BodyType::Text);
+    assert_eq!(plain.body, "Noon at the café?");
+    assert!(plain.attachments.is_empty());
+}
+
+#[test]
+fn reads_addresses_dates_and_threading_headers() {
+    let message = message("multipart_alternative.eml", BodyPreference::Html);
+    assert_eq!(message.subject, "Lunch plans");
+    assert_eq!(message.from.name, "Doe, Jane");
+    assert_eq!(message.from.address, "jane@example.com");
+    let to: Vec<&str> = message.to.iter().map(|recipient| recipient.address.as_str()).collect();
+    assert_eq!(to, ["bob@example.com", "carol@example.com"]);
+    assert_eq!(message.cc[0].address, "dave@example.com");
+    assert_eq!(message.date, 1641204000);
+    assert_eq!(message.internet_message_id.as_deref(), Some("<lunch-2@example.com>"));
+    assert_eq!(message.in_reply_to.as_deref(), Some("<lunch-1@example.com>"));
+    assert_eq!(message.references, ["<lunch-0@example.com>", "<lunch-1@example.com>"]);
+}
+
+#[test]
+fn decodes_quoted_printable() {
+    let message = message("quoted_printable.eml", BodyPreference::Html);
+    assert_eq!(
+        message.body,
+        "Café crème, with a soft line break, an equals sign =, and a bad escape =ZZ.\n"
+    );
+}
+
+#[test]
+fn decodes_base64_bodies_and_attachments() {
+    let raw = fixture("base64.eml");
+    let message = mime::to_message(&raw, "id", "mailbox", BodyPreference::Html);
+    assert_eq!(message.body, "Hello, wörld!\nSecond line.\n");
+    assert_eq!(message.attachments.len(), 1);
+    let attachment = &message.attachments[0];
+    assert_eq!(attachment.id, "2");
+    // Content-Disposition's file name wins over the Content-Type name.
+    assert_eq!(attachment.name, "report.pdf");
+    assert_eq!(attachment.content_type, "application/pdf");
+    assert!(!attachment.is_inline);
+    let part = mime::parse(&raw);
+    let attachment_part = part.find("2").unwrap();
+    assert_eq!(attachment_part.body, b"%PDF-1.4 not really a pdf");
+    assert_eq!(attachment_part.disposition(), Disposition::Attachment);
+    assert_eq!(attachment.size, attachment_part.body.len() as u64);
+}
+
+#[test]
+fn exposes_inline_images_of_related_html() {
+    let message = message("related.eml", BodyPreference::Plain);
+    // There is no plain alternative, so the HTML is shown.
+    assert_eq!(message.body_type, BodyType::Html);
+    assert!(message.body.contains("cid:logo@example.com"));
+    assert_eq!(message.attachments.len(), 1);
+    let image = &message.attachments[0];
+    assert!(image.is_inline);
+    assert_eq!(image.content_type, "image/png");
+    assert_eq!(image.content_id.as_deref(), Some("logo@example.com"));
+    assert_eq!(image.name, "part-2");
+}
+
+#[test]
+fn converts_legacy_charsets() {
+    let body = |name| message(name, BodyPreference::Html).body;
+    assert_eq!(body("iso_8859_1.eml"), "Grüße aus München\r\n");
+    assert_eq!(body("windows_1252.eml"), "“Quoted” costs €5 – today\r\n");
+    assert_eq!(body("shift_jis.eml"), "日本語のメール\r\n");
+    // Unlabelled 8-bit text is read as Windows-1252.
+    assert_eq!(body("unlabelled.eml"), "Price: €5\r\n");
+}
+
+#[test]
+fn decodes_rfc_2047_headers() {
+    let message = message("encoded_headers.eml", BodyPreference::Html);
+    assert_eq!(message.subject, "If you can read this you understand the example.");
+    assert_eq!(message.from.name, "André Pirard");
+    assert_eq!(message.to[0].name, "山田");
+    assert_eq!(message.body, "See attached.");
+}
+
+#[test]
+fn decodes_rfc_2231_file_names() {
+    let message = message("encoded_headers.eml", BodyPreference::Html);
+    let names: Vec<&str> = message.attachments.iter()
+        .map(|attachment| attachment.name.as_str())
+        .collect();
+    assert_eq!(names, [
+        "naïve notes.txt",
+        // An RFC 2047 encoded Content-Type name, as Outlook sends.
+        "résumé.txt",
+        // A broken escape next to a multibyte character is kept as it is.
+        "%aé.txt",
+        "café.txt",
+    ]);
+}
+
+#[test]
+fn percent_decode_keeps_invalid_escapes() {
+    assert_eq!(mime::percent_decode("a%20b%2"), b"a b%2");
+    assert_eq!(mime::percent_decode("%+f%zz%é"), "%+f%zz%é".as_bytes());
+    assert_eq!(mime::percent_decode("%C3%A9"), "é".as_bytes());
+}
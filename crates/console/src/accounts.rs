@@ -0,0 +1,409 @@
+use termion::event::Key;
+use api::mail::Mailbox;
+use api::outlook::auth::get_authorisation_code_request_url;
+use api::outlook::OutlookMailbox;
+use crate::input::lookup_key;
+use crate::keymap::{Action, Context};
+use crate::link::open;
+use crate::search::SearchIndex;
+use crate::setup::authenticate_outlook;
+use crate::storage::TAG_COLORS;
+use crate::ui::{Backend, Color, Terminal};
+use crate::{render, storage, sync, State, Storage};
+
+/// The list of accounts, shown instead of the messages while open.
+pub struct AccountsScreen {
+    /// What is shown of each account in storage, in order.
+    pub rows: Vec<AccountRow>,
+    pub selected_index: usize,
+    pub mode: AccountsMode,
+}
+
+pub struct AccountRow {
+    pub id: String,
+    pub name: String,
+    /// Known once the account has synced after signing in.
+    pub address: Option<String>,
+    pub color: Color,
+    pub provider: Provider,
+    pub is_disabled: bool,
+    /// When the access token expires, in seconds since the Unix epoch.
+    pub token_expiry: u64,
+}
+
+pub enum AccountsMode {
+    List,
+    /// Choosing the provider of a new account.
+    PickProvider { selected_index: usize },
+    /// Typing into the status bar.
+    Prompt { prompt: AccountPrompt, text: String },
+    /// Waiting for a yes before removing the selected account.
+    ConfirmRemove,
+    /// Waiting for the browser to come back from the sign-in page at this URL.
+    SigningIn { url: String },
+}
+
+pub enum AccountPrompt {
+    /// The client ID of the app a new account signs in with.
+    ClientId(Provider),
+    /// A new name for the selected account. Left empty, the account goes back to its default.
+    Rename,
+}
+
+#[derive(Clone, Copy)]
+pub enum Provider {
+    Outlook,
+}
+
+pub const PROVIDERS: [Provider; 1] = [Provider::Outlook];
+
+impl Provider {
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::Outlook => "Microsoft Outlook",
+        }
+    }
+}
+
+/// A sign-in waiting to be run by the event loop.
+pub enum AccountAction {
+    Add { provider: Provider, client_id: String },
+    /// Sign in again to the selected account, such as after its refresh token was revoked.
+    Reauthenticate,
+    /// Fetch the messages of an account that was enabled again.
+    Sync,
+}
+
+impl AccountsScreen {
+    pub fn new(storage: &Storage) -> AccountsScreen {
+        let mut screen = AccountsScreen {
+            rows: vec![],
+            selected_index: 0,
+            mode: AccountsMode::List,
+        };
+        screen.refresh(storage);
+        screen
+    }
+
+    /// Reads the rows again after the accounts in storage changed.
+    pub fn refresh(&mut self, storage: &Storage) {
+        self.rows = storage.outlook.iter()
+            .enumerate()
+            .map(|(i, mailbox)| AccountRow {
+                id: mailbox.get_id().to_string(),
+                name: storage::mailbox_name(mailbox, i),
+                address: mailbox.address.clone(),
+                color: storage::mailbox_color(mailbox, i),
+                provider: Provider::Outlook,
+                is_disabled: mailbox.is_disabled,
+                token_expiry: mailbox.access_token_expiry(),
+            })
+            .collect();
+        self.selected_index = self.selected_index.min(self.rows.len().saturating_sub(1));
+    }
+
+    /// The text of the status bar prompt, while one is open.
+    pub fn prompt(&self) -> Option<String> {
+        match &self.mode {
+            AccountsMode::Prompt { prompt: AccountPrompt::ClientId(provider), text } => {
+                Some(format!("{} app client ID: {}", provider.name(), text))
+            }
+            AccountsMode::Prompt { prompt: AccountPrompt::Rename, text } => {
+                Some(format!("name: {}", text))
+            }
+            AccountsMode::ConfirmRemove => self.rows.get(self.selected_index)
+                .map(|row| format!("remove {}? (y/n)", row.name)),
+            _ => None,
+        }
+    }
+}
+
+pub fn take_accounts_key(storage: &mut Storage, state: &mut State, key: Key) {
+    let screen = state.accounts.as_mut().unwrap();
+    let selected_index = screen.selected_index;
+    let context = match &mut screen.mode {
+        AccountsMode::List => Context::Accounts,
+        AccountsMode::PickProvider { .. } => Context::Providers,
+        AccountsMode::Prompt { prompt, text } => {
+            match key {
+                Key::Char('\n') => {
+                    let text = text.trim().to_string();
+                    match *prompt {
+                        AccountPrompt::ClientId(_) if text.is_empty() => (),
+                        AccountPrompt::ClientId(provider) => {
+                            screen.mode = AccountsMode::List;
+                            state.pending_account_action =
+                                Some(AccountAction::Add { provider, client_id: text });
+                        },
+                        AccountPrompt::Rename => {
+                            screen.mode = AccountsMode::List;
+                            storage.outlook[selected_index].name =
+                                if text.is_empty() { None } else { Some(text) };
+                            save(storage, state);
+                        },
+                    }
+                },
+                Key::Char(c) => text.push(c),
+                Key::Backspace => {
+                    text.pop();
+                },
+                Key::Esc => screen.mode = AccountsMode::List,
+                _ => (),
+            }
+            return;
+        },
+        AccountsMode::ConfirmRemove => {
+            screen.mode = AccountsMode::List;
+            if key == Key::Char('y') {
+                remove(storage, state, selected_index);
+            }
+            return;
+        },
+        // Keys are not read while signing in.
+        AccountsMode::SigningIn { .. } => return,
+    };
+    if let Some(action) = lookup_key(state, key, context) {
+        take_accounts_action(storage, state, action);
+    }
+}
+
+fn take_accounts_action(storage: &mut Storage, state: &mut State, action: Action) {
+    let screen = state.accounts.as_mut().unwrap();
+    let account_count = screen.rows.len();
+    let selected_index = screen.selected_index;
+    match &mut screen.mode {
+        AccountsMode::PickProvider { selected_index } => match action {
+            Action::Prev => *selected_index = selected_index.saturating_sub(1),
+            Action::Next => *selected_index = (*selected_index + 1).min(PROVIDERS.len() - 1),
+            Action::Open => {
+                screen.mode = AccountsMode::Prompt {
+                    prompt: AccountPrompt::ClientId(PROVIDERS[*selected_index]),
+                    text: String::new(),
+                };
+            },
+            Action::Help => state.show_help = true,
+            Action::Back => screen.mode = AccountsMode::List,
+            _ => (),
+        },
+        _ => match action {
+            Action::Prev => screen.selected_index = selected_index.saturating_sub(1),
+            Action::Next => {
+                screen.selected_index = (selected_index + 1).min(account_count.saturating_sub(1));
+            },
+            Action::AddAccount => screen.mode = AccountsMode::PickProvider { selected_index: 0 },
+            Action::SignInAgain if account_count > 0 => {
+                state.pending_account_action = Some(AccountAction::Reauthenticate);
+            },
+            Action::RenameAccount if account_count > 0 => {
+                let text = storage.outlook[selected_index].name.clone().unwrap_or_default();
+                screen.mode = AccountsMode::Prompt { prompt: AccountPrompt::Rename, text };
+            },
+            Action::ChangeAccountColor if account_count > 0 => {
+                cycle_color(storage, state, selected_index);
+            },
+            Action::ToggleAccount if account_count > 0 => {
+                toggle_disabled(storage, state, selected_index);
+            },
+            Action::RemoveAccount if account_count > 0 => {
+                screen.mode = AccountsMode::ConfirmRemove;
+            },
+            Action::Help => state.show_help = true,
+            Action::Back => state.accounts = None,
+            _ => (),
+        },
+    }
+}
+
+/// Saves a change to the accounts and shows it.
+fn save(storage: &Storage, state: &mut State) {
+    storage::set(storage);
+    sync::list_mailboxes(state, storage);
+    if let Some(screen) = &mut state.accounts {
+        screen.refresh(storage);
+    }
+}
+
+/// Gives the account the next of the tag colours. A colour typed into storage by hand
+/// starts the cycle over.
+fn cycle_color(storage: &mut Storage, state: &mut State, index: usize) {
+    let mailbox = &mut storage.outlook[index];
+    let next_index = match TAG_COLORS.iter().position(|color| {
+        Some(*color) == mailbox.color.as_deref()
+    }) {
+        Some(position) => position + 1,
+        // Without a colour of its own, the account shows the one picked by its position.
+        None if mailbox.color.is_none() => index % TAG_COLORS.len() + 1,
+        None => 0,
+    };
+    mailbox.color = Some(TAG_COLORS[next_index % TAG_COLORS.len()].to_string());
+    save(storage, state);
+}
+
+fn toggle_disabled(storage: &mut Storage, state: &mut State, index: usize) {
+    let mailbox = &mut storage.outlook[index];
+    mailbox.is_disabled = !mailbox.is_disabled;
+    let (id, is_disabled) = (mailbox.get_id().to_string(), mailbox.is_disabled);
+    save(storage, state);
+    if is_disabled {
+        forget_messages(state, &id);
+    } else {
+        state.pending_account_action = Some(AccountAction::Sync);
+    }
+}
+
+fn remove(storage: &mut Storage, state: &mut State, index: usize) {
+    let mailbox = remove_account(storage, &mut state.search_index, index);
+    save(storage, state);
+    state.sync_statuses.remove(mailbox.get_id());
+    state.snoozed_count = storage.snoozes.len();
+    state.outbox = storage.outbox.clone();
+    forget_messages(state, mailbox.get_id());
+    state.status_message = Some(format!("removed {}", mailbox.get_id()));
+}
+
+/// Removes the account and everything kept for it, from the screen or the command line:
+/// its snoozes, queued messages, local drafts and indexed messages. The storage is left for
+/// the caller to save.
+pub fn remove_account(
+    storage: &mut Storage,
+    search_index: &mut SearchIndex,
+    index: usize,
+) -> OutlookMailbox {
+    let mailbox = storage.remove_mailbox(index);
+    search_index.remove_mailbox(mailbox.get_id());
+    mailbox
+}
+
+/// Takes the messages of an account that is no longer synced out of the list.
+fn forget_messages(state: &mut State, mailbox_id: &str) {
+    let messages = state.unread_messages.iter()
+        .filter(|message| message.mailbox_id != mailbox_id)
+        .cloned()
+        .collect();
+    if let Some(search_results) = &mut state.search_results {
+        search_results.messages.retain(|message| message.mailbox_id != mailbox_id);
+    }
+    sync::replace_unread_messages(state, messages);
+}
+
+/// Runs the pending sign-in, then fetches the messages of the account.
+pub async fn run(
+    state: &mut State,
+    storage: &mut Storage,
+    terminal: &mut Terminal<impl Backend>,
+) {
+    let action = match state.pending_account_action.take() {
+        Some(action) => action,
+        None => return,
+    };
+    let selected_index = match &state.accounts {
+        Some(screen) => screen.selected_index,
+        None => return,
+    };
+    let auth_options = state.config.outlook.auth_options();
+    let result = match action {
+        AccountAction::Add { provider: Provider::Outlook, client_id } => {
+            let url = get_authorisation_code_request_url(&client_id, &auth_options);
+            show_sign_in(state, url, terminal);
+            authenticate_outlook(&client_id, &auth_options).await.map(|response| {
+                let mut mailbox = OutlookMailbox::open(&client_id, response);
+                mailbox.auth_options = auth_options;
+                mailbox.body_preference = state.config.body_preference(&mailbox);
+                storage.outlook.push(mailbox);
+                format!("added {}", client_id)
+            })
+        }
+        AccountAction::Reauthenticate => {
+            let client_id = storage.outlook[selected_index].client_id.clone();
+            let url = get_authorisation_code_request_url(&client_id, &auth_options);
+            show_sign_in(state, url, terminal);
+            authenticate_outlook(&client_id, &auth_options).await.map(|response| {
+                storage.outlook[selected_index].set_auth(response);
+                format!("signed in to {} again", client_id)
+            })
+        }
+        AccountAction::Sync => Ok(String::new()),
+    };
+    if let Some(screen) = &mut state.accounts {
+        screen.mode = AccountsMode::List;
+    }
+    match result {
+        Ok(message) => {
+            save(storage, state);
+            sync::run(state, storage, terminal).await;
+            // A failed sync is worth more than the confirmation.
+            if state.status_message.is_none() && !message.is_empty() {
+                state.status_message = Some(message);
+            }
+        }
+        Err(error) => state.status_message = Some(format!("could not sign in: {}", error)),
+    }
+}
+
+/// Shows the sign-in URL and opens it in the browser, which may not work over SSH.
+fn show_sign_in(state: &mut State, url: String, terminal: &mut Terminal<impl Backend>) {
+    let _ = open(&url);
+    if let Some(screen) = &mut state.accounts {
+        screen.mode = AccountsMode::SigningIn { url };
+    }
+    render::progress(state, "waiting for the sign-in in the browser...", terminal);
+}
+
+#[cfg(test)]
+mod tests {
+    use api::mail::{Message, OutgoingMessage};
+    use crate::drafts::LocalDraft;
+    use crate::outbox::QueuedMessage;
+    use crate::snooze::Snooze;
+    use crate::storage::test_storage;
+    use super::*;
+
+    #[test]
+    fn removes_everything_kept_for_an_account() {
+        let mut storage = test_storage("remove-account", 2);
+        let mut search_index = SearchIndex::default();
+        let ids: Vec<String> = storage.outlook.iter()
+            .map(|mailbox| mailbox.get_id().to_string())
+            .collect();
+        for id in &ids {
+            storage.snoozes.push(Snooze {
+                mailbox_id: id.clone(),
+                message_id: "snoozed".to_string(),
+                until: 0,
+                subject: String::new(),
+            });
+            storage.outbox.push(QueuedMessage {
+                mailbox_id: id.clone(),
+                message: OutgoingMessage::default(),
+                send_at: 0,
+                scheduled_for: None,
+                error: None,
+            });
+            storage.drafts.push(LocalDraft {
+                key: id.clone(),
+                mailbox_id: id.clone(),
+                message: OutgoingMessage::default(),
+                updated_at: 0,
+                retry_at: 0,
+                error: None,
+            });
+            search_index.insert(&[Message::test(id).with_mailbox(id).with_subject("Hello")]);
+        }
+        let removed = remove_account(&mut storage, &mut search_index, 0);
+        assert_eq!(removed.get_id(), ids[0]);
+        let kept = [ids[1].as_str()];
+        let mailboxes: Vec<&str> = storage.outlook.iter().map(|mailbox| mailbox.get_id()).collect();
+        assert_eq!(mailboxes, kept);
+        assert!(storage.snoozes.iter().all(|snooze| snooze.mailbox_id == ids[1]));
+        assert!(storage.outbox.iter().all(|queued| queued.mailbox_id == ids[1]));
+        assert!(storage.drafts.iter().all(|draft| draft.mailbox_id == ids[1]));
+        assert_eq!(
+            (storage.snoozes.len(), storage.outbox.len(), storage.drafts.len()),
+            (1, 1, 1)
+        );
+        let query = api::search::SearchQuery::parse("hello").unwrap();
+        // Even asked for the removed account, the index has nothing of it left.
+        let found = search_index.search(&query, &[ids[0].as_str(), ids[1].as_str()]);
+        assert_eq!(found.iter().map(|message| message.id.as_str()).collect::<Vec<_>>(), kept);
+    }
+}
@@ -0,0 +1,198 @@
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use api::mail::{Attachment, Mailbox, Message};
+use crate::render;
+use crate::ui::{Backend, Terminal};
+use crate::{State, Storage};
+
+pub enum AttachmentAction {
+    /// Save to the download directory.
+    Save,
+    /// Save to a temporary directory and open with the mailcap entry or `xdg-open`.
+    Open,
+}
+
+/// Runs the pending action on the selected attachment of the selected message.
+pub async fn run(
+    state: &mut State,
+    storage: &Storage,
+    terminal: &mut Terminal<impl Backend>,
+) {
+    let action = match state.pending_attachment_action.take() {
+        Some(action) => action,
+        None => return,
+    };
+    let message = state.selected_message().clone();
+    let attachment = match message.attachments.get(state.selected_attachment_index) {
+        Some(attachment) => attachment,
+        None => return,
+    };
+    let directory = match action {
+        AttachmentAction::Save => dirs::download_dir()
+            .or_else(dirs::home_dir)
+            .unwrap_or_else(std::env::temp_dir),
+        AttachmentAction::Open => std::env::temp_dir().join("dashboard"),
+    };
+    render::progress(state, &format!("downloading {}...", attachment.name), terminal);
+    let path = match download(storage, &message, attachment, &directory).await {
+        Ok(path) => path,
+        Err(error) => {
+            state.status_message = Some(format!("failed to download {}: {}", attachment.name, error));
+            return;
+        }
+    };
+    state.status_message = Some(match action {
+        AttachmentAction::Save => format!("saved to {}", path.display()),
+        AttachmentAction::Open => match open(&path, &attachment.content_type) {
+            Ok(()) => format!("opened {}", path.display()),
+            Err(error) => format!("failed to open {}: {}", path.display(), error),
+        },
+    });
+}
+
+async fn download(
+    storage: &Storage,
+    message: &Message,
+    attachment: &Attachment,
+    directory: &Path,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let mailbox = storage.get_mailbox_by_id(&message.mailbox_id)
+        .ok_or("mailbox not found")?;
+    fs::create_dir_all(directory)?;
+    let path = get_available_path(directory, &attachment.name);
+    let mut file = File::create(&path)?;
+    if let Err(error) = mailbox.download_attachment(&message.id, &attachment.id, &mut file).await {
+        // Do not leave a truncated file behind.
+        let _ = fs::remove_file(&path);
+        return Err(error);
+    }
+    Ok(path)
+}
+
+/// Returns a path in `directory` for the file name that does not overwrite an existing file,
+/// e.g. `report (1).pdf`.
+fn get_available_path(directory: &Path, name: &str) -> PathBuf {
+    let name: String = name.chars()
+        .map(|c| if c == '/' || c == '\\' || c == '\0' { '_' } else { c })
+        .collect();
+    let name = match name.trim_start_matches('.') {
+        "" => "attachment".to_string(),
+        _ => name,
+    };
+    let path = directory.join(&name);
+    if !path.exists() {
+        return path;
+    }
+    let (stem, extension) = match name.rfind('.') {
+        Some(index) if index > 0 => (&name[..index], &name[index..]),
+        _ => (name.as_str(), ""),
+    };
+    (1..)
+        .map(|i| directory.join(format!("{} ({}){}", stem, i, extension)))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+fn open(path: &Path, content_type: &str) -> std::io::Result<()> {
+    let quoted_path = shell_quote(&path.to_string_lossy());
+    let command = get_mailcap_command(content_type, &quoted_path)
+        .unwrap_or_else(|| format!("xdg-open {}", quoted_path));
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn get_mailcap_paths() -> Vec<PathBuf> {
+    if let Ok(mailcaps) = std::env::var("MAILCAPS") {
+        return mailcaps.split(':').map(PathBuf::from).collect();
+    }
+    let mut paths = vec![];
+    if let Some(home) = dirs::home_dir() {
+        paths.push(home.join(".mailcap"));
+    }
+    paths.push(PathBuf::from("/etc/mailcap"));
+    paths
+}
+
+/// Finds the first RFC 1524 mailcap entry which can view `content_type` without a terminal,
+/// returning its command with `%s` replaced by the (already quoted) file path.
+fn get_mailcap_command(content_type: &str, quoted_path: &str) -> Option<String> {
+    let content_type = content_type.to_lowercase();
+    let main_type = content_type.split('/').next().unwrap_or_default();
+    for path in get_mailcap_paths() {
+        let mailcap = match fs::read_to_string(&path) {
+            Ok(mailcap) => mailcap.replace("\\\n", ""),
+            Err(_) => continue,
+        };
+        for line in mailcap.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = split_mailcap_fields(line);
+            if fields.len() < 2 {
+                continue;
+            }
+            let entry_type = fields[0].to_lowercase();
+            let is_match = entry_type == content_type
+                || entry_type == format!("{}/*", main_type)
+                || entry_type == main_type;
+            // Commands without %s read the file from stdin, and terminal viewers would draw
+            // over the interface, so neither is usable here.
+            let is_usable = fields[1].contains("%s") && !fields[2..].iter().any(|flag| {
+                let flag = flag.to_lowercase();
+                flag == "needsterminal" || flag == "copiousoutput"
+            });
+            if !is_match || !is_usable {
+                continue;
+            }
+            let test = fields[2..].iter()
+                .find_map(|field| field.strip_prefix("test="))
+                .map(|test| test.replace("%s", quoted_path));
+            if let Some(test) = test {
+                let passed = Command::new("sh").arg("-c").arg(test)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .status()
+                    .map(|status| status.success())
+                    .unwrap_or(false);
+                if !passed {
+                    continue;
+                }
+            }
+            return Some(fields[1].replace("%s", quoted_path));
+        }
+    }
+    None
+}
+
+/// Splits a mailcap line on unescaped semicolons.
+fn split_mailcap_fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => {
+                if let Some(escaped) = chars.next() {
+                    field.push(escaped);
+                }
+            }
+            ';' => fields.push(std::mem::take(&mut field).trim().to_string()),
+            c => field.push(c),
+        }
+    }
+    fields.push(field.trim().to_string());
+    fields
+}
@@ -0,0 +1,406 @@
+use std::io::Read;
+use std::path::PathBuf;
+use chrono::{Local, TimeZone};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use api::mail::{Mailbox, Message, OutgoingMessage, Recipient};
+use api::outlook::OutlookMailbox;
+use crate::config::Config;
+use crate::parse::{parse_message_body_plain, sort_messages_by_date};
+use crate::search::SearchIndex;
+use crate::{accounts, rules, setup, storage, sync, when, Storage};
+
+/// Width bodies are wrapped to when printed, if the terminal's cannot be read.
+const DEFAULT_WIDTH: usize = 80;
+
+const EXIT_STATUSES: &str = "Exit statuses:
+  0  success
+  1  a request to the server failed
+  2  invalid arguments, or an attachment or body that cannot be read
+  3  no such message or account
+  4  no account has been added
+  5  the config could not be read";
+
+#[derive(Parser)]
+#[command(name = "dashboard", about = "Email in the terminal", after_help = EXIT_STATUSES)]
+pub struct Cli {
+    /// Read the config from this file instead of the default one.
+    #[arg(long, global = true, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// What to do instead of opening the console.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Adds, lists or removes accounts.
+    Account {
+        #[command(subcommand)]
+        command: AccountCommand,
+    },
+    /// Lists the unread messages of every account, newest first.
+    Unread {
+        /// Print a JSON array instead of a line per message.
+        #[arg(long)]
+        json: bool,
+    },
+    /// Prints a message as text.
+    Read {
+        id: String,
+    },
+    /// Marks a message as read.
+    MarkRead {
+        id: String,
+    },
+    /// Refreshes the sign-in of every account and counts its unread messages.
+    Sync,
+    /// Applies the rules of the config to the unread messages of every account.
+    Rules {
+        /// Print what each rule would do instead of doing it.
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Sends a plain-text message, reading the body from stdin.
+    Send {
+        /// Recipients, as a comma-separated list or by repeating the option.
+        #[arg(long, required = true)]
+        to: Vec<String>,
+        #[arg(long)]
+        cc: Vec<String>,
+        #[arg(long, default_value = "")]
+        subject: String,
+        /// Files to attach.
+        #[arg(long, value_name = "PATH")]
+        attach: Vec<PathBuf>,
+        /// The id or address of the account to send from, instead of the first one.
+        #[arg(long, value_name = "ID")]
+        account: Option<String>,
+        /// When the server should deliver the message, such as "tomorrow at 8", instead of
+        /// straight away.
+        #[arg(long, value_name = "WHEN")]
+        at: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum AccountCommand {
+    /// Signs in to an Outlook account in the browser.
+    Add {
+        /// Client ID of the Azure app to sign in with. Asked for when left out.
+        #[arg(long)]
+        client_id: Option<String>,
+    },
+    /// Prints the id, address, name and state of each account.
+    List,
+    Remove {
+        /// The id or address of the account.
+        id: String,
+    },
+}
+
+/// The exit statuses listed in `EXIT_STATUSES`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Exit {
+    Success = 0,
+    RequestFailed = 1,
+    InvalidArguments = 2,
+    NotFound = 3,
+    NoAccounts = 4,
+    Config = 5,
+}
+
+/// A message as printed by `unread --json`.
+#[derive(Serialize)]
+struct MessageSummary<'a> {
+    id: &'a str,
+    account: &'a str,
+    /// Seconds since the Unix epoch.
+    date: u64,
+    from: &'a Recipient,
+    to: &'a [Recipient],
+    subject: &'a str,
+    flagged: bool,
+    categories: &'a [String],
+    attachments: usize,
+}
+
+pub async fn run(command: Command, config: &Config, storage: &mut Storage) -> Exit {
+    if let Command::Account { command } = command {
+        return run_account(command, config, storage).await;
+    }
+    if storage.outlook.is_empty() {
+        eprintln!("no account has been added, see `dashboard account add`");
+        return Exit::NoAccounts;
+    }
+    for (id, error) in sync::refresh_outlook_access_tokens(storage).await {
+        eprintln!("could not refresh the sign-in of {}: {}", id, error);
+    }
+    sync::look_up_addresses(storage).await;
+    match command {
+        Command::Account { .. } => unreachable!(),
+        Command::Unread { json } => unread(storage, json).await,
+        Command::Read { id } => read(config, storage, &id).await,
+        Command::MarkRead { id } => mark_read(storage, &id).await,
+        Command::Sync => sync(storage).await,
+        Command::Rules { dry_run } => rules(config, storage, dry_run).await,
+        Command::Send { to, cc, subject, attach, account, at } => {
+            let send_at = match at.map(|at| when::parse(&at, Local::now())).transpose() {
+                Ok(send_at) => send_at.map(|send_at| send_at.timestamp() as u64),
+                Err(error) => {
+                    eprintln!("invalid --at: {}", error);
+                    return Exit::InvalidArguments;
+                }
+            };
+            let message = OutgoingMessage {
+                to: Recipient::parse_list(&to.join(",")),
+                cc: Recipient::parse_list(&cc.join(",")),
+                subject,
+                send_at,
+                ..OutgoingMessage::default()
+            };
+            send(storage, message, &attach, account.as_deref()).await
+        }
+    }
+}
+
+async fn run_account(command: AccountCommand, config: &Config, storage: &mut Storage) -> Exit {
+    match command {
+        AccountCommand::Add { client_id } => {
+            let auth_options = config.outlook.auth_options();
+            match setup::add_outlook_mailbox(storage, client_id, &auth_options).await {
+                Ok(()) => Exit::Success,
+                Err(error) => {
+                    eprintln!("could not add the account: {}", error);
+                    Exit::RequestFailed
+                }
+            }
+        }
+        AccountCommand::List => {
+            for (i, mailbox) in storage.outlook.iter().enumerate() {
+                let status = if mailbox.is_disabled { "disabled" } else { "enabled" };
+                println!(
+                    "{}\toutlook\t{}\t{}\t{}",
+                    mailbox.get_id(),
+                    mailbox.address.as_deref().unwrap_or("-"),
+                    storage::mailbox_name(mailbox, i),
+                    status
+                );
+            }
+            Exit::Success
+        }
+        AccountCommand::Remove { id } => {
+            let index = match storage.find_mailbox(&id) {
+                Some(index) => index,
+                None => {
+                    eprintln!("no account {}", id);
+                    return Exit::NotFound;
+                }
+            };
+            let mut search_index = match SearchIndex::open(&SearchIndex::path(storage)) {
+                Ok(search_index) => search_index,
+                Err(error) => {
+                    // The account goes anyway, and its messages are left out of searches.
+                    eprintln!("could not open the search index: {}", error);
+                    SearchIndex::default()
+                }
+            };
+            accounts::remove_account(storage, &mut search_index, index);
+            storage::set(storage);
+            Exit::Success
+        }
+    }
+}
+
+async fn unread(storage: &Storage, json: bool) -> Exit {
+    let mut exit = Exit::Success;
+    let mut messages = vec![];
+    for mailbox in storage.enabled_mailboxes() {
+        match mailbox.fetch_unread().await {
+            Ok(mut mailbox_messages) => messages.append(&mut mailbox_messages),
+            Err(error) => {
+                eprintln!("could not fetch {}: {}", mailbox.get_id(), error);
+                exit = Exit::RequestFailed;
+            }
+        }
+    }
+    let messages = sort_messages_by_date(&messages);
+    if json {
+        let summaries: Vec<MessageSummary> = messages.iter()
+            .map(|message| MessageSummary {
+                id: &message.id,
+                account: &message.mailbox_id,
+                date: message.date,
+                from: &message.from,
+                to: &message.to,
+                subject: &message.subject,
+                flagged: message.flagged.is_flagged(),
+                categories: &message.categories,
+                attachments: message.attachments.len(),
+            })
+            .collect();
+        println!("{}", serde_json::to_string(&summaries).unwrap());
+    } else {
+        for message in &messages {
+            println!("{}\t{}\t{}", message.id, message.from.address, message.subject);
+        }
+    }
+    exit
+}
+
+async fn read(config: &Config, storage: &Storage, id: &str) -> Exit {
+    let (_, message) = match find_message(storage, id).await {
+        Ok(found) => found,
+        Err(exit) => return exit,
+    };
+    let list = |recipients: &[Recipient]| recipients.iter()
+        .map(|recipient| recipient.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let date = Local.timestamp_opt(message.date as i64, 0)
+        .single()
+        .map(|date| date.format(&config.date_format).to_string())
+        .unwrap_or_default();
+    println!("From: {}", message.from);
+    println!("To: {}", list(&message.to));
+    println!("Date: {}", date);
+    println!("Subject: {}", message.subject);
+    for attachment in &message.attachments {
+        println!("Attachment: {} ({} bytes)", attachment.name, attachment.size);
+    }
+    println!();
+    let width = termion::terminal_size()
+        .map(|(width, _)| width as usize)
+        .unwrap_or(DEFAULT_WIDTH);
+    let body = parse_message_body_plain(&message.body, message.body_type, width);
+    println!("{}", body.replace("\r\n", "\n"));
+    Exit::Success
+}
+
+async fn mark_read(storage: &Storage, id: &str) -> Exit {
+    let (mailbox, _) = match find_message(storage, id).await {
+        Ok(found) => found,
+        Err(exit) => return exit,
+    };
+    match mailbox.clone().set_as_read(id.to_string()).await {
+        Ok(()) => Exit::Success,
+        Err(error) => {
+            eprintln!("could not mark {} as read: {}", id, error);
+            Exit::RequestFailed
+        }
+    }
+}
+
+async fn sync(storage: &Storage) -> Exit {
+    let mut exit = Exit::Success;
+    for mailbox in storage.enabled_mailboxes() {
+        match mailbox.fetch_unread().await {
+            Ok(messages) => println!("{}\t{}", mailbox.get_id(), messages.len()),
+            Err(error) => {
+                eprintln!("could not sync {}: {}", mailbox.get_id(), error);
+                exit = Exit::RequestFailed;
+            }
+        }
+    }
+    exit
+}
+
+async fn rules(config: &Config, storage: &Storage, dry_run: bool) -> Exit {
+    if config.rules.is_empty() {
+        eprintln!("the config has no rules");
+        return Exit::Success;
+    }
+    let mut exit = Exit::Success;
+    for (i, mailbox) in storage.outlook.iter().enumerate() {
+        if mailbox.is_disabled {
+            continue;
+        }
+        let messages = match mailbox.fetch_unread().await {
+            Ok(messages) => messages,
+            Err(error) => {
+                eprintln!("could not fetch {}: {}", mailbox.get_id(), error);
+                exit = Exit::RequestFailed;
+                continue;
+            }
+        };
+        let account_names = storage::account_names(mailbox, i);
+        let account_names: Vec<&str> = account_names.iter().map(String::as_str).collect();
+        for message in &messages {
+            let plan = match rules::plan(&config.rules, message, &account_names) {
+                Some(plan) => plan,
+                None => continue,
+            };
+            println!("{}\t{}\t{}", message.id, message.subject, plan);
+            if dry_run {
+                continue;
+            }
+            if let Err(error) = rules::apply(mailbox, message, &plan).await {
+                eprintln!("could not apply the rules to {}: {}", message.id, error);
+                exit = Exit::RequestFailed;
+            }
+        }
+    }
+    exit
+}
+
+async fn send(
+    storage: &Storage,
+    mut message: OutgoingMessage,
+    attachments: &[PathBuf],
+    account: Option<&str>,
+) -> Exit {
+    let mailbox = match account {
+        Some(account) => storage.find_mailbox(account).map(|index| &storage.outlook[index]),
+        None => storage.enabled_mailboxes().next(),
+    };
+    let mailbox = match mailbox {
+        Some(mailbox) => mailbox,
+        None => {
+            eprintln!("no account {}", account.unwrap_or_default());
+            return Exit::NotFound;
+        }
+    };
+    if message.send_at.is_some() && !mailbox.can_defer_sending() {
+        eprintln!("this account cannot schedule messages on the server");
+        return Exit::InvalidArguments;
+    }
+    for path in attachments {
+        if let Err(error) = message.attach(path, mailbox.max_attachments_size()) {
+            eprintln!("could not attach {}: {}", path.display(), error);
+            return Exit::InvalidArguments;
+        }
+    }
+    if let Err(error) = std::io::stdin().read_to_string(&mut message.body) {
+        eprintln!("could not read the body from stdin: {}", error);
+        return Exit::InvalidArguments;
+    }
+    match mailbox.send(message).await {
+        Ok(()) => Exit::Success,
+        Err(error) => {
+            eprintln!("could not send the message: {}", error);
+            Exit::RequestFailed
+        }
+    }
+}
+
+/// Looks for the message in every account, as ids do not say which account they are from.
+async fn find_message<'a>(
+    storage: &'a Storage,
+    id: &str,
+) -> Result<(&'a OutlookMailbox, Message), Exit> {
+    let mut exit = Exit::NotFound;
+    for mailbox in &storage.outlook {
+        match mailbox.fetch_message(id).await {
+            Ok(Some(message)) => return Ok((mailbox, message)),
+            Ok(None) => (),
+            Err(error) => {
+                eprintln!("could not fetch from {}: {}", mailbox.get_id(), error);
+                exit = Exit::RequestFailed;
+            }
+        }
+    }
+    if exit == Exit::NotFound {
+        eprintln!("no message {}", id);
+    }
+    Err(exit)
+}
@@ -0,0 +1,224 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+use api::mail::{Mailbox, OutgoingMessage, Recipient};
+use crate::ui::{Backend, Terminal};
+use crate::{drafts, render, State, Storage};
+
+/// How often the editor is checked on, to see whether it has exited.
+const EDITOR_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+pub struct Compose {
+    /// The mailbox the message is sent from.
+    pub mailbox_id: String,
+    pub message: OutgoingMessage,
+    /// Path typed into the attach prompt, while it is open.
+    pub attach_prompt: Option<String>,
+    /// Time typed into the send-later prompt, while it is open.
+    pub schedule_prompt: Option<String>,
+    /// Tells the draft of the message apart in storage. See [`drafts::LocalDraft`].
+    pub draft_key: String,
+    /// Whether the message changed since it was last saved as a draft.
+    pub is_dirty: bool,
+    /// When the message was last saved as a draft, in seconds since the Unix epoch.
+    pub saved_at: u64,
+}
+
+pub enum ComposeAction {
+    /// Edit the headers and body in `$VISUAL` or `$EDITOR`.
+    Edit,
+    /// Save the message as a draft and close it.
+    Close,
+}
+
+impl Compose {
+    pub fn new(mailbox_id: &str) -> Compose {
+        Compose {
+            mailbox_id: mailbox_id.to_string(),
+            message: OutgoingMessage::default(),
+            attach_prompt: None,
+            schedule_prompt: None,
+            draft_key: drafts::new_key(),
+            is_dirty: false,
+            saved_at: 0,
+        }
+    }
+
+    pub fn submit_attach_prompt(&mut self, storage: &Storage) -> Result<(), String> {
+        let path = expand_home(self.attach_prompt.as_deref().unwrap_or_default().trim());
+        let mailbox = storage.get_mailbox_by_id(&self.mailbox_id)
+            .ok_or("mailbox not found")?;
+        self.message.attach(&path, mailbox.max_attachments_size())
+            .map_err(|error| error.to_string())?;
+        self.attach_prompt = None;
+        self.is_dirty = true;
+        Ok(())
+    }
+
+    /// Completes the attach prompt to the longest path shared by every matching file.
+    pub fn complete_attach_prompt(&mut self) {
+        let prompt = match &mut self.attach_prompt {
+            Some(prompt) => prompt,
+            None => return,
+        };
+        let (directory, prefix) = match prompt.rfind('/') {
+            Some(index) => (prompt[..index + 1].to_string(), prompt[index + 1..].to_string()),
+            None => ("".to_string(), prompt.clone()),
+        };
+        let read_directory = if directory.is_empty() { "." } else { directory.as_str() };
+        let entries = match fs::read_dir(expand_home(read_directory)) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        let mut matches: Vec<(String, bool)> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| (
+                entry.file_name().to_string_lossy().to_string(),
+                entry.path().is_dir(),
+            ))
+            .filter(|(name, _)| name.starts_with(&prefix))
+            // Hidden files are only completed when asked for.
+            .filter(|(name, _)| prefix.starts_with('.') || !name.starts_with('.'))
+            .collect();
+        matches.sort();
+        let completion = match matches.as_slice() {
+            [] => return,
+            [(name, is_dir)] => format!("{}{}", name, if *is_dir { "/" } else { "" }),
+            [(first, _), rest @ ..] => rest.iter().fold(first.clone(), |common, (name, _)| {
+                common.chars()
+                    .zip(name.chars())
+                    .take_while(|(a, b)| a == b)
+                    .map(|(a, _)| a)
+                    .collect()
+            }),
+        };
+        *prompt = format!("{}{}", directory, completion);
+    }
+}
+
+/// Runs the pending compose action.
+pub async fn run(
+    state: &mut State,
+    storage: &mut Storage,
+    terminal: &mut Terminal<impl Backend>,
+) {
+    let action = match state.pending_compose_action.take() {
+        Some(action) => action,
+        None => return,
+    };
+    let compose = match &mut state.compose {
+        Some(compose) => compose,
+        None => return,
+    };
+    match action {
+        ComposeAction::Edit => {
+            let result = edit(compose, storage, state.config.draft_autosave).await;
+            // The editor drew over the screen.
+            terminal.invalidate();
+            if let Err(error) = result {
+                state.status_message = Some(format!("failed to edit message: {}", error));
+            }
+        }
+        ComposeAction::Close => {
+            if compose.is_dirty || compose.message.draft_id.is_none() {
+                render::progress(state, "saving draft...", terminal);
+                let compose = state.compose.as_mut().unwrap();
+                state.status_message = match drafts::save(storage, compose).await {
+                    Ok(()) if compose.message.draft_id.is_some() => {
+                        Some("saved to drafts".to_string())
+                    }
+                    Ok(()) => None,
+                    Err(error) => Some(format!("draft kept offline: {}", error)),
+                };
+            }
+            state.compose = None;
+        }
+    }
+}
+
+/// Opens the message in the user's editor as a header block followed by the body, saving it
+/// as a draft every `autosave` seconds while it changes.
+async fn edit(compose: &mut Compose, storage: &mut Storage, autosave: u64) -> io::Result<()> {
+    let path = std::env::temp_dir().join(format!("dashboard-compose-{}.eml", std::process::id()));
+    let list = |recipients: &[Recipient]| recipients.iter()
+        .map(|recipient| recipient.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let message = &compose.message;
+    let mut saved_content = format!(
+        "To: {}\nCc: {}\nSubject: {}\n\n{}",
+        list(&message.to),
+        list(&message.cc),
+        message.subject,
+        message.body
+    );
+    fs::write(&path, &saved_content)?;
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&path)
+        .spawn()?;
+    let mut saved_at = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        tokio::time::sleep(EDITOR_POLL_INTERVAL).await;
+        if autosave == 0 || saved_at.elapsed() < Duration::from_secs(autosave) {
+            continue;
+        }
+        saved_at = Instant::now();
+        // Editors write the file when told to, so only saved changes are seen here.
+        match fs::read_to_string(&path) {
+            Ok(content) if content != saved_content => {
+                read_into(&mut compose.message, &content);
+                // Whatever the server says, the draft is kept in storage.
+                let _ = drafts::save(storage, compose).await;
+                saved_content = content;
+            }
+            _ => (),
+        }
+    };
+    let content = fs::read_to_string(&path);
+    let _ = fs::remove_file(&path);
+    if !status.success() {
+        return Err(io::Error::other("editor exited with an error"));
+    }
+    let content = content?;
+    if content != saved_content {
+        read_into(&mut compose.message, &content);
+        compose.is_dirty = true;
+    }
+    Ok(())
+}
+
+/// Reads the header block and body written in the editor into the message.
+fn read_into(message: &mut OutgoingMessage, content: &str) {
+    let (headers, body) = content.split_once("\n\n").unwrap_or((content, ""));
+    for line in headers.lines() {
+        let (name, value) = match line.split_once(':') {
+            Some((name, value)) => (name.trim().to_lowercase(), value.trim()),
+            None => continue,
+        };
+        match name.as_str() {
+            "to" => message.to = Recipient::parse_list(value),
+            "cc" => message.cc = Recipient::parse_list(value),
+            "subject" => message.subject = value.to_string(),
+            _ => (),
+        }
+    }
+    message.body = body.to_string();
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), dirs::home_dir()) {
+        (Some(rest), Some(home)) => home.join(rest),
+        _ => Path::new(path).to_path_buf(),
+    }
+}
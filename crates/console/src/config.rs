@@ -0,0 +1,250 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use chrono::format::{Item, StrftimeItems};
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use toml_edit::DocumentMut;
+use api::mime::BodyPreference;
+use api::mail::Mailbox;
+use api::outlook::auth::AuthOptions;
+use api::outlook::OutlookMailbox;
+use crate::keymap::KeymapConfig;
+use crate::layout::Layout;
+use crate::reply::QuoteStyle;
+use crate::rules::Rule;
+use crate::theme::ThemeConfig;
+use crate::view::ViewConfig;
+
+const CONFIG_DIR_NAME: &str = "dashboard";
+const CONFIG_FILE_NAME: &str = "config.toml";
+/// Syncing more often than this would mostly fetch the same messages again.
+const MIN_SYNC_INTERVAL: u64 = 30;
+
+/// Settings written by the user, kept apart from the tokens in storage.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Where storage is kept, instead of `dashboard.json` in the config directory.
+    pub storage_path: Option<PathBuf>,
+    /// Seconds between fetching new messages, or 0 to only fetch at startup.
+    #[serde(deserialize_with = "deserialize_sync_interval")]
+    pub sync_interval: u64,
+    /// How dates are shown, in `strftime` format.
+    #[serde(deserialize_with = "deserialize_date_format")]
+    pub date_format: String,
+    /// Seconds a sent message waits in the outbox, during which it can be taken back to the
+    /// editor, or 0 to send it straight away.
+    pub send_delay: u64,
+    /// Seconds between saves of the message being written to the server's drafts while it
+    /// changes, or 0 to only save it when it is closed.
+    pub draft_autosave: u64,
+    /// How replies and forwards set out the original message.
+    pub quote_style: QuoteStyle,
+    /// Whether to show the plain-text or the HTML alternative of a message, unless an
+    /// account says otherwise.
+    pub body_preference: BodyPreference,
+    pub layout: Layout,
+    pub theme: ThemeConfig,
+    pub keymap: KeymapConfig,
+    pub outlook: OutlookConfig,
+    /// Options of each account, by account id or address.
+    pub accounts: HashMap<String, AccountConfig>,
+    /// Smart views, listed after the unified and per-account views.
+    pub views: Vec<ViewConfig>,
+    /// Rules applied to new unread messages on each sync, in order.
+    pub rules: Vec<Rule>,
+    /// The file this was read from, where changes made in the console are written back.
+    #[serde(skip)]
+    pub path: PathBuf,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            storage_path: None,
+            sync_interval: 300,
+            date_format: "%Y-%m-%d %H:%M".to_string(),
+            send_delay: 10,
+            draft_autosave: 30,
+            quote_style: QuoteStyle::default(),
+            body_preference: BodyPreference::default(),
+            layout: Layout::default(),
+            theme: ThemeConfig::default(),
+            keymap: KeymapConfig::default(),
+            outlook: OutlookConfig::default(),
+            accounts: HashMap::new(),
+            views: vec![],
+            rules: vec![],
+            path: default_path(),
+        }
+    }
+}
+
+/// How Outlook accounts are authorised. Changing the scopes takes effect on the next sign-in.
+#[derive(Deserialize, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct OutlookConfig {
+    /// Local port the sign-in page redirects to. It must match the app registration.
+    pub redirect_port: u16,
+    pub scopes: Vec<String>,
+}
+
+impl Default for OutlookConfig {
+    fn default() -> OutlookConfig {
+        let options = AuthOptions::default();
+        OutlookConfig {
+            redirect_port: options.redirect_port,
+            scopes: options.scopes,
+        }
+    }
+}
+
+impl OutlookConfig {
+    pub fn auth_options(&self) -> AuthOptions {
+        AuthOptions {
+            redirect_port: self.redirect_port,
+            scopes: self.scopes.clone(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountConfig {
+    pub body_preference: Option<BodyPreference>,
+    /// Put under the `-- ` line of new replies and forwards.
+    pub signature: Option<String>,
+}
+
+impl Config {
+    pub fn account(&self, mailbox: &OutlookMailbox) -> Option<&AccountConfig> {
+        self.accounts.get(mailbox.get_id())
+            .or_else(|| mailbox.address.as_ref().and_then(|address| self.accounts.get(address)))
+    }
+
+    pub fn body_preference(&self, mailbox: &OutlookMailbox) -> BodyPreference {
+        self.account(mailbox)
+            .and_then(|account| account.body_preference)
+            .unwrap_or(self.body_preference)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Write(PathBuf, io::Error),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, error) => {
+                write!(f, "could not read {}: {}", path.display(), error)
+            }
+            // The TOML error names the line and column, and shows the offending entry.
+            ConfigError::Parse(path, error) => write!(f, "in {}: {}", path.display(), error),
+            ConfigError::Write(path, error) => {
+                write!(f, "could not write {}: {}", path.display(), error)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+fn default_path() -> PathBuf {
+    dirs::config_dir().unwrap().join(CONFIG_DIR_NAME).join(CONFIG_FILE_NAME)
+}
+
+/// Reads the config at the given path, or at the default path when there is none. Only the
+/// default file may be missing, in which case every setting has its default value.
+pub fn load(path: Option<PathBuf>) -> Result<Config, ConfigError> {
+    let is_default_path = path.is_none();
+    let path = path.unwrap_or_else(default_path);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) if is_default_path && error.kind() == io::ErrorKind::NotFound => {
+            String::new()
+        }
+        Err(error) => return Err(ConfigError::Read(path, error)),
+    };
+    let mut config: Config = toml::from_str(&text)
+        .map_err(|error| ConfigError::Parse(path.clone(), error))?;
+    config.path = path;
+    Ok(config)
+}
+
+/// Writes the layout into the config file, keeping the rest of the file and its comments.
+pub fn save_layout(config: &Config) -> Result<(), ConfigError> {
+    let layout = &config.layout;
+    update(&config.path, |document| {
+        let table = document.entry("layout")
+            .or_insert_with(toml_edit::table)
+            .as_table_like_mut();
+        // A layout that is not a table would not have loaded.
+        if let Some(table) = table {
+            table.insert("kind", toml_edit::value(layout.kind.name()));
+            table.insert("folders_width", toml_edit::value(layout.folders_width as i64));
+            table.insert("list_percent", toml_edit::value(layout.list_percent as i64));
+        }
+    })
+}
+
+fn update(path: &Path, change: impl FnOnce(&mut DocumentMut)) -> Result<(), ConfigError> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(error) => return Err(ConfigError::Read(path.to_path_buf(), error)),
+    };
+    // The file was parsed at startup, so it can only fail if it was edited since.
+    let mut document = text.parse::<DocumentMut>().unwrap_or_default();
+    change(&mut document);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|error| ConfigError::Write(path.to_path_buf(), error))?;
+    }
+    fs::write(path, document.to_string())
+        .map_err(|error| ConfigError::Write(path.to_path_buf(), error))
+}
+
+fn deserialize_sync_interval<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    let interval = u64::deserialize(deserializer)?;
+    if interval != 0 && interval < MIN_SYNC_INTERVAL {
+        return Err(de::Error::custom(format!(
+            "sync_interval must be 0 or at least {} seconds",
+            MIN_SYNC_INTERVAL
+        )));
+    }
+    Ok(interval)
+}
+
+fn deserialize_date_format<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let format = String::deserialize(deserializer)?;
+    if StrftimeItems::new(&format).any(|item| item == Item::Error) {
+        return Err(de::Error::custom(format!("invalid date format \"{}\"", format)));
+    }
+    Ok(format)
+}
+
+/// Reads a number and checks that it is within `MIN..=MAX`.
+pub fn deserialize_in_range<'de, D, const MIN: u16, const MAX: u16>(
+    deserializer: D,
+) -> Result<u16, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = u16::deserialize(deserializer)?;
+    if !(MIN..=MAX).contains(&value) {
+        return Err(de::Error::custom(format!(
+            "expected a number from {} to {}, found {}",
+            MIN, MAX, value
+        )));
+    }
+    Ok(value)
+}
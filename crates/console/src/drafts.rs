@@ -0,0 +1,373 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Deserialize};
+use termion::event::Key;
+use api::mail::{Mailbox, Message, OutgoingMessage};
+use crate::compose::Compose;
+use crate::input::lookup_key;
+use crate::keymap::{Action, Context};
+use crate::ui::{Backend, Terminal};
+use crate::{render, storage, State, Storage};
+
+/// How long to wait before trying again to save a draft the server did not take.
+const DRAFT_RETRY_DELAY: u64 = 60;
+
+/// A draft the server does not have yet, such as one written offline. Kept in storage until it
+/// is saved there.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct LocalDraft {
+    /// Tells the draft apart from others before the server has given it an id.
+    pub key: String,
+    pub mailbox_id: String,
+    pub message: OutgoingMessage,
+    /// When the draft was last changed, in seconds since the Unix epoch.
+    pub updated_at: u64,
+    /// When to try saving it to the server again.
+    #[serde(default)]
+    pub retry_at: u64,
+    /// Why the server did not take it.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// The drafts of every account, shown instead of the messages while open.
+pub struct DraftsScreen {
+    pub rows: Vec<DraftRow>,
+    pub selected_index: usize,
+    /// Waiting for a yes before discarding the selected draft.
+    pub is_confirming_discard: bool,
+}
+
+pub struct DraftRow {
+    pub mailbox_id: String,
+    /// Set on drafts kept in storage, waiting to be saved to the server.
+    pub key: Option<String>,
+    pub message: OutgoingMessage,
+    pub updated_at: u64,
+    pub error: Option<String>,
+}
+
+/// A change to the drafts waiting to be run by the event loop.
+pub enum DraftsAction {
+    Load,
+    Discard { mailbox_id: String, draft_id: String },
+}
+
+impl DraftsScreen {
+    pub fn open(state: &mut State, storage: &Storage) {
+        let mut screen = DraftsScreen {
+            rows: vec![],
+            selected_index: 0,
+            is_confirming_discard: false,
+        };
+        screen.set_rows(storage, vec![]);
+        state.drafts = Some(screen);
+        state.pending_drafts_action = Some(DraftsAction::Load);
+    }
+
+    /// Lists the drafts in storage along with those fetched from the server, newest first.
+    fn set_rows(&mut self, storage: &Storage, fetched: Vec<Message>) {
+        let local_rows = storage.drafts.iter().map(|draft| DraftRow {
+            mailbox_id: draft.mailbox_id.clone(),
+            key: Some(draft.key.clone()),
+            message: draft.message.clone(),
+            updated_at: draft.updated_at,
+            error: draft.error.clone(),
+        });
+        // A draft changed offline is newer than the server's copy of it.
+        let fetched_rows = fetched.into_iter()
+            .filter(|message| !storage.drafts.iter()
+                .any(|draft| draft.message.draft_id.as_ref() == Some(&message.id)))
+            .map(|message| DraftRow {
+                mailbox_id: message.mailbox_id.clone(),
+                key: None,
+                updated_at: message.date,
+                message: to_outgoing_message(message),
+                error: None,
+            });
+        self.rows = local_rows.chain(fetched_rows).collect();
+        self.rows.sort_by_key(|row| std::cmp::Reverse(row.updated_at));
+        self.selected_index = self.selected_index.min(self.rows.len().saturating_sub(1));
+    }
+
+    /// The text of the status bar prompt, while one is open.
+    pub fn prompt(&self) -> Option<String> {
+        if !self.is_confirming_discard {
+            return None;
+        }
+        self.rows.get(self.selected_index)
+            .map(|row| format!("discard \"{}\"? (y/n)", row.message.subject))
+    }
+}
+
+fn to_outgoing_message(message: Message) -> OutgoingMessage {
+    OutgoingMessage {
+        to: message.to,
+        cc: message.cc,
+        subject: message.subject,
+        body: message.body,
+        draft_id: Some(message.id),
+        ..OutgoingMessage::default()
+    }
+}
+
+pub fn take_drafts_key(storage: &mut Storage, state: &mut State, key: Key) {
+    let screen = state.drafts.as_mut().unwrap();
+    let selected_index = screen.selected_index;
+    if screen.is_confirming_discard {
+        screen.is_confirming_discard = false;
+        if key == Key::Char('y') {
+            discard(storage, state, selected_index);
+        }
+        return;
+    }
+    if let Some(action) = lookup_key(state, key, Context::Drafts) {
+        take_drafts_action(state, action);
+    }
+}
+
+fn take_drafts_action(state: &mut State, action: Action) {
+    let screen = state.drafts.as_mut().unwrap();
+    let selected_index = screen.selected_index;
+    match action {
+        Action::Prev => screen.selected_index = selected_index.saturating_sub(1),
+        Action::Next => {
+            screen.selected_index = (selected_index + 1).min(screen.rows.len().saturating_sub(1));
+        },
+        Action::Open if !screen.rows.is_empty() => {
+            let row = screen.rows.remove(selected_index);
+            let mut compose = Compose::new(&row.mailbox_id);
+            if let Some(key) = row.key {
+                compose.draft_key = key;
+            }
+            compose.message = row.message;
+            state.drafts = None;
+            state.compose = Some(compose);
+        },
+        Action::DiscardDraft if !screen.rows.is_empty() => screen.is_confirming_discard = true,
+        Action::FetchDrafts => state.pending_drafts_action = Some(DraftsAction::Load),
+        Action::Help => state.show_help = true,
+        Action::Back => state.drafts = None,
+        _ => (),
+    }
+}
+
+fn discard(storage: &mut Storage, state: &mut State, index: usize) {
+    let screen = state.drafts.as_mut().unwrap();
+    let row = screen.rows.remove(index);
+    screen.selected_index = index.min(screen.rows.len().saturating_sub(1));
+    if let Some(key) = &row.key {
+        storage.drafts.retain(|draft| &draft.key != key);
+        storage::set(storage);
+    }
+    match row.message.draft_id {
+        Some(draft_id) => {
+            state.pending_drafts_action = Some(DraftsAction::Discard {
+                mailbox_id: row.mailbox_id,
+                draft_id,
+            });
+        }
+        None => state.status_message = Some("discarded the draft".to_string()),
+    }
+}
+
+/// Runs the pending drafts action.
+pub async fn run(state: &mut State, storage: &Storage, terminal: &mut Terminal<impl Backend>) {
+    let action = match state.pending_drafts_action.take() {
+        Some(action) => action,
+        None => return,
+    };
+    match action {
+        DraftsAction::Load => {
+            render::progress(state, "fetching drafts...", terminal);
+            let mut messages = vec![];
+            let mut errors = vec![];
+            for mailbox in storage.enabled_mailboxes() {
+                match mailbox.fetch_drafts().await {
+                    Ok(mut drafts) => messages.append(&mut drafts),
+                    Err(error) => errors.push(format!("could not fetch drafts: {}", error)),
+                }
+            }
+            if let Some(screen) = &mut state.drafts {
+                screen.set_rows(storage, messages);
+            }
+            state.status_message = errors.into_iter().next();
+        }
+        DraftsAction::Discard { mailbox_id, draft_id } => {
+            let mailbox = match storage.get_mailbox_by_id(&mailbox_id) {
+                Some(mailbox) => mailbox,
+                None => return,
+            };
+            render::progress(state, "discarding the draft...", terminal);
+            state.status_message = Some(match mailbox.delete_message(&draft_id).await {
+                Ok(()) => "discarded the draft".to_string(),
+                Err(error) => format!("could not discard the draft: {}", error),
+            });
+        }
+    }
+}
+
+/// Saves the message being written to the server's drafts, keeping it in storage if the
+/// server cannot be reached, so that it is saved there later.
+pub async fn save(storage: &mut Storage, compose: &mut Compose) -> Result<(), String> {
+    let now = now();
+    compose.is_dirty = false;
+    compose.saved_at = now;
+    let message = &compose.message;
+    let is_empty = message.to.is_empty()
+        && message.cc.is_empty()
+        && message.subject.is_empty()
+        && message.body.trim().is_empty();
+    if is_empty && message.draft_id.is_none() {
+        return Ok(());
+    }
+    // Kept before trying the server, in case the console is closed while waiting for it.
+    let local_draft = LocalDraft {
+        key: compose.draft_key.clone(),
+        mailbox_id: compose.mailbox_id.clone(),
+        message: message.clone(),
+        updated_at: now,
+        retry_at: now + DRAFT_RETRY_DELAY,
+        error: None,
+    };
+    match storage.drafts.iter_mut().find(|draft| draft.key == compose.draft_key) {
+        Some(draft) => *draft = local_draft,
+        None => storage.drafts.push(local_draft),
+    }
+    storage::set(storage);
+    let result = match storage.get_mailbox_by_id(&compose.mailbox_id) {
+        Some(mailbox) => mailbox.save_draft(message).await.map_err(|error| error.to_string()),
+        None => Err("mailbox not found".to_string()),
+    };
+    match result {
+        Ok(draft_id) => {
+            compose.message.draft_id = Some(draft_id);
+            storage.drafts.retain(|draft| draft.key != compose.draft_key);
+            storage::set(storage);
+            Ok(())
+        }
+        Err(error) => {
+            if let Some(draft) = storage.drafts.iter_mut()
+                .find(|draft| draft.key == compose.draft_key) {
+                draft.error = Some(error.clone());
+            }
+            storage::set(storage);
+            Err(error)
+        }
+    }
+}
+
+/// Saves the message being written if it changed since the autosave interval, and tries
+/// again to save the drafts the server did not take. Returns whether the screen needs to be
+/// drawn again.
+pub async fn autosave(
+    state: &mut State,
+    storage: &mut Storage,
+    terminal: &mut Terminal<impl Backend>,
+) -> bool {
+    let now = now();
+    let interval = state.config.draft_autosave;
+    let mut has_changed = false;
+    if let Some(compose) = &mut state.compose {
+        let is_due = compose.is_dirty && interval > 0 && compose.saved_at + interval <= now;
+        // Retried from the message as it is now, rather than its older copy in storage.
+        let is_retry_due = storage.drafts.iter()
+            .any(|draft| draft.key == compose.draft_key && draft.retry_at <= now);
+        if is_due || is_retry_due {
+            has_changed = true;
+            if let Err(error) = save(storage, compose).await {
+                state.status_message = Some(format!("draft kept offline: {}", error));
+            }
+        }
+    }
+    let open_key = state.compose.as_ref().map(|compose| compose.draft_key.clone());
+    let due_keys: Vec<String> = storage.drafts.iter()
+        .filter(|draft| draft.retry_at <= now && Some(&draft.key) != open_key.as_ref())
+        .map(|draft| draft.key.clone())
+        .collect();
+    for key in due_keys {
+        let draft = match storage.drafts.iter().find(|draft| draft.key == key) {
+            Some(draft) => draft,
+            None => continue,
+        };
+        let mailbox = match storage.get_mailbox_by_id(&draft.mailbox_id) {
+            Some(mailbox) if !mailbox.is_disabled => mailbox,
+            _ => continue,
+        };
+        has_changed = true;
+        render::progress(state, "saving drafts...", terminal);
+        let result = mailbox.save_draft(&draft.message).await.map_err(|error| error.to_string());
+        match result {
+            Ok(_) => storage.drafts.retain(|draft| draft.key != key),
+            Err(error) => {
+                let draft = storage.drafts.iter_mut().find(|draft| draft.key == key).unwrap();
+                draft.retry_at = now + DRAFT_RETRY_DELAY;
+                draft.error = Some(error);
+            }
+        }
+        storage::set(storage);
+    }
+    has_changed
+}
+
+/// A key for a new draft, unique enough among the drafts of one person.
+pub fn new_key() -> String {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!("{:x}{:08x}", time.as_secs(), time.subsec_nanos())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::test_storage;
+    use super::*;
+
+    fn local_draft(key: &str, draft_id: Option<&str>, updated_at: u64) -> LocalDraft {
+        LocalDraft {
+            key: key.to_string(),
+            mailbox_id: "mailbox".to_string(),
+            message: OutgoingMessage {
+                subject: format!("Kept offline {}", key),
+                draft_id: draft_id.map(|draft_id| draft_id.to_string()),
+                ..OutgoingMessage::default()
+            },
+            updated_at,
+            retry_at: 0,
+            error: Some("offline".to_string()),
+        }
+    }
+
+    #[test]
+    fn lists_drafts_changed_offline_instead_of_the_server_copies() {
+        let mut storage = test_storage("drafts-merge", 0);
+        storage.drafts = vec![
+            local_draft("new", None, 100),
+            local_draft("changed", Some("2"), 300),
+        ];
+        let mut screen = DraftsScreen {
+            rows: vec![],
+            selected_index: 5,
+            is_confirming_discard: false,
+        };
+        screen.set_rows(&storage, vec![
+            Message::test("1").with_subject("On the server").with_date(200),
+            Message::test("2").with_subject("Before the change").with_date(50),
+        ]);
+        let rows: Vec<(&str, Option<&str>, Option<&str>)> = screen.rows.iter()
+            .map(|row| (
+                row.message.subject.as_str(),
+                row.key.as_deref(),
+                row.message.draft_id.as_deref(),
+            ))
+            .collect();
+        assert_eq!(rows, [
+            ("Kept offline changed", Some("changed"), Some("2")),
+            ("On the server", None, Some("1")),
+            ("Kept offline new", Some("new"), None),
+        ]);
+        assert_eq!(screen.rows[0].error.as_deref(), Some("offline"));
+        assert_eq!(screen.selected_index, 2);
+    }
+}
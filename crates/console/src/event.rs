@@ -0,0 +1,113 @@
+use std::io::stdin;
+use std::sync::mpsc;
+use std::time::Duration;
+use termion::event::Key;
+use termion::input::TermRead;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// Something for the event loop to react to.
+pub enum Event {
+    Key(Key),
+    /// The terminal changed size.
+    Resize,
+    /// It is time to fetch new messages.
+    Sync,
+    /// A second has passed, for whatever is scheduled to run at a set time.
+    Tick,
+}
+
+/// How often scheduled work, such as waking snoozed messages, is looked at.
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Keys, resizes, syncs and ticks, in the order they happened.
+pub struct Events {
+    receiver: UnboundedReceiver<Event>,
+    resume_keys: mpsc::Sender<()>,
+}
+
+impl Events {
+    pub async fn next(&mut self) -> Option<Event> {
+        self.receiver.recv().await
+    }
+
+    /// Lets the next key be read. Keys are read one at a time so that stdin is left alone
+    /// while a key is handled, which may start a program such as the editor.
+    pub fn resume_keys(&self) {
+        let _ = self.resume_keys.send(());
+    }
+}
+
+/// Starts listening. Syncs are sent every `sync_interval`, unless it is zero.
+pub fn listen(sync_interval: Duration) -> Events {
+    let (sender, receiver) = unbounded_channel();
+    let (resume_keys, resumed_keys) = mpsc::channel();
+    listen_for_keys(sender.clone(), resumed_keys);
+    listen_for_resizes(sender.clone());
+    schedule_ticks(sender.clone());
+    if !sync_interval.is_zero() {
+        schedule_syncs(sender, sync_interval);
+    }
+    Events {
+        receiver,
+        resume_keys,
+    }
+}
+
+fn listen_for_keys(sender: UnboundedSender<Event>, resumed_keys: mpsc::Receiver<()>) {
+    // Reading stdin blocks, so it gets a thread of its own.
+    std::thread::spawn(move || {
+        for key in stdin().keys() {
+            let key = match key {
+                Ok(key) => key,
+                Err(_) => break,
+            };
+            if sender.send(Event::Key(key)).is_err() || resumed_keys.recv().is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn listen_for_resizes(sender: UnboundedSender<Event>) {
+    let mut window_changes = match signal(SignalKind::window_change()) {
+        Ok(window_changes) => window_changes,
+        // Without the signal, a new size is still picked up on the next key press.
+        Err(_) => return,
+    };
+    tokio::spawn(async move {
+        while window_changes.recv().await.is_some() {
+            if sender.send(Event::Resize).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn schedule_ticks(sender: UnboundedSender<Event>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(TICK_INTERVAL);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            if sender.send(Event::Tick).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+fn schedule_syncs(sender: UnboundedSender<Event>, sync_interval: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(Instant::now() + sync_interval, sync_interval);
+        // A sync that took longer than the interval is not followed by a burst of others.
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            if sender.send(Event::Sync).is_err() {
+                break;
+            }
+        }
+    });
+}
@@ -0,0 +1,383 @@
+use std::fmt;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use api::mail::Message;
+
+/// A condition on messages, such as `account:work AND from:*@github.com`.
+///
+/// Terms are `field:pattern`, combined with `AND`, `OR`, `NOT` and parentheses. Terms next to
+/// each other without an operator must all match. A pattern matches anywhere in the field,
+/// ignoring case, unless it has `*` or `?` wildcards, in which case it must match the whole
+/// field. A word without a field matches the subject, sender or recipients.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Filter {
+    And(Box<Filter>, Box<Filter>),
+    Or(Box<Filter>, Box<Filter>),
+    Not(Box<Filter>),
+    Term(Field, String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Field {
+    /// The name, address or id of the account.
+    Account,
+    /// The name or address of the sender.
+    From,
+    /// The name or address of any recipient, whether in to or cc.
+    To,
+    Subject,
+    Category,
+    /// `unread`, `read` or `flagged`.
+    Is,
+    /// `attachment`.
+    Has,
+    /// Subject, sender or recipients.
+    Text,
+}
+
+const FIELDS: [(&str, Field); 7] = [
+    ("account", Field::Account),
+    ("from", Field::From),
+    ("to", Field::To),
+    ("subject", Field::Subject),
+    ("category", Field::Category),
+    ("is", Field::Is),
+    ("has", Field::Has),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum FilterError {
+    Empty,
+    MissingValue(String),
+    UnknownValue(String, String),
+    /// An operator or parenthesis where a term was expected.
+    Unexpected(String),
+    UnclosedParenthesis,
+    UnterminatedQuote,
+    /// An operator at the end, without a term after it.
+    UnexpectedEnd,
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::Empty => write!(f, "filter is empty"),
+            FilterError::MissingValue(field) => write!(f, "{}: is missing a value", field),
+            FilterError::UnknownValue(field, value) => {
+                write!(f, "unknown value \"{}\" for {}:", value, field)
+            }
+            FilterError::Unexpected(token) => write!(f, "unexpected \"{}\"", token),
+            FilterError::UnclosedParenthesis => write!(f, "unclosed parenthesis"),
+            FilterError::UnterminatedQuote => write!(f, "unterminated quote"),
+            FilterError::UnexpectedEnd => write!(f, "expected a term at the end"),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl Filter {
+    pub fn parse(text: &str) -> Result<Filter, FilterError> {
+        let tokens = tokenize(text)?;
+        if tokens.is_empty() {
+            return Err(FilterError::Empty);
+        }
+        let mut parser = Parser { tokens, position: 0 };
+        let filter = parser.parse_or()?;
+        match parser.tokens.get(parser.position) {
+            Some(token) => Err(FilterError::Unexpected(token.to_string())),
+            None => Ok(filter),
+        }
+    }
+
+    /// Whether the message matches, given the names its account goes by.
+    pub fn matches(&self, message: &Message, account_names: &[&str]) -> bool {
+        match self {
+            Filter::And(left, right) => {
+                left.matches(message, account_names) && right.matches(message, account_names)
+            }
+            Filter::Or(left, right) => {
+                left.matches(message, account_names) || right.matches(message, account_names)
+            }
+            Filter::Not(filter) => !filter.matches(message, account_names),
+            Filter::Term(field, pattern) => {
+                let recipients = || message.to.iter().chain(&message.cc)
+                    .flat_map(|recipient| [recipient.name.as_str(), recipient.address.as_str()]);
+                let sender = [message.from.name.as_str(), message.from.address.as_str()];
+                match field {
+                    Field::Account => account_names.iter().any(|name| matches(pattern, name)),
+                    Field::From => sender.iter().any(|value| matches(pattern, value)),
+                    Field::To => recipients().any(|value| matches(pattern, value)),
+                    Field::Subject => matches(pattern, &message.subject),
+                    Field::Category => {
+                        message.categories.iter().any(|category| matches(pattern, category))
+                    }
+                    Field::Is => match pattern.as_str() {
+                        "unread" => !message.is_read,
+                        "read" => message.is_read,
+                        _ => message.flagged.is_flagged(),
+                    },
+                    Field::Has => {
+                        message.attachments.iter().any(|attachment| !attachment.is_inline)
+                    }
+                    Field::Text => matches(pattern, &message.subject)
+                        || sender.iter().any(|value| matches(pattern, value))
+                        || recipients().any(|value| matches(pattern, value)),
+                }
+            }
+        }
+    }
+}
+
+/// Reads a filter written as a string in the config.
+pub fn deserialize_filter<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Filter, D::Error> {
+    let filter = String::deserialize(deserializer)?;
+    Filter::parse(&filter).map_err(de::Error::custom)
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Open => write!(f, "("),
+            Token::Close => write!(f, ")"),
+            Token::And => write!(f, "AND"),
+            Token::Or => write!(f, "OR"),
+            Token::Not => write!(f, "NOT"),
+            Token::Word(word) => write!(f, "{}", word),
+        }
+    }
+}
+
+/// Splits on whitespace and parentheses, keeping double-quoted sections (including
+/// `field:"a b"`) together. Only upper case `AND`, `OR` and `NOT` are operators.
+fn tokenize(text: &str) -> Result<Vec<Token>, FilterError> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    let mut is_quoted = false;
+    let mut was_quoted = false;
+    let end_word = |word: &mut String, was_quoted: &mut bool, tokens: &mut Vec<Token>| {
+        if word.is_empty() && !*was_quoted {
+            return;
+        }
+        let word = std::mem::take(word);
+        tokens.push(match word.as_str() {
+            "AND" if !*was_quoted => Token::And,
+            "OR" if !*was_quoted => Token::Or,
+            "NOT" if !*was_quoted => Token::Not,
+            _ => Token::Word(word),
+        });
+        *was_quoted = false;
+    };
+    for c in text.chars() {
+        match c {
+            '"' => {
+                is_quoted = !is_quoted;
+                was_quoted = true;
+            }
+            c if is_quoted => word.push(c),
+            '(' | ')' => {
+                end_word(&mut word, &mut was_quoted, &mut tokens);
+                tokens.push(if c == '(' { Token::Open } else { Token::Close });
+            }
+            c if c.is_whitespace() => end_word(&mut word, &mut was_quoted, &mut tokens),
+            c => word.push(c),
+        }
+    }
+    if is_quoted {
+        return Err(FilterError::UnterminatedQuote);
+    }
+    end_word(&mut word, &mut was_quoted, &mut tokens);
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn parse_or(&mut self) -> Result<Filter, FilterError> {
+        let mut filter = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.position += 1;
+            filter = Filter::Or(Box::new(filter), Box::new(self.parse_and()?));
+        }
+        Ok(filter)
+    }
+
+    fn parse_and(&mut self) -> Result<Filter, FilterError> {
+        let mut filter = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => self.position += 1,
+                // Terms next to each other must both match.
+                Some(Token::Open) | Some(Token::Not) | Some(Token::Word(_)) => (),
+                _ => return Ok(filter),
+            }
+            filter = Filter::And(Box::new(filter), Box::new(self.parse_not()?));
+        }
+    }
+
+    fn parse_not(&mut self) -> Result<Filter, FilterError> {
+        if self.peek() == Some(&Token::Not) {
+            self.position += 1;
+            return Ok(Filter::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_term()
+    }
+
+    fn parse_term(&mut self) -> Result<Filter, FilterError> {
+        let token = match self.tokens.get(self.position) {
+            Some(token) => token,
+            None => return Err(FilterError::UnexpectedEnd),
+        };
+        self.position += 1;
+        match token {
+            Token::Open => {
+                let filter = self.parse_or()?;
+                if self.peek() != Some(&Token::Close) {
+                    return Err(FilterError::UnclosedParenthesis);
+                }
+                self.position += 1;
+                Ok(filter)
+            }
+            Token::Word(word) => parse_term(word),
+            token => Err(FilterError::Unexpected(token.to_string())),
+        }
+    }
+}
+
+fn parse_term(word: &str) -> Result<Filter, FilterError> {
+    let field = word.split_once(':').and_then(|(name, value)| {
+        let name = name.to_lowercase();
+        FIELDS.iter()
+            .find(|(field_name, _)| *field_name == name)
+            .map(|(_, field)| (name, *field, value))
+    });
+    let (name, field, value) = match field {
+        Some(field) => field,
+        // Not a field, e.g. a time or URL: match it as text.
+        None => return Ok(Filter::Term(Field::Text, word.to_lowercase())),
+    };
+    if value.is_empty() {
+        return Err(FilterError::MissingValue(name));
+    }
+    let value = value.to_lowercase();
+    let is_known = match field {
+        Field::Is => ["unread", "read", "flagged"].contains(&value.as_str()),
+        Field::Has => value == "attachment",
+        _ => true,
+    };
+    if !is_known {
+        return Err(FilterError::UnknownValue(name, value));
+    }
+    Ok(Filter::Term(field, value))
+}
+
+/// Matches a lower case pattern against text, ignoring case: anywhere in the text, or the
+/// whole of it when the pattern has wildcards.
+fn matches(pattern: &str, text: &str) -> bool {
+    let text = text.to_lowercase();
+    if !pattern.contains(['*', '?']) {
+        return text.contains(pattern);
+    }
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    // matched[j]: whether the pattern so far matches the first j characters of the text.
+    let mut matched = vec![false; text.len() + 1];
+    matched[0] = true;
+    for p in pattern {
+        let previous = matched.clone();
+        matched[0] = p == '*' && previous[0];
+        for j in 1..=text.len() {
+            matched[j] = match p {
+                '*' => previous[j] || matched[j - 1],
+                '?' => previous[j - 1],
+                p => previous[j - 1] && text[j - 1] == p,
+            };
+        }
+    }
+    matched[text.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(word: &str) -> Box<Filter> {
+        Box::new(Filter::Term(Field::Text, word.to_string()))
+    }
+
+    fn matches_message(filter: &str, message: &Message) -> bool {
+        Filter::parse(filter).unwrap().matches(message, &["work"])
+    }
+
+    #[test]
+    fn binds_not_before_and_before_or() {
+        assert_eq!(
+            Filter::parse("a OR b c").unwrap(),
+            Filter::Or(term("a"), Box::new(Filter::And(term("b"), term("c")))),
+        );
+        assert_eq!(
+            Filter::parse("NOT a AND b").unwrap(),
+            Filter::And(Box::new(Filter::Not(term("a"))), term("b")),
+        );
+    }
+
+    #[test]
+    fn groups_terms_in_parentheses() {
+        assert_eq!(
+            Filter::parse("(a OR b) c").unwrap(),
+            Filter::And(Box::new(Filter::Or(term("a"), term("b"))), term("c")),
+        );
+        assert_eq!(
+            Filter::parse("NOT (a OR b)").unwrap(),
+            Filter::Not(Box::new(Filter::Or(term("a"), term("b")))),
+        );
+    }
+
+    #[test]
+    fn matches_wildcards_against_the_whole_field() {
+        let message = Message::test("1").with_from("GitHub", "Notifications@GitHub.com");
+        assert!(matches_message("from:*@github.com", &message));
+        assert!(matches_message("from:notification?@github.com", &message));
+        assert!(!matches_message("from:*@github", &message));
+        assert!(matches_message("from:github", &message));
+        assert!(!matches_message("from:*@github.com", &message.with_from("", "a@github.com.evil")));
+    }
+
+    #[test]
+    fn matches_recipients_in_cc_as_well_as_to() {
+        let message = Message::test("1")
+            .with_to("bob@example.com")
+            .with_cc("Jane <jane@example.com>");
+        assert!(matches_message("to:jane", &message));
+        assert!(matches_message("to:bob", &message));
+        assert!(matches_message("jane", &message));
+        assert!(!matches_message("to:alice", &message));
+    }
+
+    #[test]
+    fn rejects_unbalanced_parentheses_and_empty_filters() {
+        assert_eq!(Filter::parse("(a OR b"), Err(FilterError::UnclosedParenthesis));
+        assert_eq!(Filter::parse("a)"), Err(FilterError::Unexpected(")".to_string())));
+        assert_eq!(Filter::parse("()"), Err(FilterError::Unexpected(")".to_string())));
+        assert_eq!(Filter::parse(""), Err(FilterError::Empty));
+        assert_eq!(Filter::parse("  "), Err(FilterError::Empty));
+        assert_eq!(Filter::parse("a AND"), Err(FilterError::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,397 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as FmtWrite;
+use std::sync::atomic::{AtomicU32, Ordering};
+use base64::Engine;
+use image::imageops::FilterType;
+use image::{Rgba, RgbaImage};
+use termion::{cursor, terminal_size, terminal_size_pixels};
+use api::mail::{Attachment, Mailbox};
+use crate::parse::{body_width, try_parse_selected_message};
+use crate::ui::{Backend, Color, Frame, Overlay, Rect, Style, Terminal};
+use crate::{State, Storage};
+
+/// Cell size assumed when the terminal does not report its size in pixels.
+const DEFAULT_CELL_SIZE: (u32, u32) = (8, 16);
+/// Inline attachments larger than this are not downloaded for display.
+const MAX_IMAGE_BYTES: u64 = 10 * 1024 * 1024;
+/// Kitty accepts base64 payloads in chunks of at most this many bytes.
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+static NEXT_KITTY_IMAGE_ID: AtomicU32 = AtomicU32::new(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum GraphicsProtocol {
+    Kitty,
+    Sixel,
+    /// Two pixels per cell drawn with `▀` in true colour, which any terminal can show.
+    HalfBlock,
+}
+
+/// Guesses the best protocol from the environment, as querying the terminal would compete
+/// with the input loop for stdin.
+pub fn detect() -> GraphicsProtocol {
+    let env = |name: &str| std::env::var(name).unwrap_or_default();
+    let term = env("TERM").to_lowercase();
+    let term_program = env("TERM_PROGRAM");
+    // Multiplexers swallow graphics escapes unless configured to pass them through.
+    if std::env::var_os("TMUX").is_some()
+        || term.starts_with("screen")
+        || term.starts_with("tmux") {
+        return GraphicsProtocol::HalfBlock;
+    }
+    if std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || term.contains("kitty")
+        || term.contains("ghostty")
+        || term_program == "ghostty"
+        || term_program == "WezTerm" {
+        return GraphicsProtocol::Kitty;
+    }
+    if term.contains("sixel")
+        || term.starts_with("foot")
+        || term.starts_with("mlterm")
+        || term.starts_with("contour")
+        || term_program == "iTerm.app" {
+        return GraphicsProtocol::Sixel;
+    }
+    GraphicsProtocol::HalfBlock
+}
+
+/// A decoded image, scaled for the protocol in use.
+pub struct InlineImage {
+    pub columns: u16,
+    pub rows: u16,
+    original: RgbaImage,
+    pixels: RgbaImage,
+    /// The maximum size in cells and the cell size in pixels the image was scaled for.
+    fitted_to: ((u16, u16), (u32, u32)),
+    /// Id of the copy transmitted to Kitty, which placements refer to.
+    kitty_id: Option<u32>,
+}
+
+impl InlineImage {
+    fn new(image: RgbaImage, protocol: GraphicsProtocol, bounds: (u16, u16)) -> InlineImage {
+        let mut inline_image = InlineImage {
+            columns: 0,
+            rows: 0,
+            original: image,
+            pixels: RgbaImage::new(0, 0),
+            fitted_to: ((0, 0), (0, 0)),
+            kitty_id: None,
+        };
+        inline_image.fit(protocol, bounds);
+        inline_image
+    }
+
+    /// Scales the image to fit within the given number of columns and rows, never enlarging
+    /// it.
+    fn fit(&mut self, protocol: GraphicsProtocol, (max_columns, max_rows): (u16, u16)) {
+        let (cell_width, cell_height) = get_cell_size();
+        self.fitted_to = ((max_columns, max_rows), (cell_width, cell_height));
+        let image = &self.original;
+        let (width, height) = (image.width().max(1), image.height().max(1));
+        let scale = 1f64
+            .min(max_columns as f64 * cell_width as f64 / width as f64)
+            .min(max_rows as f64 * cell_height as f64 / height as f64);
+        let scaled_width = ((width as f64 * scale) as u32).max(1);
+        let scaled_height = ((height as f64 * scale) as u32).max(1);
+        let columns = scaled_width.div_ceil(cell_width);
+        let rows = scaled_height.div_ceil(cell_height);
+        let (pixel_width, pixel_height) = match protocol {
+            GraphicsProtocol::HalfBlock => (columns, rows * 2),
+            _ => (scaled_width, scaled_height),
+        };
+        self.pixels =
+            image::imageops::resize(image, pixel_width, pixel_height, FilterType::Triangle);
+        self.columns = columns as u16;
+        self.rows = rows as u16;
+    }
+
+    fn is_fitted_to(&self, bounds: (u16, u16)) -> bool {
+        self.fitted_to == (bounds, get_cell_size())
+    }
+
+    /// Draws the image with its top-left cell at the top left of the area, skipping the
+    /// first `skip_rows` rows and drawing at most the height of the area, for images
+    /// scrolled partly off screen.
+    pub fn draw(&self, protocol: GraphicsProtocol, area: Rect, skip_rows: u16, frame: &mut Frame) {
+        let visible_rows = area.height.min(self.rows.saturating_sub(skip_rows));
+        if visible_rows == 0 {
+            return;
+        }
+        let pixel_rows_per_row = self.pixels.height() as f64 / self.rows as f64;
+        let top = (skip_rows as f64 * pixel_rows_per_row) as u32;
+        let bottom = (((skip_rows + visible_rows) as f64 * pixel_rows_per_row) as u32)
+            .min(self.pixels.height());
+        let overlay_area = Rect::new(area.x, area.y, self.columns.min(area.width), visible_rows);
+        match (protocol, self.kitty_id) {
+            (GraphicsProtocol::Kitty, Some(kitty_id)) => frame.overlays.push(Overlay {
+                area: overlay_area,
+                data: format!(
+                    "{}\x1b_Ga=p,i={},y={},h={},C=1,q=2\x1b\\",
+                    cursor::Goto(area.x + 1, area.y + 1),
+                    kitty_id,
+                    top,
+                    bottom - top
+                ),
+            }),
+            (GraphicsProtocol::Sixel, _) => frame.overlays.push(Overlay {
+                area: overlay_area,
+                data: format!(
+                    "{}{}",
+                    cursor::Goto(area.x + 1, area.y + 1),
+                    encode_sixel(&self.pixels, top, bottom)
+                ),
+            }),
+            _ => {
+                let width = self.pixels.width().min(area.width as u32);
+                for row in 0..visible_rows {
+                    let pixel_row = (skip_rows + row) as u32 * 2;
+                    for column in 0..width {
+                        let top_pixel = get_opaque_pixel(&self.pixels, column, pixel_row);
+                        let bottom_pixel = get_opaque_pixel(&self.pixels, column, pixel_row + 1);
+                        let rgb = |pixel: Rgba<u8>| Color::Rgb(pixel[0], pixel[1], pixel[2]);
+                        let (symbol, style) = match (top_pixel, bottom_pixel) {
+                            (Some(top), Some(bottom)) => ("▀", Style {
+                                fg: rgb(top),
+                                bg: rgb(bottom),
+                                ..Style::default()
+                            }),
+                            (Some(top), None) => {
+                                ("▀", Style { fg: rgb(top), ..Style::default() })
+                            }
+                            (None, Some(bottom)) => {
+                                ("▄", Style { fg: rgb(bottom), ..Style::default() })
+                            }
+                            (None, None) => (" ", Style::default()),
+                        };
+                        frame.buffer.set_string(
+                            area.x + column as u16,
+                            area.y + row,
+                            symbol,
+                            &style,
+                            1
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sends the pixels to Kitty once, so that each redraw only places them.
+    fn transmit_to_kitty(&mut self, terminal: &mut Terminal<impl Backend>) {
+        if let Some(kitty_id) = self.kitty_id {
+            // Free the copy at the old size.
+            terminal.write_raw(&format!("\x1b_Ga=d,d=I,i={},q=2\x1b\\", kitty_id));
+        }
+        let kitty_id = NEXT_KITTY_IMAGE_ID.fetch_add(1, Ordering::Relaxed);
+        let payload = base64::engine::general_purpose::STANDARD.encode(self.pixels.as_raw());
+        let chunks: Vec<&[u8]> = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+        let mut output = String::new();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let has_more = if i + 1 < chunks.len() { 1 } else { 0 };
+            if i == 0 {
+                let _ = write!(
+                    output,
+                    "\x1b_Ga=t,f=32,s={},v={},i={},q=2,m={};",
+                    self.pixels.width(),
+                    self.pixels.height(),
+                    kitty_id,
+                    has_more
+                );
+            } else {
+                let _ = write!(output, "\x1b_Gm={};", has_more);
+            }
+            output.push_str(std::str::from_utf8(chunk).unwrap());
+            output.push_str("\x1b\\");
+        }
+        terminal.write_raw(&output);
+        self.kitty_id = Some(kitty_id);
+    }
+}
+
+/// Removes every Kitty image placement from the screen, keeping the image data for reuse.
+pub const CLEAR_KITTY_PLACEMENTS: &str = "\x1b_Ga=d,d=a,q=2\x1b\\";
+
+/// Downloads and decodes the inline images of the pending message, then lays its body out
+/// again with room for them.
+pub async fn run(
+    state: &mut State,
+    storage: &Storage,
+    terminal: &mut Terminal<impl Backend>,
+) {
+    let message_id = match state.pending_inline_images.take() {
+        Some(message_id) => message_id,
+        None => return,
+    };
+    let message = match state.messages().iter().find(|message| message.id == message_id) {
+        Some(message) => message.clone(),
+        None => return,
+    };
+    let mailbox = match storage.get_mailbox_by_id(&message.mailbox_id) {
+        Some(mailbox) => mailbox,
+        None => return,
+    };
+    let bounds = image_bounds(state, terminal);
+    let mut images: HashMap<String, InlineImage> = HashMap::new();
+    for attachment in message.attachments.iter().filter(|attachment| is_inline_image(attachment)) {
+        let mut data: Vec<u8> = vec![];
+        if mailbox.download_attachment(&message.id, &attachment.id, &mut data).await.is_err() {
+            continue;
+        }
+        // Formats other than PNG, JPEG and GIF are not compiled in and fail here.
+        let decoded = match image::load_from_memory(&data) {
+            Ok(decoded) => decoded.into_rgba8(),
+            Err(_) => continue,
+        };
+        let mut image = InlineImage::new(decoded, state.graphics_protocol, bounds);
+        if state.graphics_protocol == GraphicsProtocol::Kitty {
+            image.transmit_to_kitty(terminal);
+        }
+        // Bodies refer to images by Content-ID, which some senders set to the file name.
+        let key = attachment.content_id.clone().unwrap_or_else(|| attachment.name.clone());
+        images.insert(key, image);
+    }
+    state.inline_images.insert(message_id.clone(), images);
+    state.parsed_message_bodies.remove(&message_id);
+    if state.should_view_message_body && state.selected_message().id == message_id {
+        try_parse_selected_message(state);
+    }
+}
+
+/// Scales the decoded images again if the room for them changed, such as when the
+/// terminal is resized or the body moves between the preview pane and the full screen.
+pub fn fit_images(state: &mut State, terminal: &mut Terminal<impl Backend>) {
+    let bounds = image_bounds(state, terminal);
+    let protocol = state.graphics_protocol;
+    let mut refitted_message_ids = vec![];
+    for (message_id, images) in &mut state.inline_images {
+        let mut is_refitted = false;
+        for image in images.values_mut().filter(|image| !image.is_fitted_to(bounds)) {
+            image.fit(protocol, bounds);
+            if protocol == GraphicsProtocol::Kitty {
+                image.transmit_to_kitty(terminal);
+            }
+            is_refitted = true;
+        }
+        if is_refitted {
+            refitted_message_ids.push(message_id.clone());
+        }
+    }
+    // Make room for the images at their new size.
+    for message_id in refitted_message_ids {
+        state.parsed_message_bodies.remove(&message_id);
+    }
+}
+
+/// The most columns and rows an image may take: a little less than the width of the body,
+/// and half the height of the screen.
+fn image_bounds(state: &State, terminal: &Terminal<impl Backend>) -> (u16, u16) {
+    let size = terminal.size();
+    let width = body_width(state).unwrap_or(size.width as usize) as u16;
+    (width.saturating_sub(2).max(1), (size.height / 2).max(1))
+}
+
+fn is_inline_image(attachment: &Attachment) -> bool {
+    attachment.is_inline
+        && attachment.size <= MAX_IMAGE_BYTES
+        && (attachment.content_type.starts_with("image/") || attachment.content_id.is_some())
+}
+
+/// The size of a terminal cell in pixels.
+fn get_cell_size() -> (u32, u32) {
+    match (terminal_size(), terminal_size_pixels()) {
+        // Some terminals report zero pixels rather than failing.
+        (Ok((columns, rows)), Ok((width, height)))
+            if columns > 0 && rows > 0 && width > 0 && height > 0 => (
+            (width / columns).max(1) as u32,
+            (height / rows).max(1) as u32,
+        ),
+        _ => DEFAULT_CELL_SIZE,
+    }
+}
+
+fn get_opaque_pixel(pixels: &RgbaImage, x: u32, y: u32) -> Option<Rgba<u8>> {
+    if y >= pixels.height() {
+        return None;
+    }
+    let pixel = *pixels.get_pixel(x, y);
+    if pixel[3] < 128 {
+        return None;
+    }
+    Some(pixel)
+}
+
+/// Encodes rows `top..bottom` of the image as sixels, with colours reduced to a 6×6×6 cube.
+fn encode_sixel(pixels: &RgbaImage, top: u32, bottom: u32) -> String {
+    let colour_index = |pixel: &Rgba<u8>| {
+        let level = |value: u8| (value as u32 * 5 + 127) / 255;
+        (level(pixel[0]) * 36 + level(pixel[1]) * 6 + level(pixel[2])) as usize
+    };
+    let width = pixels.width();
+    // P2 = 1 leaves transparent pixels showing the background.
+    let mut output = format!("\x1bP0;1;0q\"1;1;{};{}", width, bottom - top);
+    let mut band_top = top;
+    let mut defined_colours: BTreeSet<usize> = BTreeSet::new();
+    while band_top < bottom {
+        let band_bottom = (band_top + 6).min(bottom);
+        let mut band_colours: BTreeSet<usize> = BTreeSet::new();
+        for y in band_top..band_bottom {
+            for x in 0..width {
+                if let Some(pixel) = get_opaque_pixel(pixels, x, y) {
+                    band_colours.insert(colour_index(&pixel));
+                }
+            }
+        }
+        for colour in &band_colours {
+            if defined_colours.insert(*colour) {
+                let percent = |level: usize| level * 100 / 5;
+                let _ = write!(
+                    output,
+                    "#{};2;{};{};{}",
+                    colour,
+                    percent(colour / 36),
+                    percent(colour / 6 % 6),
+                    percent(colour % 6)
+                );
+            }
+            let _ = write!(output, "#{}", colour);
+            let mut run: Option<(char, usize)> = None;
+            for x in 0..width {
+                let mut bits = 0;
+                for y in band_top..band_bottom {
+                    let is_colour = get_opaque_pixel(pixels, x, y)
+                        .map(|pixel| colour_index(&pixel) == *colour)
+                        .unwrap_or(false);
+                    if is_colour {
+                        bits |= 1 << (y - band_top);
+                    }
+                }
+                let sixel = (63 + bits) as u8 as char;
+                run = match run {
+                    Some((run_sixel, length)) if run_sixel == sixel => Some((sixel, length + 1)),
+                    Some((run_sixel, length)) => {
+                        push_sixel_run(&mut output, run_sixel, length);
+                        Some((sixel, 1))
+                    }
+                    None => Some((sixel, 1)),
+                };
+            }
+            if let Some((run_sixel, length)) = run {
+                push_sixel_run(&mut output, run_sixel, length);
+            }
+            output.push('$');
+        }
+        output.push('-');
+        band_top = band_bottom;
+    }
+    output.push_str("\x1b\\");
+    output
+}
+
+fn push_sixel_run(output: &mut String, sixel: char, length: usize) {
+    if length > 3 {
+        let _ = write!(output, "!{}{}", length, sixel);
+    } else {
+        output.extend(std::iter::repeat_n(sixel, length));
+    }
+}
@@ -0,0 +1,993 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use html5ever::driver::ParseOpts;
+use html5ever::parse_document;
+use html5ever::tendril::TendrilSink;
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use termion::style;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::width::{grapheme_width, str_width};
+use crate::link::{hyperlink, unwrap_redirect};
+
+/// Columns of a data table are separated by this, and its rows by rules crossing it.
+const COLUMN_SEPARATOR: &str = " │ ";
+/// Width used to measure the natural, unwrapped width of table cells.
+const UNBOUNDED_WIDTH: usize = 10000;
+/// Reserves the cells an image is drawn over. Unlike a space, it is neither collapsed nor
+/// trimmed, and it still looks blank before the image is drawn.
+const IMAGE_PLACEHOLDER: char = '\u{2800}';
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    /// Index of the link target in [`Document::links`].
+    link: Option<usize>,
+    /// Index of the image in [`Builder::images`], set on the first row of its placeholder.
+    image: Option<usize>,
+}
+
+#[derive(Clone)]
+struct Span {
+    text: String,
+    style: Style,
+}
+
+type Line = Vec<Span>;
+
+enum Block {
+    Text {
+        spans: Vec<Span>,
+        is_preformatted: bool,
+    },
+    Quote(Vec<Block>),
+    List {
+        /// The number of the first item, or None for a bulleted list.
+        start: Option<usize>,
+        items: Vec<Vec<Block>>,
+    },
+    Table(Table),
+    Image {
+        index: usize,
+        columns: u16,
+        rows: u16,
+    },
+    Rule,
+    /// A blank line, collapsed with its neighbours and dropped at either end.
+    Gap,
+}
+
+struct Table {
+    rows: Vec<Row>,
+}
+
+struct Row {
+    cells: Vec<Cell>,
+    is_header: bool,
+}
+
+struct Cell {
+    blocks: Vec<Block>,
+    colspan: usize,
+}
+
+/// An HTML body laid out for a terminal of a given width.
+pub struct Document {
+    lines: Vec<Line>,
+    /// Link targets in footnote order, so that `[n]` in the text refers to `links[n - 1]`.
+    pub links: Vec<String>,
+    pub images: Vec<ImagePlacement>,
+}
+
+/// Where an inline image goes in the laid out text.
+pub struct ImagePlacement {
+    pub content_id: String,
+    /// Index of the line holding the top row of the image.
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Document {
+    /// The text with bold, italic and underline as terminal attributes and links as OSC 8
+    /// hyperlinks, lines separated by `\r\n`.
+    pub fn styled(&self) -> String {
+        let mut text = String::new();
+        for (i, line) in self.lines.iter().enumerate() {
+            if i > 0 {
+                text.push_str("\r\n");
+            }
+            let mut current = Style::default();
+            for span in line {
+                if span.style != current {
+                    if current != Style::default() {
+                        let _ = write!(text, "{}", style::Reset);
+                    }
+                    if span.style.bold {
+                        let _ = write!(text, "{}", style::Bold);
+                    }
+                    if span.style.italic {
+                        let _ = write!(text, "{}", style::Italic);
+                    }
+                    if span.style.underline {
+                        let _ = write!(text, "{}", style::Underline);
+                    }
+                    current = span.style;
+                }
+                match span.style.link {
+                    Some(link) => text.push_str(&hyperlink(&self.links[link], &span.text)),
+                    None => text.push_str(&span.text),
+                }
+            }
+            if current != Style::default() {
+                let _ = write!(text, "{}", style::Reset);
+            }
+        }
+        text
+    }
+
+    /// The text without terminal attributes.
+    pub fn plain(&self) -> String {
+        self.lines.iter()
+            .map(|line| line.iter().map(|span| span.text.as_str()).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\r\n")
+    }
+}
+
+/// Lays out an HTML document for the given width. Data tables are drawn as a grid, tables
+/// used only for positioning are read as a single column, and links become numbered
+/// footnotes listed at the end. Images whose size in cells is given, keyed by Content-ID,
+/// are left room for; others are replaced by their alt text.
+pub fn render(html: &str, width: usize, image_sizes: &HashMap<String, (u16, u16)>) -> Document {
+    let dom = parse_document(RcDom::default(), ParseOpts::default())
+        .from_utf8()
+        .read_from(&mut html.as_bytes())
+        .unwrap_or_default();
+    let mut builder = Builder {
+        links: vec![],
+        images: vec![],
+        image_sizes,
+        list_depth: 0,
+    };
+    let mut blocks = vec![];
+    let mut inline = vec![];
+    builder.collect(&dom.document, Style::default(), false, &mut blocks, &mut inline);
+    flush(&mut blocks, &mut inline, false);
+    if !builder.links.is_empty() {
+        blocks.push(Block::Gap);
+        for (i, link) in builder.links.iter().enumerate() {
+            blocks.push(Block::Text {
+                spans: vec![
+                    Span {
+                        text: format!("[{}] ", i + 1),
+                        style: Style::default(),
+                    },
+                    Span {
+                        text: link.clone(),
+                        style: Style { link: Some(i), ..Style::default() },
+                    },
+                ],
+                is_preformatted: false,
+            });
+        }
+    }
+    let lines = render_blocks(&blocks, width.max(1));
+    let mut images = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        let mut column = 0;
+        for span in line {
+            if let Some(image) = span.style.image {
+                images.push(ImagePlacement {
+                    content_id: builder.images[image].clone(),
+                    line: i,
+                    column,
+                });
+            }
+            column += str_width(&span.text);
+        }
+    }
+    Document {
+        lines,
+        links: builder.links,
+        images,
+    }
+}
+
+struct Builder<'a> {
+    links: Vec<String>,
+    /// Content-IDs of the images left room for, in order of appearance.
+    images: Vec<String>,
+    image_sizes: &'a HashMap<String, (u16, u16)>,
+    /// Lists nested inside a list item are not separated by blank lines.
+    list_depth: usize,
+}
+
+impl Builder<'_> {
+    /// Appends the content of `node` to `blocks`, gathering inline content in `inline` until
+    /// a block element ends the current paragraph.
+    fn collect(
+        &mut self,
+        node: &Handle,
+        style: Style,
+        is_preformatted: bool,
+        blocks: &mut Vec<Block>,
+        inline: &mut Vec<Span>,
+    ) {
+        let (name, attrs) = match &node.data {
+            NodeData::Document => {
+                self.collect_children(node, style, is_preformatted, blocks, inline);
+                return;
+            }
+            NodeData::Text { contents } => {
+                let text = contents.borrow().to_string();
+                // Outside <pre>, line breaks in the source are just whitespace.
+                let text = if is_preformatted {
+                    text
+                } else {
+                    text.replace(['\n', '\r', '\t'], " ")
+                };
+                inline.push(Span { text, style });
+                return;
+            }
+            NodeData::Element { name, attrs, .. } => (name.local.to_string(), attrs),
+            _ => return,
+        };
+        let attr = |attr_name: &str| attrs.borrow().iter()
+            .find(|attr| attr.name.local.as_ref() == attr_name)
+            .map(|attr| attr.value.to_string());
+        match name.as_str() {
+            "head" | "script" | "style" | "title" | "template" | "noscript" => (),
+            "br" => inline.push(Span {
+                text: "\n".to_string(),
+                style,
+            }),
+            "b" | "strong" => {
+                let style = Style { bold: true, ..style };
+                self.collect_children(node, style, is_preformatted, blocks, inline);
+            }
+            "i" | "em" | "cite" | "var" => {
+                let style = Style { italic: true, ..style };
+                self.collect_children(node, style, is_preformatted, blocks, inline);
+            }
+            "u" | "ins" => {
+                let style = Style { underline: true, ..style };
+                self.collect_children(node, style, is_preformatted, blocks, inline);
+            }
+            "a" => {
+                let href = attr("href").map(|href| href.trim().to_string()).unwrap_or_default();
+                let is_link = !href.is_empty()
+                    && !href.starts_with('#')
+                    && !href.to_lowercase().starts_with("javascript:");
+                if !is_link {
+                    self.collect_children(node, style, is_preformatted, blocks, inline);
+                    return;
+                }
+                let href = unwrap_redirect(&href);
+                let index = match self.links.iter().position(|link| *link == href) {
+                    Some(index) => index,
+                    None => {
+                        self.links.push(href);
+                        self.links.len() - 1
+                    }
+                };
+                let link_style = Style { underline: true, link: Some(index), ..style };
+                self.collect_children(node, link_style, is_preformatted, blocks, inline);
+                inline.push(Span {
+                    text: format!("[{}]", index + 1),
+                    style,
+                });
+            }
+            "img" => {
+                // Senders set the Content-ID to anything from a bare file name to a
+                // `name@domain` id, and reference either.
+                let image = attr("src")
+                    .filter(|src| {
+                        src.get(..4).is_some_and(|scheme| scheme.eq_ignore_ascii_case("cid:"))
+                    })
+                    .and_then(|src| {
+                        let content_id = src[4..].trim().to_string();
+                        let name = content_id.split('@').next().unwrap_or_default().to_string();
+                        vec![content_id, name].into_iter()
+                            .find_map(|key| self.image_sizes.get(&key).map(|size| (key, *size)))
+                    });
+                match image {
+                    Some((content_id, (columns, rows))) => {
+                        flush(blocks, inline, is_preformatted);
+                        self.images.push(content_id);
+                        blocks.push(Block::Image {
+                            index: self.images.len() - 1,
+                            columns,
+                            rows,
+                        });
+                    }
+                    None => {
+                        if let Some(alt) = attr("alt").filter(|alt| !alt.trim().is_empty()) {
+                            inline.push(Span {
+                                text: format!("[{}]", alt.trim()),
+                                style,
+                            });
+                        }
+                    }
+                }
+            }
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let style = Style {
+                    bold: true,
+                    underline: name == "h1" || style.underline,
+                    ..style
+                };
+                flush(blocks, inline, is_preformatted);
+                blocks.push(Block::Gap);
+                self.collect_children(node, style, is_preformatted, blocks, inline);
+                flush(blocks, inline, is_preformatted);
+                blocks.push(Block::Gap);
+            }
+            "pre" | "listing" | "xmp" => {
+                flush(blocks, inline, is_preformatted);
+                blocks.push(Block::Gap);
+                self.collect_children(node, style, true, blocks, inline);
+                flush(blocks, inline, true);
+                blocks.push(Block::Gap);
+            }
+            "blockquote" => {
+                flush(blocks, inline, is_preformatted);
+                let mut quote_blocks = vec![];
+                let mut quote_inline = vec![];
+                self.collect_children(
+                    node,
+                    style,
+                    is_preformatted,
+                    &mut quote_blocks,
+                    &mut quote_inline
+                );
+                flush(&mut quote_blocks, &mut quote_inline, is_preformatted);
+                blocks.push(Block::Gap);
+                blocks.push(Block::Quote(quote_blocks));
+                blocks.push(Block::Gap);
+            }
+            "ul" | "ol" | "menu" | "dir" => {
+                flush(blocks, inline, is_preformatted);
+                let start = if name == "ol" {
+                    Some(attr("start").and_then(|start| start.trim().parse().ok()).unwrap_or(1))
+                } else {
+                    None
+                };
+                self.list_depth += 1;
+                let mut items = vec![];
+                for child in node.children.borrow().iter() {
+                    let mut item_blocks = vec![];
+                    let mut item_inline = vec![];
+                    self.collect(child, style, is_preformatted, &mut item_blocks, &mut item_inline);
+                    flush(&mut item_blocks, &mut item_inline, is_preformatted);
+                    // Whitespace between items yields nothing and is not an item.
+                    if !item_blocks.is_empty() || is_element(child, "li") {
+                        items.push(item_blocks);
+                    }
+                }
+                self.list_depth -= 1;
+                let is_nested = self.list_depth > 0;
+                if !is_nested {
+                    blocks.push(Block::Gap);
+                }
+                blocks.push(Block::List { start, items });
+                if !is_nested {
+                    blocks.push(Block::Gap);
+                }
+            }
+            "hr" => {
+                flush(blocks, inline, is_preformatted);
+                blocks.push(Block::Rule);
+            }
+            "table" => {
+                flush(blocks, inline, is_preformatted);
+                let is_presentation = attr("role")
+                    .map(|role| role == "presentation" || role == "none")
+                    .unwrap_or(false);
+                let mut rows = vec![];
+                self.collect_rows(node, style, &mut rows);
+                let table = Table { rows };
+                if is_presentation || !table.is_data() {
+                    // Layout tables read top to bottom, left to right.
+                    for row in table.rows {
+                        for cell in row.cells {
+                            blocks.extend(cell.blocks);
+                        }
+                    }
+                } else {
+                    blocks.push(Block::Gap);
+                    blocks.push(Block::Table(table));
+                    blocks.push(Block::Gap);
+                }
+            }
+            "p" | "dl" | "figure" | "address" => {
+                flush(blocks, inline, is_preformatted);
+                blocks.push(Block::Gap);
+                self.collect_children(node, style, is_preformatted, blocks, inline);
+                flush(blocks, inline, is_preformatted);
+                blocks.push(Block::Gap);
+            }
+            "div" | "li" | "dt" | "dd" | "section" | "article" | "header" | "footer" | "nav"
+            | "aside" | "main" | "center" | "figcaption" | "form" | "fieldset" | "details"
+            | "summary" | "tr" | "td" | "th" | "caption" | "tbody" | "thead" | "tfoot" => {
+                flush(blocks, inline, is_preformatted);
+                self.collect_children(node, style, is_preformatted, blocks, inline);
+                flush(blocks, inline, is_preformatted);
+            }
+            _ => self.collect_children(node, style, is_preformatted, blocks, inline),
+        }
+    }
+
+    fn collect_children(
+        &mut self,
+        node: &Handle,
+        style: Style,
+        is_preformatted: bool,
+        blocks: &mut Vec<Block>,
+        inline: &mut Vec<Span>,
+    ) {
+        for child in node.children.borrow().iter() {
+            self.collect(child, style, is_preformatted, blocks, inline);
+        }
+    }
+
+    /// Collects the rows of a table through thead, tbody and tfoot, but not through nested
+    /// tables, whose rows are their own.
+    fn collect_rows(&mut self, node: &Handle, style: Style, rows: &mut Vec<Row>) {
+        for child in node.children.borrow().iter() {
+            if is_element(child, "tr") {
+                let mut cells = vec![];
+                let mut is_header = true;
+                for cell_node in child.children.borrow().iter() {
+                    let is_header_cell = is_element(cell_node, "th");
+                    if !is_header_cell && !is_element(cell_node, "td") {
+                        continue;
+                    }
+                    is_header &= is_header_cell;
+                    let cell_style = Style { bold: style.bold || is_header_cell, ..style };
+                    let mut blocks = vec![];
+                    let mut inline = vec![];
+                    self.collect_children(cell_node, cell_style, false, &mut blocks, &mut inline);
+                    flush(&mut blocks, &mut inline, false);
+                    let colspan = get_attr(cell_node, "colspan")
+                        .and_then(|colspan| colspan.trim().parse::<usize>().ok())
+                        .unwrap_or(1)
+                        .max(1);
+                    cells.push(Cell { blocks, colspan });
+                }
+                rows.push(Row {
+                    is_header: is_header && !cells.is_empty(),
+                    cells,
+                });
+            } else if is_element(child, "thead")
+                || is_element(child, "tbody")
+                || is_element(child, "tfoot") {
+                self.collect_rows(child, style, rows);
+            }
+        }
+    }
+}
+
+impl Table {
+    fn column_count(&self) -> usize {
+        self.rows.iter()
+            .map(|row| row.cells.iter().map(|cell| cell.colspan).sum::<usize>())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Whether the table holds tabular data rather than positioning the content of a
+    /// newsletter. Data cells are short runs of text; layout cells hold whole sections.
+    fn is_data(&self) -> bool {
+        let non_empty_rows = self.rows.iter()
+            .filter(|row| row.cells.iter().any(|cell| !cell.blocks.is_empty()))
+            .count();
+        if self.column_count() < 2 || non_empty_rows < 2 {
+            return false;
+        }
+        self.rows.iter().flat_map(|row| row.cells.iter()).all(|cell| {
+            let content_blocks = cell.blocks.iter()
+                .filter(|block| !matches!(block, Block::Gap))
+                .count();
+            content_blocks <= 1 && cell.blocks.iter().all(|block| matches!(
+                block,
+                Block::Text { is_preformatted: false, .. } | Block::Gap
+            ))
+        })
+    }
+}
+
+fn is_element(node: &Handle, element_name: &str) -> bool {
+    match &node.data {
+        NodeData::Element { name, .. } => name.local.as_ref() == element_name,
+        _ => false,
+    }
+}
+
+fn get_attr(node: &Handle, attr_name: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs.borrow().iter()
+            .find(|attr| attr.name.local.as_ref() == attr_name)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+/// Ends the current paragraph, unless it is only whitespace.
+fn flush(blocks: &mut Vec<Block>, inline: &mut Vec<Span>, is_preformatted: bool) {
+    let spans = std::mem::take(inline);
+    let has_text = spans.iter()
+        .any(|span| span.text.chars().any(|c| !is_space(c) && !is_invisible(c)));
+    if has_text {
+        blocks.push(Block::Text { spans, is_preformatted });
+    }
+}
+
+/// Breaking whitespace. Non-breaking spaces are kept inside words.
+fn is_space(c: char) -> bool {
+    c.is_whitespace() && c != '\u{a0}'
+}
+
+/// Zero-width characters that newsletters use to pad their preview text.
+fn is_invisible(c: char) -> bool {
+    matches!(c, '\u{200b}'..='\u{200d}' | '\u{feff}' | '\u{34f}' | '\u{ad}' | '\u{2060}')
+}
+
+fn render_blocks(blocks: &[Block], width: usize) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![];
+    let mut has_pending_gap = false;
+    for block in blocks {
+        let block_lines = match block {
+            Block::Gap => {
+                has_pending_gap = true;
+                continue;
+            }
+            Block::Text { spans, is_preformatted: true } => wrap_preformatted(spans, width),
+            Block::Text { spans, is_preformatted: false } => wrap(spans, width),
+            Block::Quote(quote_blocks) => {
+                let prefix_width = 2.min(width.saturating_sub(1));
+                render_blocks(quote_blocks, width - prefix_width).into_iter()
+                    .map(|line| prefix_line(line, &"> "[..prefix_width]))
+                    .collect()
+            }
+            Block::List { start, items } => render_list(*start, items, width),
+            Block::Table(table) => render_table(table, width)
+                .unwrap_or_else(|| render_table_linear(table, width)),
+            Block::Image { index, columns, rows } => {
+                let placeholder = Span {
+                    text: IMAGE_PLACEHOLDER.to_string().repeat((*columns as usize).min(width)),
+                    style: Style { image: Some(*index), ..Style::default() },
+                };
+                let mut lines = vec![vec![placeholder]];
+                lines.resize((*rows as usize).max(1), vec![]);
+                lines
+            }
+            Block::Rule => vec![vec![Span {
+                text: "─".repeat(width),
+                style: Style::default(),
+            }]],
+        };
+        if block_lines.is_empty() {
+            continue;
+        }
+        if has_pending_gap && !lines.is_empty() {
+            lines.push(vec![]);
+        }
+        has_pending_gap = false;
+        lines.extend(block_lines);
+    }
+    lines
+}
+
+fn render_list(start: Option<usize>, items: &[Vec<Block>], width: usize) -> Vec<Line> {
+    let markers: Vec<String> = (0..items.len())
+        .map(|i| match start {
+            Some(start) => format!("{}. ", start + i),
+            None => "• ".to_string(),
+        })
+        .collect();
+    let indent = markers.iter()
+        .map(|marker| str_width(marker))
+        .max()
+        .unwrap_or(0)
+        .min(width.saturating_sub(1));
+    let mut lines = vec![];
+    for (item, marker) in items.iter().zip(markers) {
+        let item_lines = render_blocks(item, width - indent);
+        let marker = format!("{:>indent$}", marker, indent = indent);
+        if item_lines.is_empty() {
+            lines.push(vec![Span {
+                text: marker.trim_end().to_string(),
+                style: Style::default(),
+            }]);
+            continue;
+        }
+        for (i, line) in item_lines.into_iter().enumerate() {
+            let prefix = if i == 0 { marker.clone() } else { " ".repeat(indent) };
+            lines.push(prefix_line(line, &prefix));
+        }
+    }
+    lines
+}
+
+fn render_table_linear(table: &Table, width: usize) -> Vec<Line> {
+    let gap = Block::Gap;
+    let mut blocks: Vec<&Block> = vec![];
+    for row in &table.rows {
+        for cell in &row.cells {
+            blocks.extend(cell.blocks.iter());
+        }
+        blocks.push(&gap);
+    }
+    let mut lines = vec![];
+    for block in blocks {
+        match block {
+            Block::Gap => {
+                if lines.last().is_some_and(|line: &Line| !line.is_empty()) {
+                    lines.push(vec![]);
+                }
+            }
+            block => lines.extend(render_blocks(std::slice::from_ref(block), width)),
+        }
+    }
+    if lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Draws a data table as a grid, or returns None when its columns cannot fit the width.
+fn render_table(table: &Table, width: usize) -> Option<Vec<Line>> {
+    let column_count = table.column_count();
+    let separator_width = str_width(COLUMN_SEPARATOR) * (column_count - 1);
+    let available_width = width.checked_sub(separator_width)?;
+    // Minimum (longest word) and natural (longest line) widths of each column, measured
+    // from cells spanning a single column.
+    let mut minimum_widths = vec![1; column_count];
+    let mut natural_widths = vec![1; column_count];
+    for row in &table.rows {
+        let mut column = 0;
+        for cell in &row.cells {
+            if cell.colspan == 1 {
+                let lines = render_blocks(&cell.blocks, UNBOUNDED_WIDTH);
+                for line in &lines {
+                    let text: String = line.iter().map(|span| span.text.as_str()).collect();
+                    natural_widths[column] = natural_widths[column].max(str_width(&text));
+                    let longest_word = text.split(' ').map(str_width).max().unwrap_or(0);
+                    minimum_widths[column] = minimum_widths[column].max(longest_word);
+                }
+            }
+            column += cell.colspan;
+        }
+    }
+    let minimum_width: usize = minimum_widths.iter().sum();
+    let natural_width: usize = natural_widths.iter().sum();
+    if minimum_width > available_width {
+        return None;
+    }
+    let widths: Vec<usize> = if natural_width <= available_width {
+        natural_widths
+    } else {
+        // Share the remaining width in proportion to how much each column wants to grow.
+        let extra_width = available_width - minimum_width;
+        let wanted_width = natural_width - minimum_width;
+        minimum_widths.iter().zip(&natural_widths)
+            .map(|(minimum, natural)| minimum + extra_width * (natural - minimum) / wanted_width)
+            .collect()
+    };
+    let mut rendered_rows: Vec<(Vec<Line>, bool)> = vec![];
+    for row in &table.rows {
+        let mut cell_lines: Vec<(Vec<Line>, usize)> = vec![];
+        let mut column = 0;
+        for cell in &row.cells {
+            let end = (column + cell.colspan).min(column_count);
+            let cell_width = widths[column..end].iter().sum::<usize>()
+                + str_width(COLUMN_SEPARATOR) * (end - column - 1);
+            cell_lines.push((render_blocks(&cell.blocks, cell_width.max(1)), cell_width));
+            column = end;
+        }
+        // Rows with fewer cells are padded so the separators still line up.
+        while column < column_count {
+            cell_lines.push((vec![], widths[column]));
+            column += 1;
+        }
+        let height = cell_lines.iter().map(|(lines, _)| lines.len()).max().unwrap_or(0).max(1);
+        let mut lines = vec![];
+        for i in 0..height {
+            let mut line: Line = vec![];
+            for (j, (lines, cell_width)) in cell_lines.iter().enumerate() {
+                if j > 0 {
+                    line.push(Span {
+                        text: COLUMN_SEPARATOR.to_string(),
+                        style: Style::default(),
+                    });
+                }
+                let cell_line = lines.get(i).cloned().unwrap_or_default();
+                let padding = cell_width.saturating_sub(line_width(&cell_line));
+                line.extend(cell_line);
+                if j + 1 < cell_lines.len() && padding > 0 {
+                    line.push(Span {
+                        text: " ".repeat(padding),
+                        style: Style::default(),
+                    });
+                }
+            }
+            lines.push(trim_line_end(line));
+        }
+        rendered_rows.push((lines, row.is_header));
+    }
+    let rule: Line = vec![Span {
+        text: widths.iter()
+            .map(|width| "─".repeat(*width))
+            .collect::<Vec<String>>()
+            .join(&COLUMN_SEPARATOR.replace(' ', "─").replace('│', "┼")),
+        style: Style::default(),
+    }];
+    // Rows spanning several lines are hard to tell apart without a rule between them.
+    let has_tall_rows = rendered_rows.iter().any(|(lines, _)| lines.len() > 1);
+    let mut lines = vec![];
+    for (i, (row_lines, is_header)) in rendered_rows.iter().enumerate() {
+        if i > 0 {
+            let previous_is_header = rendered_rows[i - 1].1;
+            if has_tall_rows || (previous_is_header && !is_header) {
+                lines.push(rule.clone());
+            }
+        }
+        lines.extend(row_lines.iter().cloned());
+    }
+    Some(lines)
+}
+
+/// Word-wraps inline content, collapsing whitespace as a browser would.
+fn wrap(spans: &[Span], width: usize) -> Vec<Line> {
+    enum Token {
+        Word(Line),
+        Space,
+        Break,
+    }
+    let mut tokens: Vec<Token> = vec![];
+    for span in spans {
+        for c in span.text.chars() {
+            if c == '\n' {
+                tokens.push(Token::Break);
+            } else if is_space(c) {
+                if !matches!(tokens.last(), Some(Token::Space)) {
+                    tokens.push(Token::Space);
+                }
+            } else if !is_invisible(c) {
+                let c = if c == '\u{a0}' { ' ' } else { c };
+                match tokens.last_mut() {
+                    Some(Token::Word(word)) => push_char(word, c, span.style),
+                    _ => {
+                        let mut word = vec![];
+                        push_char(&mut word, c, span.style);
+                        tokens.push(Token::Word(word));
+                    }
+                }
+            }
+        }
+    }
+    let mut lines: Vec<Line> = vec![];
+    let mut line: Line = vec![];
+    let mut current_width = 0;
+    let mut has_pending_space = false;
+    for token in tokens {
+        match token {
+            Token::Space => has_pending_space = current_width > 0,
+            Token::Break => {
+                lines.push(std::mem::take(&mut line));
+                current_width = 0;
+                has_pending_space = false;
+            }
+            Token::Word(word) => {
+                let word_width = line_width(&word);
+                if current_width > 0 && current_width + 1 + word_width > width {
+                    lines.push(std::mem::take(&mut line));
+                    current_width = 0;
+                } else if has_pending_space {
+                    push_char(&mut line, ' ', Style::default());
+                    current_width += 1;
+                }
+                has_pending_space = false;
+                if word_width <= width {
+                    line.extend(word);
+                    current_width += word_width;
+                    continue;
+                }
+                // Words wider than the line are broken wherever they reach the edge.
+                for span in word {
+                    for grapheme in span.text.graphemes(true) {
+                        let grapheme_width = grapheme_width(grapheme);
+                        if current_width + grapheme_width > width && current_width > 0 {
+                            lines.push(std::mem::take(&mut line));
+                            current_width = 0;
+                        }
+                        push_str(&mut line, grapheme, span.style);
+                        current_width += grapheme_width;
+                    }
+                }
+            }
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    // Leading breaks, such as a <br> opening a cell, would show as blank lines.
+    while lines.first().is_some_and(|line| line.is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+/// Keeps the spacing and line breaks of preformatted text, breaking only lines that are
+/// wider than the terminal.
+fn wrap_preformatted(spans: &[Span], width: usize) -> Vec<Line> {
+    let mut lines: Vec<Line> = vec![vec![]];
+    let mut current_width = 0;
+    for span in spans {
+        for grapheme in span.text.graphemes(true) {
+            match grapheme {
+                "\r" => (),
+                "\n" | "\r\n" => {
+                    lines.push(vec![]);
+                    current_width = 0;
+                }
+                grapheme => {
+                    // Tabs are expanded to the next multiple of eight columns.
+                    let (grapheme, count) = if grapheme == "\t" {
+                        (" ", 8 - current_width % 8)
+                    } else {
+                        (grapheme, 1)
+                    };
+                    for _ in 0..count {
+                        let grapheme_width = grapheme_width(grapheme);
+                        if current_width + grapheme_width > width && current_width > 0 {
+                            lines.push(vec![]);
+                            current_width = 0;
+                        }
+                        push_str(lines.last_mut().unwrap(), grapheme, span.style);
+                        current_width += grapheme_width;
+                    }
+                }
+            }
+        }
+    }
+    while lines.first().is_some_and(|line| line.is_empty()) {
+        lines.remove(0);
+    }
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn push_char(line: &mut Line, c: char, style: Style) {
+    push_str(line, c.encode_utf8(&mut [0; 4]), style);
+}
+
+fn push_str(line: &mut Line, text: &str, style: Style) {
+    match line.last_mut() {
+        Some(span) if span.style == style => span.text.push_str(text),
+        _ => line.push(Span {
+            text: text.to_string(),
+            style,
+        }),
+    }
+}
+
+fn prefix_line(line: Line, prefix: &str) -> Line {
+    let mut prefixed = vec![Span {
+        text: prefix.to_string(),
+        style: Style::default(),
+    }];
+    prefixed.extend(line);
+    trim_line_end(prefixed)
+}
+
+fn trim_line_end(mut line: Line) -> Line {
+    while let Some(span) = line.last_mut() {
+        let trimmed_length = span.text.trim_end_matches(' ').len();
+        span.text.truncate(trimmed_length);
+        if !span.text.is_empty() {
+            break;
+        }
+        line.pop();
+    }
+    line
+}
+
+fn line_width(line: &Line) -> usize {
+    line.iter().map(|span| str_width(&span.text)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn places_cid_images_and_skips_other_sources() {
+        let image_sizes = HashMap::from([("logo".to_string(), (4, 2))]);
+        // Byte 4 of "ci€:" falls inside the "€", where slicing the string would panic.
+        let document = render(
+            r#"<img src="ci€:logo" alt="a"><img src="CID:logo@example.com"><img src="cid:">"#,
+            80,
+            &image_sizes,
+        );
+        let content_ids: Vec<&str> = document.images.iter()
+            .map(|image| image.content_id.as_str())
+            .collect();
+        assert_eq!(content_ids, ["logo"]);
+    }
+
+    fn plain(html: &str, width: usize) -> String {
+        render(html, width, &HashMap::new()).plain()
+    }
+
+    #[test]
+    fn draws_data_tables_as_a_grid_with_colspans() {
+        let html = "<table>
+            <tr><th>Item</th><th>Qty</th><th>Price</th></tr>
+            <tr><td>Tea</td><td>2</td><td>£3</td></tr>
+            <tr><td colspan=2>Total</td><td>£6</td></tr>
+        </table>";
+        assert_eq!(plain(html, 40), [
+            "Item │ Qty │ Price",
+            "─────┼─────┼──────",
+            "Tea  │ 2   │ £3",
+            "Total      │ £6",
+        ].join("\r\n"));
+    }
+
+    #[test]
+    fn reads_layout_tables_as_a_single_column() {
+        // A cell holding several paragraphs positions content rather than holding data.
+        let html = "<table><tr>
+            <td><p>Our news</p><p>This month we moved.</p></td>
+            <td><p>Events</p></td>
+        </tr></table>";
+        assert_eq!(plain(html, 40), "Our news\r\n\r\nThis month we moved.\r\n\r\nEvents");
+    }
+
+    #[test]
+    fn reads_tables_too_wide_for_the_width_linearly() {
+        let html = "<table>
+            <tr><td>Supercalifragilistic</td><td>Antidisestablishment</td></tr>
+            <tr><td>a</td><td>b</td></tr>
+        </table>";
+        assert_eq!(
+            plain(html, 30),
+            "Supercalifragilistic\r\nAntidisestablishment\r\n\r\na\r\nb"
+        );
+    }
+
+    #[test]
+    fn numbers_lists_from_their_start() {
+        let html = "<ol start=9><li>Nine</li><li>Ten</li></ol><ul><li>Dot</li></ul>";
+        assert_eq!(plain(html, 40), " 9. Nine\r\n10. Ten\r\n\r\n• Dot");
+    }
+
+    #[test]
+    fn prefixes_quoted_lines() {
+        let html = "<p>Sounds good.</p><blockquote><p>Lunch?</p><blockquote>Noon</blockquote>\
+            </blockquote>";
+        assert_eq!(plain(html, 40), "Sounds good.\r\n\r\n> Lunch?\r\n>\r\n> > Noon");
+    }
+
+    #[test]
+    fn numbers_each_link_once_and_drops_scripts_and_anchors() {
+        let html = r##"<a href="https://example.com/a">one</a>
+            <a href="javascript:void(0)">script</a> <a href="#top">top</a>
+            <a href="https://example.com/b">two</a> <a href="https://example.com/a">again</a>"##;
+        let document = render(html, 80, &HashMap::new());
+        assert_eq!(document.links, ["https://example.com/a", "https://example.com/b"]);
+        assert_eq!(document.plain(), [
+            "one[1] script top two[2] again[1]",
+            "",
+            "[1] https://example.com/a",
+            "[2] https://example.com/b",
+        ].join("\r\n"));
+    }
+}
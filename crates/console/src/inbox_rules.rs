@@ -0,0 +1,515 @@
+use termion::event::Key;
+use api::mail::{Mailbox, Message};
+use api::outlook::rules::{MailFolder, MessageRule};
+use api::thread::normalise_subject;
+use crate::input::lookup_key;
+use crate::keymap::{Action, Context};
+use crate::ui::{Backend, Terminal};
+use crate::{render, storage, State, Storage};
+
+/// The rules an Outlook account runs on the server, shown instead of the messages while open.
+pub struct InboxRulesScreen {
+    /// The account in storage whose rules are shown.
+    pub mailbox_id: String,
+    pub mailbox_name: String,
+    /// In the order they run. Empty until loaded.
+    pub rules: Vec<MessageRule>,
+    /// The account's folders, to name where rules move messages.
+    pub folders: Vec<MailFolder>,
+    pub selected_index: usize,
+    pub mode: InboxRulesMode,
+}
+
+pub enum InboxRulesMode {
+    List,
+    Edit(Box<RuleForm>),
+    /// Waiting for a yes before deleting the selected rule.
+    ConfirmDelete,
+}
+
+/// A rule being written, as text for each of `RULE_FIELDS`.
+pub struct RuleForm {
+    /// The rule being edited, whose conditions and actions not in the form are kept, or
+    /// `None` for a new rule.
+    pub rule: Option<MessageRule>,
+    pub values: Vec<String>,
+    pub selected_index: usize,
+    /// Whether keys are typed into the selected field.
+    pub is_editing: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum RuleField {
+    Name,
+    From,
+    SenderContains,
+    SubjectContains,
+    BodyContains,
+    HasAttachments,
+    ExceptFrom,
+    ExceptSubjectContains,
+    ExceptBodyContains,
+    MarkRead,
+    MoveTo,
+    Categories,
+    ForwardTo,
+    Delete,
+    StopProcessing,
+}
+
+pub const RULE_FIELDS: [RuleField; 15] = [
+    RuleField::Name,
+    RuleField::From,
+    RuleField::SenderContains,
+    RuleField::SubjectContains,
+    RuleField::BodyContains,
+    RuleField::HasAttachments,
+    RuleField::ExceptFrom,
+    RuleField::ExceptSubjectContains,
+    RuleField::ExceptBodyContains,
+    RuleField::MarkRead,
+    RuleField::MoveTo,
+    RuleField::Categories,
+    RuleField::ForwardTo,
+    RuleField::Delete,
+    RuleField::StopProcessing,
+];
+
+impl RuleField {
+    pub fn label(self) -> &'static str {
+        match self {
+            RuleField::Name => "name",
+            RuleField::From => "from",
+            RuleField::SenderContains => "sender contains",
+            RuleField::SubjectContains => "subject contains",
+            RuleField::BodyContains => "subject or body contains",
+            RuleField::HasAttachments => "has attachments",
+            RuleField::ExceptFrom => "except from",
+            RuleField::ExceptSubjectContains => "except subject contains",
+            RuleField::ExceptBodyContains => "except subject or body contains",
+            RuleField::MarkRead => "mark read",
+            RuleField::MoveTo => "move to folder",
+            RuleField::Categories => "categorise as",
+            RuleField::ForwardTo => "forward to",
+            RuleField::Delete => "delete",
+            RuleField::StopProcessing => "stop processing more rules",
+        }
+    }
+
+    /// Fields written as `yes` or `no` rather than a comma-separated list.
+    pub fn is_yes_no(self) -> bool {
+        matches!(
+            self,
+            RuleField::HasAttachments
+                | RuleField::MarkRead
+                | RuleField::Delete
+                | RuleField::StopProcessing
+        )
+    }
+}
+
+/// A change to the rules waiting to be run by the event loop.
+pub enum InboxRulesAction {
+    /// Fetch the rules and folders of the screen's account.
+    Load,
+    /// Create the rule, or replace the one with its id.
+    Save(Box<MessageRule>),
+    SetEnabled { rule_id: String, is_enabled: bool },
+    Delete { rule_id: String },
+}
+
+impl InboxRulesScreen {
+    /// Opens the rules of the selected message's account, or of the first enabled account,
+    /// with a new rule for the selected message if `from_message` is set.
+    pub fn open(state: &mut State, storage: &Storage, from_message: bool) {
+        let message = state.messages().get(state.selected_message_index);
+        let mailbox_id = match message {
+            Some(message) => Some(message.mailbox_id.clone()),
+            None => storage.enabled_mailboxes().next().map(|mailbox| mailbox.get_id().to_string()),
+        };
+        let index = match mailbox_id.and_then(|id| storage.find_mailbox(&id)) {
+            Some(index) => index,
+            None => {
+                state.status_message = Some("no account to manage the rules of".to_string());
+                return;
+            }
+        };
+        let mode = match message {
+            Some(message) if from_message => {
+                InboxRulesMode::Edit(Box::new(RuleForm::from_message(message)))
+            }
+            _ => InboxRulesMode::List,
+        };
+        state.inbox_rules = Some(InboxRulesScreen::new(storage, index, mode));
+        state.pending_inbox_rules_action = Some(InboxRulesAction::Load);
+    }
+
+    fn new(storage: &Storage, index: usize, mode: InboxRulesMode) -> InboxRulesScreen {
+        let mailbox = &storage.outlook[index];
+        InboxRulesScreen {
+            mailbox_id: mailbox.get_id().to_string(),
+            mailbox_name: storage::mailbox_name(mailbox, index),
+            rules: vec![],
+            folders: vec![],
+            selected_index: 0,
+            mode,
+        }
+    }
+
+    /// The text of the status bar prompt, while one is open.
+    pub fn prompt(&self) -> Option<String> {
+        match self.mode {
+            InboxRulesMode::ConfirmDelete => self.rules.get(self.selected_index)
+                .map(|rule| format!("delete {}? (y/n)", rule.display_name)),
+            _ => None,
+        }
+    }
+
+    /// The name of the folder with this id, or the id if it is not a top-level folder.
+    pub fn folder_name<'a>(&'a self, folder_id: &'a str) -> &'a str {
+        self.folders.iter()
+            .find(|folder| folder.id == folder_id)
+            .map(|folder| folder.display_name.as_str())
+            .unwrap_or(folder_id)
+    }
+
+    /// The text of each of `RULE_FIELDS` for the rule.
+    pub fn field_values(&self, rule: &MessageRule) -> Vec<String> {
+        let list = |values: &[String]| values.join(", ");
+        let yes_no = |value: Option<bool>| match value {
+            Some(true) => "yes".to_string(),
+            Some(false) => "no".to_string(),
+            None => String::new(),
+        };
+        let (conditions, exceptions, actions) = (&rule.conditions, &rule.exceptions, &rule.actions);
+        RULE_FIELDS.iter()
+            .map(|field| match field {
+                RuleField::Name => rule.display_name.clone(),
+                RuleField::From => list(&conditions.from_addresses),
+                RuleField::SenderContains => list(&conditions.sender_contains),
+                RuleField::SubjectContains => list(&conditions.subject_contains),
+                RuleField::BodyContains => list(&conditions.body_or_subject_contains),
+                RuleField::HasAttachments => yes_no(conditions.has_attachments),
+                RuleField::ExceptFrom => list(&exceptions.from_addresses),
+                RuleField::ExceptSubjectContains => list(&exceptions.subject_contains),
+                RuleField::ExceptBodyContains => list(&exceptions.body_or_subject_contains),
+                RuleField::MarkRead => yes_no(actions.mark_as_read),
+                RuleField::MoveTo => actions.move_to_folder.as_deref()
+                    .map(|id| self.folder_name(id).to_string())
+                    .unwrap_or_default(),
+                RuleField::Categories => list(&actions.assign_categories),
+                RuleField::ForwardTo => list(&actions.forward_to),
+                RuleField::Delete => yes_no(actions.delete),
+                RuleField::StopProcessing => yes_no(actions.stop_processing_rules),
+            })
+            .collect()
+    }
+
+    /// What the rule does, in one line for the list.
+    pub fn describe(&self, rule: &MessageRule) -> String {
+        let mut parts: Vec<String> = RULE_FIELDS.iter()
+            .zip(self.field_values(rule))
+            .filter(|(field, value)| **field != RuleField::Name && !value.is_empty())
+            .map(|(field, value)| format!("{}: {}", field.label(), value))
+            .collect();
+        let has_other = [&rule.conditions.other, &rule.exceptions.other, &rule.actions.other]
+            .iter()
+            .any(|other| other.values().any(|value| !value.is_null()));
+        if has_other {
+            parts.push("and more".to_string());
+        }
+        parts.join("; ")
+    }
+
+    fn edit_selected_rule(&mut self) -> Result<(), String> {
+        let rule = match self.rules.get(self.selected_index) {
+            Some(rule) => rule,
+            None => return Ok(()),
+        };
+        if rule.is_read_only {
+            return Err(format!("{} was made elsewhere and cannot be edited", rule.display_name));
+        }
+        let values = self.field_values(rule);
+        self.mode = InboxRulesMode::Edit(Box::new(RuleForm {
+            rule: Some(rule.clone()),
+            values,
+            selected_index: 0,
+            is_editing: false,
+        }));
+        Ok(())
+    }
+}
+
+impl RuleForm {
+    fn new() -> RuleForm {
+        RuleForm {
+            rule: None,
+            values: vec![String::new(); RULE_FIELDS.len()],
+            selected_index: 0,
+            is_editing: false,
+        }
+    }
+
+    /// A new rule for messages from the same sender with the same subject.
+    fn from_message(message: &Message) -> RuleForm {
+        let mut form = RuleForm::new();
+        let sender = if message.from.name.is_empty() {
+            &message.from.address
+        } else {
+            &message.from.name
+        };
+        form.set(RuleField::Name, format!("from {}", sender));
+        form.set(RuleField::From, message.from.address.clone());
+        form.set(RuleField::SubjectContains, normalise_subject(&message.subject));
+        form
+    }
+
+    fn set(&mut self, field: RuleField, value: String) {
+        let index = RULE_FIELDS.iter().position(|f| *f == field).unwrap();
+        self.values[index] = value;
+    }
+
+    fn get(&self, field: RuleField) -> &str {
+        let index = RULE_FIELDS.iter().position(|f| *f == field).unwrap();
+        self.values[index].trim()
+    }
+
+    /// The rule as written in the form, on top of the rule being edited.
+    fn to_rule(&self, folders: &[MailFolder], sequence: u32) -> Result<MessageRule, String> {
+        let mut rule = self.rule.clone().unwrap_or(MessageRule {
+            sequence,
+            is_enabled: true,
+            ..MessageRule::default()
+        });
+        let list = |field: RuleField| -> Vec<String> {
+            self.get(field).split(',')
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+        let yes_no = |field: RuleField| -> Result<Option<bool>, String> {
+            match self.get(field).to_lowercase().as_str() {
+                "" => Ok(None),
+                "yes" | "y" => Ok(Some(true)),
+                "no" | "n" => Ok(Some(false)),
+                _ => Err(format!("{}: write yes or no", field.label())),
+            }
+        };
+        // Only `has attachments` means something when set to no.
+        let yes = |field: RuleField| -> Result<Option<bool>, String> {
+            Ok(yes_no(field)?.filter(|value| *value))
+        };
+        rule.display_name = self.get(RuleField::Name).to_string();
+        if rule.display_name.is_empty() {
+            return Err("the rule needs a name".to_string());
+        }
+        let conditions = &mut rule.conditions;
+        conditions.from_addresses = list(RuleField::From);
+        conditions.sender_contains = list(RuleField::SenderContains);
+        conditions.subject_contains = list(RuleField::SubjectContains);
+        conditions.body_or_subject_contains = list(RuleField::BodyContains);
+        conditions.has_attachments = yes_no(RuleField::HasAttachments)?;
+        let exceptions = &mut rule.exceptions;
+        exceptions.from_addresses = list(RuleField::ExceptFrom);
+        exceptions.subject_contains = list(RuleField::ExceptSubjectContains);
+        exceptions.body_or_subject_contains = list(RuleField::ExceptBodyContains);
+        let actions = &mut rule.actions;
+        actions.mark_as_read = yes(RuleField::MarkRead)?;
+        actions.move_to_folder = match self.get(RuleField::MoveTo) {
+            "" => None,
+            name => match folders.iter()
+                .find(|folder| folder.display_name.eq_ignore_ascii_case(name)) {
+                Some(folder) => Some(folder.id.clone()),
+                None => return Err(format!("no folder named {}", name)),
+            },
+        };
+        actions.assign_categories = list(RuleField::Categories);
+        actions.forward_to = list(RuleField::ForwardTo);
+        actions.delete = yes(RuleField::Delete)?;
+        actions.stop_processing_rules = yes(RuleField::StopProcessing)?;
+        if rule.conditions.is_empty() {
+            // Graph would run the rule on every message.
+            return Err("the rule needs a condition".to_string());
+        }
+        let has_actions = actions.mark_as_read.is_some()
+            || actions.move_to_folder.is_some()
+            || !actions.assign_categories.is_empty()
+            || !actions.forward_to.is_empty()
+            || actions.delete.is_some()
+            || actions.other.values().any(|value| !value.is_null());
+        if !has_actions {
+            return Err("the rule needs an action".to_string());
+        }
+        Ok(rule)
+    }
+}
+
+pub fn take_inbox_rules_key(storage: &Storage, state: &mut State, key: Key) {
+    let screen = state.inbox_rules.as_mut().unwrap();
+    let context = match &mut screen.mode {
+        InboxRulesMode::List => Context::InboxRules,
+        InboxRulesMode::Edit(form) if form.is_editing => {
+            match key {
+                Key::Char('\n') | Key::Esc => form.is_editing = false,
+                Key::Char(c) => form.values[form.selected_index].push(c),
+                Key::Backspace => {
+                    form.values[form.selected_index].pop();
+                },
+                _ => (),
+            }
+            return;
+        },
+        InboxRulesMode::Edit(_) => Context::RuleForm,
+        InboxRulesMode::ConfirmDelete => {
+            screen.mode = InboxRulesMode::List;
+            if key == Key::Char('y') {
+                state.pending_inbox_rules_action = Some(InboxRulesAction::Delete {
+                    rule_id: screen.rules[screen.selected_index].id.clone(),
+                });
+            }
+            return;
+        },
+    };
+    if let Some(action) = lookup_key(state, key, context) {
+        take_inbox_rules_action(storage, state, action);
+    }
+}
+
+fn take_inbox_rules_action(storage: &Storage, state: &mut State, action: Action) {
+    let screen = state.inbox_rules.as_mut().unwrap();
+    let rule_count = screen.rules.len();
+    let selected_index = screen.selected_index;
+    match &mut screen.mode {
+        InboxRulesMode::Edit(form) => match action {
+            Action::Prev => form.selected_index = form.selected_index.saturating_sub(1),
+            Action::Next => {
+                form.selected_index = (form.selected_index + 1).min(RULE_FIELDS.len() - 1);
+            },
+            Action::Open => form.is_editing = true,
+            Action::SaveRule => {
+                let sequence = screen.rules.iter().map(|rule| rule.sequence + 1).max().unwrap_or(1);
+                match form.to_rule(&screen.folders, sequence) {
+                    Ok(rule) => {
+                        let action = InboxRulesAction::Save(Box::new(rule));
+                        state.pending_inbox_rules_action = Some(action);
+                    }
+                    Err(error) => state.status_message = Some(error),
+                }
+            },
+            Action::Help => state.show_help = true,
+            Action::Back => screen.mode = InboxRulesMode::List,
+            _ => (),
+        },
+        _ => match action {
+            Action::Prev => screen.selected_index = selected_index.saturating_sub(1),
+            Action::Next => {
+                screen.selected_index = (selected_index + 1).min(rule_count.saturating_sub(1));
+            },
+            Action::AddRule => screen.mode = InboxRulesMode::Edit(Box::new(RuleForm::new())),
+            Action::Open => state.status_message = screen.edit_selected_rule().err(),
+            Action::ToggleRule if rule_count > 0 => {
+                let rule = &screen.rules[selected_index];
+                state.pending_inbox_rules_action = Some(InboxRulesAction::SetEnabled {
+                    rule_id: rule.id.clone(),
+                    is_enabled: !rule.is_enabled,
+                });
+            },
+            Action::DeleteRule if rule_count > 0 => screen.mode = InboxRulesMode::ConfirmDelete,
+            Action::NextAccount => next_account(storage, state),
+            Action::Help => state.show_help = true,
+            Action::Back => state.inbox_rules = None,
+            _ => (),
+        },
+    }
+}
+
+/// Shows the rules of the next enabled account.
+fn next_account(storage: &Storage, state: &mut State) {
+    let screen = state.inbox_rules.as_ref().unwrap();
+    let index = storage.find_mailbox(&screen.mailbox_id).unwrap_or(0);
+    let next_index = (1..=storage.outlook.len())
+        .map(|offset| (index + offset) % storage.outlook.len())
+        .find(|i| !storage.outlook[*i].is_disabled);
+    if let Some(next_index) = next_index {
+        state.inbox_rules = Some(InboxRulesScreen::new(storage, next_index, InboxRulesMode::List));
+        state.pending_inbox_rules_action = Some(InboxRulesAction::Load);
+    }
+}
+
+/// Runs the pending change to the rules, then lists them again as the server has them.
+pub async fn run(
+    state: &mut State,
+    storage: &Storage,
+    terminal: &mut Terminal<impl Backend>,
+) {
+    let action = match state.pending_inbox_rules_action.take() {
+        Some(action) => action,
+        None => return,
+    };
+    let mailbox = match &state.inbox_rules {
+        Some(screen) => match storage.get_mailbox_by_id(&screen.mailbox_id) {
+            Some(mailbox) => mailbox,
+            None => return,
+        },
+        None => return,
+    };
+    if !mailbox.can_manage_rules() {
+        // Asking anyway would only be refused, and refreshing the token cannot add the scope.
+        state.status_message = Some(
+            "this account has not allowed rules: sign in again from the accounts screen"
+                .to_string()
+        );
+        return;
+    }
+    let result = match action {
+        InboxRulesAction::Load => Ok(None),
+        InboxRulesAction::Save(rule) => {
+            render::progress(state, "saving the rule...", terminal);
+            let name = rule.display_name.clone();
+            let result = if rule.id.is_empty() {
+                mailbox.create_rule(&rule).await
+            } else {
+                mailbox.update_rule(&rule.id, &rule).await
+            };
+            result.map(|_| Some(format!("saved {}", name)))
+        }
+        InboxRulesAction::SetEnabled { rule_id, is_enabled } => {
+            mailbox.set_rule_enabled(&rule_id, is_enabled).await
+                .map(|rule| {
+                    let state = if is_enabled { "enabled" } else { "disabled" };
+                    Some(format!("{} {}", state, rule.display_name))
+                })
+        }
+        InboxRulesAction::Delete { rule_id } => {
+            mailbox.delete_rule(&rule_id).await.map(|()| Some("deleted the rule".to_string()))
+        }
+    };
+    let message = match result {
+        Ok(message) => message,
+        Err(error) => {
+            // The form stays open, so the rule can be fixed and saved again.
+            state.status_message = Some(error.to_string());
+            return;
+        }
+    };
+    render::progress(state, "fetching the rules...", terminal);
+    let rules = mailbox.list_rules().await;
+    // Rules can be read without their folders, which are only needed to name them.
+    let folders = mailbox.list_folders().await.unwrap_or_default();
+    let screen = match &mut state.inbox_rules {
+        Some(screen) => screen,
+        None => return,
+    };
+    match rules {
+        Ok(rules) => {
+            screen.rules = rules;
+            screen.folders = folders;
+            screen.selected_index = screen.selected_index.min(screen.rules.len().saturating_sub(1));
+            if message.is_some() {
+                screen.mode = InboxRulesMode::List;
+            }
+            state.status_message = message;
+        }
+        Err(error) => state.status_message = Some(format!("could not fetch the rules: {}", error)),
+    }
+}
@@ -1,44 +1,299 @@
 use termion::event::Key;
-use crate::{State, Storage};
-use crate::parse::try_parse_selected_message;
+use crate::{config, outbox, snooze, State, Storage};
+use crate::accounts::{take_accounts_key, AccountsScreen};
+use crate::attachment::AttachmentAction;
+use crate::compose::ComposeAction;
+use crate::drafts::{take_drafts_key, DraftsScreen};
+use crate::inbox_rules::{take_inbox_rules_key, InboxRulesScreen};
+use crate::keymap::{format_keys, Action, Context, Lookup};
+use crate::layout::Layout;
+use crate::reply::ReplyKind;
+use crate::link::{take_link_picker_key, LinkPicker};
+
+/// Percentage points the list pane grows or shrinks by per key press.
+const LIST_RESIZE_STEP: i16 = 5;
+/// Columns the folders pane grows or shrinks by per key press.
+const FOLDERS_RESIZE_STEP: i16 = 2;
 
 pub fn take_key(storage: &mut Storage, state: &mut State, key: Key) {
     state.should_skip_render = false;
-    match key {
-        Key::Ctrl('c') => state.should_exit = true,
-        Key::Left => {
-            if !state.should_view_message_body {
-                state.set_selected_message_as_read(&storage);
-            } else {
-                // go back to list of messages
-                state.should_view_message_body = false;
-            }
-        },
-        Key::Right => if state.unread_messages.len() > 0 {
-            if !state.should_view_message_body {
-                state.cursor_height = 0;
-                state.should_view_message_body = true;
-                try_parse_selected_message(state);
+    state.status_message = None;
+    if key == Key::Ctrl('c') {
+        state.should_exit = true;
+        return;
+    }
+    if state.show_help {
+        // Any key closes the help, rather than acting on the screen hidden behind it.
+        state.show_help = false;
+        return;
+    }
+    if state.search_prompt.is_some() {
+        take_search_prompt_key(state, key);
+        return;
+    }
+    if state.snooze_prompt.is_some() {
+        take_snooze_prompt_key(state, key);
+        return;
+    }
+    if state.link_picker.is_some() {
+        take_link_picker_key(state, key);
+        return;
+    }
+    if state.accounts.is_some() {
+        take_accounts_key(storage, state, key);
+        return;
+    }
+    if state.inbox_rules.is_some() {
+        take_inbox_rules_key(storage, state, key);
+        return;
+    }
+    if state.drafts.is_some() {
+        take_drafts_key(storage, state, key);
+        return;
+    }
+    if state.compose.is_some() {
+        take_compose_key(storage, state, key);
+        return;
+    }
+    state.pending_keys.push(key);
+    let context = if state.should_view_message_body { Context::Body } else { Context::List };
+    match state.keymap.lookup(&state.pending_keys, context) {
+        Lookup::Action(action) => {
+            state.pending_keys.clear();
+            take_action(storage, state, action);
+        }
+        Lookup::Prefix => {
+            state.status_message = Some(format!("{} -", format_keys(&state.pending_keys)));
+        }
+        Lookup::None if state.pending_keys.len() > 1 => {
+            // The sequence went nowhere, but the last key may start another one.
+            state.pending_keys.clear();
+            take_key(storage, state, key);
+        }
+        Lookup::None => {
+            state.pending_keys.clear();
+            // Digits that are not bound select a view, counting from 1.
+            let digit = match key {
+                Key::Char(c) => c.to_digit(10).filter(|digit| *digit > 0),
+                _ => None,
+            };
+            if let (Some(digit), Context::List) = (digit, context) {
+                state.select_view(digit as usize - 1);
             }
         },
-        Key::Up => {
-            if !state.should_view_message_body {
-                state.decrease_selected_message_index()
-            } else if state.cursor_height > 0 {
+    }
+}
+
+fn take_action(storage: &mut Storage, state: &mut State, action: Action) {
+    let is_body = state.should_view_message_body;
+    match action {
+        Action::Quit => state.should_exit = true,
+        Action::Help => state.show_help = true,
+        Action::Next if is_body => state.cursor_height = state.cursor_height.saturating_add(1),
+        Action::Next => state.increase_selected_message_index(),
+        Action::Prev if is_body => {
+            if state.cursor_height > 0 {
                 state.cursor_height -= 1;
             } else {
                 state.should_skip_render = true;
             }
         },
-        Key::Down => {
-            if !state.should_view_message_body {
-                state.increase_selected_message_index()
-            } else if state.cursor_height < usize::MAX {
-                state.cursor_height += 1;
+        Action::Prev => state.decrease_selected_message_index(),
+        Action::First if is_body => state.cursor_height = 0,
+        Action::First => state.select_first_message(),
+        Action::Last if is_body => state.scroll_body_to_bottom(),
+        Action::Last => state.select_last_message(),
+        Action::Open if !is_body && !state.messages().is_empty() => {
+            state.open_selected_message_body();
+        },
+        Action::Back if is_body => {
+            state.should_view_message_body = false;
+            state.fit_selection_to_visible();
+        },
+        Action::Back => state.close_search_results(),
+        Action::MarkRead => {
+            state.should_view_message_body = false;
+            state.set_selected_message_as_read(storage);
+        },
+        Action::ToggleFlag => state.toggle_selected_message_flag(storage),
+        Action::Snooze if !state.messages().is_empty() => state.snooze_prompt = Some(String::new()),
+        Action::ToggleThread if !is_body => state.toggle_selected_thread(),
+        Action::NextInThread if is_body && state.select_next_message_in_thread() => {
+            state.open_selected_message_body();
+        },
+        Action::PrevInThread if is_body && state.select_previous_message_in_thread() => {
+            state.open_selected_message_body();
+        },
+        Action::NextAttachment if is_body => state.select_next_attachment(),
+        Action::SaveAttachment if is_body
+            && !state.selected_message().attachments.is_empty() => {
+            state.pending_attachment_action = Some(AttachmentAction::Save);
+        },
+        Action::OpenAttachment if is_body
+            && !state.selected_message().attachments.is_empty() => {
+            state.pending_attachment_action = Some(AttachmentAction::Open);
+        },
+        Action::Links if is_body => {
+            if state.selected_message_links().is_empty() {
+                state.status_message = Some("no links in this message".to_string());
             } else {
-                state.should_skip_render = true;
+                state.link_picker = Some(LinkPicker::new());
             }
         },
+        Action::Search if !is_body => state.search_prompt = Some(String::new()),
+        Action::Compose if !is_body => state.start_compose(storage),
+        Action::UndoSend if !is_body => outbox::undo(state, storage),
+        Action::Drafts if !is_body => DraftsScreen::open(state, storage),
+        Action::Reply if !state.messages().is_empty() => {
+            state.pending_reply = Some(ReplyKind::Reply);
+        },
+        Action::ReplyAll if !state.messages().is_empty() => {
+            state.pending_reply = Some(ReplyKind::ReplyAll);
+        },
+        Action::Forward if !state.messages().is_empty() => {
+            state.pending_reply = Some(ReplyKind::Forward);
+        },
+        Action::ForwardAsAttachment if !state.messages().is_empty() => {
+            state.pending_reply = Some(ReplyKind::ForwardAsAttachment);
+        },
+        Action::Accounts if !is_body => state.accounts = Some(AccountsScreen::new(storage)),
+        Action::InboxRules if !is_body => InboxRulesScreen::open(state, storage, false),
+        Action::RuleFromMessage if !state.messages().is_empty() => {
+            InboxRulesScreen::open(state, storage, true);
+        },
+        Action::NextView if !is_body => {
+            state.select_view((state.view_index + 1) % state.views.len());
+        },
+        Action::PrevView if !is_body => {
+            let view_count = state.views.len();
+            state.select_view((state.view_index + view_count - 1) % view_count);
+        },
+        Action::SwitchLayout if !is_body => change_layout(state, Layout::cycle_kind),
+        Action::GrowList if !is_body => {
+            change_layout(state, |layout| layout.resize_list(LIST_RESIZE_STEP));
+        },
+        Action::ShrinkList if !is_body => {
+            change_layout(state, |layout| layout.resize_list(-LIST_RESIZE_STEP));
+        },
+        Action::GrowFolders if !is_body => {
+            change_layout(state, |layout| layout.resize_folders(FOLDERS_RESIZE_STEP));
+        },
+        Action::ShrinkFolders if !is_body => {
+            change_layout(state, |layout| layout.resize_folders(-FOLDERS_RESIZE_STEP));
+        },
+        _ => (),
+    }
+}
+
+/// Applies a change to the layout and saves it to the config for the next session.
+fn change_layout(state: &mut State, change: impl FnOnce(&mut Layout)) {
+    change(&mut state.config.layout);
+    if let Err(error) = config::save_layout(&state.config) {
+        state.status_message = Some(error.to_string());
+    }
+}
+
+fn take_search_prompt_key(state: &mut State, key: Key) {
+    let prompt = state.search_prompt.as_mut().unwrap();
+    match key {
+        Key::Char('\n') => state.submit_search_prompt(),
+        Key::Char(c) => prompt.push(c),
+        Key::Backspace => {
+            prompt.pop();
+        },
+        Key::Esc => {
+            state.search_prompt = None;
+            state.search_error = None;
+        },
+        _ => (),
+    }
+}
+
+fn take_snooze_prompt_key(state: &mut State, key: Key) {
+    let prompt = state.snooze_prompt.as_mut().unwrap();
+    match key {
+        Key::Char('\n') => snooze::submit_prompt(state),
+        Key::Char(c) => prompt.push(c),
+        Key::Backspace => {
+            prompt.pop();
+        },
+        Key::Esc => state.snooze_prompt = None,
+        _ => (),
+    }
+}
+
+fn take_compose_key(storage: &mut Storage, state: &mut State, key: Key) {
+    let compose = state.compose.as_mut().unwrap();
+    if let Some(prompt) = &mut compose.schedule_prompt {
+        match key {
+            Key::Char('\n') => outbox::submit_schedule_prompt(state, storage),
+            Key::Char(c) => prompt.push(c),
+            Key::Backspace => {
+                prompt.pop();
+            },
+            Key::Esc => compose.schedule_prompt = None,
+            _ => (),
+        }
+        return;
+    }
+    if let Some(prompt) = &mut compose.attach_prompt {
+        match key {
+            Key::Char('\n') => {
+                state.status_message = compose.submit_attach_prompt(storage).err();
+            },
+            Key::Char('\t') => compose.complete_attach_prompt(),
+            Key::Char(c) => prompt.push(c),
+            Key::Backspace => {
+                prompt.pop();
+            },
+            Key::Esc => compose.attach_prompt = None,
+            _ => (),
+        }
+        return;
+    }
+    if let Some(action) = lookup_key(state, key, Context::Compose) {
+        take_compose_action(storage, state, action);
+    }
+}
+
+/// Looks the key up in a screen's context after the keys typed before it, returning the
+/// action once a sequence is complete.
+pub fn lookup_key(state: &mut State, key: Key, context: Context) -> Option<Action> {
+    state.pending_keys.push(key);
+    match state.keymap.lookup(&state.pending_keys, context) {
+        Lookup::Action(action) => {
+            state.pending_keys.clear();
+            Some(action)
+        }
+        Lookup::Prefix => {
+            state.status_message = Some(format!("{} -", format_keys(&state.pending_keys)));
+            None
+        }
+        Lookup::None if state.pending_keys.len() > 1 => {
+            // The sequence went nowhere, but the last key may start another one.
+            state.pending_keys.clear();
+            lookup_key(state, key, context)
+        }
+        Lookup::None => {
+            state.pending_keys.clear();
+            None
+        }
+    }
+}
+
+fn take_compose_action(storage: &mut Storage, state: &mut State, action: Action) {
+    let compose = state.compose.as_mut().unwrap();
+    match action {
+        Action::Help => state.show_help = true,
+        Action::EditMessage => state.pending_compose_action = Some(ComposeAction::Edit),
+        Action::Attach => compose.attach_prompt = Some(String::new()),
+        Action::RemoveAttachment => {
+            compose.message.attachments.pop();
+            compose.is_dirty = true;
+        },
+        Action::Send => outbox::queue(state, storage, None),
+        Action::SendLater => compose.schedule_prompt = Some(String::new()),
+        Action::CloseCompose => state.pending_compose_action = Some(ComposeAction::Close),
         _ => (),
     }
 }
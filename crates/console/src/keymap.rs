@@ -0,0 +1,842 @@
+use std::fmt;
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use termion::event::Key;
+
+/// Something a key sequence can be bound to.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub enum Action {
+    Next,
+    Prev,
+    First,
+    Last,
+    Open,
+    Back,
+    MarkRead,
+    ToggleFlag,
+    Snooze,
+    ToggleThread,
+    NextInThread,
+    PrevInThread,
+    NextAttachment,
+    SaveAttachment,
+    OpenAttachment,
+    Links,
+    Search,
+    Compose,
+    UndoSend,
+    Drafts,
+    Reply,
+    ReplyAll,
+    Forward,
+    ForwardAsAttachment,
+    SwitchLayout,
+    GrowList,
+    ShrinkList,
+    GrowFolders,
+    ShrinkFolders,
+    Accounts,
+    InboxRules,
+    RuleFromMessage,
+    NextView,
+    PrevView,
+    EditMessage,
+    Attach,
+    RemoveAttachment,
+    Send,
+    SendLater,
+    CloseCompose,
+    AddAccount,
+    SignInAgain,
+    RenameAccount,
+    ChangeAccountColor,
+    ToggleAccount,
+    RemoveAccount,
+    AddRule,
+    ToggleRule,
+    DeleteRule,
+    NextAccount,
+    SaveRule,
+    DiscardDraft,
+    FetchDrafts,
+    Help,
+    Quit,
+}
+
+/// Where an action does something, which decides whether it is listed in the help.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Context {
+    List,
+    Body,
+    Both,
+    Compose,
+    Accounts,
+    /// The providers to add an account with, listed on the accounts screen.
+    Providers,
+    InboxRules,
+    /// The form a rule is written in, on the inbox rules screen.
+    RuleForm,
+    Drafts,
+}
+
+/// Contexts where keys are looked up.
+const CONTEXTS: [Context; 8] = [
+    Context::List,
+    Context::Body,
+    Context::Compose,
+    Context::Accounts,
+    Context::Providers,
+    Context::InboxRules,
+    Context::RuleForm,
+    Context::Drafts,
+];
+
+const ACTIONS: [Action; 55] = [
+    Action::Next,
+    Action::Prev,
+    Action::First,
+    Action::Last,
+    Action::Open,
+    Action::Back,
+    Action::MarkRead,
+    Action::ToggleFlag,
+    Action::Snooze,
+    Action::ToggleThread,
+    Action::NextInThread,
+    Action::PrevInThread,
+    Action::NextAttachment,
+    Action::SaveAttachment,
+    Action::OpenAttachment,
+    Action::Links,
+    Action::Search,
+    Action::Compose,
+    Action::UndoSend,
+    Action::Drafts,
+    Action::Reply,
+    Action::ReplyAll,
+    Action::Forward,
+    Action::ForwardAsAttachment,
+    Action::SwitchLayout,
+    Action::GrowList,
+    Action::ShrinkList,
+    Action::GrowFolders,
+    Action::ShrinkFolders,
+    Action::Accounts,
+    Action::InboxRules,
+    Action::RuleFromMessage,
+    Action::NextView,
+    Action::PrevView,
+    Action::EditMessage,
+    Action::Attach,
+    Action::RemoveAttachment,
+    Action::Send,
+    Action::SendLater,
+    Action::CloseCompose,
+    Action::AddAccount,
+    Action::SignInAgain,
+    Action::RenameAccount,
+    Action::ChangeAccountColor,
+    Action::ToggleAccount,
+    Action::RemoveAccount,
+    Action::AddRule,
+    Action::ToggleRule,
+    Action::DeleteRule,
+    Action::NextAccount,
+    Action::SaveRule,
+    Action::DiscardDraft,
+    Action::FetchDrafts,
+    Action::Help,
+    Action::Quit,
+];
+
+impl Action {
+    /// The name used for the action in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Next => "next",
+            Action::Prev => "prev",
+            Action::First => "first",
+            Action::Last => "last",
+            Action::Open => "open",
+            Action::Back => "back",
+            Action::MarkRead => "mark-read",
+            Action::ToggleFlag => "toggle-flag",
+            Action::Snooze => "snooze",
+            Action::ToggleThread => "toggle-thread",
+            Action::NextInThread => "next-in-thread",
+            Action::PrevInThread => "prev-in-thread",
+            Action::NextAttachment => "next-attachment",
+            Action::SaveAttachment => "save-attachment",
+            Action::OpenAttachment => "open-attachment",
+            Action::Links => "links",
+            Action::Search => "search",
+            Action::Compose => "compose",
+            Action::UndoSend => "undo-send",
+            Action::Drafts => "drafts",
+            Action::Reply => "reply",
+            Action::ReplyAll => "reply-all",
+            Action::Forward => "forward",
+            Action::ForwardAsAttachment => "forward-as-attachment",
+            Action::SwitchLayout => "switch-layout",
+            Action::GrowList => "grow-list",
+            Action::ShrinkList => "shrink-list",
+            Action::GrowFolders => "grow-folders",
+            Action::ShrinkFolders => "shrink-folders",
+            Action::Accounts => "accounts",
+            Action::InboxRules => "inbox-rules",
+            Action::RuleFromMessage => "rule-from-message",
+            Action::NextView => "next-view",
+            Action::PrevView => "prev-view",
+            Action::EditMessage => "edit-message",
+            Action::Attach => "attach",
+            Action::RemoveAttachment => "remove-attachment",
+            Action::Send => "send",
+            Action::SendLater => "send-later",
+            Action::CloseCompose => "close-compose",
+            Action::AddAccount => "add-account",
+            Action::SignInAgain => "sign-in-again",
+            Action::RenameAccount => "rename-account",
+            Action::ChangeAccountColor => "change-account-color",
+            Action::ToggleAccount => "toggle-account",
+            Action::RemoveAccount => "remove-account",
+            Action::AddRule => "add-rule",
+            Action::ToggleRule => "toggle-rule",
+            Action::DeleteRule => "delete-rule",
+            Action::NextAccount => "next-account",
+            Action::SaveRule => "save-rule",
+            Action::DiscardDraft => "discard-draft",
+            Action::FetchDrafts => "fetch-drafts",
+            Action::Help => "help",
+            Action::Quit => "quit",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().copied().find(|action| action.name() == name)
+    }
+
+    /// What the action does, for the help.
+    pub fn description(&self, context: Context) -> &'static str {
+        match (self, context) {
+            (Action::Next, Context::Accounts) => "select the next account",
+            (Action::Next, Context::Providers) => "select the next provider",
+            (Action::Prev, Context::Accounts) => "select the previous account",
+            (Action::Prev, Context::Providers) => "select the previous provider",
+            (Action::Open, Context::Providers) => "add an account with the provider",
+            (Action::Back, Context::Accounts) => "close",
+            (Action::Back, Context::Providers) => "back to the accounts",
+            (Action::Next, Context::InboxRules) => "select the next rule",
+            (Action::Prev, Context::InboxRules) => "select the previous rule",
+            (Action::Open, Context::InboxRules) => "edit",
+            (Action::Back, Context::InboxRules) => "close",
+            (Action::Next, Context::RuleForm) => "select the next field",
+            (Action::Prev, Context::RuleForm) => "select the previous field",
+            (Action::Open, Context::RuleForm) => "edit the field, then finish",
+            (Action::Back, Context::RuleForm) => "cancel",
+            (Action::Next, Context::Drafts) => "select the next draft",
+            (Action::Prev, Context::Drafts) => "select the previous draft",
+            (Action::Open, Context::Drafts) => "resume writing",
+            (Action::Back, Context::Drafts) => "close",
+            (Action::Next, Context::Body) => "scroll down",
+            (Action::Next, _) => "select the next message",
+            (Action::Prev, Context::Body) => "scroll up",
+            (Action::Prev, _) => "select the previous message",
+            (Action::First, Context::Body) => "scroll to the top",
+            (Action::First, _) => "select the first message",
+            (Action::Last, Context::Body) => "scroll to the bottom",
+            (Action::Last, _) => "select the last message",
+            (Action::Open, _) => "open the message",
+            (Action::Back, Context::Body) => "back to the list",
+            (Action::Back, _) => "leave search results",
+            (Action::MarkRead, _) => "mark as read",
+            (Action::ToggleFlag, _) => "toggle flag",
+            (Action::Snooze, _) => "snooze until a later time",
+            (Action::ToggleThread, _) => "expand/collapse thread",
+            (Action::NextInThread, _) => "next message in thread",
+            (Action::PrevInThread, _) => "previous message in thread",
+            (Action::NextAttachment, _) => "select the next attachment",
+            (Action::SaveAttachment, _) => "save the attachment",
+            (Action::OpenAttachment, _) => "open the attachment",
+            (Action::Links, _) => "pick a link",
+            (Action::Search, _) => "search",
+            (Action::Compose, _) => "compose",
+            (Action::UndoSend, _) => "take the last sent message back",
+            (Action::Drafts, _) => "drafts",
+            (Action::Reply, _) => "reply",
+            (Action::ReplyAll, _) => "reply to all",
+            (Action::Forward, _) => "forward",
+            (Action::ForwardAsAttachment, _) => "forward as an attachment",
+            (Action::SwitchLayout, _) => "switch layout",
+            (Action::GrowList, _) => "grow the list pane",
+            (Action::ShrinkList, _) => "shrink the list pane",
+            (Action::GrowFolders, _) => "grow the folders pane",
+            (Action::ShrinkFolders, _) => "shrink the folders pane",
+            (Action::Accounts, _) => "manage accounts",
+            (Action::InboxRules, _) => "manage the inbox rules on the server",
+            (Action::RuleFromMessage, _) => "create an inbox rule from the message",
+            (Action::NextView, _) => "show the next view",
+            (Action::PrevView, _) => "show the previous view",
+            (Action::EditMessage, _) => "edit in $EDITOR",
+            (Action::Attach, _) => "attach a file",
+            (Action::RemoveAttachment, _) => "remove the last attachment",
+            (Action::Send, _) => "send",
+            (Action::SendLater, _) => "send later",
+            (Action::CloseCompose, _) => "close, keeping a draft",
+            (Action::AddAccount, _) => "add an account",
+            (Action::SignInAgain, _) => "sign in again",
+            (Action::RenameAccount, _) => "rename",
+            (Action::ChangeAccountColor, _) => "change the colour of its tag",
+            (Action::ToggleAccount, _) => "disable or enable",
+            (Action::RemoveAccount, _) => "remove",
+            (Action::AddRule, _) => "add a rule",
+            (Action::ToggleRule, _) => "disable or enable",
+            (Action::DeleteRule, _) => "delete",
+            (Action::NextAccount, _) => "show the rules of the next account",
+            (Action::SaveRule, _) => "save the rule",
+            (Action::DiscardDraft, _) => "discard",
+            (Action::FetchDrafts, _) => "fetch again",
+            (Action::Help, _) => "show/hide this help",
+            (Action::Quit, _) => "quit",
+        }
+    }
+
+    pub fn context(&self) -> Context {
+        match self {
+            Action::Open
+            | Action::ToggleThread
+            | Action::Search
+            | Action::Compose
+            | Action::UndoSend
+            | Action::Drafts
+            | Action::SwitchLayout
+            | Action::GrowList
+            | Action::ShrinkList
+            | Action::GrowFolders
+            | Action::ShrinkFolders
+            | Action::Accounts
+            | Action::InboxRules
+            | Action::NextView
+            | Action::PrevView => Context::List,
+            Action::NextInThread
+            | Action::PrevInThread
+            | Action::NextAttachment
+            | Action::SaveAttachment
+            | Action::OpenAttachment
+            | Action::Links => Context::Body,
+            Action::EditMessage
+            | Action::Attach
+            | Action::RemoveAttachment
+            | Action::Send
+            | Action::SendLater
+            | Action::CloseCompose => Context::Compose,
+            Action::AddAccount
+            | Action::SignInAgain
+            | Action::RenameAccount
+            | Action::ChangeAccountColor
+            | Action::ToggleAccount
+            | Action::RemoveAccount => Context::Accounts,
+            Action::AddRule
+            | Action::ToggleRule
+            | Action::DeleteRule
+            | Action::NextAccount => Context::InboxRules,
+            Action::SaveRule => Context::RuleForm,
+            Action::DiscardDraft | Action::FetchDrafts => Context::Drafts,
+            _ => Context::Both,
+        }
+    }
+
+    /// Whether the action does something in the context. Of the actions for both the list and
+    /// the body, only the help also opens over the compose screen, while the other screens
+    /// also share moving the selection, choosing and closing.
+    pub fn applies_to(&self, context: Context) -> bool {
+        match context {
+            Context::List | Context::Body => [context, Context::Both].contains(&self.context()),
+            Context::Compose => self.context() == context || *self == Action::Help,
+            screen => {
+                let is_shared = match self {
+                    Action::Next | Action::Prev | Action::Back | Action::Help => true,
+                    // Accounts are not opened, only their providers picked.
+                    Action::Open => screen != Context::Accounts,
+                    _ => false,
+                };
+                is_shared || self.context() == screen
+            }
+        }
+    }
+
+    /// Whether there is a context where both actions do something, so that they cannot share
+    /// a key.
+    fn shares_context_with(&self, other: &Action) -> bool {
+        CONTEXTS.iter().any(|context| self.applies_to(*context) && other.applies_to(*context))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Preset {
+    #[default]
+    Default,
+    Vim,
+    Emacs,
+}
+
+/// Key sequences and what they are bound to, where `None` removes a binding.
+pub type Bindings = Vec<(Vec<Key>, Option<Action>)>;
+
+/// The keymap as configured: a preset, and bindings that replace or add to its own.
+#[derive(Deserialize, Clone, PartialEq, Eq, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeymapConfig {
+    pub preset: Preset,
+    /// Key sequences such as `"g g"` or `"C-x C-c"`, each bound to an action name, or to
+    /// `"none"` to remove the preset's binding.
+    #[serde(deserialize_with = "deserialize_bindings")]
+    pub bindings: Bindings,
+}
+
+/// Checks the bindings as they are read, so that a mistake is reported at its line.
+fn deserialize_bindings<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Bindings, D::Error> {
+    struct KeySequence(Vec<Key>);
+
+    impl<'de> Deserialize<'de> for KeySequence {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let keys = String::deserialize(deserializer)?;
+            parse_keys(&keys)
+                .map(KeySequence)
+                .ok_or_else(|| de::Error::custom(format!("invalid key sequence \"{}\"", keys)))
+        }
+    }
+
+    struct Binding(Option<Action>);
+
+    impl<'de> Deserialize<'de> for Binding {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let name = String::deserialize(deserializer)?;
+            if name == "none" {
+                return Ok(Binding(None));
+            }
+            Action::from_name(&name)
+                .map(|action| Binding(Some(action)))
+                .ok_or_else(|| de::Error::custom(format!("unknown action \"{}\"", name)))
+        }
+    }
+
+    struct BindingsVisitor;
+
+    impl<'de> Visitor<'de> for BindingsVisitor {
+        type Value = Bindings;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a table of key sequences and action names")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut bindings = vec![];
+            while let Some((KeySequence(keys), Binding(action))) = map.next_entry()? {
+                bindings.push((keys, action));
+            }
+            Ok(bindings)
+        }
+    }
+
+    deserializer.deserialize_map(BindingsVisitor)
+}
+
+/// What the keys typed so far amount to.
+pub enum Lookup {
+    Action(Action),
+    /// The start of one or more longer sequences.
+    Prefix,
+    None,
+}
+
+pub struct Keymap {
+    bindings: Vec<(Vec<Key>, Action)>,
+}
+
+impl Keymap {
+    pub fn preset(preset: Preset) -> Keymap {
+        let bindings: &[(&str, Action)] = match preset {
+            Preset::Default => &[
+                ("Down", Action::Next),
+                ("Up", Action::Prev),
+                ("Home", Action::First),
+                ("End", Action::Last),
+                ("Right", Action::Open),
+                ("Enter", Action::Open),
+                ("Left", Action::Back),
+                ("Esc", Action::Back),
+                ("r", Action::MarkRead),
+                ("f", Action::ToggleFlag),
+                ("z", Action::Snooze),
+                ("Space", Action::ToggleThread),
+                ("n", Action::NextInThread),
+                ("p", Action::PrevInThread),
+                ("Tab", Action::NextAttachment),
+                ("s", Action::SaveAttachment),
+                ("o", Action::OpenAttachment),
+                ("l", Action::Links),
+                ("/", Action::Search),
+                ("c", Action::Compose),
+                ("u", Action::UndoSend),
+                ("D", Action::Drafts),
+                ("w", Action::Reply),
+                ("W", Action::ReplyAll),
+                ("F", Action::Forward),
+                ("M-f", Action::ForwardAsAttachment),
+                ("v", Action::SwitchLayout),
+                ("+", Action::GrowList),
+                ("-", Action::ShrinkList),
+                (">", Action::GrowFolders),
+                ("<", Action::ShrinkFolders),
+                ("A", Action::Accounts),
+                ("I", Action::InboxRules),
+                ("R", Action::RuleFromMessage),
+                ("Tab", Action::NextView),
+                ("BackTab", Action::PrevView),
+                ("e", Action::EditMessage),
+                ("a", Action::Attach),
+                ("x", Action::RemoveAttachment),
+                ("s", Action::Send),
+                ("l", Action::SendLater),
+                ("Esc", Action::CloseCompose),
+                ("a", Action::AddAccount),
+                ("e", Action::SignInAgain),
+                ("r", Action::RenameAccount),
+                ("c", Action::ChangeAccountColor),
+                ("d", Action::ToggleAccount),
+                ("x", Action::RemoveAccount),
+                ("a", Action::AddRule),
+                ("Space", Action::ToggleRule),
+                ("x", Action::DeleteRule),
+                ("Tab", Action::NextAccount),
+                ("s", Action::SaveRule),
+                ("x", Action::DiscardDraft),
+                ("g", Action::FetchDrafts),
+                ("?", Action::Help),
+            ],
+            Preset::Vim => &[
+                ("j", Action::Next),
+                ("k", Action::Prev),
+                ("g g", Action::First),
+                ("G", Action::Last),
+                ("l", Action::Open),
+                ("Enter", Action::Open),
+                ("h", Action::Back),
+                ("q", Action::Back),
+                ("Esc", Action::Back),
+                ("d d", Action::MarkRead),
+                ("f", Action::ToggleFlag),
+                ("z s", Action::Snooze),
+                ("z a", Action::ToggleThread),
+                ("J", Action::NextInThread),
+                ("K", Action::PrevInThread),
+                ("Tab", Action::NextAttachment),
+                ("s", Action::SaveAttachment),
+                ("o", Action::OpenAttachment),
+                ("g x", Action::Links),
+                ("/", Action::Search),
+                ("c", Action::Compose),
+                ("u", Action::UndoSend),
+                ("D", Action::Drafts),
+                ("r", Action::Reply),
+                ("g r", Action::ReplyAll),
+                ("g f", Action::Forward),
+                ("g F", Action::ForwardAsAttachment),
+                ("v", Action::SwitchLayout),
+                ("+", Action::GrowList),
+                ("-", Action::ShrinkList),
+                (">", Action::GrowFolders),
+                ("<", Action::ShrinkFolders),
+                ("A", Action::Accounts),
+                ("I", Action::InboxRules),
+                ("R", Action::RuleFromMessage),
+                ("Tab", Action::NextView),
+                ("BackTab", Action::PrevView),
+                ("e", Action::EditMessage),
+                ("a", Action::Attach),
+                ("x", Action::RemoveAttachment),
+                ("s", Action::Send),
+                ("l", Action::SendLater),
+                ("q", Action::CloseCompose),
+                ("Esc", Action::CloseCompose),
+                ("a", Action::AddAccount),
+                ("e", Action::SignInAgain),
+                ("r", Action::RenameAccount),
+                ("c", Action::ChangeAccountColor),
+                ("d", Action::ToggleAccount),
+                ("x", Action::RemoveAccount),
+                ("a", Action::AddRule),
+                ("Space", Action::ToggleRule),
+                ("x", Action::DeleteRule),
+                ("Tab", Action::NextAccount),
+                ("s", Action::SaveRule),
+                ("x", Action::DiscardDraft),
+                ("g", Action::FetchDrafts),
+                ("?", Action::Help),
+                ("Z Z", Action::Quit),
+            ],
+            Preset::Emacs => &[
+                ("C-n", Action::Next),
+                ("C-p", Action::Prev),
+                ("M-<", Action::First),
+                ("M->", Action::Last),
+                ("C-f", Action::Open),
+                ("Enter", Action::Open),
+                ("C-b", Action::Back),
+                ("C-g", Action::Back),
+                ("r", Action::MarkRead),
+                ("f", Action::ToggleFlag),
+                ("C-x z", Action::Snooze),
+                ("Space", Action::ToggleThread),
+                ("M-n", Action::NextInThread),
+                ("M-p", Action::PrevInThread),
+                ("Tab", Action::NextAttachment),
+                ("s", Action::SaveAttachment),
+                ("o", Action::OpenAttachment),
+                ("l", Action::Links),
+                ("C-s", Action::Search),
+                ("m", Action::Compose),
+                ("C-x u", Action::UndoSend),
+                ("C-x d", Action::Drafts),
+                ("M-r", Action::Reply),
+                ("M-a", Action::ReplyAll),
+                ("M-f", Action::Forward),
+                ("M-F", Action::ForwardAsAttachment),
+                ("C-x 3", Action::SwitchLayout),
+                ("+", Action::GrowList),
+                ("-", Action::ShrinkList),
+                (">", Action::GrowFolders),
+                ("<", Action::ShrinkFolders),
+                ("C-x a", Action::Accounts),
+                ("C-x r", Action::InboxRules),
+                ("C-x R", Action::RuleFromMessage),
+                ("Tab", Action::NextView),
+                ("BackTab", Action::PrevView),
+                ("C-x C-e", Action::EditMessage),
+                ("C-x i", Action::Attach),
+                ("C-x k", Action::RemoveAttachment),
+                ("C-x s", Action::Send),
+                ("C-x l", Action::SendLater),
+                ("C-g", Action::CloseCompose),
+                ("a", Action::AddAccount),
+                ("e", Action::SignInAgain),
+                ("r", Action::RenameAccount),
+                ("c", Action::ChangeAccountColor),
+                ("d", Action::ToggleAccount),
+                ("x", Action::RemoveAccount),
+                ("a", Action::AddRule),
+                ("Space", Action::ToggleRule),
+                ("x", Action::DeleteRule),
+                ("Tab", Action::NextAccount),
+                ("s", Action::SaveRule),
+                ("x", Action::DiscardDraft),
+                ("g", Action::FetchDrafts),
+                ("C-h", Action::Help),
+                ("C-x C-c", Action::Quit),
+            ],
+        };
+        Keymap {
+            bindings: bindings.iter()
+                .map(|(keys, action)| (parse_keys(keys).unwrap(), *action))
+                .collect(),
+        }
+    }
+
+    pub fn from_config(config: &KeymapConfig) -> Keymap {
+        let mut keymap = Keymap::preset(config.preset);
+        for (keys, action) in &config.bindings {
+            // A key bound in one context stays bound in the others, such as `s` for sending
+            // from the compose screen when it is rebound in the list.
+            keymap.bindings.retain(|(bound_keys, bound_action)| {
+                bound_keys != keys
+                    || action.is_some_and(|action| !action.shares_context_with(bound_action))
+            });
+            if let Some(action) = action {
+                keymap.bindings.push((keys.clone(), *action));
+            }
+        }
+        keymap
+    }
+
+    /// Looks the keys up among the bindings of actions that do something in the context, so
+    /// that a key may be bound to one action in the list, another in the body and a third in
+    /// the compose screen.
+    pub fn lookup(&self, keys: &[Key], context: Context) -> Lookup {
+        let mut bindings = self.bindings.iter()
+            .filter(|(_, action)| action.applies_to(context));
+        if let Some((_, action)) = bindings.clone().find(|(bound_keys, _)| bound_keys == keys) {
+            return Lookup::Action(*action);
+        }
+        if bindings.any(|(bound_keys, _)| bound_keys.starts_with(keys)) {
+            return Lookup::Prefix;
+        }
+        Lookup::None
+    }
+
+    /// Every action with the sequences bound to it, in the order of the actions.
+    pub fn actions(&self) -> Vec<(Action, Vec<String>)> {
+        ACTIONS.iter()
+            .map(|action| (*action, self.bindings.iter()
+                .filter(|(_, bound_action)| bound_action == action)
+                .map(|(keys, _)| format_keys(keys))
+                .collect::<Vec<String>>()))
+            .filter(|(_, keys)| !keys.is_empty())
+            .collect()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Keymap {
+        Keymap::preset(Preset::Default)
+    }
+}
+
+/// Parses space-separated keys, such as `"g g"`, `"C-x C-c"` or `"Enter"`.
+fn parse_keys(keys: &str) -> Option<Vec<Key>> {
+    let keys: Option<Vec<Key>> = keys.split_whitespace().map(parse_key).collect();
+    keys.filter(|keys| !keys.is_empty())
+}
+
+fn parse_key(key: &str) -> Option<Key> {
+    let single_char = |text: &str| {
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    };
+    if let Some(rest) = key.strip_prefix("C-") {
+        return single_char(rest).map(|c| Key::Ctrl(c.to_ascii_lowercase()));
+    }
+    if let Some(rest) = key.strip_prefix("M-") {
+        return single_char(rest).map(Key::Alt);
+    }
+    Some(match key {
+        "Up" => Key::Up,
+        "Down" => Key::Down,
+        "Left" => Key::Left,
+        "Right" => Key::Right,
+        "Home" => Key::Home,
+        "End" => Key::End,
+        "PageUp" => Key::PageUp,
+        "PageDown" => Key::PageDown,
+        "Backspace" => Key::Backspace,
+        "Delete" => Key::Delete,
+        "Esc" => Key::Esc,
+        "Enter" => Key::Char('\n'),
+        "Tab" => Key::Char('\t'),
+        "BackTab" => Key::BackTab,
+        "Space" => Key::Char(' '),
+        key => Key::Char(single_char(key)?),
+    })
+}
+
+pub fn format_keys(keys: &[Key]) -> String {
+    keys.iter().map(format_key).collect::<Vec<String>>().join(" ")
+}
+
+fn format_key(key: &Key) -> String {
+    match key {
+        Key::Up => "Up".to_string(),
+        Key::Down => "Down".to_string(),
+        Key::Left => "Left".to_string(),
+        Key::Right => "Right".to_string(),
+        Key::Home => "Home".to_string(),
+        Key::End => "End".to_string(),
+        Key::PageUp => "PageUp".to_string(),
+        Key::PageDown => "PageDown".to_string(),
+        Key::Backspace => "Backspace".to_string(),
+        Key::Delete => "Delete".to_string(),
+        Key::Esc => "Esc".to_string(),
+        Key::Char('\n') => "Enter".to_string(),
+        Key::Char('\t') => "Tab".to_string(),
+        Key::BackTab => "BackTab".to_string(),
+        Key::Char(' ') => "Space".to_string(),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl(c) => format!("C-{}", c),
+        Key::Alt(c) => format!("M-{}", c),
+        key => format!("{:?}", key),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(keymap: &Keymap, keys: &str, context: Context) -> Option<Action> {
+        match keymap.lookup(&parse_keys(keys).unwrap(), context) {
+            Lookup::Action(action) => Some(action),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn looks_compose_keys_up_only_in_the_compose_screen() {
+        let keymap = Keymap::default();
+        assert_eq!(lookup(&keymap, "s", Context::Compose), Some(Action::Send));
+        assert_eq!(lookup(&keymap, "s", Context::Body), Some(Action::SaveAttachment));
+        assert_eq!(lookup(&keymap, "Esc", Context::Compose), Some(Action::CloseCompose));
+        assert_eq!(lookup(&keymap, "Esc", Context::List), Some(Action::Back));
+        assert_eq!(lookup(&keymap, "?", Context::Compose), Some(Action::Help));
+        assert_eq!(lookup(&keymap, "r", Context::Compose), None);
+        let emacs = Keymap::preset(Preset::Emacs);
+        assert!(matches!(emacs.lookup(&[Key::Ctrl('x')], Context::Compose), Lookup::Prefix));
+        assert_eq!(lookup(&emacs, "C-x s", Context::Compose), Some(Action::Send));
+    }
+
+    #[test]
+    fn looks_the_accounts_keys_up_with_the_moves_of_the_preset() {
+        let keymap = Keymap::default();
+        assert_eq!(lookup(&keymap, "r", Context::Accounts), Some(Action::RenameAccount));
+        assert_eq!(lookup(&keymap, "r", Context::List), Some(Action::MarkRead));
+        assert_eq!(lookup(&keymap, "Enter", Context::Accounts), None);
+        assert_eq!(lookup(&keymap, "Enter", Context::Providers), Some(Action::Open));
+        assert_eq!(lookup(&keymap, "a", Context::Providers), None);
+        let emacs = Keymap::preset(Preset::Emacs);
+        assert_eq!(lookup(&emacs, "C-n", Context::Accounts), Some(Action::Next));
+        assert_eq!(lookup(&emacs, "C-g", Context::Providers), Some(Action::Back));
+        let vim = Keymap::preset(Preset::Vim);
+        assert_eq!(lookup(&vim, "d", Context::Accounts), Some(Action::ToggleAccount));
+        assert_eq!(lookup(&vim, "q", Context::Accounts), Some(Action::Back));
+    }
+
+    #[test]
+    fn looks_the_inbox_rules_keys_up_in_the_list_or_the_form() {
+        let keymap = Keymap::default();
+        assert_eq!(lookup(&keymap, "Space", Context::InboxRules), Some(Action::ToggleRule));
+        assert_eq!(lookup(&keymap, "Tab", Context::InboxRules), Some(Action::NextAccount));
+        assert_eq!(lookup(&keymap, "Tab", Context::Body), Some(Action::NextAttachment));
+        assert_eq!(lookup(&keymap, "s", Context::RuleForm), Some(Action::SaveRule));
+        assert_eq!(lookup(&keymap, "s", Context::InboxRules), None);
+        assert_eq!(lookup(&keymap, "Enter", Context::RuleForm), Some(Action::Open));
+    }
+
+    #[test]
+    fn looks_the_drafts_keys_up_apart_from_longer_sequences_of_the_list() {
+        let vim = Keymap::preset(Preset::Vim);
+        assert_eq!(lookup(&vim, "g", Context::Drafts), Some(Action::FetchDrafts));
+        assert!(matches!(vim.lookup(&[Key::Char('g')], Context::List), Lookup::Prefix));
+        assert_eq!(lookup(&vim, "x", Context::Drafts), Some(Action::DiscardDraft));
+        assert_eq!(lookup(&vim, "l", Context::Drafts), Some(Action::Open));
+    }
+
+    #[test]
+    fn rebinding_a_key_keeps_its_bindings_in_other_contexts() {
+        let config = KeymapConfig {
+            preset: Preset::Default,
+            bindings: vec![
+                (parse_keys("s").unwrap(), Some(Action::Search)),
+                (parse_keys("S").unwrap(), Some(Action::Send)),
+                (parse_keys("x").unwrap(), None),
+            ],
+        };
+        let keymap = Keymap::from_config(&config);
+        assert_eq!(lookup(&keymap, "s", Context::List), Some(Action::Search));
+        // Search does nothing in the body, so `s` still saves an attachment there.
+        assert_eq!(lookup(&keymap, "s", Context::Body), Some(Action::SaveAttachment));
+        assert_eq!(lookup(&keymap, "s", Context::Compose), Some(Action::Send));
+        assert_eq!(lookup(&keymap, "S", Context::Compose), Some(Action::Send));
+        assert_eq!(lookup(&keymap, "x", Context::Compose), None);
+    }
+}
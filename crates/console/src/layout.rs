@@ -0,0 +1,153 @@
+use serde::Deserialize;
+use crate::config::deserialize_in_range;
+use crate::ui::Rect;
+
+/// Below these sizes there is no room for the panes, and fewer are shown.
+const MIN_COLUMNS_WIDTH: u16 = 80;
+const MIN_STACKED_HEIGHT: u16 = 16;
+const MIN_PANE_WIDTH: u16 = 10;
+const MIN_PANE_HEIGHT: u16 = 5;
+const MAX_LIST_PERCENT: u16 = 90;
+const MIN_LIST_PERCENT: u16 = 10;
+const MAX_FOLDERS_WIDTH: u16 = u8::MAX as u16;
+/// The to, from and subject rows, with no space between messages.
+const MIN_MESSAGE_HEIGHT: u16 = 3;
+const MAX_MESSAGE_HEIGHT: u16 = 10;
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LayoutKind {
+    /// The list on its own, with the body replacing it when opened.
+    #[default]
+    Single,
+    /// The list over a preview of the selected message.
+    Stacked,
+    /// Folders, list and preview side by side.
+    Columns,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Layout {
+    pub kind: LayoutKind,
+    /// Width of the folders pane in columns.
+    #[serde(deserialize_with = "deserialize_in_range::<_, MIN_PANE_WIDTH, MAX_FOLDERS_WIDTH>")]
+    pub folders_width: u16,
+    /// Share of the space left by the folders pane taken by the list, in percent.
+    #[serde(deserialize_with = "deserialize_in_range::<_, MIN_LIST_PERCENT, MAX_LIST_PERCENT>")]
+    pub list_percent: u16,
+    /// Rows each message takes in the list, including the space below it.
+    #[serde(
+        deserialize_with = "deserialize_in_range::<_, MIN_MESSAGE_HEIGHT, MAX_MESSAGE_HEIGHT>"
+    )]
+    pub message_height: u16,
+}
+
+impl Default for Layout {
+    fn default() -> Layout {
+        Layout {
+            kind: LayoutKind::Single,
+            folders_width: 24,
+            list_percent: 40,
+            message_height: 5,
+        }
+    }
+}
+
+/// Where each pane goes, and the lines between them.
+pub struct Panes {
+    pub folders: Option<Rect>,
+    pub list: Rect,
+    pub preview: Option<Rect>,
+    pub vertical_separators: Vec<Rect>,
+    pub horizontal_separators: Vec<Rect>,
+}
+
+impl LayoutKind {
+    /// The name used in the config.
+    pub fn name(&self) -> &'static str {
+        match self {
+            LayoutKind::Single => "single",
+            LayoutKind::Stacked => "stacked",
+            LayoutKind::Columns => "columns",
+        }
+    }
+}
+
+impl Layout {
+    /// The layout actually used in an area, which is smaller when the area cannot fit this one.
+    pub fn fit(&self, area: Rect) -> LayoutKind {
+        match self.kind {
+            LayoutKind::Columns if area.width >= MIN_COLUMNS_WIDTH => LayoutKind::Columns,
+            LayoutKind::Columns | LayoutKind::Stacked if area.height >= MIN_STACKED_HEIGHT => {
+                LayoutKind::Stacked
+            }
+            _ => LayoutKind::Single,
+        }
+    }
+
+    pub fn panes(&self, area: Rect) -> Panes {
+        let mut panes = Panes {
+            folders: None,
+            list: area,
+            preview: None,
+            vertical_separators: vec![],
+            horizontal_separators: vec![],
+        };
+        match self.fit(area) {
+            LayoutKind::Single => (),
+            LayoutKind::Stacked => {
+                let list_height = ((area.height as u32 * self.list_percent as u32 / 100) as u16)
+                    .max(MIN_PANE_HEIGHT)
+                    .min(area.height - 1 - MIN_PANE_HEIGHT);
+                panes.list = Rect::new(area.x, area.y, area.width, list_height);
+                panes.horizontal_separators
+                    .push(Rect::new(area.x, area.y + list_height, area.width, 1));
+                panes.preview = Some(Rect::new(
+                    area.x,
+                    area.y + list_height + 1,
+                    area.width,
+                    area.height - list_height - 1,
+                ));
+            }
+            LayoutKind::Columns => {
+                let folders_width = self.folders_width
+                    .max(MIN_PANE_WIDTH)
+                    .min(area.width / 3);
+                let rest = area.width - folders_width - 1;
+                let list_width = ((rest as u32 * self.list_percent as u32 / 100) as u16)
+                    .max(MIN_PANE_WIDTH)
+                    .min(rest - 1 - MIN_PANE_WIDTH);
+                let preview_width = rest - list_width - 1;
+                let list_x = area.x + folders_width + 1;
+                let preview_x = list_x + list_width + 1;
+                panes.folders = Some(Rect::new(area.x, area.y, folders_width, area.height));
+                panes.list = Rect::new(list_x, area.y, list_width, area.height);
+                panes.preview = Some(Rect::new(preview_x, area.y, preview_width, area.height));
+                panes.vertical_separators = vec![
+                    Rect::new(list_x - 1, area.y, 1, area.height),
+                    Rect::new(preview_x - 1, area.y, 1, area.height),
+                ];
+            }
+        }
+        panes
+    }
+
+    pub fn cycle_kind(&mut self) {
+        self.kind = match self.kind {
+            LayoutKind::Single => LayoutKind::Stacked,
+            LayoutKind::Stacked => LayoutKind::Columns,
+            LayoutKind::Columns => LayoutKind::Single,
+        };
+    }
+
+    pub fn resize_list(&mut self, change: i16) {
+        self.list_percent = (self.list_percent as i16 + change)
+            .clamp(MIN_LIST_PERCENT as i16, MAX_LIST_PERCENT as i16) as u16;
+    }
+
+    pub fn resize_folders(&mut self, change: i16) {
+        self.folders_width = (self.folders_width as i16 + change)
+            .clamp(MIN_PANE_WIDTH as i16, MAX_FOLDERS_WIDTH as i16) as u16;
+    }
+}
@@ -0,0 +1,128 @@
+use std::process::{Command, Stdio};
+use termion::event::Key;
+use api::mime::percent_decode;
+use crate::State;
+
+/// Hosts that wrap links in a redirect through themselves, and the query parameter holding
+/// the real destination.
+const REDIRECT_HOSTS: [(&str, &str); 1] = [
+    ("safelinks.protection.outlook.com", "url"),
+];
+
+/// The numbered list of links of the open message, shown over the body.
+pub struct LinkPicker {
+    pub selected_index: usize,
+    /// Digits typed so far, selecting the link with that number.
+    pub typed_number: String,
+}
+
+impl LinkPicker {
+    pub fn new() -> LinkPicker {
+        LinkPicker {
+            selected_index: 0,
+            typed_number: String::new(),
+        }
+    }
+}
+
+pub fn take_link_picker_key(state: &mut State, key: Key) {
+    let link_count = state.selected_message_links().len();
+    let picker = state.link_picker.as_mut().unwrap();
+    match key {
+        Key::Up => {
+            picker.selected_index = picker.selected_index.saturating_sub(1);
+            picker.typed_number.clear();
+        },
+        Key::Down => {
+            picker.selected_index = (picker.selected_index + 1).min(link_count.saturating_sub(1));
+            picker.typed_number.clear();
+        },
+        Key::Char(c) if c.is_ascii_digit() => {
+            picker.typed_number.push(c);
+            let mut number = picker.typed_number.parse::<usize>().unwrap_or(0);
+            // Start over from this digit once the number grows past the last link.
+            if number > link_count {
+                picker.typed_number = c.to_string();
+                number = c.to_digit(10).unwrap_or(0) as usize;
+            }
+            if (1..=link_count).contains(&number) {
+                picker.selected_index = number - 1;
+            }
+        },
+        Key::Char('\n') => {
+            let selected_index = picker.selected_index;
+            let url = state.selected_message_links()[selected_index].clone();
+            state.link_picker = None;
+            state.status_message = Some(match open(&url) {
+                Ok(()) => format!("opened {}", url),
+                Err(error) => format!("failed to open {}: {}", url, error),
+            });
+        },
+        Key::Esc | Key::Char('q') => state.link_picker = None,
+        _ => (),
+    }
+}
+
+/// Opens the URL with `$BROWSER`, falling back to `xdg-open`.
+pub fn open(url: &str) -> std::io::Result<()> {
+    let browser = std::env::var("BROWSER").unwrap_or_else(|_| "xdg-open".to_string());
+    Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", browser))
+        .arg("sh")
+        .arg(url)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()?;
+    Ok(())
+}
+
+/// Wraps text in an OSC 8 escape sequence, so that terminals supporting it make the text
+/// clickable. Other terminals ignore the sequence.
+pub fn hyperlink(url: &str, text: &str) -> String {
+    // The sequence is terminated by ST, and a URL containing it would end it early.
+    let url = url.replace(['\x1b', '\x07'], "");
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, text)
+}
+
+/// Returns the destination of a tracking redirect such as Outlook's Safe Links, or the URL
+/// itself if it is not one.
+pub fn unwrap_redirect(url: &str) -> String {
+    let host = url.split_once("://")
+        .map(|(_, rest)| rest.split(['/', '?', '#']).next().unwrap_or_default())
+        .unwrap_or_default()
+        .to_lowercase();
+    let parameter = REDIRECT_HOSTS.iter()
+        .find(|(redirect_host, _)| host == *redirect_host
+            || host.ends_with(&format!(".{}", redirect_host)))
+        .map(|(_, parameter)| *parameter);
+    let parameter = match parameter {
+        Some(parameter) => parameter,
+        None => return url.to_string(),
+    };
+    let query = url.split_once('?').map(|(_, query)| query).unwrap_or_default();
+    let query = query.split('#').next().unwrap_or_default();
+    let destination = query.split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == parameter)
+        .map(|(_, value)| String::from_utf8_lossy(&percent_decode(value)).to_string());
+    match destination {
+        // Redirects are sometimes wrapped more than once.
+        Some(destination) if destination.contains("://") => unwrap_redirect(&destination),
+        _ => url.to_string(),
+    }
+}
+
+/// Finds the URL in a word of plain text, without surrounding brackets or punctuation.
+pub fn find_url(word: &str) -> Option<&str> {
+    let start = ["https://", "http://"].iter()
+        .filter_map(|scheme| word.find(scheme))
+        .min()?;
+    let url = word[start..]
+        .trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']', '>', '"', '\'']);
+    if url.ends_with("://") {
+        return None;
+    }
+    Some(url)
+}
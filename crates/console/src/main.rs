@@ -1,26 +1,107 @@
-use std::io::{stdin, stdout, Write};
+use std::time::Duration;
+use clap::Parser;
 use termion::event::Key;
-use termion::input::TermRead;
 use termion::raw::IntoRawMode;
+use crate::cli::{Cli, Exit};
+use crate::event::Event;
+use crate::keymap::Keymap;
+use crate::search::SearchIndex;
 use crate::state::State;
 use crate::storage::Storage;
+use crate::theme::Theme;
+use crate::ui::{Backend, Terminal, TermionBackend};
 
 pub mod storage;
 mod state;
 mod render;
 mod parse;
+mod html;
 mod input;
 mod setup;
+mod search;
+mod attachment;
+mod compose;
+mod link;
+mod graphics;
+mod event;
+mod layout;
+mod keymap;
+mod ui;
+mod width;
+mod config;
+mod sync;
+mod theme;
+mod cli;
+mod accounts;
+mod filter;
+mod view;
+mod rules;
+mod inbox_rules;
+mod when;
+mod snooze;
+mod outbox;
+mod drafts;
+mod reply;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
+    let cli = Cli::parse();
+    // Reported before the terminal is taken over, where the message can be read.
+    let config = match config::load(cli.config) {
+        Ok(config) => config,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(Exit::Config as i32);
+        }
+    };
+    let mut storage: Storage = storage::get(
+        config.storage_path.clone().unwrap_or_else(storage::default_path)
+    );
+    for mailbox in &mut storage.outlook {
+        mailbox.body_preference = config.body_preference(mailbox);
+        mailbox.auth_options = config.outlook.auth_options();
+    }
+    if let Some(command) = cli.command {
+        let exit = cli::run(command, &config, &mut storage).await;
+        std::process::exit(exit as i32);
+    }
     let mut state = State::new();
-    let mut storage: Storage = storage::get();
-    let mut stdout = stdout().into_raw_mode().unwrap();
-    setup::setup(&mut state, &mut storage, &mut stdout).await;
-    for key in stdin().keys() {
-       update(&mut state, &mut storage, &mut stdout, key.unwrap());
+    match SearchIndex::open(&SearchIndex::path(&storage)) {
+        Ok(search_index) => state.search_index = search_index,
+        Err(error) => {
+            state.status_message = Some(format!("search is limited to this session: {}", error));
+        }
+    }
+    state.graphics_protocol = graphics::detect();
+    state.keymap = Keymap::from_config(&config.keymap);
+    state.theme = Theme::from_config(&config.theme);
+    let color_support = config.theme.colors.color_support();
+    let sync_interval = Duration::from_secs(config.sync_interval);
+    state.config = config;
+    let stdout = std::io::stdout().into_raw_mode().unwrap();
+    let mut terminal = Terminal::new(TermionBackend::new(stdout, color_support));
+    setup::setup(&mut state, &mut storage, &mut terminal).await;
+    let mut events = event::listen(sync_interval);
+    while let Some(event) = events.next().await {
+        match event {
+            Event::Key(key) => {
+                update(&mut state, &mut storage, &mut terminal, key).await;
+                events.resume_keys();
+            }
+            Event::Resize => redraw(&mut state, &mut terminal),
+            Event::Sync => {
+                sync::run(&mut state, &mut storage, &mut terminal).await;
+                redraw(&mut state, &mut terminal);
+            }
+            Event::Tick => {
+                let has_woken = snooze::wake_due(&mut state, &mut storage, &mut terminal).await;
+                let has_sent = outbox::send_due(&mut state, &mut storage, &mut terminal).await;
+                let has_saved = drafts::autosave(&mut state, &mut storage, &mut terminal).await;
+                if has_woken || has_sent || has_saved {
+                    redraw(&mut state, &mut terminal);
+                }
+            }
+        }
         if state.should_exit {
             break;
         }
@@ -28,12 +109,50 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-fn update(
+async fn update(
     state: &mut State,
     storage: &mut Storage,
-    stdout: &mut impl Write,
+    terminal: &mut Terminal<impl Backend>,
     key: Key
 ) {
     input::take_key(storage, state, key);
-    render::screen(&state, stdout);
+    if state.pending_search.is_some() {
+        search::run(state, storage, terminal).await;
+    }
+    if state.pending_attachment_action.is_some() {
+        attachment::run(state, storage, terminal).await;
+    }
+    if state.pending_reply.is_some() {
+        reply::run(state, storage, terminal).await;
+    }
+    if state.pending_compose_action.is_some() {
+        compose::run(state, storage, terminal).await;
+    }
+    if state.pending_snooze.is_some() {
+        snooze::run(state, storage, terminal).await;
+    }
+    if state.pending_account_action.is_some() {
+        accounts::run(state, storage, terminal).await;
+    }
+    if state.pending_inbox_rules_action.is_some() {
+        inbox_rules::run(state, storage, terminal).await;
+    }
+    if state.pending_drafts_action.is_some() {
+        drafts::run(state, storage, terminal).await;
+    }
+    if state.pending_inline_images.is_some() {
+        // Show the text while the images load.
+        render::screen(state, terminal);
+        graphics::run(state, storage, terminal).await;
+    }
+    redraw(state, terminal);
+}
+
+/// Lays out the selected body and its images for the current size and layout, then draws
+/// the screen.
+fn redraw(state: &mut State, terminal: &mut Terminal<impl Backend>) {
+    graphics::fit_images(state, terminal);
+    parse::try_parse_shown_message(state);
+    render::scroll_list(state, terminal);
+    render::screen(state, terminal);
 }
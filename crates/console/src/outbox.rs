@@ -0,0 +1,312 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{Local, TimeZone};
+use serde::{Serialize, Deserialize};
+use api::mail::{Mailbox, OutgoingMessage};
+use api::outlook::OutlookMailbox;
+use crate::compose::Compose;
+use crate::keymap::Action;
+use crate::ui::{Backend, Terminal};
+use crate::{render, storage, when, State, Storage};
+
+/// How long to wait before trying again to send a message that could not be sent.
+const SEND_RETRY_DELAY: u64 = 60;
+
+/// A message waiting in the outbox. Kept in storage, so that it is still sent after a
+/// restart.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QueuedMessage {
+    /// The mailbox the message is sent from.
+    pub mailbox_id: String,
+    pub message: OutgoingMessage,
+    /// When the message is handed to the server, in seconds since the Unix epoch. Until then,
+    /// it can be taken back into the editor.
+    pub send_at: u64,
+    /// When the message was scheduled for, if it was.
+    #[serde(default)]
+    pub scheduled_for: Option<u64>,
+    /// Why the last try to send it failed.
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Puts the message being written in the outbox, to be sent once the undo delay has passed
+/// or, if `scheduled_for` is set, at that time.
+pub fn queue(state: &mut State, storage: &mut Storage, scheduled_for: Option<u64>) {
+    let compose = match state.compose.take() {
+        Some(compose) => compose,
+        None => return,
+    };
+    let now = now();
+    // Sending replaces the draft on the server, but not its copy in storage.
+    storage.drafts.retain(|draft| draft.key != compose.draft_key);
+    let mut message = compose.message;
+    message.send_at = None;
+    let can_defer = storage.get_mailbox_by_id(&compose.mailbox_id)
+        .is_some_and(|mailbox| mailbox.can_defer_sending());
+    // A server that can hold the message sends it at the time even while the console is
+    // closed. Otherwise it waits here.
+    let send_at = match scheduled_for {
+        Some(time) if can_defer => {
+            message.send_at = Some(time);
+            now + state.config.send_delay
+        }
+        Some(time) => time,
+        None => now + state.config.send_delay,
+    };
+    storage.outbox.push(QueuedMessage {
+        mailbox_id: compose.mailbox_id,
+        message,
+        send_at,
+        scheduled_for,
+        error: None,
+    });
+    save(state, storage);
+    // Otherwise the countdown from `status` shows instead.
+    state.status_message = scheduled_for
+        .map(|time| format!("scheduled for {}", date_str(state, time)));
+}
+
+/// The status bar text of the send-later prompt, with when the text typed so far would send
+/// the message.
+pub fn schedule_prompt(state: &State) -> Option<String> {
+    let text = state.compose.as_ref()?.schedule_prompt.as_ref()?;
+    let when = match when::parse(text, Local::now()) {
+        Ok(time) => time.format(&state.config.date_format).to_string(),
+        Err(error) => error.to_string(),
+    };
+    Some(format!("send at: {}  ({})", text, when))
+}
+
+/// Queues the message for the time typed into the send-later prompt, leaving the prompt open
+/// if it cannot be read.
+pub fn submit_schedule_prompt(state: &mut State, storage: &mut Storage) {
+    let text = state.compose.as_ref()
+        .and_then(|compose| compose.schedule_prompt.clone())
+        .unwrap_or_default();
+    match when::parse(&text, Local::now()) {
+        Ok(time) => queue(state, storage, Some(time.timestamp() as u64)),
+        Err(error) => state.status_message = Some(error.to_string()),
+    }
+}
+
+/// Takes the last message put in the outbox back into the editor.
+pub fn undo(state: &mut State, storage: &mut Storage) {
+    if state.compose.is_some() {
+        state.status_message = Some("finish the message being written first".to_string());
+        return;
+    }
+    let queued_message = match storage.outbox.pop() {
+        Some(queued_message) => queued_message,
+        None => {
+            state.status_message = Some("the outbox is empty".to_string());
+            return;
+        }
+    };
+    save(state, storage);
+    let mut compose = Compose::new(&queued_message.mailbox_id);
+    compose.message = queued_message.message;
+    compose.message.send_at = None;
+    state.compose = Some(compose);
+    state.status_message = Some("taken back from the outbox".to_string());
+}
+
+/// Sends the messages in the outbox that are due, returning whether the screen needs to be
+/// drawn again, which it does every second while a message can still be taken back.
+pub async fn send_due(
+    state: &mut State,
+    storage: &mut Storage,
+    terminal: &mut Terminal<impl Backend>,
+) -> bool {
+    let now = now();
+    let mut has_changed = false;
+    let mut start = 0;
+    while let Some((index, mailbox)) = next_due(storage, start, now) {
+        has_changed = true;
+        render::progress(state, "sending...", terminal);
+        match mailbox.send(storage.outbox[index].message.clone()).await {
+            Ok(()) => {
+                let queued_message = storage.outbox.remove(index);
+                state.status_message = Some(match queued_message.message.send_at {
+                    Some(time) => format!("scheduled on the server for {}", date_str(state, time)),
+                    None => "message sent".to_string(),
+                });
+                start = index;
+            }
+            Err(error) => {
+                keep_unsent(state, &mut storage.outbox[index], &error.to_string(), now);
+                start = index + 1;
+            }
+        }
+    }
+    if has_changed {
+        save(state, storage);
+    }
+    has_changed || status(state).is_some()
+}
+
+/// The first message from `start` on that is due, with the mailbox to send it from. Messages
+/// of an account that is gone wait, in case it comes back.
+fn next_due(storage: &Storage, start: usize, now: u64) -> Option<(usize, &OutlookMailbox)> {
+    storage.outbox.iter().enumerate().skip(start)
+        .filter(|(_, queued_message)| queued_message.send_at <= now)
+        .find_map(|(index, queued_message)| {
+            storage.get_mailbox_by_id(&queued_message.mailbox_id).map(|mailbox| (index, mailbox))
+        })
+}
+
+/// Keeps a message that could not be sent in the outbox, to try again after a while.
+fn keep_unsent(state: &mut State, queued_message: &mut QueuedMessage, error: &str, now: u64) {
+    queued_message.send_at = now + SEND_RETRY_DELAY;
+    queued_message.error = Some(error.to_string());
+    state.status_message = Some(format!("failed to send message: {}", error));
+}
+
+/// How long until the next message is sent, while it can still be taken back.
+pub fn status(state: &State) -> Option<String> {
+    let now = now();
+    let queued_message = state.outbox.iter()
+        .filter(|queued_message| {
+            queued_message.error.is_none()
+                && queued_message.scheduled_for.is_none()
+                && queued_message.send_at > now
+        })
+        .min_by_key(|queued_message| queued_message.send_at)?;
+    let undo_keys = state.keymap.actions().into_iter()
+        .find(|(action, _)| *action == Action::UndoSend)
+        .and_then(|(_, keys)| keys.into_iter().next());
+    let undo = match undo_keys {
+        Some(keys) => format!(" ({}: undo)", keys),
+        None => String::new(),
+    };
+    Some(format!(
+        "sending \"{}\" in {}s{}",
+        queued_message.message.subject,
+        queued_message.send_at - now,
+        undo
+    ))
+}
+
+/// Saves the outbox and shows what is in it.
+fn save(state: &mut State, storage: &Storage) {
+    storage::set(storage);
+    state.outbox = storage.outbox.clone();
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs()
+}
+
+fn date_str(state: &State, timestamp: u64) -> String {
+    Local.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|date| date.format(&state.config.date_format).to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drafts::LocalDraft;
+    use crate::storage::test_storage;
+    use super::*;
+
+    fn queued_message(mailbox_id: &str, send_at: u64) -> QueuedMessage {
+        QueuedMessage {
+            mailbox_id: mailbox_id.to_string(),
+            message: OutgoingMessage::default(),
+            send_at,
+            scheduled_for: None,
+            error: None,
+        }
+    }
+
+    /// Opens the editor with a message and its draft in storage.
+    fn write(state: &mut State, storage: &mut Storage, subject: &str) {
+        let mut compose = Compose::new(storage.outlook[0].get_id());
+        compose.message.subject = subject.to_string();
+        storage.drafts.push(LocalDraft {
+            key: compose.draft_key.clone(),
+            mailbox_id: compose.mailbox_id.clone(),
+            message: compose.message.clone(),
+            updated_at: 0,
+            retry_at: 0,
+            error: None,
+        });
+        state.compose = Some(compose);
+    }
+
+    #[test]
+    fn queues_the_message_until_the_undo_delay_has_passed() {
+        let mut storage = test_storage("outbox-queue", 1);
+        let mut state = State::new();
+        write(&mut state, &mut storage, "Hello");
+        queue(&mut state, &mut storage, None);
+        assert!(state.compose.is_none() && storage.drafts.is_empty());
+        assert_eq!(state.outbox.len(), 1);
+        let queued_message = &storage.outbox[0];
+        assert_eq!(queued_message.message.subject, "Hello");
+        assert!(queued_message.send_at > now() && queued_message.message.send_at.is_none());
+        assert!(status(&state).unwrap().starts_with("sending \"Hello\" in "));
+        // The server holds a scheduled message, after the same delay to take it back.
+        write(&mut state, &mut storage, "Later");
+        let time = now() + 86400;
+        queue(&mut state, &mut storage, Some(time));
+        let queued_message = &storage.outbox[1];
+        assert_eq!(queued_message.message.send_at, Some(time));
+        assert_eq!(queued_message.scheduled_for, Some(time));
+        assert!(queued_message.send_at < time);
+        let _ = std::fs::remove_file(&storage.path);
+    }
+
+    #[test]
+    fn takes_the_last_message_back_into_the_editor() {
+        let mut storage = test_storage("outbox-undo", 1);
+        let mut state = State::new();
+        write(&mut state, &mut storage, "First");
+        queue(&mut state, &mut storage, None);
+        write(&mut state, &mut storage, "Second");
+        queue(&mut state, &mut storage, Some(now() + 86400));
+        undo(&mut state, &mut storage);
+        let compose = state.compose.as_ref().unwrap();
+        assert_eq!(compose.message.subject, "Second");
+        assert_eq!(compose.message.send_at, None);
+        assert_eq!(state.outbox.len(), 1);
+        undo(&mut state, &mut storage);
+        assert_eq!(state.status_message.as_deref(), Some("finish the message being written first"));
+        state.compose = None;
+        undo(&mut state, &mut storage);
+        assert_eq!(state.compose.as_ref().unwrap().message.subject, "First");
+        state.compose = None;
+        undo(&mut state, &mut storage);
+        assert_eq!(state.status_message.as_deref(), Some("the outbox is empty"));
+        let _ = std::fs::remove_file(&storage.path);
+    }
+
+    #[test]
+    fn sends_the_messages_that_are_due_from_accounts_still_there() {
+        let mut storage = test_storage("outbox-due", 1);
+        let id = storage.outlook[0].get_id().to_string();
+        storage.outbox = vec![
+            queued_message(&id, 200),
+            queued_message("removed", 100),
+            queued_message(&id, 100),
+            queued_message(&id, 150),
+        ];
+        let due = |start| next_due(&storage, start, 150).map(|(index, _)| index);
+        assert_eq!(due(0), Some(2));
+        assert_eq!(due(3), Some(3));
+        assert_eq!(due(4), None);
+    }
+
+    #[test]
+    fn keeps_a_message_that_failed_to_send_to_try_again() {
+        let mut state = State::new();
+        let mut queued_message = queued_message("mailbox", 100);
+        keep_unsent(&mut state, &mut queued_message, "timed out", 100);
+        assert_eq!(queued_message.send_at, 100 + SEND_RETRY_DELAY);
+        assert_eq!(queued_message.error.as_deref(), Some("timed out"));
+        assert_eq!(state.status_message.as_deref(), Some("failed to send message: timed out"));
+        // It can no longer be taken back, so no countdown is shown for it.
+        state.outbox = vec![queued_message];
+        assert_eq!(status(&state), None);
+    }
+}
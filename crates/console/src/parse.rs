@@ -1,50 +1,156 @@
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use termion::terminal_size;
-use api::mail::Message;
+use unicode_segmentation::UnicodeSegmentation;
+use api::mail::{BodyType, Message};
+use crate::html::{self, ImagePlacement};
+use crate::link::{find_url, hyperlink, unwrap_redirect};
+use crate::ui::Rect;
+use crate::width::{grapheme_width, str_width};
 use crate::State;
 
-fn remove_empty_lines(string: &mut String) {
-    let mut index_option = string.find(" \r\n");
-    while index_option != None {
-        let index = index_option.unwrap();
-        string.replace_range(index..index + 1, "");
-        index_option = string.find(" \r\n");
+pub struct ParsedBody {
+    pub text: String,
+    pub links: Vec<String>,
+    pub images: Vec<ImagePlacement>,
+}
+
+/// Lays out a message body for the terminal. `image_sizes` gives the size in cells of each
+/// decoded inline image, keyed by Content-ID.
+pub fn parse_message_body(
+    body: &str,
+    body_type: BodyType,
+    width: usize,
+    image_sizes: &HashMap<String, (u16, u16)>,
+) -> ParsedBody {
+    if body_type == BodyType::Text {
+        let (text, links) = wrap_text(body, width, true);
+        return ParsedBody {
+            text,
+            links,
+            images: vec![],
+        };
+    }
+    let document = html::render(body, width, image_sizes);
+    ParsedBody {
+        text: document.styled(),
+        links: document.links,
+        images: document.images,
     }
-    index_option = string.find("\r\n\r\n");
-    while index_option != None {
-        let index = index_option.unwrap();
-        string.replace_range(index..index + 2, "");
-        index_option = string.find("\r\n\r\n");
+}
+
+/// Like [`parse_message_body`], without terminal attributes or hyperlinks.
+pub fn parse_message_body_plain(body: &str, body_type: BodyType, width: usize) -> String {
+    if body_type == BodyType::Text {
+        return wrap_text(body, width, false).0;
     }
+    html::render(body, width, &HashMap::new()).plain()
 }
 
-fn parse_message_body(body: &str) -> String {
-    let terminal_size = terminal_size().unwrap();
-    let bytes = body.as_bytes();
-    let mut text = html2text
-    ::from_read(bytes, terminal_size.0 as usize)
-        .replace("\n", "\r\n")
-        .replace("─", "")
-        .replace("┴", "")
-        .replace("┬", "");
-    // .replace("│", ""); // TODO: use this char to parse columns into rows.
-    remove_empty_lines(&mut text);
-    text
+/// Wraps plain text at word boundaries, keeping its own line breaks, and collects the URLs
+/// it contains. When styled, URLs are made into hyperlinks.
+fn wrap_text(text: &str, width: usize, is_styled: bool) -> (String, Vec<String>) {
+    let width = width.max(1);
+    let mut lines: Vec<String> = vec![];
+    let mut links: Vec<String> = vec![];
+    for line in text.lines() {
+        let line = line.trim_end();
+        let mut current = String::new();
+        let mut current_width = 0;
+        for word in line.split(' ') {
+            let word_width = str_width(word);
+            if current_width > 0 && current_width + 1 + word_width > width {
+                lines.push(std::mem::take(&mut current));
+                current_width = 0;
+            }
+            if current_width > 0 {
+                current.push(' ');
+                current_width += 1;
+            }
+            let link = find_url(word).map(unwrap_redirect);
+            if let Some(link) = &link {
+                if !links.contains(link) {
+                    links.push(link.clone());
+                }
+            }
+            let push_piece = |current: &mut String, piece: &str| match &link {
+                Some(link) if is_styled => current.push_str(&hyperlink(link, piece)),
+                _ => current.push_str(piece),
+            };
+            // Words longer than the line are broken wherever they reach the edge.
+            let mut piece = String::new();
+            for grapheme in word.graphemes(true) {
+                let grapheme_width = grapheme_width(grapheme);
+                if current_width + grapheme_width > width && current_width > 0 {
+                    push_piece(&mut current, &std::mem::take(&mut piece));
+                    lines.push(std::mem::take(&mut current));
+                    current_width = 0;
+                }
+                piece.push_str(grapheme);
+                current_width += grapheme_width;
+            }
+            push_piece(&mut current, &piece);
+        }
+        lines.push(current);
+    }
+    (lines.join("\r\n"), links)
+}
+
+/// Width of the pane the selected body is shown in: the whole screen when it is open, and
+/// otherwise the preview pane, if the layout has one.
+pub fn body_width(state: &State) -> Option<usize> {
+    let (width, height) = terminal_size().unwrap_or((80, 24));
+    if state.should_view_message_body {
+        return Some(width as usize);
+    }
+    let main = Rect::new(0, 0, width, height.saturating_sub(1));
+    state.config.layout.panes(main).preview.map(|preview| preview.width as usize)
+}
+
+/// Lays out the selected body for the pane showing it, if any, at that pane's width.
+pub fn try_parse_shown_message(state: &mut State) {
+    if state.is_loaded && !state.messages().is_empty() && body_width(state).is_some() {
+        try_parse_selected_message(state);
+    }
 }
 
 pub fn try_parse_selected_message(state: &mut State) {
-    let selected_message_id = state.unread_messages[state.selected_message_index].id.clone();
+    let width = match body_width(state) {
+        Some(width) => width,
+        None => return,
+    };
+    if width != state.parsed_width {
+        // Bodies laid out for another pane would not fit this one.
+        state.parsed_message_bodies.clear();
+        state.message_links.clear();
+        state.message_images.clear();
+        state.parsed_width = width;
+    }
+    let selected_message_id = state.selected_message().id.clone();
     let parsed_cache = state.parsed_message_bodies.get(&selected_message_id);
     if parsed_cache.is_some() {
         return;
     }
-    let parsed = parse_message_body(&state.unread_messages[state.selected_message_index].body);
+    let message = state.selected_message();
+    let image_sizes: HashMap<String, (u16, u16)> = state.inline_images.get(&message.id)
+        .map(|images| images.iter()
+            .map(|(content_id, image)| (content_id.clone(), (image.columns, image.rows)))
+            .collect())
+        .unwrap_or_default();
+    let parsed = parse_message_body(
+        &message.body,
+        message.body_type,
+        width,
+        &image_sizes
+    );
     state.parsed_message_bodies
-        .insert(selected_message_id, parsed.clone());
+        .insert(selected_message_id.clone(), parsed.text);
+    state.message_links.insert(selected_message_id.clone(), parsed.links);
+    state.message_images.insert(selected_message_id, parsed.images);
 }
 
-pub fn sort_messages_by_date(messages: &Vec<Message>) -> Vec<Message> {
-    let mut sorted_messages = messages.clone();
+pub fn sort_messages_by_date(messages: &[Message]) -> Vec<Message> {
+    let mut sorted_messages = messages.to_vec();
     // Sort by descending order.
     sorted_messages.sort_by(|a, b| if a.date < b.date {
         Ordering::Greater
@@ -52,4 +158,27 @@ pub fn sort_messages_by_date(messages: &Vec<Message>) -> Vec<Message> {
         Ordering::Less
     });
     sorted_messages
-}
\ No newline at end of file
+}
+
+pub fn thread_key(message: &Message) -> &str {
+    message.thread_id.as_deref().unwrap_or(&message.id)
+}
+
+/// Sorts messages so that each thread is contiguous, newest message first, and threads are
+/// ordered by their newest message.
+pub fn sort_messages_by_thread(messages: &[Message]) -> Vec<Message> {
+    let mut thread_dates: HashMap<&str, u64> = HashMap::new();
+    for message in messages {
+        let date = thread_dates.entry(thread_key(message)).or_insert(0);
+        *date = (*date).max(message.date);
+    }
+    let mut sorted_messages = sort_messages_by_date(messages);
+    sorted_messages.sort_by(|a, b| {
+        let a_thread = thread_key(a);
+        let b_thread = thread_key(b);
+        thread_dates[b_thread].cmp(&thread_dates[a_thread])
+            .then_with(|| a_thread.cmp(b_thread))
+            .then_with(|| b.date.cmp(&a.date))
+    });
+    sorted_messages
+}
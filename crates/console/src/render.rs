@@ -1,99 +1,939 @@
-use std::cmp::{max, min};
-use std::io::Write;
-use std::process::exit;
-use termion::terminal_size;
-use api::mail::Recipient;
+use std::cmp::min;
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{Local, TimeZone};
+use api::mail::{Flag, Recipient};
+use crate::accounts::{AccountsMode, PROVIDERS};
+use crate::inbox_rules::{InboxRulesMode, RULE_FIELDS};
+use crate::graphics::{GraphicsProtocol, CLEAR_KITTY_PLACEMENTS};
+use crate::keymap::{Action, Context};
+use crate::parse::thread_key;
+use crate::{outbox, snooze};
 use crate::state::State;
+use crate::sync::SyncStatus;
+use crate::view::ViewKind;
+use crate::ui::{Backend, Color, Frame, Rect, Style, Terminal};
+use crate::width::str_width;
 
-pub fn print_screen(text: &str, stdout: &mut impl Write) {
-    write!(
-        stdout,
-        "{}{}{}",
-        termion::cursor::Goto(1, 1),
-        termion::clear::All,
-        text
-    ).unwrap();
-    stdout.flush().unwrap();
+pub fn screen(state: &State, terminal: &mut Terminal<impl Backend>) {
+    draw(state, None, terminal);
 }
 
-pub fn screen(state: &State, stdout: &mut impl Write) {
-    print_screen("", stdout);
-    if !state.is_loaded {
-        print_screen("Welcome to dashboard.", stdout);
-        return;
+/// Draws the screen with a message in the status bar, while a slow action runs.
+pub fn progress(state: &State, text: &str, terminal: &mut Terminal<impl Backend>) {
+    draw(state, Some(text), terminal);
+}
+
+fn draw(state: &State, progress: Option<&str>, terminal: &mut Terminal<impl Backend>) {
+    terminal.draw(|frame| {
+        if state.graphics_protocol == GraphicsProtocol::Kitty {
+            // Kitty images are not part of the text, so writing cells leaves them behind.
+            frame.overlay_reset = CLEAR_KITTY_PLACEMENTS.to_string();
+        }
+        let (main, status_bar) = frame.area().split_bottom(1);
+        if !state.is_loaded {
+            print_lines(frame, main, &["Welcome to dashboard."]);
+        } else if state.accounts.is_some() {
+            render_accounts(state, main, frame);
+        } else if state.inbox_rules.is_some() {
+            render_inbox_rules(state, main, frame);
+        } else if state.drafts.is_some() {
+            render_drafts(state, main, frame);
+        } else if state.compose.is_some() {
+            render_compose(state, main, frame);
+        } else if state.link_picker.is_some() {
+            render_link_picker(state, main, frame);
+        } else if state.should_view_message_body {
+            render_message_body(state, main, frame);
+        } else {
+            render_panes(state, main, frame);
+        }
+        render_status_bar(state, progress, status_bar, frame);
+        if state.show_help {
+            render_help(state, main, frame);
+        }
+    });
+}
+
+/// Writes one line per row from the top of the area, cutting off what does not fit.
+fn print_lines<S: AsRef<str>>(frame: &mut Frame, area: Rect, lines: &[S]) {
+    for (i, line) in lines.iter().take(area.height as usize).enumerate() {
+        let y = area.y + i as u16;
+        frame.buffer.set_string(area.x, y, line.as_ref(), &Style::default(), area.width);
     }
-    if state.should_view_message_body {
-        render_message_body(state, stdout);
-        return;
+}
+
+/// Like `print_lines`, with a style for each line.
+fn print_styled_lines(frame: &mut Frame, area: Rect, lines: &[(String, &Style)]) {
+    for (i, (line, style)) in lines.iter().take(area.height as usize).enumerate() {
+        let y = area.y + i as u16;
+        frame.buffer.set_string(area.x, y, line, style, area.width);
     }
-    render_messages(&state, stdout);
 }
 
-fn render_message_body(state: &State, stdout: &mut impl Write) {
-    let mut content: String = String::new();
-    let selected_message_id = state.unread_messages[state.selected_message_index].id.clone();
-    let body = state.parsed_message_bodies.get(&selected_message_id).unwrap().clone();
-    let terminal_size = termion::terminal_size().unwrap();
-    let max_rows: usize = terminal_size.1 as usize;
-    let truncated = body.split("\r\n")
-        .skip(state.cursor_height)
-        .take(max_rows).collect::<Vec<&str>>().join("\r\n");
-    content.push_str(
-        &truncated
-    );
-    print_screen(&content, stdout);
-}
-
-fn render_messages(state: &State, stout: &mut impl Write) {
-    let mut content: String = String::new();
-    let terminal_size = terminal_size().unwrap();
-    let terminal_width = terminal_size.0 as usize;
-    let terminal_height = terminal_size.1 as usize;
-    let message_height = 5;
-    let messages_per_page = terminal_height / message_height;
-    if state.unread_messages.len() == 0 {
-        print_screen("mailbox is empty\r\n", stout);
+/// The bottom row: progress, then any prompt, then the outcome of the last action, falling
+/// back to a hint about the help screen.
+fn render_status_bar(state: &State, progress: Option<&str>, area: Rect, frame: &mut Frame) {
+    let compose_prompt = state.compose.as_ref()
+        .and_then(|compose| compose.attach_prompt.as_ref())
+        .map(|prompt| format!("attach file: {}", prompt));
+    let accounts_prompt = state.accounts.as_ref().and_then(|accounts| accounts.prompt())
+        .or_else(|| state.inbox_rules.as_ref().and_then(|rules| rules.prompt()))
+        .or_else(|| state.drafts.as_ref().and_then(|drafts| drafts.prompt()));
+    let search_prompt = state.search_prompt.as_ref().map(|prompt| {
+        let error = match &state.search_error {
+            Some(error) => format!("  ({})", error),
+            None => "".to_string(),
+        };
+        format!("/{}{}", prompt, error)
+    });
+    let text = progress.map(|progress| progress.to_string())
+        .or(accounts_prompt)
+        .or(compose_prompt)
+        .or(search_prompt)
+        .or_else(|| snooze::prompt(state))
+        .or_else(|| outbox::schedule_prompt(state))
+        .or_else(|| state.status_message.clone())
+        .or_else(|| outbox::status(state));
+    let theme = &state.theme;
+    frame.buffer.fill(area, &theme.status_bar);
+    match text {
+        Some(text) => {
+            frame.buffer.set_string(area.x, area.y, &text, &theme.status_bar, area.width);
+        }
+        None if state.is_loaded => {
+            let help_keys = state.keymap.actions().into_iter()
+                .find(|(action, _)| *action == Action::Help)
+                .and_then(|(_, keys)| keys.into_iter().next());
+            if let Some(keys) = help_keys {
+                let hint = format!("{}: help", keys);
+                let x = area.right().saturating_sub(str_width(&hint) as u16);
+                let mut style = theme.status_bar.clone();
+                style.patch(&theme.dim);
+                frame.buffer.set_string(x, area.y, &hint, &style, area.width);
+            }
+        }
+        None => (),
+    }
+}
+
+/// The keys of the current screen, in a box over it.
+/// The keys bound to each action that does something in the context, with what it does.
+fn keymap_help(state: &State, context: Context) -> Vec<(String, String)> {
+    state.keymap.actions().into_iter()
+        .filter(|(action, _)| action.applies_to(context))
+        .map(|(action, keys)| (keys.join(", "), action.description(context).to_string()))
+        .collect()
+}
+
+fn render_help(state: &State, area: Rect, frame: &mut Frame) {
+    let mut keys: Vec<(String, String)> = if let Some(screen) = &state.accounts {
+        let context = match screen.mode {
+            AccountsMode::PickProvider { .. } => Context::Providers,
+            _ => Context::Accounts,
+        };
+        keymap_help(state, context)
+    } else if let Some(screen) = &state.inbox_rules {
+        let context = match screen.mode {
+            InboxRulesMode::Edit(_) => Context::RuleForm,
+            _ => Context::InboxRules,
+        };
+        keymap_help(state, context)
+    } else if state.drafts.is_some() {
+        keymap_help(state, Context::Drafts)
+    } else {
+        let context = if state.compose.is_some() {
+            Context::Compose
+        } else if state.should_view_message_body {
+            Context::Body
+        } else {
+            Context::List
+        };
+        let mut keys = keymap_help(state, context);
+        if context == Context::List {
+            keys.push(("1-9".to_string(), "show a view by its number".to_string()));
+        }
+        keys
+    };
+    keys.push(("C-c".to_string(), "quit".to_string()));
+    let keys_width = keys.iter().map(|(keys, _)| str_width(keys)).max().unwrap_or(0);
+    let lines: Vec<String> = keys.iter()
+        .map(|(keys, description)| format!(
+            "{}{}  {}",
+            " ".repeat(keys_width - str_width(keys)),
+            keys,
+            description
+        ))
+        .collect();
+    let width = (lines.iter().map(|line| str_width(line)).max().unwrap_or(0) as u16)
+        .saturating_add(4);
+    let help_area = area.centered(width, (lines.len() as u16).saturating_add(2));
+    // Too small for the border around a line, as a terminal can be while it is resized.
+    if help_area.width < 5 || help_area.height < 3 {
         return;
     }
-    let to_index = max(
-        min(messages_per_page, state.unread_messages.len() - 1),
-        state.selected_message_index
+    frame.buffer.fill(help_area, &Style::default());
+    let border = |left: &str, middle: &str, right: &str| format!(
+        "{}{}{}",
+        left,
+        middle.repeat((help_area.width as usize).saturating_sub(2)),
+        right
     );
-    let from_index = if messages_per_page >= to_index {
-        0
+    let style = Style::default();
+    let (x, width) = (help_area.x, help_area.width);
+    frame.buffer.set_string(x, help_area.y, &border("┌", "─", "┐"), &style, width);
+    frame.buffer.set_string(x + 2, help_area.y, " keys ", &style, width.saturating_sub(4));
+    for (i, line) in lines.iter().enumerate().take((help_area.height as usize).saturating_sub(2)) {
+        let y = help_area.y + 1 + i as u16;
+        frame.buffer.set_string(x, y, "│", &style, 1);
+        frame.buffer.set_string(x + 2, y, line, &style, width.saturating_sub(4));
+        frame.buffer.set_string(help_area.right() - 1, y, "│", &style, 1);
+    }
+    frame.buffer.set_string(x, help_area.bottom() - 1, &border("└", "─", "┘"), &style, width);
+}
+
+fn render_message_body(state: &State, area: Rect, frame: &mut Frame) {
+    let mut lines: Vec<String> = vec![];
+    let selected_message_id = &state.selected_message().id;
+    let body = match state.parsed_message_bodies.get(selected_message_id) {
+        Some(body) => body,
+        None => return,
+    };
+    let (thread_position, thread_size) = state.thread_position(state.selected_message_index);
+    if thread_size > 1 {
+        lines.push(format!(
+            "message {}/{} in thread",
+            thread_position + 1,
+            thread_size
+        ));
+    }
+    let attachments = &state.selected_message().attachments;
+    if !attachments.is_empty() {
+        lines.push("attachments:".to_string());
+        for (i, attachment) in attachments.iter().enumerate() {
+            lines.push(format!(
+                "{} [{}] {} ({}, {}{})",
+                if i == state.selected_attachment_index { ">" } else { " " },
+                i + 1,
+                attachment.name,
+                size_str(attachment.size),
+                attachment.content_type,
+                if attachment.is_inline { ", inline" } else { "" }
+            ));
+        }
+        lines.push("".to_string());
+    }
+    print_lines(frame, area, &lines);
+    let header_rows = (lines.len() as u16).min(area.height);
+    let body_area = Rect::new(area.x, area.y + header_rows, area.width, area.height - header_rows);
+    render_body(state, body, state.cursor_height, body_area, frame);
+}
+
+/// Draws the parsed body of the selected message from line `first_line` onwards.
+fn render_body(state: &State, body: &str, first_line: usize, area: Rect, frame: &mut Frame) {
+    for (i, line) in body.split("\r\n")
+        .skip(first_line)
+        .take(area.height as usize)
+        .enumerate() {
+        let y = area.y + i as u16;
+        let style = state.theme.quote(quote_level(line)).cloned().unwrap_or_default();
+        frame.buffer.set_ansi_string(area.x, y, line, &style, area.width);
+    }
+    render_inline_images(state, first_line, area, frame);
+}
+
+/// How many `>` the line starts with, ignoring spaces between them and escape sequences.
+fn quote_level(line: &str) -> usize {
+    let mut level = 0;
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '>' => level += 1,
+            ' ' => (),
+            '\x1b' => match chars.next() {
+                Some('[') => {
+                    chars.by_ref().find(|c| ('@'..='~').contains(c));
+                }
+                Some(']') => {
+                    // OSC sequences end with BEL or ESC \.
+                    let end = chars.by_ref().find(|c| *c == '\x1b' || *c == '\x07');
+                    if end == Some('\x1b') {
+                        chars.next();
+                    }
+                }
+                _ => (),
+            },
+            _ => break,
+        }
+    }
+    level
+}
+
+/// Draws the images of the selected message over their placeholders in the visible part of
+/// the body.
+fn render_inline_images(state: &State, first_line: usize, area: Rect, frame: &mut Frame) {
+    let message_id = &state.selected_message().id;
+    let (placements, images) = match (
+        state.message_images.get(message_id),
+        state.inline_images.get(message_id),
+    ) {
+        (Some(placements), Some(images)) => (placements, images),
+        _ => return,
+    };
+    let last_line = first_line + area.height as usize;
+    for placement in placements {
+        let image = match images.get(&placement.content_id) {
+            Some(image) => image,
+            None => continue,
+        };
+        let image_end = placement.line + image.rows as usize;
+        if image_end <= first_line || placement.line >= last_line
+            || placement.column >= area.width as usize {
+            continue;
+        }
+        let top_line = placement.line.max(first_line);
+        let image_area = Rect::new(
+            area.x + placement.column as u16,
+            area.y + (top_line - first_line) as u16,
+            area.width - placement.column as u16,
+            (image_end.min(last_line) - top_line) as u16,
+        );
+        image.draw(
+            state.graphics_protocol,
+            image_area,
+            (top_line - placement.line) as u16,
+            frame,
+        );
+    }
+}
+
+fn render_link_picker(state: &State, area: Rect, frame: &mut Frame) {
+    let picker = state.link_picker.as_ref().unwrap();
+    let links = state.selected_message_links();
+    let max_rows = (area.height as usize).saturating_sub(2).max(1);
+    // Scroll just enough to keep the selection on screen.
+    let first_row = (picker.selected_index + 1).saturating_sub(max_rows);
+    let mut lines = vec![
+        "links (up/down or number: select, enter: open, esc: close)".to_string(),
+        "".to_string(),
+    ];
+    for (i, link) in links.iter().enumerate().skip(first_row).take(max_rows) {
+        lines.push(format!(
+            "{} [{}] {}",
+            if i == picker.selected_index { ">" } else { " " },
+            i + 1,
+            link
+        ));
+    }
+    print_lines(frame, area, &lines);
+}
+
+fn render_compose(state: &State, area: Rect, frame: &mut Frame) {
+    let compose = state.compose.as_ref().unwrap();
+    let message = &compose.message;
+    let list = |recipients: &[Recipient]| recipients.iter()
+        .map(|recipient| recipient.to_string())
+        .collect::<Vec<String>>()
+        .join(", ");
+    let compose_keys: Vec<String> = state.keymap.actions().into_iter()
+        .filter(|(action, _)| action.context() == Context::Compose)
+        .map(|(action, keys)| format!("{}: {}", keys[0], action.name().replace('-', " ")))
+        .collect();
+    let mut lines = vec![
+        format!("compose ({})", compose_keys.join(", ")),
+        "".to_string(),
+        format!("     to: {}", list(&message.to)),
+        format!("     cc: {}", list(&message.cc)),
+        format!("subject: {}", message.subject),
+    ];
+    for attachment in &message.attachments {
+        lines.push(format!(
+            " attach: {} ({}, {})",
+            attachment.name,
+            size_str(attachment.size),
+            attachment.content_type
+        ));
+    }
+    lines.push("".to_string());
+    lines.extend(message.body.lines().map(|line| line.to_string()));
+    print_lines(frame, area, &lines);
+}
+
+fn render_accounts(state: &State, area: Rect, frame: &mut Frame) {
+    let screen = state.accounts.as_ref().unwrap();
+    let theme = &state.theme;
+    let default_style = Style::default();
+    let mut lines = vec![
+        (
+            "accounts (a: add, e: sign in again, r: rename, c: colour, d: disable, x: remove)"
+                .to_string(),
+            &default_style,
+        ),
+        ("".to_string(), &default_style),
+    ];
+    if screen.rows.is_empty() {
+        lines.push(("no account has been added".to_string(), &theme.dim));
+    }
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let name_width = screen.rows.iter().map(|row| str_width(&row.name)).max().unwrap_or(0);
+    let address_width = screen.rows.iter()
+        .map(|row| str_width(row.address.as_deref().unwrap_or(&row.id)))
+        .max()
+        .unwrap_or(0);
+    let first_row = lines.len();
+    for (i, row) in screen.rows.iter().enumerate() {
+        let token = if row.token_expiry > now {
+            format!("token expires {}", date_str(state, row.token_expiry))
+        } else {
+            "token expired".to_string()
+        };
+        let sync = match state.sync_statuses.get(&row.id) {
+            _ if row.is_disabled => "disabled".to_string(),
+            Some(SyncStatus { time, error: None }) => format!("synced {}", date_str(state, *time)),
+            Some(SyncStatus { time, error: Some(error) }) => {
+                format!("sync failed {}: {}", date_str(state, *time), error)
+            }
+            None => "not synced".to_string(),
+        };
+        // The id stands in for the address until it has been looked up.
+        let address = row.address.as_deref().unwrap_or(&row.id);
+        let line = format!(
+            "{} {}{}  {}{}  {}  {}  {}",
+            if i == screen.selected_index { ">" } else { " " },
+            row.name,
+            " ".repeat(name_width - str_width(&row.name)),
+            address,
+            " ".repeat(address_width - str_width(address)),
+            row.provider.name(),
+            token,
+            sync
+        );
+        lines.push((line, if row.is_disabled { &theme.dim } else { &default_style }));
+    }
+    match &screen.mode {
+        AccountsMode::PickProvider { selected_index } => {
+            lines.push(("".to_string(), &default_style));
+            lines.push((
+                "add an account with (enter: select, esc: cancel)".to_string(),
+                &default_style,
+            ));
+            for (i, provider) in PROVIDERS.iter().enumerate() {
+                let marker = if i == *selected_index { ">" } else { " " };
+                lines.push((format!("{} {}", marker, provider.name()), &default_style));
+            }
+        }
+        AccountsMode::SigningIn { url } => {
+            lines.push(("".to_string(), &default_style));
+            lines.push(("sign in at this address, if the browser did not open:".to_string(),
+                &default_style));
+            // The whole address is needed to sign in, so it wraps instead of being cut off.
+            let chars: Vec<char> = url.chars().collect();
+            for chunk in chars.chunks((area.width as usize).max(1)) {
+                lines.push((chunk.iter().collect(), &default_style));
+            }
+        }
+        _ => (),
+    }
+    print_styled_lines(frame, area, &lines);
+    if theme.no_color {
+        return;
+    }
+    for (i, row) in screen.rows.iter().enumerate() {
+        let y = area.y + (first_row + i) as u16;
+        if y >= area.bottom() {
+            break;
+        }
+        let style = Style { fg: row.color, bold: true, ..Style::default() };
+        frame.buffer.set_string(area.x + 2, y, &row.name, &style, area.width.saturating_sub(2));
+    }
+}
+
+fn render_inbox_rules(state: &State, area: Rect, frame: &mut Frame) {
+    let screen = state.inbox_rules.as_ref().unwrap();
+    let theme = &state.theme;
+    let default_style = Style::default();
+    let mut lines = vec![];
+    match &screen.mode {
+        InboxRulesMode::Edit(form) => {
+            let title = match &form.rule {
+                Some(rule) => format!("edit {}", rule.display_name),
+                None => format!("new rule for {}", screen.mailbox_name),
+            };
+            lines.push((
+                format!("{} (enter: edit field, s: save, esc: cancel)", title),
+                &default_style,
+            ));
+            lines.push(("".to_string(), &default_style));
+            let label_width = RULE_FIELDS.iter()
+                .map(|field| str_width(field.label()))
+                .max()
+                .unwrap_or(0);
+            for (i, (field, value)) in RULE_FIELDS.iter().zip(&form.values).enumerate() {
+                let is_selected = i == form.selected_index;
+                let value = if is_selected && form.is_editing {
+                    let hint = if field.is_yes_no() { "  (yes or no)" } else { "" };
+                    format!("{}_{}", value, hint)
+                } else {
+                    value.clone()
+                };
+                let line = format!(
+                    "{} {}{}  {}",
+                    if is_selected { ">" } else { " " },
+                    field.label(),
+                    " ".repeat(label_width - str_width(field.label())),
+                    value
+                );
+                lines.push((line, &default_style));
+            }
+        }
+        _ => {
+            lines.push((
+                format!(
+                    "inbox rules of {} (a: add, enter: edit, space: enable, x: delete, \
+                        tab: next account)",
+                    screen.mailbox_name
+                ),
+                &default_style,
+            ));
+            lines.push(("".to_string(), &default_style));
+            if screen.rules.is_empty() {
+                lines.push(("no rules".to_string(), &theme.dim));
+            }
+            for (i, rule) in screen.rules.iter().enumerate() {
+                let mut notes = String::new();
+                if rule.has_error {
+                    notes.push_str("  (failing)");
+                }
+                if rule.is_read_only {
+                    notes.push_str("  (read only)");
+                }
+                let line = format!(
+                    "{} [{}] {}{}  {}",
+                    if i == screen.selected_index { ">" } else { " " },
+                    if rule.is_enabled { "x" } else { " " },
+                    rule.display_name,
+                    notes,
+                    screen.describe(rule)
+                );
+                lines.push((line, if rule.is_enabled { &default_style } else { &theme.dim }));
+            }
+        }
+    }
+    print_styled_lines(frame, area, &lines);
+}
+
+fn render_drafts(state: &State, area: Rect, frame: &mut Frame) {
+    let screen = state.drafts.as_ref().unwrap();
+    let theme = &state.theme;
+    let default_style = Style::default();
+    let mut lines = vec![
+        ("drafts (enter: resume, x: discard, g: fetch again)".to_string(), &default_style),
+        ("".to_string(), &default_style),
+    ];
+    if screen.rows.is_empty() {
+        lines.push(("no drafts".to_string(), &theme.dim));
+    }
+    let show_account = state.mailboxes.len() > 1;
+    for (i, row) in screen.rows.iter().enumerate() {
+        let account = match state.mailbox_tag(&row.mailbox_id) {
+            Some(tag) if show_account => format!("{}  ", tag.name),
+            _ => String::new(),
+        };
+        let to = row.message.to.iter()
+            .map(|recipient| recipient.to_string())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let subject = if row.message.subject.is_empty() {
+            "(no subject)"
+        } else {
+            &row.message.subject
+        };
+        let sync = match (&row.key, &row.error) {
+            (Some(_), Some(error)) => format!("  (not saved to the server: {})", error),
+            (Some(_), None) => "  (not saved to the server)".to_string(),
+            _ => String::new(),
+        };
+        let line = format!(
+            "{} {}{}  {}  to: {}{}",
+            if i == screen.selected_index { ">" } else { " " },
+            account,
+            date_str(state, row.updated_at),
+            subject,
+            to,
+            sync
+        );
+        lines.push((line, if row.key.is_some() { &theme.dim } else { &default_style }));
+    }
+    print_styled_lines(frame, area, &lines);
+}
+
+/// The date in the local time zone, as snooze and send times are typed in.
+fn date_str(state: &State, timestamp: u64) -> String {
+    Local.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|date| date.format(&state.config.date_format).to_string())
+        .unwrap_or_default()
+}
+
+fn size_str(size: u64) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, units[unit])
     } else {
-        to_index - messages_per_page
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+/// The list with the folders and preview panes the layout has room for.
+fn render_panes(state: &State, area: Rect, frame: &mut Frame) {
+    let panes = state.config.layout.panes(area);
+    if let Some(folders) = panes.folders {
+        render_folders(state, folders, frame);
+    }
+    render_messages(state, panes.list, frame);
+    if let Some(preview) = panes.preview {
+        render_preview(state, preview, frame);
+    }
+    let style = &state.theme.dim;
+    for separator in &panes.vertical_separators {
+        for y in separator.y..separator.bottom() {
+            frame.buffer.set_string(separator.x, y, "│", style, 1);
+        }
+    }
+    for separator in &panes.horizontal_separators {
+        let line = "─".repeat(separator.width as usize);
+        frame.buffer.set_string(separator.x, separator.y, &line, style, separator.width);
+    }
+}
+
+/// Where the listed messages come from, with how many there are in each.
+fn render_folders(state: &State, area: Rect, frame: &mut Frame) {
+    let mut rows: Vec<(String, usize, bool)> = vec![];
+    for (i, view) in state.views.iter().enumerate() {
+        let name = match view.kind {
+            ViewKind::Account(_) => format!("  {}", view.name),
+            _ => view.name.clone(),
+        };
+        let count = state.unread_messages.iter()
+            .filter(|message| view.includes(message, state.mailbox_tag(&message.mailbox_id)))
+            .count();
+        rows.push((name, count, i == state.view_index && state.search_results.is_none()));
+    }
+    if state.snoozed_count > 0 {
+        rows.push(("snoozed".to_string(), state.snoozed_count, false));
+    }
+    if !state.outbox.is_empty() {
+        rows.push(("outbox".to_string(), state.outbox.len(), false));
+    }
+    if let Some(search_results) = &state.search_results {
+        rows.push((
+            format!("search: {}", search_results.query),
+            search_results.messages.len(),
+            true,
+        ));
+    }
+    for (i, (name, count, is_selected)) in rows.iter().enumerate() {
+        let y = area.y + i as u16;
+        if y >= area.bottom() {
+            break;
+        }
+        let count = count.to_string();
+        let name_width = area.width.saturating_sub(count.len() as u16 + 1);
+        frame.buffer.set_string(area.x, y, name, &Style::default(), name_width);
+        frame.buffer.set_string(
+            area.right().saturating_sub(count.len() as u16),
+            y,
+            &count,
+            &Style::default(),
+            area.width
+        );
+        if *is_selected {
+            frame.buffer.patch_style(area.row(i as u16), &state.theme.selected);
+        }
+    }
+}
+
+/// The headers and start of the body of the selected message.
+fn render_preview(state: &State, area: Rect, frame: &mut Frame) {
+    let message = match state.messages().get(state.selected_message_index) {
+        Some(message) => message,
+        None => return,
+    };
+    let date = date_str(state, message.date);
+    let theme = &state.theme;
+    let lines = [
+        (format!("   from: {} <{}>", message.from.name, message.from.address), &theme.from),
+        (format!("   date: {}", date), &theme.date),
+        (format!("subject: {}", message.subject), &theme.subject),
+    ];
+    print_styled_lines(frame, area, &lines);
+    let header_rows = (lines.len() as u16 + 1).min(area.height);
+    let body = match state.parsed_message_bodies.get(&message.id) {
+        Some(body) => body,
+        None => return,
+    };
+    let body_area = Rect::new(area.x, area.y + header_rows, area.width, area.height - header_rows);
+    render_body(state, body, 0, body_area, frame);
+}
+
+fn render_messages(state: &State, area: Rect, frame: &mut Frame) {
+    let message_height = state.config.layout.message_height;
+    let view = state.view();
+    let mut header = match view.kind {
+        ViewKind::Unified => String::new(),
+        _ => format!("{} ({} unread)", view.name, state.view_messages.len()),
     };
-    let render_array = &state.unread_messages[from_index..to_index + 1];
-    fn print_char(content: &mut String, c: char, index: usize, terminal_width: usize) {
-        let mut index = index;
-        while index < terminal_width {
-            content.push(c);
-            index += 1;
+    if let Some(search_results) = &state.search_results {
+        header = format!(
+            "search: {} ({} results, esc to return)",
+            search_results.query,
+            search_results.messages.len()
+        );
+    }
+    if state.messages().is_empty() {
+        let mut lines = vec![header];
+        if state.search_results.is_some() {
+            lines.push("no messages found".to_string());
+        } else if view.kind == ViewKind::Unified {
+            lines[0] = "mailbox is empty".to_string();
+        } else {
+            lines.push("no unread messages".to_string());
         }
+        print_lines(frame, area, &lines);
+        return;
     }
-    for (i, message) in render_array.iter().enumerate() {
+    print_lines(frame, area, &[header]);
+    let messages_per_page = messages_per_page(state, area);
+    let rows = state.visible_message_indices();
+    let from_row = list_scroll(state, messages_per_page);
+    let to_row = min(from_row + messages_per_page, rows.len()) - 1;
+    for (i, index) in rows[from_row..to_row + 1].iter().cloned().enumerate() {
+        let message = &state.messages()[index];
         let first_recipient = message.to.first().unwrap_or(&Recipient {
             name: "unknown".to_string(),
             address: "".to_string(),
         }).clone();
-        content.push_str("\r\n\n");
-        if from_index + i == state.selected_message_index {
-            content.push_str(&format!("{}", termion::color::Bg(termion::color::LightBlack)));
-        }
-        let to_str = format!("     to: {}", &first_recipient.address);
-        content.push_str(to_str.as_str());
-        print_char(&mut content, ' ', to_str.len(), terminal_width);
-        content.push_str("\r\n");
-        let from_str = format!("   from: {} <{}>", &message.from.name, &message.from.address);
-        content.push_str(from_str.as_str());
-        print_char(&mut content, ' ', from_str.len(), terminal_width);
-        content.push_str("\r\n");
-        let subject_str = format!("subject: {}", &message.subject);
-        content.push_str(subject_str.as_str());
-        print_char(&mut content, ' ', subject_str.len(), terminal_width);
-        content.push_str(&format!("{}", termion::color::Bg(termion::color::Reset)));
-    }
-    content.push_str("\r\n");
-    print_screen(&content, stout);
+        let theme = &state.theme;
+        let subject_style = if message.flagged.is_flagged() {
+            let mut style = theme.subject.clone();
+            style.patch(&theme.flagged);
+            style
+        } else {
+            theme.subject.clone()
+        };
+        let default_style = Style::default();
+        let lines = [
+            (format!("     to: {}", &first_recipient.address), &default_style),
+            (format!("   from: {} <{}>", &message.from.name, &message.from.address), &theme.from),
+            (
+                format!(
+                    "subject: {}{}{}{}",
+                    thread_str(state, index),
+                    flag_str(&message.flagged),
+                    &message.subject,
+                    categories_str(&message.categories)
+                ),
+                &subject_style,
+            ),
+        ];
+        let y = area.y + 2 + (i * message_height as usize) as u16;
+        if y + lines.len() as u16 > area.bottom() {
+            break;
+        }
+        let message_area = Rect::new(area.x, y, area.width, lines.len() as u16);
+        print_styled_lines(frame, message_area, &lines);
+        // With several accounts listed together, each message says which one it is from.
+        if let Some(tag) = state.mailbox_tag(&message.mailbox_id)
+            .filter(|_| state.mailboxes.len() > 1) {
+            let text = format!(" {} ", tag.name);
+            let style = Style {
+                fg: if theme.no_color { Color::Reset } else { tag.color },
+                bold: true,
+                ..Style::default()
+            };
+            let x = area.right().saturating_sub(str_width(&text) as u16);
+            frame.buffer.set_string(x, y, &text, &style, area.width);
+        }
+        if state.search_results.is_some() && !message.is_read {
+            // Only search results mix read and unread messages.
+            frame.buffer.patch_style(message_area, &theme.unread);
+        }
+        if index == state.selected_message_index {
+            frame.buffer.patch_style(message_area, &theme.selected);
+        }
+    }
+}
+
+fn messages_per_page(state: &State, area: Rect) -> usize {
+    (area.height.saturating_sub(1) / state.config.layout.message_height).max(1) as usize
+}
+
+/// The first row of the list to show: the same as before, unless the selected message would
+/// be off the page.
+fn list_scroll(state: &State, messages_per_page: usize) -> usize {
+    let selected_row = state.visible_message_indices().iter()
+        .position(|index| *index == state.selected_message_index)
+        .unwrap_or(0);
+    state.list_scroll
+        .min(selected_row)
+        .max((selected_row + 1).saturating_sub(messages_per_page))
+}
+
+/// Remembers where the list is scrolled to for the size of the list pane, before drawing.
+pub fn scroll_list(state: &mut State, terminal: &Terminal<impl Backend>) {
+    let (main, _) = terminal.size().split_bottom(1);
+    let list = state.config.layout.panes(main).list;
+    state.list_scroll = list_scroll(state, messages_per_page(state, list));
+}
+
+/// Marks collapsed threads with their size and indents the older messages of expanded ones.
+fn thread_str(state: &State, index: usize) -> String {
+    if !state.is_thread_head(index) {
+        return "  ↳ ".to_string();
+    }
+    let thread_size = state.thread_size(index);
+    if thread_size == 1 {
+        return "".to_string();
+    }
+    let is_expanded = state.expanded_threads
+        .contains(thread_key(&state.messages()[index]));
+    format!("{} ({}) ", if is_expanded { "▾" } else { "▸" }, thread_size)
+}
+
+fn flag_str(flag: &Flag) -> String {
+    match flag {
+        Flag::NotFlagged => "".to_string(),
+        Flag::Flagged(None) => "⚑ ".to_string(),
+        Flag::Flagged(Some(due)) => match Local.timestamp_opt(*due as i64, 0).single() {
+            Some(due) => format!("⚑ (due {}) ", due.format("%Y-%m-%d")),
+            None => "⚑ ".to_string(),
+        },
+        Flag::Complete => "✓ ".to_string(),
+    }
+}
+
+fn categories_str(categories: &[String]) -> String {
+    if categories.is_empty() {
+        return "".to_string();
+    }
+    format!(" [{}]", categories.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use api::mail::Message;
+    use crate::layout::LayoutKind;
+    use crate::ui::{Cell, MemoryBackend};
+    use super::*;
+
+    fn state(layout: LayoutKind) -> State {
+        let mut state = State::new();
+        state.is_loaded = true;
+        state.config.layout.kind = layout;
+        state.unread_messages = vec![
+            Message::test("1")
+                .with_from("Alice", "alice@example.com")
+                .with_subject("Lunch")
+                .with_date(1641204000),
+            Message::test("2")
+                .with_from("Bob", "bob@example.com")
+                .with_subject("Weekly report")
+                .with_date(1641204000),
+        ];
+        state.refresh_view_messages();
+        state
+    }
+
+    fn draw(state: &State, width: u16, height: u16) -> Terminal<MemoryBackend> {
+        let mut terminal = Terminal::new(MemoryBackend::new(width, height));
+        screen(state, &mut terminal);
+        terminal
+    }
+
+    fn cell(terminal: &Terminal<MemoryBackend>, x: u16, y: u16) -> &Cell {
+        let buffer = &terminal.backend().buffer;
+        &buffer.cells()[y as usize * buffer.area.width as usize + x as usize]
+    }
+
+    #[test]
+    fn lists_messages_with_the_selected_one_highlighted() {
+        let mut state = state(LayoutKind::Single);
+        state.selected_message_index = 1;
+        let terminal = draw(&state, 60, 16);
+        let lines = terminal.backend().lines();
+        assert_eq!(lines[2..5], [
+            "     to: me@example.com",
+            "   from: Alice <alice@example.com>",
+            "subject: Lunch",
+        ]);
+        assert_eq!(lines[7..10], [
+            "     to: me@example.com",
+            "   from: Bob <bob@example.com>",
+            "subject: Weekly report",
+        ]);
+        let selected_bg = state.theme.selected.bg;
+        assert_ne!(cell(&terminal, 0, 2).style.bg, selected_bg);
+        assert!((7..10).all(|y| cell(&terminal, 0, y).style.bg == selected_bg));
+    }
+
+    #[test]
+    fn previews_the_selected_message_under_the_list() {
+        let mut state = state(LayoutKind::Stacked);
+        state.parsed_message_bodies.insert("1".to_string(), "See you at noon.".to_string());
+        let terminal = draw(&state, 60, 24);
+        let lines = terminal.backend().lines();
+        // The list takes 40% of the 23 rows above the status bar.
+        assert_eq!(lines[9], "─".repeat(60));
+        // Dates are shown in the local time zone, whichever the tests run in.
+        let date = Local.timestamp_opt(1641204000, 0).unwrap().format(&state.config.date_format);
+        assert_eq!(lines[10..15], [
+            "   from: Alice <alice@example.com>",
+            &format!("   date: {}", date),
+            "subject: Lunch",
+            "",
+            "See you at noon.",
+        ]);
+    }
+
+    #[test]
+    fn shows_the_status_message_or_else_the_help_key() {
+        let mut state = state(LayoutKind::Single);
+        let terminal = draw(&state, 60, 16);
+        let status_bar = &terminal.backend().lines()[15];
+        assert!(status_bar.ends_with("?: help"), "{:?}", status_bar);
+        state.status_message = Some("message sent".to_string());
+        let terminal = draw(&state, 60, 16);
+        assert_eq!(terminal.backend().lines()[15], "message sent");
+        assert_eq!(cell(&terminal, 59, 15).style, state.theme.status_bar);
+    }
+
+    #[test]
+    fn writes_only_the_cells_of_the_status_bar_that_changed() {
+        let mut state = state(LayoutKind::Single);
+        let mut terminal = draw(&state, 60, 16);
+        let cells_written = terminal.backend().cells_written;
+        state.status_message = Some("message sent".to_string());
+        screen(&state, &mut terminal);
+        // The 11 letters of "message sent" and the 7 cells of the "?: help" it replaces. The
+        // space between the words was blank already.
+        assert_eq!(terminal.backend().cells_written - cells_written, 18);
+        screen(&state, &mut terminal);
+        assert_eq!(terminal.backend().cells_written - cells_written, 18);
+    }
+
+    #[test]
+    fn leaves_the_help_out_of_a_terminal_too_small_for_it() {
+        let mut state = state(LayoutKind::Single);
+        state.show_help = true;
+        for (width, height) in [(1, 1), (4, 3), (5, 2), (6, 4)] {
+            draw(&state, width, height);
+        }
+        let terminal = draw(&state, 5, 4);
+        assert_eq!(terminal.backend().lines()[..3], ["┌─ ─┐", "│   │", "└───┘"]);
+    }
 }
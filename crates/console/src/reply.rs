@@ -0,0 +1,388 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::PathBuf;
+use chrono::{Local, TimeZone};
+use serde::Deserialize;
+use api::mail::{BodyType, Mailbox, Message, OutgoingMessage, Recipient};
+use crate::compose::{Compose, ComposeAction};
+use crate::parse::parse_message_body_plain;
+use crate::ui::{Backend, Terminal};
+use crate::width::str_width;
+use crate::{render, State, Storage};
+
+/// Columns quoted text is reflowed to, prefix included.
+const QUOTE_WIDTH: usize = 72;
+
+/// How replies and forwards set out the original message.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum QuoteStyle {
+    /// "On <date>, <sender> wrote:" and the original with each line behind `> `, with the reply
+    /// and signature written under it.
+    #[default]
+    Prefix,
+    /// The reply and signature on top, over the original as it was under a block of its
+    /// From, Sent, To and Subject, as Outlook does.
+    Header,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ReplyKind {
+    Reply,
+    /// Reply to the sender and every other recipient but oneself.
+    ReplyAll,
+    /// Forward with the original in the body, along with its attachments.
+    Forward,
+    /// Forward with the original attached whole, as an `.eml` file.
+    ForwardAsAttachment,
+}
+
+/// Starts the pending reply to or forward of the selected message in the editor.
+pub async fn run(state: &mut State, storage: &Storage, terminal: &mut Terminal<impl Backend>) {
+    let kind = match state.pending_reply.take() {
+        Some(kind) => kind,
+        None => return,
+    };
+    let original = match state.messages().get(state.selected_message_index) {
+        Some(message) => message.clone(),
+        None => return,
+    };
+    let mailbox = match storage.get_mailbox_by_id(&original.mailbox_id) {
+        Some(mailbox) => mailbox,
+        None => return,
+    };
+    let own_addresses: Vec<&str> = mailbox.address.iter()
+        .map(|address| address.as_str())
+        .collect();
+    let signature = state.config.account(mailbox).and_then(|account| account.signature.clone());
+    let style = state.config.quote_style;
+    let date = date_str(state, original.date);
+    let mut message = match kind {
+        ReplyKind::Reply | ReplyKind::ReplyAll => {
+            let mut message = reply(&original, &own_addresses, kind == ReplyKind::ReplyAll);
+            message.body = reply_body(&original, style, &date, signature.as_deref());
+            message
+        }
+        ReplyKind::Forward | ReplyKind::ForwardAsAttachment => OutgoingMessage {
+            subject: prefixed_subject(&original.subject, "Fwd", &["fwd:", "fw:"]),
+            body: forward_body(
+                &original,
+                style,
+                &date,
+                signature.as_deref(),
+                kind == ReplyKind::Forward,
+            ),
+            ..OutgoingMessage::default()
+        },
+    };
+    let has_attachments = original.attachments.iter().any(|attachment| !attachment.is_inline);
+    let result = match kind {
+        ReplyKind::Forward if has_attachments => {
+            render::progress(state, "downloading attachments...", terminal);
+            attach_attachments(mailbox, &original, &mut message).await
+        }
+        ReplyKind::ForwardAsAttachment => {
+            render::progress(state, "downloading the message...", terminal);
+            attach_original(mailbox, &original, &mut message).await
+        }
+        _ => Ok(()),
+    };
+    if let Err(error) = result {
+        state.status_message = Some(format!("could not forward: {}", error));
+        return;
+    }
+    let mut compose = Compose::new(&original.mailbox_id);
+    compose.message = message;
+    state.compose = Some(compose);
+    state.pending_compose_action = Some(ComposeAction::Edit);
+}
+
+/// The recipients, subject and threading headers of a reply. Reply-all leaves out the
+/// account's own addresses, and a reply to one's own message goes to its recipients.
+fn reply(original: &Message, own_addresses: &[&str], is_all: bool) -> OutgoingMessage {
+    let is_own = |recipient: &Recipient| own_addresses.iter()
+        .any(|address| address.eq_ignore_ascii_case(&recipient.address));
+    let mut to = if is_own(&original.from) {
+        original.to.clone()
+    } else {
+        vec![original.from.clone()]
+    };
+    let mut cc = vec![];
+    if is_all {
+        to.extend(original.to.iter().cloned());
+        cc = original.cc.clone();
+    }
+    let mut seen = HashSet::new();
+    let mut keep = |recipient: &Recipient| {
+        !is_own(recipient) && seen.insert(recipient.address.to_lowercase())
+    };
+    to.retain(&mut keep);
+    cc.retain(&mut keep);
+    if to.is_empty() && cc.is_empty() {
+        // A message sent to oneself alone is answered to oneself.
+        to.push(original.from.clone());
+    }
+    let mut references = original.references.clone();
+    if let Some(in_reply_to) = &original.in_reply_to {
+        if references.last() != Some(in_reply_to) {
+            references.push(in_reply_to.clone());
+        }
+    }
+    references.extend(original.internet_message_id.clone());
+    OutgoingMessage {
+        to,
+        cc,
+        subject: prefixed_subject(&original.subject, "Re", &["re:"]),
+        in_reply_to: original.internet_message_id.clone(),
+        references,
+        ..OutgoingMessage::default()
+    }
+}
+
+/// Puts `prefix: ` in front of the subject, unless it already starts with one of `existing`.
+fn prefixed_subject(subject: &str, prefix: &str, existing: &[&str]) -> String {
+    let lowercase = subject.trim_start().to_lowercase();
+    if existing.iter().any(|existing| lowercase.starts_with(existing)) {
+        return subject.to_string();
+    }
+    format!("{}: {}", prefix, subject)
+}
+
+fn reply_body(
+    original: &Message,
+    style: QuoteStyle,
+    date: &str,
+    signature: Option<&str>,
+) -> String {
+    let signature = signature_block(signature);
+    match style {
+        QuoteStyle::Prefix => format!(
+            "On {}, {} wrote:\n{}\n\n\n{}",
+            date,
+            original.from,
+            quote(&original_text(original, QUOTE_WIDTH - 2), QUOTE_WIDTH),
+            signature.trim_end()
+        ),
+        QuoteStyle::Header => format!(
+            "\n\n{}{}\n{}",
+            signature,
+            header_block("________________________________", original, date),
+            original_text(original, QUOTE_WIDTH)
+        ),
+    }
+}
+
+fn forward_body(
+    original: &Message,
+    style: QuoteStyle,
+    date: &str,
+    signature: Option<&str>,
+    is_inline: bool,
+) -> String {
+    let signature = signature_block(signature);
+    if !is_inline {
+        return format!("\n\n{}", signature);
+    }
+    let separator = match style {
+        QuoteStyle::Prefix => "-------- Forwarded Message --------",
+        QuoteStyle::Header => "________________________________",
+    };
+    format!(
+        "\n\n{}{}\n{}",
+        signature,
+        header_block(separator, original, date),
+        original_text(original, QUOTE_WIDTH)
+    )
+}
+
+/// The signature under the `-- ` line that tells mail clients where it starts.
+fn signature_block(signature: Option<&str>) -> String {
+    match signature {
+        Some(signature) => format!("-- \n{}\n\n", signature.trim_end()),
+        None => String::new(),
+    }
+}
+
+fn header_block(separator: &str, original: &Message, date: &str) -> String {
+    let list = |recipients: &[Recipient]| recipients.iter()
+        .map(|recipient| recipient.to_string())
+        .collect::<Vec<String>>()
+        .join("; ");
+    let mut block = format!(
+        "{}\nFrom: {}\nSent: {}\nTo: {}\n",
+        separator,
+        original.from,
+        date,
+        list(&original.to)
+    );
+    if !original.cc.is_empty() {
+        block.push_str(&format!("Cc: {}\n", list(&original.cc)));
+    }
+    block.push_str(&format!("Subject: {}\n", original.subject));
+    block
+}
+
+/// The original body as plain text. HTML is wrapped to `width` as it is turned into text,
+/// while plain text is left for [`quote`] to reflow.
+fn original_text(original: &Message, width: usize) -> String {
+    match original.body_type {
+        BodyType::Text => original.body.trim_end().to_string(),
+        BodyType::Html => parse_message_body_plain(&original.body, BodyType::Html, width)
+            .trim_end()
+            .to_string(),
+    }
+}
+
+/// Puts `> ` in front of each line, wrapping lines that would then be wider than `width`.
+/// Lines already quoted get one more `>`, on each line they are wrapped to.
+fn quote(text: &str, width: usize) -> String {
+    let mut lines = vec![];
+    for line in text.lines() {
+        let content_start = line.find(|c: char| c != '>' && c != ' ').unwrap_or(line.len());
+        let (prefix, content) = line.split_at(content_start);
+        let prefix = format!("{} ", ">".repeat(prefix.matches('>').count() + 1));
+        let available = width.saturating_sub(prefix.len()).max(1);
+        if content.is_empty() {
+            lines.push(prefix.trim_end().to_string());
+            continue;
+        }
+        if str_width(content) <= available {
+            lines.push(format!("{}{}", prefix, content));
+            continue;
+        }
+        let mut current = String::new();
+        for word in content.split_whitespace() {
+            if !current.is_empty() && str_width(&current) + 1 + str_width(word) > available {
+                lines.push(format!("{}{}", prefix, current));
+                current.clear();
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        lines.push(format!("{}{}", prefix, current));
+    }
+    lines.join("\n")
+}
+
+/// Downloads the attachments of the original, other than images in its HTML, and attaches
+/// them to the forward.
+async fn attach_attachments(
+    mailbox: &impl Mailbox,
+    original: &Message,
+    message: &mut OutgoingMessage,
+) -> Result<(), String> {
+    for attachment in original.attachments.iter().filter(|attachment| !attachment.is_inline) {
+        let path = forward_path(original, &attachment.name)?;
+        let mut file = File::create(&path).map_err(|error| error.to_string())?;
+        mailbox.download_attachment(&original.id, &attachment.id, &mut file).await
+            .map_err(|error| error.to_string())?;
+        message.attach(&path, mailbox.max_attachments_size())
+            .map_err(|error| error.to_string())?;
+    }
+    Ok(())
+}
+
+/// Downloads the original whole and attaches it to the forward.
+async fn attach_original(
+    mailbox: &impl Mailbox,
+    original: &Message,
+    message: &mut OutgoingMessage,
+) -> Result<(), String> {
+    let name: String = original.subject.chars()
+        .map(|c| if c == '/' || c == '\\' || c.is_control() { '_' } else { c })
+        .collect();
+    let name = if name.trim().is_empty() { "message".to_string() } else { name };
+    let path = forward_path(original, &format!("{}.eml", name))?;
+    let mut file = File::create(&path).map_err(|error| error.to_string())?;
+    mailbox.download_message(&original.id, &mut file).await.map_err(|error| error.to_string())?;
+    message.attach(&path, mailbox.max_attachments_size()).map_err(|error| error.to_string())
+}
+
+/// Where a file forwarded from the original is kept until the forward is sent. Each message
+/// gets a directory of its own, so that attachments keep their names.
+fn forward_path(original: &Message, name: &str) -> Result<PathBuf, String> {
+    // Outlook ids are long and share their start within a mailbox.
+    let id: String = original.id.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+    let directory = std::env::temp_dir()
+        .join(format!("dashboard-forward-{}", &id[id.len().saturating_sub(32)..]));
+    std::fs::create_dir_all(&directory).map_err(|error| error.to_string())?;
+    // The name comes from the sender, so it may not climb out of the directory.
+    let name = std::path::Path::new(name).file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| "attachment".to_string());
+    Ok(directory.join(name))
+}
+
+fn date_str(state: &State, timestamp: u64) -> String {
+    Local.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|date| date.format(&state.config.date_format).to_string())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addresses(recipients: &[Recipient]) -> Vec<&str> {
+        recipients.iter().map(|recipient| recipient.address.as_str()).collect()
+    }
+
+    #[test]
+    fn replies_to_everyone_but_oneself_once() {
+        let original = Message::test("1")
+            .with_from("Alice", "alice@example.com")
+            .with_to("Me <me@example.com>, Bob <bob@example.com>")
+            .with_cc("BOB@example.com, carol@example.com, ME@EXAMPLE.COM");
+        let all = reply(&original, &["me@example.com"], true);
+        assert_eq!(addresses(&all.to), ["alice@example.com", "bob@example.com"]);
+        assert_eq!(addresses(&all.cc), ["carol@example.com"]);
+        let sender = reply(&original, &["me@example.com"], false);
+        assert_eq!(addresses(&sender.to), ["alice@example.com"]);
+        assert!(sender.cc.is_empty());
+    }
+
+    #[test]
+    fn replies_to_the_recipients_of_ones_own_message() {
+        let original = Message::test("1")
+            .with_from("", "me@example.com")
+            .with_to("bob@example.com");
+        let message = reply(&original, &["me@example.com"], false);
+        assert_eq!(addresses(&message.to), ["bob@example.com"]);
+        let to_oneself = Message::test("2")
+            .with_from("", "me@example.com")
+            .with_to("me@example.com");
+        let message = reply(&to_oneself, &["me@example.com"], true);
+        assert_eq!(addresses(&message.to), ["me@example.com"]);
+    }
+
+    #[test]
+    fn prefixes_the_subject_once() {
+        let subject = |subject| {
+            reply(&Message::test("1").with_subject(subject), &[], false).subject
+        };
+        assert_eq!(subject("Plans"), "Re: Plans");
+        assert_eq!(subject("RE: Plans"), "RE: Plans");
+        assert_eq!(subject("Fwd: Plans"), "Re: Fwd: Plans");
+        let forward = |subject| prefixed_subject(subject, "Fwd", &["fwd:", "fw:"]);
+        assert_eq!(forward("Plans"), "Fwd: Plans");
+        assert_eq!(forward("FW: Plans"), "FW: Plans");
+        assert_eq!(forward("Re: Plans"), "Fwd: Re: Plans");
+    }
+
+    #[test]
+    fn threads_the_reply_under_the_original() {
+        let original = Message::test("c")
+            .with_parents(Some("<b@example.com>"), &["<a@example.com>"]);
+        let message = reply(&original, &[], false);
+        assert_eq!(message.in_reply_to.as_deref(), Some("<c@example.com>"));
+        assert_eq!(message.references, ["<a@example.com>", "<b@example.com>", "<c@example.com>"]);
+        // A parent already last in the references is not repeated.
+        let original = Message::test("c")
+            .with_parents(Some("<b@example.com>"), &["<a@example.com>", "<b@example.com>"]);
+        let message = reply(&original, &[], false);
+        assert_eq!(message.references, ["<a@example.com>", "<b@example.com>", "<c@example.com>"]);
+    }
+}
@@ -0,0 +1,249 @@
+use std::error::Error;
+use std::fmt;
+use std::process::{Command, Stdio};
+use serde::Deserialize;
+use api::mail::{FlagUpdate, Mailbox, Message};
+use api::outlook::OutlookMailbox;
+use crate::filter::{deserialize_filter, Filter};
+
+/// A rule of the config, applied to new unread messages on each sync, such as:
+///
+/// ```toml
+/// [[rules]]
+/// name = "builds"
+/// filter = "from:noreply@* subject:\"build passed\""
+/// mark_read = true
+/// ```
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct Rule {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_filter")]
+    pub filter: Filter,
+    #[serde(default)]
+    pub mark_read: bool,
+    /// The display name of the folder to move the message to, or a well-known folder such as
+    /// `archive`.
+    #[serde(default)]
+    pub move_to: Option<String>,
+    /// Categories added to those the message already has.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// Show a desktop notification with `notify-send`.
+    #[serde(default)]
+    pub notify: bool,
+}
+
+/// What the rules matching a message do to it, all together.
+#[derive(Debug, Default, PartialEq)]
+pub struct Plan<'a> {
+    /// The names of the matching rules, in the order of the config.
+    pub rules: Vec<&'a str>,
+    pub mark_read: bool,
+    /// The folder of the first matching rule that moves messages.
+    pub move_to: Option<&'a str>,
+    /// Categories the message does not have yet.
+    pub categories: Vec<String>,
+    pub notify: bool,
+}
+
+impl fmt::Display for Plan<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut actions = vec![];
+        if !self.categories.is_empty() {
+            actions.push(format!("categorise as {}", self.categories.join(", ")));
+        }
+        if self.mark_read {
+            actions.push("mark read".to_string());
+        }
+        if let Some(folder) = self.move_to {
+            actions.push(format!("move to {}", folder));
+        }
+        if self.notify {
+            actions.push("notify".to_string());
+        }
+        write!(f, "{} ({})", actions.join(", "), self.rules.join(", "))
+    }
+}
+
+impl Plan<'_> {
+    /// Whether the message leaves the unread messages of the inbox.
+    pub fn removes_message(&self) -> bool {
+        self.mark_read || self.move_to.is_some()
+    }
+}
+
+/// Works out what the rules do to the message, given the names its account goes by, or
+/// `None` if no rule with an action matches it.
+pub fn plan<'a>(rules: &'a [Rule], message: &Message, account_names: &[&str]) -> Option<Plan<'a>> {
+    let mut plan = Plan::default();
+    for rule in rules.iter().filter(|rule| rule.filter.matches(message, account_names)) {
+        plan.rules.push(&rule.name);
+        plan.mark_read |= rule.mark_read;
+        plan.move_to = plan.move_to.or(rule.move_to.as_deref());
+        for category in &rule.categories {
+            let has_category = message.categories.iter()
+                .chain(&plan.categories)
+                .any(|existing| existing.eq_ignore_ascii_case(category));
+            if !has_category {
+                plan.categories.push(category.clone());
+            }
+        }
+        plan.notify |= rule.notify;
+    }
+    let has_actions = plan.mark_read || plan.move_to.is_some() || !plan.categories.is_empty()
+        || plan.notify;
+    if has_actions { Some(plan) } else { None }
+}
+
+/// Carries out the plan for a message of the mailbox. Flags are changed before the message
+/// is moved, as moving it gives it a new id.
+pub async fn apply(
+    mailbox: &OutlookMailbox,
+    message: &Message,
+    plan: &Plan<'_>,
+) -> Result<(), Box<dyn Error>> {
+    if plan.mark_read || !plan.categories.is_empty() {
+        let categories = if plan.categories.is_empty() {
+            None
+        } else {
+            Some(message.categories.iter().chain(&plan.categories).cloned().collect())
+        };
+        mailbox.clone().update_flags(message.id.clone(), FlagUpdate {
+            is_read: if plan.mark_read { Some(true) } else { None },
+            categories,
+            ..Default::default()
+        }).await?;
+    }
+    if let Some(folder) = plan.move_to {
+        mailbox.move_message(&message.id, folder).await?;
+    }
+    if plan.notify {
+        notify(message);
+    }
+    Ok(())
+}
+
+/// Shows a desktop notification for the message. Systems without `notify-send` go without.
+fn notify(message: &Message) {
+    let sender = if message.from.name.is_empty() {
+        &message.from.address
+    } else {
+        &message.from.name
+    };
+    let _ = Command::new("notify-send")
+        .args(["--app-name", "dashboard", sender, &message.subject])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads rules as they are written in the config.
+    fn rules(toml: &str) -> Vec<Rule> {
+        #[derive(Deserialize)]
+        struct Config {
+            rules: Vec<Rule>,
+        }
+        toml::from_str::<Config>(toml).unwrap().rules
+    }
+
+    #[test]
+    fn matches_on_sender_subject_and_account() {
+        let rules = rules(r#"
+            [[rules]]
+            name = "builds"
+            filter = "from:noreply@* subject:\"build passed\""
+            mark_read = true
+
+            [[rules]]
+            name = "work"
+            filter = "account:work"
+            notify = true
+        "#);
+        let build = Message::test("1")
+            .with_from("", "noreply@ci.example.com")
+            .with_subject("Build passed: #12");
+        let home_plan = plan(&rules, &build, &["home"]).unwrap();
+        assert_eq!(home_plan.rules, ["builds"]);
+        assert!(home_plan.mark_read && !home_plan.notify);
+        let work_plan = plan(&rules, &build, &["work", "me@work.example.com"]).unwrap();
+        assert_eq!(work_plan.rules, ["builds", "work"]);
+        assert!(work_plan.mark_read && work_plan.notify);
+        let other = Message::test("1")
+            .with_from("", "alice@example.com")
+            .with_subject("Build passed: #12");
+        assert_eq!(plan(&rules, &other, &["home"]), None);
+    }
+
+    #[test]
+    fn merges_the_rules_that_match() {
+        let rules = rules(r#"
+            [[rules]]
+            name = "receipts"
+            filter = "subject:receipt"
+            move_to = "Receipts"
+            categories = ["Money", "Shop"]
+
+            [[rules]]
+            name = "archive"
+            filter = "subject:receipt"
+            move_to = "archive"
+            categories = ["money", "Tax", "TAX"]
+        "#);
+        let message = Message::test("1")
+            .with_from("", "shop@example.com")
+            .with_subject("Your receipt")
+            .with_categories(&["shop"]);
+        let plan = plan(&rules, &message, &[]).unwrap();
+        assert_eq!(plan.rules, ["receipts", "archive"]);
+        // The first rule that moves the message decides where to.
+        assert_eq!(plan.move_to, Some("Receipts"));
+        // Categories are compared ignoring case, with those the message has and each other.
+        assert_eq!(plan.categories, ["Money", "Tax"]);
+        assert!(plan.removes_message());
+    }
+
+    #[test]
+    fn ignores_rules_without_anything_left_to_do() {
+        let rules = rules(r#"
+            [[rules]]
+            name = "tag"
+            filter = "from:alice"
+            categories = ["Friends"]
+        "#);
+        let message = Message::test("1")
+            .with_from("", "alice@example.com")
+            .with_subject("Hi")
+            .with_categories(&["friends"]);
+        assert_eq!(plan(&rules, &message, &[]), None);
+    }
+
+    #[test]
+    fn describes_the_plan_for_the_dry_run() {
+        let rules = rules(r#"
+            [[rules]]
+            name = "newsletters"
+            filter = "from:news@*"
+            mark_read = true
+            categories = ["News"]
+
+            [[rules]]
+            name = "archive"
+            filter = "from:news@*"
+            move_to = "archive"
+            notify = true
+        "#);
+        let message = Message::test("1")
+            .with_from("", "news@example.com")
+            .with_subject("This week");
+        let plan = plan(&rules, &message, &[]).unwrap();
+        assert_eq!(
+            plan.to_string(),
+            "categorise as News, mark read, move to archive, notify (newsletters, archive)"
+        );
+    }
+}
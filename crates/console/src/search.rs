@@ -0,0 +1,324 @@
+use std::path::{Path, PathBuf};
+use rusqlite::{params, Connection, OptionalExtension};
+use api::mail::{Mailbox, Message};
+use api::search::SearchQuery;
+use crate::parse::{parse_message_body_plain, sort_messages_by_thread};
+use crate::render;
+use crate::state::SearchResults;
+use crate::ui::{Backend, Terminal};
+use crate::{State, Storage};
+
+/// Width used to convert bodies to text for indexing, wide enough that lines never wrap.
+const INDEX_WIDTH: usize = 1000;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS messages (
+        id TEXT PRIMARY KEY,
+        mailbox_id TEXT NOT NULL,
+        date INTEGER NOT NULL,
+        message TEXT NOT NULL
+    );
+    CREATE VIRTUAL TABLE IF NOT EXISTS message_text USING fts5(
+        id UNINDEXED,
+        sender,
+        recipients,
+        subject,
+        body,
+        tokenize = 'unicode61 remove_diacritics 2'
+    );
+";
+
+/// Full-text index over every message fetched, read or not. Kept in an SQLite FTS5 database
+/// next to the storage, so that mail from earlier sessions can still be searched offline.
+pub struct SearchIndex {
+    /// `None` if not even an in-memory database could be made, in which case searches only
+    /// go to the mailboxes.
+    connection: Option<Connection>,
+}
+
+/// An index that only lasts for the session, used when the one on disk cannot be opened.
+impl Default for SearchIndex {
+    fn default() -> Self {
+        let connection = Connection::open_in_memory()
+            .and_then(|connection| connection.execute_batch(SCHEMA).map(|()| connection));
+        SearchIndex { connection: connection.ok() }
+    }
+}
+
+impl SearchIndex {
+    /// Where the index of the storage is kept, next to it.
+    pub fn path(storage: &Storage) -> PathBuf {
+        storage.path.with_extension("search.sqlite")
+    }
+
+    pub fn open(path: &Path) -> rusqlite::Result<SearchIndex> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(SearchIndex { connection: Some(connection) })
+    }
+
+    /// Adds the messages to the index, or updates their flags if they are in it already.
+    pub fn insert(&mut self, messages: &[Message]) {
+        // The index is a cache: a message that fails to be written is only missing from
+        // offline results.
+        let _ = self.try_insert(messages);
+    }
+
+    fn try_insert(&mut self, messages: &[Message]) -> rusqlite::Result<()> {
+        let transaction = match &mut self.connection {
+            Some(connection) => connection.transaction()?,
+            None => return Ok(()),
+        };
+        for message in messages {
+            let is_indexed = transaction
+                .query_row("SELECT 1 FROM messages WHERE id = ?1", [&message.id], |_| Ok(()))
+                .optional()?
+                .is_some();
+            transaction.execute(
+                "INSERT INTO messages (id, mailbox_id, date, message) VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT (id) DO UPDATE
+                    SET date = excluded.date, message = excluded.message",
+                params![
+                    message.id,
+                    message.mailbox_id,
+                    message.date as i64,
+                    serde_json::to_string(message).unwrap(),
+                ],
+            )?;
+            // The text of a message does not change, only its flags do.
+            if is_indexed {
+                continue;
+            }
+            let recipients = message.to.iter().chain(&message.cc)
+                .map(|recipient| format!("{} {}", recipient.name, recipient.address))
+                .collect::<Vec<String>>()
+                .join(" ");
+            transaction.execute(
+                "INSERT INTO message_text (id, sender, recipients, subject, body)
+                    VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    message.id,
+                    format!("{} {}", message.from.name, message.from.address),
+                    recipients,
+                    message.subject,
+                    parse_message_body_plain(&message.body, message.body_type, INDEX_WIDTH),
+                ],
+            )?;
+        }
+        transaction.commit()
+    }
+
+    /// Forgets the messages of an account that was removed.
+    pub fn remove_mailbox(&mut self, mailbox_id: &str) {
+        let _ = self.try_remove_mailbox(mailbox_id);
+    }
+
+    fn try_remove_mailbox(&mut self, mailbox_id: &str) -> rusqlite::Result<()> {
+        let transaction = match &mut self.connection {
+            Some(connection) => connection.transaction()?,
+            None => return Ok(()),
+        };
+        transaction.execute(
+            "DELETE FROM message_text WHERE id IN
+                (SELECT id FROM messages WHERE mailbox_id = ?1)",
+            [mailbox_id],
+        )?;
+        transaction.execute("DELETE FROM messages WHERE mailbox_id = ?1", [mailbox_id])?;
+        transaction.commit()
+    }
+
+    /// Returns matching messages of the given accounts, newest first. Messages of other
+    /// accounts, such as one removed while the index could not be written, are left out.
+    pub fn search(&self, query: &SearchQuery, mailbox_ids: &[&str]) -> Vec<Message> {
+        let mut messages = self.try_search(query).unwrap_or_default();
+        messages.retain(|message| mailbox_ids.contains(&message.mailbox_id.as_str()));
+        messages
+    }
+
+    fn try_search(&self, query: &SearchQuery) -> rusqlite::Result<Vec<Message>> {
+        let connection = match &self.connection {
+            Some(connection) => connection,
+            None => return Ok(vec![]),
+        };
+        let mut statement = connection.prepare_cached(
+            "SELECT message FROM messages
+                WHERE (?1 IS NULL OR id IN
+                    (SELECT id FROM message_text WHERE message_text MATCH ?1))
+                AND (?2 IS NULL OR date < ?2)
+                AND (?3 IS NULL OR date >= ?3)
+                ORDER BY date DESC",
+        )?;
+        let rows = statement.query_map(
+            params![
+                match_expression(query),
+                query.before.map(|before| before as i64),
+                query.after.map(|after| after as i64),
+            ],
+            |row| row.get::<_, String>(0),
+        )?;
+        let mut messages = vec![];
+        for row in rows {
+            // Rows written by an older version that no longer read are left out.
+            if let Ok(message) = serde_json::from_str(&row?) {
+                messages.push(message);
+            }
+        }
+        Ok(messages)
+    }
+}
+
+/// The FTS5 query for the text and field operators of the search, matching each word as a
+/// prefix so that "invoic" finds "invoice" and "invoices".
+fn match_expression(query: &SearchQuery) -> Option<String> {
+    let fields = [
+        (None, &query.text),
+        (Some("sender"), &query.from),
+        (Some("recipients"), &query.to),
+        (Some("subject"), &query.subject),
+    ];
+    let mut terms = vec![];
+    for (column, values) in fields {
+        for word in values.iter().flat_map(|value| split_words(value)) {
+            // Words are alphanumeric only, so they need no escaping inside the quotes.
+            let term = format!("\"{}\"*", word);
+            terms.push(match column {
+                Some(column) => format!("{} : {}", column, term),
+                None => term,
+            });
+        }
+    }
+    if terms.is_empty() {
+        return None;
+    }
+    Some(terms.join(" AND "))
+}
+
+fn split_words(text: &str) -> impl Iterator<Item = &str> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+}
+
+/// Runs the pending search against the local index and every mailbox, then shows the results.
+pub async fn run(
+    state: &mut State,
+    storage: &Storage,
+    terminal: &mut Terminal<impl Backend>,
+) {
+    let (query_str, query) = match state.pending_search.take() {
+        Some(pending_search) => pending_search,
+        None => return,
+    };
+    let mailbox_ids: Vec<&str> = storage.outlook.iter().map(|mailbox| mailbox.get_id()).collect();
+    let mut messages = state.search_index.search(&query, &mailbox_ids);
+    for (i, outlook_mailbox) in storage.outlook.iter().enumerate() {
+        render::progress(state, &format!(
+            "searching mailboxes ({}/{})...",
+            i + 1,
+            storage.outlook.len()
+        ), terminal);
+        // A failing mailbox should not hide results from the others or the local index.
+        let remote_messages = match outlook_mailbox.search(&query).await {
+            Ok(remote_messages) => remote_messages,
+            Err(_) => continue,
+        };
+        state.search_index.insert(&remote_messages);
+        for message in remote_messages {
+            if !messages.iter().any(|existing| existing.id == message.id) {
+                messages.push(message);
+            }
+        }
+    }
+    let (previous_selected_message_index, previous_list_scroll) = match &state.search_results {
+        Some(search_results) => {
+            (search_results.previous_selected_message_index, search_results.previous_list_scroll)
+        }
+        None => (state.selected_message_index, state.list_scroll),
+    };
+    api::thread::assign_thread_ids(&mut messages);
+    state.search_results = Some(SearchResults {
+        query: query_str,
+        messages: sort_messages_by_thread(&messages),
+        previous_selected_message_index,
+        previous_list_scroll,
+    });
+    state.selected_message_index = 0;
+    state.list_scroll = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(index: &SearchIndex, query: &str) -> Vec<String> {
+        index.search(&SearchQuery::parse(query).unwrap(), &["mailbox"]).into_iter()
+            .map(|message| message.id)
+            .collect()
+    }
+
+    fn index() -> SearchIndex {
+        let mut index = SearchIndex::default();
+        index.insert(&[
+            Message::test("1")
+                .with_from("", "alice@example.com")
+                .with_subject("Invoices for March")
+                .with_body("Please pay.")
+                .with_date(100),
+            Message::test("2")
+                .with_from("", "bob@example.org")
+                .with_subject("Lunch")
+                .with_body("The café at noon?")
+                .with_date(200),
+            Message::test("3")
+                .with_from("", "alice@example.com")
+                .with_subject("Weekly report")
+                .with_body("Numbers are up.")
+                .with_date(300),
+        ]);
+        index
+    }
+
+    #[test]
+    fn matches_words_by_prefix_newest_first() {
+        let index = index();
+        assert_eq!(ids(&index, "invoic"), ["1"]);
+        assert_eq!(ids(&index, "from:alice"), ["3", "1"]);
+        assert_eq!(ids(&index, "cafe"), ["2"]);
+        assert!(ids(&index, "invoice lunch").is_empty());
+    }
+
+    #[test]
+    fn matches_operators_against_their_field() {
+        let index = index();
+        assert_eq!(ids(&index, "subject:report"), ["3"]);
+        assert!(ids(&index, "subject:alice").is_empty());
+        assert_eq!(ids(&index, "to:me@example.com after:1970-01-01"), ["3", "2", "1"]);
+        assert_eq!(ids(&index, "from:example before:1970-01-02 after:1970-01-01"), ["3", "2", "1"]);
+    }
+
+    #[test]
+    fn keeps_messages_between_sessions() {
+        let old_mail = || Message::test("1").with_subject("Old mail").with_date(100);
+        let path = std::env::temp_dir()
+            .join(format!("dashboard-search-test-{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        SearchIndex::open(&path).unwrap().insert(&[old_mail()]);
+        let mut read = old_mail();
+        read.is_read = true;
+        let mut index = SearchIndex::open(&path).unwrap();
+        index.insert(&[read]);
+        let messages = index.search(&SearchQuery::parse("old").unwrap(), &["mailbox"]);
+        assert_eq!(messages.len(), 1);
+        assert!(messages[0].is_read);
+        index.remove_mailbox("mailbox");
+        assert!(ids(&index, "old").is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn leaves_out_messages_of_accounts_that_are_gone() {
+        let mut index = index();
+        let removed = Message::test("4").with_mailbox("removed").with_subject("Invoices for April");
+        index.insert(&[removed]);
+        assert_eq!(ids(&index, "invoices"), ["1"]);
+    }
+}
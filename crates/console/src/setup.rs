@@ -1,90 +1,85 @@
-use std::io::Write;
-use api::mail::Mailbox;
-use api::outlook::auth::AccessTokenRequestType;
+use std::error::Error;
+use std::time::Duration;
+use tokio::time::timeout;
+use api::outlook::auth::{
+    get_access_token,
+    get_authorisation_code,
+    get_authorisation_code_request_url,
+    AccessTokenRequestType,
+    AccessTokenResponse,
+    AuthOptions,
+};
 use api::outlook::OutlookMailbox;
-use crate::{render, State, Storage};
-use crate::parse::sort_messages_by_date;
-use crate::render::print_screen;
-use crate::storage;
+use crate::{render, storage, sync, State, Storage};
+use crate::parse::try_parse_shown_message;
+use crate::ui::{Backend, Terminal};
+
+/// How long the browser has to come back from the sign-in page.
+const SIGN_IN_TIMEOUT: Duration = Duration::from_secs(180);
 
 pub async fn setup(
     state: &mut State,
     storage: &mut Storage,
-    stdout: &mut impl Write,
+    terminal: &mut Terminal<impl Backend>,
 ) {
-    // add_outlook_mailbox(&mut storage).await;
-    render::screen(state, stdout);
-    print_screen("initialising authentication...\r\n", stdout);
-    refresh_outlook_access_tokens(storage).await;
-    let mut update_status_message = |i: usize, length: usize| {
-        let message = format!(
-            "fetching unread messages from mailboxes ({}/{})...\r\n",
-            i + 1,
-            length
-        );
-        print_screen(&message, stdout);
-    };
-    state.unread_messages = vec![];
-    for (i, outlook_mailbox) in storage.outlook.iter().enumerate() {
-        update_status_message(i, storage.outlook.len());
-        state.unread_messages.append(
-            &mut outlook_mailbox.fetch_unread().await.unwrap().clone(),
-        );
+    if storage.outlook.is_empty() {
+        state.status_message =
+            Some("no account has been added, see `dashboard account add`".to_string());
     }
-    state.unread_messages = sort_messages_by_date(&state.unread_messages);
+    render::progress(state, "initialising authentication...", terminal);
+    sync::run(state, storage, terminal).await;
+    state.snoozed_count = storage.snoozes.len();
+    state.outbox = storage.outbox.clone();
     state.is_loaded = true;
-    let unread_count = state.unread_messages.len();
-    state.selected_message_index = if unread_count == 0 { 0 } else { unread_count - 1 };
-    render::screen(state, stdout);
+    try_parse_shown_message(state);
+    render::screen(state, terminal);
 }
 
-async fn refresh_outlook_access_tokens(storage: &mut Storage) {
-    let mut should_save_storage: bool = false;
-    for outlook in &mut storage.outlook {
-        let refreshed = outlook.try_refresh_access_token().await;
-        if refreshed && !should_save_storage {
-            should_save_storage = true;
+/// Signs in to an Outlook account from the command line, asking for the client ID of the
+/// Azure app if it is not given.
+pub async fn add_outlook_mailbox(
+    storage: &mut Storage,
+    client_id: Option<String>,
+    auth_options: &AuthOptions,
+) -> Result<(), Box<dyn Error>> {
+    println!("Authenticating Microsoft Outlook account.");
+    let client_id = match client_id {
+        Some(client_id) => client_id,
+        None => {
+            let register_app_txt: &str = "Register Azure app @ \
+                https://docs.microsoft.com/en-us/graph/auth-register-app-v2 -- then, enter \
+                        the Azure app client ID:";
+            println!("{}", register_app_txt);
+            let mut client_id = String::new();
+            std::io::stdin().read_line(&mut client_id)?;
+            client_id.trim().to_string()
         }
-    }
-    if should_save_storage {
-        storage::set(&storage);
-    }
-}
-
-async fn add_outlook_mailbox(storage: &mut Storage) {
-    let client_id: String = {
-        // TODO: make client_id global per storage instead of per outlook mailbox?
-        // if storage.outlook.len() > 0 {
-        //     storage.outlook[0].client_id.clone()
-        // } else {
-        println!("Authenticating Microsoft Outlook account.");
-        let register_app_txt: &str = "Register Azure app @ \
-            https://docs.microsoft.com/en-us/graph/auth-register-app-v2 -- then, enter \
-                    the Azure app client ID:";
-        println!("{}", register_app_txt);
-        let mut client_id = String::new();
-        std::io::stdin().read_line(&mut client_id).expect("Failed to read client_id");
-        let mut chars = client_id.chars();
-        chars.next_back();
-        chars.as_str().to_owned()
-        // }
     };
-    let response= authenticate_outlook(&client_id).await;
-    let outlook_mail = OutlookMailbox::open(
-        client_id.as_str(),
-        response.clone()
-    );
+    println!("Visit the URL below to authenticate with Outlook");
+    println!("{}", get_authorisation_code_request_url(&client_id, auth_options));
+    let response = authenticate_outlook(&client_id, auth_options).await?;
+    let mut outlook_mail = OutlookMailbox::open(client_id.as_str(), response);
+    outlook_mail.auth_options = auth_options.clone();
+    // Looked up again on the next sync if this fails.
+    outlook_mail.address = outlook_mail.fetch_address().await.ok();
     storage.outlook.push(outlook_mail);
-    storage::set(&storage);
+    storage::set(storage);
+    Ok(())
 }
 
-async fn authenticate_outlook(client_id: &str) -> api::outlook::auth::AccessTokenResponse {
-    println!("Visit the URL below to authenticate with Outlook");
-    let authorisation_url = api::outlook::auth::get_authorisation_code_request_url(&client_id);
-    println!("{}", authorisation_url);
-    let authorisation_code = api::outlook::auth::get_authorisation_code();
-    api::outlook::auth::get_access_token(
-        &client_id,
-        AccessTokenRequestType::AuthorizationCode(authorisation_code)
+/// Waits for the browser to be redirected back with an authorisation code, then exchanges it
+/// for tokens. The URL to visit is shown by the caller.
+pub async fn authenticate_outlook(
+    client_id: &str,
+    auth_options: &AuthOptions,
+) -> Result<AccessTokenResponse, Box<dyn Error>> {
+    // Giving up frees the redirect port for the next attempt.
+    let authorisation_code = timeout(SIGN_IN_TIMEOUT, get_authorisation_code(auth_options))
+        .await
+        .map_err(|_| "timed out waiting for the sign-in")??;
+    get_access_token(
+        client_id,
+        AccessTokenRequestType::AuthorizationCode(authorisation_code),
+        auth_options,
     ).await
 }
@@ -0,0 +1,145 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use chrono::{Local, TimeZone};
+use serde::{Serialize, Deserialize};
+use api::mail::Mailbox;
+use crate::ui::{Backend, Terminal};
+use crate::{render, storage, sync, when, State, Storage};
+
+/// What the prompt snoozes until when nothing is typed.
+const DEFAULT_SNOOZE: &str = "tomorrow";
+/// How long to wait before trying again to wake a message that could not be woken.
+const WAKE_RETRY_DELAY: u64 = 5 * 60;
+
+/// A message hidden until a later time. Kept in storage, so that it is still woken after a
+/// restart.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Snooze {
+    pub mailbox_id: String,
+    /// The id of the message while it is snoozed.
+    pub message_id: String,
+    /// When to wake the message, in seconds since the Unix epoch.
+    pub until: u64,
+    pub subject: String,
+}
+
+/// The status bar text of the snooze prompt, with when the text typed so far would wake the
+/// message.
+pub fn prompt(state: &State) -> Option<String> {
+    let text = state.snooze_prompt.as_ref()?;
+    let when = match when::parse(or_default(text), Local::now()) {
+        Ok(time) => time.format(&state.config.date_format).to_string(),
+        Err(error) => error.to_string(),
+    };
+    Some(format!("snooze until: {}  ({})", text, when))
+}
+
+fn or_default(text: &str) -> &str {
+    if text.trim().is_empty() { DEFAULT_SNOOZE } else { text }
+}
+
+/// Reads the time typed into the prompt, leaving the prompt open if it cannot be read.
+pub fn submit_prompt(state: &mut State) {
+    let text = state.snooze_prompt.clone().unwrap_or_default();
+    match when::parse(or_default(&text), Local::now()) {
+        Ok(time) => {
+            state.snooze_prompt = None;
+            state.pending_snooze = Some(time.timestamp() as u64);
+        }
+        Err(error) => state.status_message = Some(error.to_string()),
+    }
+}
+
+/// Snoozes the selected message until the pending time.
+pub async fn run(state: &mut State, storage: &mut Storage, terminal: &mut Terminal<impl Backend>) {
+    let until = match state.pending_snooze.take() {
+        Some(until) => until,
+        None => return,
+    };
+    let message = match state.messages().get(state.selected_message_index) {
+        Some(message) => message.clone(),
+        None => return,
+    };
+    let mailbox = match storage.get_mailbox_by_id(&message.mailbox_id) {
+        Some(mailbox) => mailbox,
+        None => return,
+    };
+    render::progress(state, "snoozing...", terminal);
+    match mailbox.snooze(&message.id).await {
+        Ok(message_id) => {
+            storage.snoozes.push(Snooze {
+                mailbox_id: message.mailbox_id.clone(),
+                message_id,
+                until,
+                subject: message.subject.clone(),
+            });
+            storage::set(storage);
+            state.snoozed_count = storage.snoozes.len();
+            state.remove_message(&message.id);
+            state.status_message = Some(format!("snoozed until {}", date_str(state, until)));
+        }
+        Err(error) => state.status_message = Some(format!("could not snooze: {}", error)),
+    }
+}
+
+/// Puts the messages whose snooze is over back in the inbox and lists them, returning whether
+/// any were.
+pub async fn wake_due(
+    state: &mut State,
+    storage: &mut Storage,
+    terminal: &mut Terminal<impl Backend>,
+) -> bool {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    // Snoozes of removed accounts can never be woken.
+    let snooze_count = storage.snoozes.len();
+    let mailbox_ids: Vec<String> = storage.outlook.iter()
+        .map(|mailbox| mailbox.get_id().to_string())
+        .collect();
+    storage.snoozes.retain(|snooze| mailbox_ids.contains(&snooze.mailbox_id));
+    let mut has_changed = storage.snoozes.len() != snooze_count;
+    let mut woken_messages = vec![];
+    let mut errors = vec![];
+    for snooze in storage.snoozes.iter_mut().filter(|snooze| snooze.until <= now) {
+        let mailbox = match storage.outlook.iter()
+            .find(|mailbox| mailbox.get_id() == snooze.mailbox_id && !mailbox.is_disabled) {
+            Some(mailbox) => mailbox,
+            None => continue,
+        };
+        render::progress(state, "waking snoozed messages...", terminal);
+        has_changed = true;
+        match mailbox.wake(&snooze.message_id).await {
+            Ok(message_id) => {
+                snooze.until = 0;
+                if let Ok(Some(message)) = mailbox.fetch_message(&message_id).await {
+                    woken_messages.push(message);
+                }
+            }
+            Err(error) => {
+                // Trying on every tick would flood the server while it is unreachable.
+                snooze.until = now + WAKE_RETRY_DELAY;
+                errors.push(format!("could not wake {}: {}", snooze.subject, error));
+            }
+        }
+    }
+    if !has_changed {
+        return false;
+    }
+    storage.snoozes.retain(|snooze| snooze.until != 0);
+    storage::set(storage);
+    state.snoozed_count = storage.snoozes.len();
+    if !woken_messages.is_empty() {
+        let mut messages = state.unread_messages.clone();
+        messages.append(&mut woken_messages);
+        sync::replace_unread_messages(state, messages);
+    }
+    if let Some(error) = errors.into_iter().next() {
+        state.status_message = Some(error);
+    }
+    true
+}
+
+fn date_str(state: &State, timestamp: u64) -> String {
+    Local.timestamp_opt(timestamp as i64, 0)
+        .single()
+        .map(|date| date.format(&state.config.date_format).to_string())
+        .unwrap_or_default()
+}
@@ -1,60 +1,551 @@
-use std::collections::HashMap;
-use api::mail::{Mailbox, Message};
+use std::collections::{HashMap, HashSet};
+use termion::event::Key;
+use api::mail::{BodyType, Flag, FlagUpdate, Mailbox, Message};
+use api::outlook::OutlookMailbox;
+use api::search::SearchQuery;
+use crate::accounts::{AccountAction, AccountsScreen};
+use crate::attachment::AttachmentAction;
+use crate::compose::{Compose, ComposeAction};
+use crate::config::Config;
+use crate::drafts::{DraftsAction, DraftsScreen};
+use crate::inbox_rules::{InboxRulesAction, InboxRulesScreen};
+use crate::graphics::{GraphicsProtocol, InlineImage};
+use crate::html::ImagePlacement;
+use crate::keymap::Keymap;
+use crate::link::LinkPicker;
+use crate::outbox::QueuedMessage;
+use crate::reply::ReplyKind;
+use crate::parse::{thread_key, try_parse_selected_message};
+use crate::search::SearchIndex;
+use crate::sync::SyncStatus;
+use crate::theme::Theme;
+use crate::view::{self, View, ViewKind};
+use crate::ui::Color;
 use crate::Storage;
 
 pub struct State {
     pub is_loaded: bool,
+    /// The unread messages of every enabled mailbox.
     pub unread_messages: Vec<Message>,
+    /// Unified, per-account and smart views. There is always at least the unified one.
+    pub views: Vec<View>,
+    pub view_index: usize,
+    /// The unread messages of the current view, as listed.
+    pub view_messages: Vec<Message>,
+    /// The first row of the list on screen.
+    pub list_scroll: usize,
+    /// The enabled mailboxes in storage, in order, for the folders pane and message tags.
+    pub mailboxes: Vec<MailboxTag>,
+    /// Outcome of the last sync of each mailbox, by id.
+    pub sync_statuses: HashMap<String, SyncStatus>,
     pub parsed_message_bodies: HashMap<String, String>,
+    /// Width the parsed bodies were laid out for.
+    pub parsed_width: usize,
+    /// Links of each parsed body, numbered as in the body text.
+    pub message_links: HashMap<String, Vec<String>>,
+    /// Where the inline images of each parsed body are drawn.
+    pub message_images: HashMap<String, Vec<ImagePlacement>>,
+    /// Decoded inline images by message id, then Content-ID. A message is present once its
+    /// images have been fetched, even if none could be decoded.
+    pub inline_images: HashMap<String, HashMap<String, InlineImage>>,
+    /// A message whose inline images are waiting to be fetched by the event loop.
+    pub pending_inline_images: Option<String>,
+    /// Detected once at startup.
+    pub graphics_protocol: GraphicsProtocol,
+    /// The link picker over the message body, while it is open.
+    pub link_picker: Option<LinkPicker>,
+    /// Loaded at startup. Only the layout changes while running.
+    pub config: Config,
+    pub theme: Theme,
+    pub keymap: Keymap,
+    /// Keys typed so far of a sequence bound in the keymap.
+    pub pending_keys: Vec<Key>,
+    /// Whether the keys of the current screen are shown over it.
+    pub show_help: bool,
     pub selected_message_index: usize,
     pub cursor_height: usize,
     pub should_view_message_body: bool,
     pub should_exit: bool,
     pub should_skip_render: bool,
+    pub selected_attachment_index: usize,
+    /// An attachment download waiting to be run by the event loop.
+    pub pending_attachment_action: Option<AttachmentAction>,
+    /// Outcome of the last action, shown in the status bar.
+    pub status_message: Option<String>,
+    /// The message being written, shown instead of the list while set.
+    pub compose: Option<Compose>,
+    pub pending_compose_action: Option<ComposeAction>,
+    /// The accounts screen, shown instead of the list while open.
+    pub accounts: Option<AccountsScreen>,
+    /// A sign-in waiting to be run by the event loop.
+    pub pending_account_action: Option<AccountAction>,
+    /// The inbox rules screen, shown instead of the list while open.
+    pub inbox_rules: Option<InboxRulesScreen>,
+    /// A reply or forward of the selected message waiting to be started by the event loop,
+    /// which may first download what it forwards.
+    pub pending_reply: Option<ReplyKind>,
+    /// A change to the inbox rules waiting to be run by the event loop.
+    pub pending_inbox_rules_action: Option<InboxRulesAction>,
+    /// The drafts screen, shown instead of the list while open.
+    pub drafts: Option<DraftsScreen>,
+    /// A fetch or discard of drafts waiting to be run by the event loop.
+    pub pending_drafts_action: Option<DraftsAction>,
+    /// Thread keys of the threads whose messages are all listed. Others show only the newest.
+    pub expanded_threads: HashSet<String>,
+    pub search_index: SearchIndex,
+    /// Text typed into the `/` prompt, while it is open.
+    pub search_prompt: Option<String>,
+    pub search_error: Option<String>,
+    /// A submitted query waiting to be run by the event loop.
+    pub pending_search: Option<(String, SearchQuery)>,
+    /// When set, the list shows these results instead of the unread messages.
+    pub search_results: Option<SearchResults>,
+    /// Text typed into the snooze prompt, while it is open.
+    pub snooze_prompt: Option<String>,
+    /// When to wake the selected message, once the event loop has snoozed it.
+    pub pending_snooze: Option<u64>,
+    /// How many messages are snoozed, as kept in storage.
+    pub snoozed_count: usize,
+    /// The messages waiting to be sent, as kept in storage.
+    pub outbox: Vec<QueuedMessage>,
+}
+
+/// How a mailbox is told apart from the others on screen.
+pub struct MailboxTag {
+    pub id: String,
+    pub name: String,
+    pub address: Option<String>,
+    pub color: Color,
+}
+
+pub struct SearchResults {
+    pub query: String,
+    pub messages: Vec<Message>,
+    /// Selection in the unread list to restore when leaving the results.
+    pub previous_selected_message_index: usize,
+    pub previous_list_scroll: usize,
 }
 
 impl State {
+    pub fn mailbox_tag(&self, mailbox_id: &str) -> Option<&MailboxTag> {
+        self.mailboxes.iter().find(|mailbox| mailbox.id == mailbox_id)
+    }
+
     pub fn new() -> State {
         State {
             is_loaded: false,
             unread_messages: Vec::new(),
+            views: vec![View::new("unread", ViewKind::Unified)],
+            view_index: 0,
+            view_messages: Vec::new(),
+            list_scroll: 0,
+            mailboxes: Vec::new(),
+            sync_statuses: HashMap::new(),
             parsed_message_bodies: Default::default(),
+            parsed_width: 0,
+            message_links: Default::default(),
+            message_images: Default::default(),
+            inline_images: Default::default(),
+            pending_inline_images: None,
+            graphics_protocol: GraphicsProtocol::HalfBlock,
+            link_picker: None,
+            config: Config::default(),
+            theme: Theme::dark(),
+            keymap: Keymap::default(),
+            pending_keys: vec![],
+            show_help: false,
             selected_message_index: 0,
             cursor_height: 0,
             should_view_message_body: false,
             should_exit: false,
-            should_skip_render: false
+            should_skip_render: false,
+            selected_attachment_index: 0,
+            pending_attachment_action: None,
+            status_message: None,
+            compose: None,
+            pending_compose_action: None,
+            accounts: None,
+            pending_account_action: None,
+            inbox_rules: None,
+            pending_inbox_rules_action: None,
+            pending_reply: None,
+            drafts: None,
+            pending_drafts_action: None,
+            expanded_threads: Default::default(),
+            search_index: Default::default(),
+            search_prompt: None,
+            search_error: None,
+            pending_search: None,
+            search_results: None,
+            snooze_prompt: None,
+            pending_snooze: None,
+            snoozed_count: 0,
+            outbox: vec![],
         }
     }
 
+    /// The messages currently listed: search results if any, otherwise the unread messages of
+    /// the current view.
+    pub fn messages(&self) -> &Vec<Message> {
+        match &self.search_results {
+            Some(search_results) => &search_results.messages,
+            None => &self.view_messages,
+        }
+    }
+
+    fn messages_mut(&mut self) -> &mut Vec<Message> {
+        match &mut self.search_results {
+            Some(search_results) => &mut search_results.messages,
+            None => &mut self.view_messages,
+        }
+    }
+
+    pub fn view(&self) -> &View {
+        &self.views[self.view_index]
+    }
+
+    fn messages_in_view(&self, view: &View) -> Vec<Message> {
+        self.unread_messages.iter()
+            .filter(|message| view.includes(message, self.mailbox_tag(&message.mailbox_id)))
+            .cloned()
+            .collect()
+    }
+
+    /// Lists the views of the current mailboxes and config, staying on the current view if
+    /// it is still there.
+    pub fn list_views(&mut self) {
+        self.remember_view_position();
+        let current_kind = self.view().kind.clone();
+        let views = std::mem::take(&mut self.views);
+        self.views = view::list_views(views, &self.mailboxes, &self.config.views);
+        match self.views.iter().position(|view| view.kind == current_kind) {
+            Some(index) => self.view_index = index,
+            None => {
+                self.view_index = 0;
+                self.show_view();
+            }
+        }
+    }
+
+    pub fn select_view(&mut self, index: usize) {
+        if index >= self.views.len() || index == self.view_index {
+            return;
+        }
+        self.close_search_results();
+        self.remember_view_position();
+        self.view_index = index;
+        self.show_view();
+    }
+
+    fn remember_view_position(&mut self) {
+        let selected_message_index = match &self.search_results {
+            Some(search_results) => search_results.previous_selected_message_index,
+            None => self.selected_message_index,
+        };
+        let selected_message_id = self.view_messages.get(selected_message_index)
+            .map(|message| message.id.clone());
+        let list_scroll = self.list_scroll;
+        let view = &mut self.views[self.view_index];
+        view.selected_message_id = selected_message_id;
+        view.list_scroll = list_scroll;
+    }
+
+    /// Lists the messages of the current view with the selection and scroll it had.
+    fn show_view(&mut self) {
+        self.view_messages = self.messages_in_view(self.view());
+        let view = &self.views[self.view_index];
+        let selected_message_index = view.selected_message_id.as_ref()
+            .and_then(|id| self.view_messages.iter().position(|message| message.id == *id));
+        self.selected_message_index = selected_message_index.unwrap_or(0);
+        self.list_scroll = view.list_scroll;
+        self.should_view_message_body = false;
+        self.fit_selection_to_visible();
+    }
+
+    /// Lists the messages of the current view again after the unread messages changed,
+    /// keeping the selected message selected if it is still unread.
+    pub fn refresh_view_messages(&mut self) {
+        let messages = self.messages_in_view(self.view());
+        if let Some(search_results) = &mut self.search_results {
+            // The results stay listed, so only the selection to return to can be kept.
+            search_results.previous_selected_message_index = search_results
+                .previous_selected_message_index
+                .min(messages.len().saturating_sub(1));
+            self.view_messages = messages;
+            return;
+        }
+        let selected_message_id = self.view_messages.get(self.selected_message_index)
+            .map(|message| message.id.clone());
+        self.view_messages = messages;
+        let selected_message_index = selected_message_id.and_then(|id| self.view_messages
+            .iter()
+            .position(|message| message.id == id));
+        match selected_message_index {
+            Some(index) => self.selected_message_index = index,
+            // The message was read elsewhere, so there is no body to show.
+            None => self.should_view_message_body = false,
+        }
+        self.fit_selection_to_visible();
+    }
+
+    /// Takes a message that left the inbox, such as a snoozed one, out of every list.
+    pub fn remove_message(&mut self, message_id: &str) {
+        let selected_message = self.messages().get(self.selected_message_index);
+        if selected_message.is_some_and(|message| message.id == message_id) {
+            self.should_view_message_body = false;
+        }
+        self.unread_messages.retain(|message| message.id != message_id);
+        if let Some(search_results) = &mut self.search_results {
+            search_results.messages.retain(|message| message.id != message_id);
+        }
+        self.parsed_message_bodies.remove(message_id);
+        self.message_links.remove(message_id);
+        self.message_images.remove(message_id);
+        self.inline_images.remove(message_id);
+        self.refresh_view_messages();
+        self.fit_selection_to_visible();
+    }
+
+    pub fn selected_message(&self) -> &Message {
+        &self.messages()[self.selected_message_index]
+    }
+
+    /// Links of the selected message, once its body has been parsed.
+    pub fn selected_message_links(&self) -> &[String] {
+        self.message_links.get(&self.selected_message().id)
+            .map(|links| links.as_slice())
+            .unwrap_or_default()
+    }
+
     pub fn set_selected_message_as_read(&mut self, storage: &Storage) {
-        if self.unread_messages.len() == 0 {
+        if self.messages().is_empty() {
             return;
         }
-        let selected_message_id = self
-            .unread_messages[self.selected_message_index].id.clone();
-        let selected_message_mailbox_id = self
-            .unread_messages[self.selected_message_index].mailbox_id.clone();
-        self.unread_messages.remove(self.selected_message_index);
+        let selected_message_id = self.selected_message().id.clone();
+        let mailbox = match self.selected_message_mailbox(storage) {
+            Some(mailbox) => mailbox.clone(),
+            None => return,
+        };
+        if self.search_results.is_some() {
+            // Results also list read mail, so the message stays where it is.
+            let index = self.selected_message_index;
+            self.messages_mut()[index].is_read = true;
+        } else {
+            self.view_messages.remove(self.selected_message_index);
+            self.decrease_selected_message_index();
+            self.fit_selection_to_visible();
+        }
+        self.unread_messages.retain(|message| message.id != selected_message_id);
+        self.view_messages.retain(|message| message.id != selected_message_id);
         self.parsed_message_bodies.remove(&selected_message_id);
-        self.decrease_selected_message_index();
-        let mailbox = storage
-            .get_mailbox_by_id(selected_message_mailbox_id.as_str())
-            .unwrap().clone();
+        self.message_links.remove(&selected_message_id);
+        self.message_images.remove(&selected_message_id);
+        self.inline_images.remove(&selected_message_id);
         tokio::task::spawn(mailbox.set_as_read(selected_message_id));
     }
 
+    pub fn toggle_selected_message_flag(&mut self, storage: &Storage) {
+        if self.messages().is_empty() {
+            return;
+        }
+        let mailbox = match self.selected_message_mailbox(storage) {
+            Some(mailbox) => mailbox.clone(),
+            None => return,
+        };
+        let index = self.selected_message_index;
+        let message = &mut self.messages_mut()[index];
+        message.flagged = if message.flagged.is_flagged() {
+            Flag::NotFlagged
+        } else {
+            Flag::Flagged(None)
+        };
+        let update = FlagUpdate {
+            flagged: Some(message.flagged.clone()),
+            ..Default::default()
+        };
+        let message_id = message.id.clone();
+        tokio::task::spawn(mailbox.update_flags(message_id, update));
+    }
+
+    /// The account of the selected message, or `None` with a status message if it was removed
+    /// since the message was fetched, as search results from the index can be.
+    fn selected_message_mailbox<'a>(
+        &mut self,
+        storage: &'a Storage,
+    ) -> Option<&'a OutlookMailbox> {
+        let mailbox = storage.get_mailbox_by_id(&self.selected_message().mailbox_id);
+        if mailbox.is_none() {
+            self.status_message = Some("account no longer exists".to_string());
+        }
+        mailbox
+    }
+
+    pub fn open_selected_message_body(&mut self) {
+        self.cursor_height = 0;
+        self.selected_attachment_index = 0;
+        self.should_view_message_body = true;
+        try_parse_selected_message(self);
+        let message = self.selected_message();
+        let has_inline_images = message.body_type == BodyType::Html
+            && message.body.contains("cid:")
+            && message.attachments.iter().any(|attachment| attachment.is_inline);
+        if has_inline_images && !self.inline_images.contains_key(&message.id) {
+            self.pending_inline_images = Some(message.id.clone());
+        }
+    }
+
+    pub fn select_next_attachment(&mut self) {
+        let count = self.selected_message().attachments.len();
+        if count > 0 {
+            self.selected_attachment_index = (self.selected_attachment_index + 1) % count;
+        }
+    }
+
+    /// Starts a new message from the selected message's mailbox, or the first one.
+    pub fn start_compose(&mut self, storage: &Storage) {
+        let mailbox_id = match self.messages().get(self.selected_message_index) {
+            Some(message) => message.mailbox_id.clone(),
+            None => match storage.outlook.first() {
+                Some(mailbox) => mailbox.get_id().to_string(),
+                None => {
+                    self.status_message = Some("no mailbox to send from".to_string());
+                    return;
+                }
+            },
+        };
+        self.compose = Some(Compose::new(&mailbox_id));
+        self.pending_compose_action = Some(ComposeAction::Edit);
+    }
+
+    pub fn submit_search_prompt(&mut self) {
+        let query_str = self.search_prompt.clone().unwrap_or_default();
+        match SearchQuery::parse(&query_str) {
+            Ok(query) => {
+                self.search_prompt = None;
+                self.search_error = None;
+                self.pending_search = Some((query_str, query));
+            }
+            Err(error) => self.search_error = Some(error.to_string()),
+        }
+    }
+
+    pub fn close_search_results(&mut self) {
+        if let Some(search_results) = self.search_results.take() {
+            self.selected_message_index = search_results.previous_selected_message_index;
+            self.list_scroll = search_results.previous_list_scroll;
+            self.fit_selection_to_visible();
+        }
+    }
+
     pub fn decrease_selected_message_index(&mut self) {
-        if self.selected_message_index > 0 {
-            self.selected_message_index -= 1;
+        if let Some(index) = (0..self.selected_message_index)
+            .rev()
+            .find(|index| self.is_message_visible(*index))
+        {
+            self.selected_message_index = index;
         }
     }
 
     pub fn increase_selected_message_index(&mut self) {
-        let count = self.unread_messages.len();
-        let min_index = if count == 0 { 0 } else { count - 1 };
-        if self.selected_message_index < min_index {
-            self.selected_message_index += 1;
+        if let Some(index) = (self.selected_message_index + 1..self.messages().len())
+            .find(|index| self.is_message_visible(*index))
+        {
+            self.selected_message_index = index;
+        }
+    }
+
+    pub fn select_first_message(&mut self) {
+        if let Some(index) = self.visible_message_indices().first() {
+            self.selected_message_index = *index;
+        }
+    }
+
+    pub fn select_last_message(&mut self) {
+        if let Some(index) = self.visible_message_indices().last() {
+            self.selected_message_index = *index;
         }
     }
-}
\ No newline at end of file
+
+    /// Scrolls the open body so that its last line is at the bottom of the screen.
+    pub fn scroll_body_to_bottom(&mut self) {
+        let line_count = self.parsed_message_bodies.get(&self.selected_message().id)
+            .map(|body| body.split("\r\n").count())
+            .unwrap_or(0);
+        let rows = termion::terminal_size().map(|(_, rows)| rows as usize).unwrap_or(24);
+        // Leave out the status bar.
+        self.cursor_height = line_count.saturating_sub(rows.saturating_sub(1));
+    }
+
+    /// Keeps the selection in bounds and on a listed message, e.g. after messages were removed
+    /// or the selection moved inside a collapsed thread.
+    pub fn fit_selection_to_visible(&mut self) {
+        let count = self.messages().len();
+        if self.selected_message_index >= count {
+            self.selected_message_index = count.saturating_sub(1);
+        }
+        // Thread members follow their thread head, so the head is always above.
+        while self.selected_message_index > 0
+            && !self.is_message_visible(self.selected_message_index)
+        {
+            self.selected_message_index -= 1;
+        }
+    }
+
+    /// Whether the message is the newest of its thread, which is listed even when collapsed.
+    pub fn is_thread_head(&self, index: usize) -> bool {
+        let messages = self.messages();
+        index == 0 || thread_key(&messages[index - 1]) != thread_key(&messages[index])
+    }
+
+    pub fn is_message_visible(&self, index: usize) -> bool {
+        self.is_thread_head(index)
+            || self.expanded_threads.contains(thread_key(&self.messages()[index]))
+    }
+
+    pub fn visible_message_indices(&self) -> Vec<usize> {
+        (0..self.messages().len())
+            .filter(|index| self.is_message_visible(*index))
+            .collect()
+    }
+
+    pub fn thread_size(&self, index: usize) -> usize {
+        let key = thread_key(&self.messages()[index]);
+        self.messages().iter().filter(|message| thread_key(message) == key).count()
+    }
+
+    /// Returns the position of the message in its thread, counting from the newest, and the
+    /// thread size.
+    pub fn thread_position(&self, index: usize) -> (usize, usize) {
+        let head = (0..=index).rev().find(|i| self.is_thread_head(*i)).unwrap_or(0);
+        (index - head, self.thread_size(index))
+    }
+
+    pub fn toggle_selected_thread(&mut self) {
+        if self.messages().is_empty() {
+            return;
+        }
+        let key = thread_key(self.selected_message()).to_string();
+        if !self.expanded_threads.remove(&key) {
+            self.expanded_threads.insert(key);
+        }
+        self.fit_selection_to_visible();
+    }
+
+    /// Moves to the next (older) message of the selected thread, even if it is collapsed.
+    pub fn select_next_message_in_thread(&mut self) -> bool {
+        let index = self.selected_message_index + 1;
+        if index >= self.messages().len() || self.is_thread_head(index) {
+            return false;
+        }
+        self.selected_message_index = index;
+        true
+    }
+
+    pub fn select_previous_message_in_thread(&mut self) -> bool {
+        if self.messages().is_empty() || self.is_thread_head(self.selected_message_index) {
+            return false;
+        }
+        self.selected_message_index -= 1;
+        true
+    }
+}
@@ -1,51 +1,139 @@
-use std::{fs, io};
-use std::path::{PathBuf};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
 use serde::{Serialize, Deserialize};
 use api::mail::Mailbox;
 use api::outlook::OutlookMailbox;
+use crate::drafts::LocalDraft;
+use crate::outbox::QueuedMessage;
+use crate::snooze::Snooze;
+use crate::ui::Color;
 
-const STORAGE_FILE_NAME: &'static str = "dashboard.json";
+const STORAGE_FILE_NAME: &str = "dashboard.json";
+/// Colours given to accounts in turn, and cycled through on the accounts screen.
+pub const TAG_COLORS: [&str; 6] = ["cyan", "magenta", "yellow", "green", "blue", "red"];
 
-#[derive(Serialize, Deserialize)]
+/// Credentials and other state kept between sessions. Settings are in the config instead.
+#[derive(Serialize, Deserialize, Default)]
 pub struct Storage {
     pub outlook: Vec<OutlookMailbox>,
-}
-
-impl Default for Storage {
-    fn default() -> Self {
-        Storage {
-            outlook: vec![]
-        }
-    }
+    /// Snoozed messages, waiting to be put back in the inbox.
+    #[serde(default)]
+    pub snoozes: Vec<Snooze>,
+    /// Messages waiting to be sent, for the undo delay or until the time they are scheduled.
+    #[serde(default)]
+    pub outbox: Vec<QueuedMessage>,
+    /// Drafts waiting to be saved to the server, such as those written offline.
+    #[serde(default)]
+    pub drafts: Vec<LocalDraft>,
+    /// The file this was read from and is saved to.
+    #[serde(skip)]
+    pub path: PathBuf,
 }
 
 impl Storage {
     pub fn get_mailbox_by_id(&self, id: &str) -> Option<&OutlookMailbox> {
         self.outlook.iter().find(|mailbox| mailbox.get_id() == id)
     }
+
+    /// Finds a mailbox by id or by address, as typed on the command line.
+    pub fn find_mailbox(&self, id_or_address: &str) -> Option<usize> {
+        self.outlook.iter().position(|mailbox| {
+            mailbox.get_id() == id_or_address
+                || mailbox.address.as_deref() == Some(id_or_address)
+        })
+    }
+
+    pub fn enabled_mailboxes(&self) -> impl Iterator<Item = &OutlookMailbox> {
+        self.outlook.iter().filter(|mailbox| !mailbox.is_disabled)
+    }
+
+    /// Removes the mailbox with its snoozes, queued messages and local drafts, which could
+    /// no longer be woken, sent or saved.
+    pub fn remove_mailbox(&mut self, index: usize) -> OutlookMailbox {
+        let mailbox = self.outlook.remove(index);
+        let mailbox_id = mailbox.get_id();
+        self.snoozes.retain(|snooze| snooze.mailbox_id != mailbox_id);
+        self.outbox.retain(|queued_message| queued_message.mailbox_id != mailbox_id);
+        self.drafts.retain(|draft| draft.mailbox_id != mailbox_id);
+        mailbox
+    }
+}
+
+/// The mailbox's name, or its provider and position when it has none.
+pub fn mailbox_name(mailbox: &OutlookMailbox, index: usize) -> String {
+    mailbox.name.clone().unwrap_or_else(|| format!("outlook {}", index + 1))
 }
 
-fn get_storage_path() -> PathBuf {
+/// The names the mailbox goes by in filters: its name, id and address.
+pub fn account_names(mailbox: &OutlookMailbox, index: usize) -> Vec<String> {
+    let mut names = vec![mailbox_name(mailbox, index), mailbox.get_id().to_string()];
+    names.extend(mailbox.address.clone());
+    names
+}
+
+/// The mailbox's colour, or one of `TAG_COLORS` by its position when it has none or its
+/// own cannot be read.
+pub fn mailbox_color(mailbox: &OutlookMailbox, index: usize) -> Color {
+    mailbox.color.as_deref()
+        .and_then(|color| Color::from_str(color).ok())
+        .unwrap_or_else(|| Color::from_str(TAG_COLORS[index % TAG_COLORS.len()]).unwrap())
+}
+
+pub fn default_path() -> PathBuf {
     dirs::config_dir().unwrap().join(STORAGE_FILE_NAME)
 }
 
+/// Saves the storage to a temporary file first, then moves it into place, so that the
+/// tokens are never left half written.
 pub fn set(storage: &Storage) {
+    let temporary_path = storage.path.with_extension("json.tmp");
     fs::write(
-        get_storage_path(),
+        &temporary_path,
         serde_json::to_string(storage)
             .expect("storage::set: could not serialize storage before saving")
     ).expect("storage::set: failed to write to storage");
+    fs::rename(&temporary_path, &storage.path).expect("storage::set: failed to replace storage");
 }
 
-pub fn get() -> Storage {
-    fn read() -> io::Result<String> {
-        fs::read_to_string(get_storage_path())
-    }
+pub fn get(path: PathBuf) -> Storage {
+    let read = || fs::read_to_string(&path);
     let mut storage_string = read();
     if storage_string.is_err() {
-        set(&Storage::default());
+        set(&Storage { path: path.clone(), ..Storage::default() });
         storage_string = read();
     }
-    serde_json::from_str(&storage_string.unwrap())
-        .expect("storage::get: could not deserialize storage")
+    let mut storage: Storage = serde_json::from_str(&storage_string.unwrap())
+        .expect("storage::get: could not deserialize storage");
+    storage.path = path;
+    // Accounts added before they had ids get one now, and keep it from then on.
+    let mut has_new_ids = false;
+    for mailbox in storage.outlook.iter_mut().filter(|mailbox| mailbox.id.is_empty()) {
+        mailbox.id = api::mail::new_mailbox_id();
+        has_new_ids = true;
+    }
+    if has_new_ids {
+        set(&storage);
+    }
+    storage
+}
+
+/// Storage for tests, saved to its own file in the temporary directory, with signed-in
+/// mailboxes that are never synced.
+#[cfg(test)]
+pub fn test_storage(name: &str, mailbox_count: usize) -> Storage {
+    use api::outlook::auth::AccessTokenResponse;
+    let auth = AccessTokenResponse {
+        access_token: String::new(),
+        token_type: "Bearer".to_string(),
+        expires_in: 3600,
+        scope: String::new(),
+        refresh_token: String::new(),
+    };
+    let file_name = format!("dashboard-test-{}-{}.json", name, std::process::id());
+    Storage {
+        outlook: (0..mailbox_count).map(|_| OutlookMailbox::open("client", auth.clone())).collect(),
+        path: std::env::temp_dir().join(file_name),
+        ..Storage::default()
+    }
 }
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use api::mail::{Mailbox, Message};
+use api::outlook::OutlookMailbox;
+use crate::{render, rules, storage, State, Storage};
+use crate::parse::sort_messages_by_thread;
+use crate::state::MailboxTag;
+use crate::ui::{Backend, Terminal};
+
+/// Outcome of the last sync of a mailbox.
+pub struct SyncStatus {
+    /// Seconds since the Unix epoch.
+    pub time: u64,
+    pub error: Option<String>,
+}
+
+/// Fetches the unread messages of every enabled mailbox again, as at startup and then every
+/// `sync_interval` seconds. A mailbox that cannot be fetched keeps the messages it had.
+pub async fn run(state: &mut State, storage: &mut Storage, terminal: &mut Terminal<impl Backend>) {
+    let mut refresh_errors = refresh_outlook_access_tokens(storage).await;
+    if look_up_addresses(storage).await {
+        // Options in the config may be keyed by the address.
+        for mailbox in &mut storage.outlook {
+            mailbox.body_preference = state.config.body_preference(mailbox);
+        }
+    }
+    list_mailboxes(state, storage);
+    let mailboxes: Vec<(usize, &OutlookMailbox)> = storage.outlook.iter()
+        .enumerate()
+        .filter(|(_, mailbox)| !mailbox.is_disabled)
+        .collect();
+    let mut unread_messages = vec![];
+    let mut failed_mailboxes = vec![];
+    let mut rule_errors = vec![];
+    for (i, (index, outlook_mailbox)) in mailboxes.iter().enumerate() {
+        let message = format!(
+            "fetching unread messages from mailboxes ({}/{})...",
+            i + 1,
+            mailboxes.len()
+        );
+        render::progress(state, &message, terminal);
+        let mailbox_id = outlook_mailbox.get_id();
+        let result = match refresh_errors.remove(mailbox_id) {
+            Some(error) => Err(error),
+            None => outlook_mailbox.fetch_unread().await.map_err(|error| error.to_string()),
+        };
+        let error = match result {
+            Ok(messages) => {
+                let (mut messages, mut errors) =
+                    apply_rules(state, outlook_mailbox, *index, messages).await;
+                unread_messages.append(&mut messages);
+                rule_errors.append(&mut errors);
+                None
+            }
+            Err(error) => {
+                unread_messages.extend(state.unread_messages.iter()
+                    .filter(|message| message.mailbox_id == mailbox_id)
+                    .cloned());
+                failed_mailboxes.push(storage::mailbox_name(outlook_mailbox, *index));
+                Some(error)
+            }
+        };
+        let time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+        state.sync_statuses.insert(mailbox_id.to_string(), SyncStatus { time, error });
+    }
+    if !failed_mailboxes.is_empty() {
+        state.status_message = Some(format!("could not sync {}", failed_mailboxes.join(", ")));
+    } else if let Some(error) = rule_errors.first() {
+        state.status_message = Some(match rule_errors.len() {
+            1 => error.clone(),
+            count => format!("{} (and {} more)", error, count - 1),
+        });
+    }
+    render::progress(state, "indexing messages...", terminal);
+    replace_unread_messages(state, unread_messages);
+}
+
+/// Applies the rules of the config to the fetched messages that were not unread at the last
+/// sync, leaving out those the rules mark as read or move. Returns the messages that stay,
+/// and why rules could not be applied to the others.
+async fn apply_rules(
+    state: &State,
+    mailbox: &OutlookMailbox,
+    index: usize,
+    messages: Vec<Message>,
+) -> (Vec<Message>, Vec<String>) {
+    if state.config.rules.is_empty() {
+        return (messages, vec![]);
+    }
+    let account_names = storage::account_names(mailbox, index);
+    let account_names: Vec<&str> = account_names.iter().map(String::as_str).collect();
+    let mut kept_messages = vec![];
+    let mut errors = vec![];
+    for mut message in messages {
+        let is_new = !state.unread_messages.iter()
+            .any(|unread_message| unread_message.id == message.id);
+        let plan = if is_new {
+            rules::plan(&state.config.rules, &message, &account_names)
+        } else {
+            None
+        };
+        let mut plan = match plan {
+            Some(plan) => plan,
+            None => {
+                kept_messages.push(message);
+                continue;
+            }
+        };
+        // The messages already in the inbox at startup are not news.
+        plan.notify &= state.is_loaded;
+        match rules::apply(mailbox, &message, &plan).await {
+            Ok(()) if plan.removes_message() => (),
+            Ok(()) => {
+                message.categories.append(&mut plan.categories);
+                kept_messages.push(message);
+            }
+            Err(error) => {
+                errors.push(format!("could not apply {}: {}", plan.rules.join(", "), error));
+                kept_messages.push(message);
+            }
+        }
+    }
+    (kept_messages, errors)
+}
+
+/// Lists the enabled mailboxes in the folders pane, with a view for each.
+pub fn list_mailboxes(state: &mut State, storage: &Storage) {
+    state.mailboxes = storage.outlook.iter()
+        .enumerate()
+        .filter(|(_, mailbox)| !mailbox.is_disabled)
+        .map(|(i, mailbox)| MailboxTag {
+            id: mailbox.get_id().to_string(),
+            name: storage::mailbox_name(mailbox, i),
+            address: mailbox.address.clone(),
+            color: storage::mailbox_color(mailbox, i),
+        })
+        .collect();
+    state.list_views();
+}
+
+/// Refreshes the access tokens that have expired, saving them if any were. Returns why the
+/// others could not be refreshed, by mailbox id.
+pub async fn refresh_outlook_access_tokens(storage: &mut Storage) -> HashMap<String, String> {
+    let mut should_save_storage: bool = false;
+    let mut errors = HashMap::new();
+    for outlook in storage.outlook.iter_mut().filter(|mailbox| !mailbox.is_disabled) {
+        match outlook.try_refresh_access_token().await {
+            Ok(refreshed) => should_save_storage |= refreshed,
+            Err(error) => {
+                errors.insert(outlook.get_id().to_string(), error.to_string());
+            }
+        }
+    }
+    if should_save_storage {
+        storage::set(storage);
+    }
+    errors
+}
+
+/// Looks up the addresses of the accounts that do not have one yet, saving them if any were
+/// found. Accounts that fail are tried again on the next sync.
+pub async fn look_up_addresses(storage: &mut Storage) -> bool {
+    let mut has_new_addresses = false;
+    for mailbox in storage.outlook.iter_mut()
+        .filter(|mailbox| !mailbox.is_disabled && mailbox.address.is_none()) {
+        if let Ok(address) = mailbox.fetch_address().await {
+            mailbox.address = Some(address);
+            has_new_addresses = true;
+        }
+    }
+    if has_new_addresses {
+        storage::set(storage);
+    }
+    has_new_addresses
+}
+
+/// Lists the fetched messages in place of the previous ones.
+pub fn replace_unread_messages(state: &mut State, mut messages: Vec<Message>) {
+    api::thread::assign_thread_ids(&mut messages);
+    let messages = sort_messages_by_thread(&messages);
+    state.search_index.insert(&messages);
+    state.unread_messages = messages;
+    state.refresh_view_messages();
+}
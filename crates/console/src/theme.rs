@@ -0,0 +1,210 @@
+use std::str::FromStr;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use crate::ui::{Color, ColorSupport, Style};
+
+/// Styles of the parts of the screen that are not message text.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    /// The selected message and folder, on top of their own style.
+    pub selected: Style,
+    /// Unread messages in search results, on top of their own style.
+    pub unread: Style,
+    /// The subject of flagged messages.
+    pub flagged: Style,
+    pub from: Style,
+    pub subject: Style,
+    pub date: Style,
+    /// Quoted lines, by how deeply they are quoted. Deeper levels reuse these in turn.
+    pub quotes: Vec<Style>,
+    pub status_bar: Style,
+    /// Hints, separators and other secondary text.
+    pub dim: Style,
+    /// Whether the colours of accounts are left out of their tags, as `NO_COLOR` asks.
+    pub no_color: bool,
+}
+
+impl Theme {
+    pub fn dark() -> Theme {
+        Theme {
+            selected: Style { bg: Color::Indexed(8), ..Style::default() },
+            unread: Style { bold: true, ..Style::default() },
+            flagged: Style { fg: Color::Indexed(3), ..Style::default() },
+            from: Style { fg: Color::Rgb(0x8c, 0xc8, 0xf0), ..Style::default() },
+            subject: Style { bold: true, ..Style::default() },
+            date: Style { fg: Color::Indexed(8), ..Style::default() },
+            quotes: vec![
+                Style { fg: Color::Rgb(0x87, 0xaf, 0x87), ..Style::default() },
+                Style { fg: Color::Rgb(0x87, 0xaf, 0xaf), ..Style::default() },
+                Style { fg: Color::Rgb(0xaf, 0x87, 0xaf), ..Style::default() },
+            ],
+            status_bar: Style { bg: Color::Rgb(0x26, 0x26, 0x26), ..Style::default() },
+            dim: Style { fg: Color::Indexed(8), ..Style::default() },
+            no_color: false,
+        }
+    }
+
+    pub fn light() -> Theme {
+        Theme {
+            selected: Style { bg: Color::Indexed(7), fg: Color::Indexed(0), ..Style::default() },
+            unread: Style { bold: true, ..Style::default() },
+            flagged: Style { fg: Color::Indexed(1), ..Style::default() },
+            from: Style { fg: Color::Rgb(0x00, 0x5f, 0xaf), ..Style::default() },
+            subject: Style { bold: true, ..Style::default() },
+            date: Style { fg: Color::Indexed(8), ..Style::default() },
+            quotes: vec![
+                Style { fg: Color::Rgb(0x00, 0x87, 0x00), ..Style::default() },
+                Style { fg: Color::Rgb(0x00, 0x87, 0x87), ..Style::default() },
+                Style { fg: Color::Rgb(0x87, 0x00, 0x87), ..Style::default() },
+            ],
+            status_bar: Style { bg: Color::Rgb(0xe4, 0xe4, 0xe4), ..Style::default() },
+            dim: Style { fg: Color::Indexed(8), ..Style::default() },
+            no_color: false,
+        }
+    }
+
+    /// Attributes only, for when `NO_COLOR` is set.
+    pub fn monochrome() -> Theme {
+        Theme {
+            selected: Style { reverse: true, ..Style::default() },
+            unread: Style { bold: true, ..Style::default() },
+            flagged: Style { bold: true, ..Style::default() },
+            from: Style::default(),
+            subject: Style { bold: true, ..Style::default() },
+            date: Style::default(),
+            quotes: vec![Style { italic: true, ..Style::default() }],
+            status_bar: Style { reverse: true, ..Style::default() },
+            dim: Style::default(),
+            no_color: true,
+        }
+    }
+
+    /// The configured theme. With `NO_COLOR` set, colours are left out of the builtin theme
+    /// and of the configured styles alike.
+    pub fn from_config(config: &ThemeConfig) -> Theme {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        let mut theme = match (no_color, config.name) {
+            (true, _) => Theme::monochrome(),
+            (false, ThemeName::Dark) => Theme::dark(),
+            (false, ThemeName::Light) => Theme::light(),
+        };
+        let styles = [
+            (&mut theme.selected, &config.selected),
+            (&mut theme.unread, &config.unread),
+            (&mut theme.flagged, &config.flagged),
+            (&mut theme.from, &config.from),
+            (&mut theme.subject, &config.subject),
+            (&mut theme.date, &config.date),
+            (&mut theme.status_bar, &config.status_bar),
+            (&mut theme.dim, &config.dim),
+        ];
+        for (style, style_config) in styles {
+            if let Some(style_config) = style_config {
+                style_config.apply(style, no_color);
+            }
+        }
+        if let Some(quotes) = &config.quotes {
+            theme.quotes = quotes.iter()
+                .map(|style_config| {
+                    let mut style = Style::default();
+                    style_config.apply(&mut style, no_color);
+                    style
+                })
+                .collect();
+        }
+        theme
+    }
+
+    /// The style of a line quoted `level` times, if it is quoted.
+    pub fn quote(&self, level: usize) -> Option<&Style> {
+        if level == 0 || self.quotes.is_empty() {
+            return None;
+        }
+        self.quotes.get((level - 1) % self.quotes.len())
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemeName {
+    #[default]
+    Dark,
+    Light,
+}
+
+/// How many colours to use, overriding what is detected.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ColorsSetting {
+    #[default]
+    #[serde(rename = "auto")]
+    Auto,
+    #[serde(rename = "16")]
+    Ansi16,
+    #[serde(rename = "256")]
+    Ansi256,
+    #[serde(rename = "truecolor")]
+    TrueColor,
+}
+
+impl ColorsSetting {
+    pub fn color_support(self) -> ColorSupport {
+        match self {
+            ColorsSetting::Auto => ColorSupport::detect(),
+            ColorsSetting::Ansi16 => ColorSupport::Ansi16,
+            ColorsSetting::Ansi256 => ColorSupport::Ansi256,
+            ColorsSetting::TrueColor => ColorSupport::TrueColor,
+        }
+    }
+}
+
+/// A builtin theme, with any of its styles changed.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    pub name: ThemeName,
+    pub colors: ColorsSetting,
+    pub selected: Option<StyleConfig>,
+    pub unread: Option<StyleConfig>,
+    pub flagged: Option<StyleConfig>,
+    pub from: Option<StyleConfig>,
+    pub subject: Option<StyleConfig>,
+    pub date: Option<StyleConfig>,
+    /// Replaces the builtin quote levels rather than changing them.
+    pub quotes: Option<Vec<StyleConfig>>,
+    pub status_bar: Option<StyleConfig>,
+    pub dim: Option<StyleConfig>,
+}
+
+/// Changes to a style. What is left out keeps the builtin theme's value.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleConfig {
+    #[serde(deserialize_with = "deserialize_color")]
+    pub fg: Option<Color>,
+    #[serde(deserialize_with = "deserialize_color")]
+    pub bg: Option<Color>,
+    pub bold: Option<bool>,
+    pub italic: Option<bool>,
+    pub underline: Option<bool>,
+    pub reverse: Option<bool>,
+}
+
+impl StyleConfig {
+    fn apply(&self, style: &mut Style, no_color: bool) {
+        if !no_color {
+            style.fg = self.fg.unwrap_or(style.fg);
+            style.bg = self.bg.unwrap_or(style.bg);
+        }
+        style.bold = self.bold.unwrap_or(style.bold);
+        style.italic = self.italic.unwrap_or(style.italic);
+        style.underline = self.underline.unwrap_or(style.underline);
+        style.reverse = self.reverse.unwrap_or(style.reverse);
+    }
+}
+
+fn deserialize_color<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Color>, D::Error> {
+    let color = String::deserialize(deserializer)?;
+    Color::from_str(&color).map(Some).map_err(de::Error::custom)
+}
@@ -0,0 +1,167 @@
+use std::fmt::Write as FmtWrite;
+use std::io::Write;
+use termion::{clear, cursor, style};
+use crate::ui::{Cell, Color, ColorSupport, Style};
+#[cfg(test)]
+use crate::ui::{Buffer, Rect};
+use crate::width::grapheme_width;
+
+/// Where frames end up: the real terminal, or memory when checking what would be drawn.
+pub trait Backend {
+    fn size(&self) -> (u16, u16);
+    /// Writes the cells at their positions, given in row-major order.
+    fn draw<'a>(&mut self, cells: impl Iterator<Item = (u16, u16, &'a Cell)>);
+    /// Writes escape sequences that are not cells, such as images.
+    fn write_raw(&mut self, data: &str);
+    fn clear(&mut self);
+    fn flush(&mut self);
+}
+
+pub struct TermionBackend<W: Write> {
+    output: W,
+    color_support: ColorSupport,
+}
+
+impl<W: Write> TermionBackend<W> {
+    pub fn new(output: W, color_support: ColorSupport) -> TermionBackend<W> {
+        TermionBackend { output, color_support }
+    }
+}
+
+impl<W: Write> Backend for TermionBackend<W> {
+    fn size(&self) -> (u16, u16) {
+        termion::terminal_size().unwrap_or((80, 24))
+    }
+
+    fn draw<'a>(&mut self, cells: impl Iterator<Item = (u16, u16, &'a Cell)>) {
+        let mut output = String::new();
+        let mut cursor_position = None;
+        let mut current_style = Style::default();
+        for (x, y, cell) in cells {
+            // The second column of a wide character is written along with the first.
+            if cell.symbol.is_empty() {
+                continue;
+            }
+            if cursor_position != Some((x, y)) {
+                let _ = write!(output, "{}", cursor::Goto(x + 1, y + 1));
+            }
+            if cell.style != current_style {
+                write_style_change(&mut output, &current_style, &cell.style, self.color_support);
+                current_style = cell.style.clone();
+            }
+            output.push_str(&cell.symbol);
+            let width = grapheme_width(&cell.symbol).max(1);
+            cursor_position = Some((x + width as u16, y));
+        }
+        write_style_change(&mut output, &current_style, &Style::default(), self.color_support);
+        self.output.write_all(output.as_bytes()).unwrap();
+    }
+
+    fn write_raw(&mut self, data: &str) {
+        self.output.write_all(data.as_bytes()).unwrap();
+    }
+
+    fn clear(&mut self) {
+        write!(self.output, "{}{}", style::Reset, clear::All).unwrap();
+    }
+
+    fn flush(&mut self) {
+        self.output.flush().unwrap();
+    }
+}
+
+fn write_style_change(output: &mut String, from: &Style, to: &Style, support: ColorSupport) {
+    if from.link != to.link {
+        let _ = write!(output, "\x1b]8;;{}\x1b\\", to.link.as_deref().unwrap_or_default());
+    }
+    if (from.fg, from.bg, from.bold, from.italic, from.underline, from.reverse)
+        == (to.fg, to.bg, to.bold, to.italic, to.underline, to.reverse) {
+        return;
+    }
+    // Resetting first is simpler than turning off each attribute on its own, and the
+    // attributes rarely change within a row.
+    output.push_str("\x1b[0");
+    if to.bold {
+        output.push_str(";1");
+    }
+    if to.italic {
+        output.push_str(";3");
+    }
+    if to.underline {
+        output.push_str(";4");
+    }
+    if to.reverse {
+        output.push_str(";7");
+    }
+    write_color(output, 38, support.downgrade(to.fg));
+    write_color(output, 48, support.downgrade(to.bg));
+    output.push('m');
+}
+
+fn write_color(output: &mut String, code: u8, color: Color) {
+    let _ = match color {
+        Color::Reset => Ok(()),
+        Color::Indexed(index) => write!(output, ";{};5;{}", code, index),
+        Color::Rgb(r, g, b) => write!(output, ";{};2;{};{};{}", code, r, g, b),
+    };
+}
+
+/// Keeps what was drawn in memory, to check rendering without a terminal.
+#[cfg(test)]
+pub struct MemoryBackend {
+    pub buffer: Buffer,
+    /// Everything passed to `write_raw`, in order.
+    pub raw: String,
+    /// How many cells have been written, to check that unchanged cells are skipped.
+    pub cells_written: usize,
+}
+
+#[cfg(test)]
+impl MemoryBackend {
+    pub fn new(width: u16, height: u16) -> MemoryBackend {
+        MemoryBackend {
+            buffer: Buffer::empty(Rect::new(0, 0, width, height)),
+            raw: String::new(),
+            cells_written: 0,
+        }
+    }
+
+    /// The text of each row, without trailing spaces.
+    pub fn lines(&self) -> Vec<String> {
+        let width = self.buffer.area.width.max(1) as usize;
+        self.buffer.cells()
+            .chunks(width)
+            .map(|row| row.iter()
+                .map(|cell| cell.symbol.as_str())
+                .collect::<String>()
+                .trim_end()
+                .to_string())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+impl Backend for MemoryBackend {
+    fn size(&self) -> (u16, u16) {
+        (self.buffer.area.width, self.buffer.area.height)
+    }
+
+    fn draw<'a>(&mut self, cells: impl Iterator<Item = (u16, u16, &'a Cell)>) {
+        for (x, y, cell) in cells {
+            if let Some(target) = self.buffer.cell_mut(x, y) {
+                *target = cell.clone();
+                self.cells_written += 1;
+            }
+        }
+    }
+
+    fn write_raw(&mut self, data: &str) {
+        self.raw.push_str(data);
+    }
+
+    fn clear(&mut self) {
+        self.buffer = Buffer::empty(self.buffer.area);
+    }
+
+    fn flush(&mut self) {}
+}
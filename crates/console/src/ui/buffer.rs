@@ -0,0 +1,307 @@
+use std::rc::Rc;
+use unicode_segmentation::UnicodeSegmentation;
+use crate::ui::{Color, Rect};
+use crate::width::grapheme_width;
+
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct Style {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    /// Swaps the foreground and background, which shows up even without colours.
+    pub reverse: bool,
+    /// Target of an OSC 8 hyperlink over the cell.
+    pub link: Option<Rc<str>>,
+}
+
+impl Style {
+    /// Applies another style on top of this one, as `Buffer::patch_style` does to cells.
+    pub fn patch(&mut self, other: &Style) {
+        if other.fg != Color::Reset {
+            self.fg = other.fg;
+        }
+        if other.bg != Color::Reset {
+            self.bg = other.bg;
+        }
+        self.bold |= other.bold;
+        self.italic |= other.italic;
+        self.underline |= other.underline;
+        self.reverse |= other.reverse;
+        if other.link.is_some() {
+            self.link = other.link.clone();
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Cell {
+    /// The character in the cell with any combining marks, or empty for the second column
+    /// of a wide character.
+    pub symbol: String,
+    pub style: Style,
+}
+
+impl Default for Cell {
+    fn default() -> Cell {
+        Cell {
+            symbol: " ".to_string(),
+            style: Style::default(),
+        }
+    }
+}
+
+/// A grid of cells covering an area of the terminal.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Buffer {
+    pub area: Rect,
+    cells: Vec<Cell>,
+}
+
+impl Buffer {
+    pub fn empty(area: Rect) -> Buffer {
+        Buffer {
+            area,
+            cells: vec![Cell::default(); area.area()],
+        }
+    }
+
+    pub fn cells(&self) -> &[Cell] {
+        &self.cells
+    }
+
+    /// The position of the cell at the given index of `cells()`.
+    pub fn position(&self, index: usize) -> (u16, u16) {
+        let width = self.area.width.max(1) as usize;
+        (self.area.x + (index % width) as u16, self.area.y + (index / width) as u16)
+    }
+
+    fn index(&self, x: u16, y: u16) -> Option<usize> {
+        if !self.area.contains(x, y) {
+            return None;
+        }
+        Some((y - self.area.y) as usize * self.area.width as usize + (x - self.area.x) as usize)
+    }
+
+    pub fn cell_mut(&mut self, x: u16, y: u16) -> Option<&mut Cell> {
+        let index = self.index(x, y)?;
+        self.cells.get_mut(index)
+    }
+
+    /// Writes text from `(x, y)` onwards, cut off after `max_width` columns. Returns the
+    /// number of columns written.
+    pub fn set_string(
+        &mut self,
+        x: u16,
+        y: u16,
+        text: &str,
+        style: &Style,
+        max_width: u16,
+    ) -> u16 {
+        let mut column = 0;
+        for grapheme in text.graphemes(true) {
+            column += self.put_grapheme(x, y, column, max_width, grapheme, style);
+        }
+        column
+    }
+
+    /// Like `set_string`, but applies the SGR and OSC 8 escape sequences in the text on top
+    /// of the given style, as produced by the body renderers.
+    pub fn set_ansi_string(
+        &mut self,
+        x: u16,
+        y: u16,
+        text: &str,
+        base: &Style,
+        max_width: u16,
+    ) -> u16 {
+        let mut style = base.clone();
+        let mut column = 0;
+        let mut chars = text.chars().peekable();
+        // Text between escape sequences, kept together so that graphemes are not split.
+        let mut run = String::new();
+        while let Some(c) = chars.next() {
+            if c != '\x1b' {
+                run.push(c);
+                continue;
+            }
+            column += self.set_string(x + column, y, &run, &style, max_width - column);
+            run.clear();
+            match chars.next() {
+                Some('[') => {
+                    let mut parameters = String::new();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            if c == 'm' {
+                                apply_sgr(&parameters, base, &mut style);
+                            }
+                            break;
+                        }
+                        parameters.push(c);
+                    }
+                }
+                Some(']') => {
+                    let mut command = String::new();
+                    while let Some(c) = chars.next() {
+                        if c == '\x07' {
+                            break;
+                        }
+                        if c == '\x1b' && chars.peek() == Some(&'\\') {
+                            chars.next();
+                            break;
+                        }
+                        command.push(c);
+                    }
+                    if let Some(rest) = command.strip_prefix("8;") {
+                        let url = rest.split_once(';').map(|(_, url)| url).unwrap_or_default();
+                        style.link = if url.is_empty() { None } else { Some(Rc::from(url)) };
+                    }
+                }
+                _ => (),
+            }
+        }
+        column + self.set_string(x + column, y, &run, &style, max_width - column)
+    }
+
+    /// Applies a style on top of the cells in the area, keeping their text. Colours that are
+    /// not set and attributes that are off leave the cells' own.
+    pub fn patch_style(&mut self, area: Rect, style: &Style) {
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                if let Some(cell) = self.cell_mut(x, y) {
+                    cell.style.patch(style);
+                }
+            }
+        }
+    }
+
+    /// Blanks every cell in the area with the given style.
+    pub fn fill(&mut self, area: Rect, style: &Style) {
+        for y in area.y..area.bottom() {
+            for x in area.x..area.right() {
+                if let Some(cell) = self.cell_mut(x, y) {
+                    cell.symbol = " ".to_string();
+                    cell.style = style.clone();
+                }
+            }
+        }
+    }
+
+    /// Puts a grapheme at `column` columns past `(x, y)`, returning how many columns it
+    /// took up.
+    fn put_grapheme(
+        &mut self,
+        x: u16,
+        y: u16,
+        column: u16,
+        max_width: u16,
+        grapheme: &str,
+        style: &Style,
+    ) -> u16 {
+        let grapheme = if grapheme == "\t" { " " } else { grapheme };
+        if grapheme.starts_with(char::is_control) {
+            return 0;
+        }
+        let width = grapheme_width(grapheme) as u16;
+        if width == 0 {
+            // Marks left over from a split grapheme join the character before them.
+            if column > 0 {
+                let mut previous = x + column - 1;
+                while previous > x && self.symbol_at(previous, y) == Some("") {
+                    previous -= 1;
+                }
+                if let Some(cell) = self.cell_mut(previous, y) {
+                    cell.symbol.push_str(grapheme);
+                }
+            }
+            return 0;
+        }
+        let x = x + column;
+        if column + width > max_width || self.index(x + width - 1, y).is_none() {
+            return 0;
+        }
+        // Blank what remains of any wide characters partly overwritten.
+        if x > self.area.x && self.symbol_at(x, y) == Some("") {
+            if let Some(cell) = self.cell_mut(x - 1, y) {
+                cell.symbol = " ".to_string();
+            }
+        }
+        let mut after = x + width;
+        while self.symbol_at(after, y) == Some("") {
+            if let Some(cell) = self.cell_mut(after, y) {
+                cell.symbol = " ".to_string();
+            }
+            after += 1;
+        }
+        if let Some(cell) = self.cell_mut(x, y) {
+            cell.symbol = grapheme.to_string();
+            cell.style = style.clone();
+        }
+        for i in 1..width {
+            if let Some(cell) = self.cell_mut(x + i, y) {
+                cell.symbol = String::new();
+                cell.style = style.clone();
+            }
+        }
+        width
+    }
+
+    fn symbol_at(&self, x: u16, y: u16) -> Option<&str> {
+        self.index(x, y).map(|index| self.cells[index].symbol.as_str())
+    }
+}
+
+fn apply_sgr(parameters: &str, base: &Style, style: &mut Style) {
+    let codes: Vec<u16> = parameters.split(';')
+        .map(|code| code.parse().unwrap_or(0))
+        .collect();
+    let mut i = 0;
+    while i < codes.len() {
+        match codes[i] {
+            0 => {
+                let link = style.link.take();
+                *style = Style { link, ..base.clone() };
+            }
+            1 => style.bold = true,
+            3 => style.italic = true,
+            4 => style.underline = true,
+            7 => style.reverse = true,
+            22 => style.bold = base.bold,
+            23 => style.italic = base.italic,
+            24 => style.underline = base.underline,
+            27 => style.reverse = base.reverse,
+            code @ 30..=37 => style.fg = Color::Indexed((code - 30) as u8),
+            code @ 90..=97 => style.fg = Color::Indexed((code - 90 + 8) as u8),
+            39 => style.fg = base.fg,
+            code @ 40..=47 => style.bg = Color::Indexed((code - 40) as u8),
+            code @ 100..=107 => style.bg = Color::Indexed((code - 100 + 8) as u8),
+            49 => style.bg = base.bg,
+            code @ (38 | 48) => {
+                let color = match codes.get(i + 1) {
+                    Some(5) => {
+                        i += 2;
+                        codes.get(i).map(|index| Color::Indexed(*index as u8))
+                    }
+                    Some(2) => {
+                        i += 4;
+                        match codes.get(i - 2..=i) {
+                            Some([r, g, b]) => Some(Color::Rgb(*r as u8, *g as u8, *b as u8)),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                if let Some(color) = color {
+                    if code == 38 {
+                        style.fg = color;
+                    } else {
+                        style.bg = color;
+                    }
+                }
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+}
@@ -0,0 +1,146 @@
+use std::str::FromStr;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum Color {
+    #[default]
+    Reset,
+    /// One of the 256 indexed colours, the first 16 of which are the terminal's palette.
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// Names of the first 8 indexed colours. The next 8 are the same names with a `bright-` prefix.
+const COLOR_NAMES: [&str; 8] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+];
+
+impl FromStr for Color {
+    type Err = String;
+
+    /// Parses `default`, a colour name such as `blue` or `bright-blue`, an index from 0 to
+    /// 255, or `#rrggbb`.
+    fn from_str(color: &str) -> Result<Color, String> {
+        let invalid = || format!(
+            "invalid colour \"{}\", expected a name, an index from 0 to 255 or #rrggbb",
+            color
+        );
+        if color == "default" {
+            return Ok(Color::Reset);
+        }
+        if let Some(hex) = color.strip_prefix('#') {
+            let channel = |i: usize| hex.get(i..i + 2)
+                .and_then(|channel| u8::from_str_radix(channel, 16).ok());
+            return match (hex.len(), channel(0), channel(2), channel(4)) {
+                (6, Some(r), Some(g), Some(b)) => Ok(Color::Rgb(r, g, b)),
+                _ => Err(invalid()),
+            };
+        }
+        if let Ok(index) = color.parse::<u8>() {
+            return Ok(Color::Indexed(index));
+        }
+        let (name, offset) = match color.strip_prefix("bright-") {
+            Some(name) => (name, 8),
+            None => (color, 0),
+        };
+        COLOR_NAMES.iter()
+            .position(|color_name| *color_name == name)
+            .map(|index| Color::Indexed(index as u8 + offset))
+            .ok_or_else(invalid)
+    }
+}
+
+/// How many colours the terminal can show. Colours it cannot show are replaced by the
+/// closest one it can.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ColorSupport {
+    /// Only the 16 colours of the terminal's palette.
+    Ansi16,
+    Ansi256,
+    TrueColor,
+}
+
+/// The xterm defaults, as the actual palette cannot be known without querying the terminal.
+const ANSI_PALETTE: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+/// Channel values of the 6×6×6 cube at indices 16 to 231.
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+const GREYSCALE_START: u8 = 232;
+
+impl ColorSupport {
+    /// Guesses from the environment, like the graphics protocol.
+    pub fn detect() -> ColorSupport {
+        let env = |name: &str| std::env::var(name).unwrap_or_default().to_lowercase();
+        let term = env("TERM");
+        let color_term = env("COLORTERM");
+        if color_term == "truecolor" || color_term == "24bit" || term.contains("direct") {
+            ColorSupport::TrueColor
+        } else if term.contains("256") {
+            ColorSupport::Ansi256
+        } else {
+            ColorSupport::Ansi16
+        }
+    }
+
+    /// The closest colour to the given one that the terminal can show.
+    pub fn downgrade(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorSupport::TrueColor, _) | (_, Color::Reset) => color,
+            (ColorSupport::Ansi256, Color::Indexed(_)) => color,
+            (ColorSupport::Ansi256, Color::Rgb(r, g, b)) => Color::Indexed(nearest_256(r, g, b)),
+            (ColorSupport::Ansi16, Color::Indexed(index)) if index < 16 => color,
+            (ColorSupport::Ansi16, _) => {
+                let rgb = to_rgb(color);
+                let index = (0..ANSI_PALETTE.len())
+                    .min_by_key(|i| distance(ANSI_PALETTE[*i], rgb))
+                    .unwrap();
+                Color::Indexed(index as u8)
+            }
+        }
+    }
+}
+
+fn to_rgb(color: Color) -> (u8, u8, u8) {
+    match color {
+        Color::Reset => (0, 0, 0),
+        Color::Rgb(r, g, b) => (r, g, b),
+        Color::Indexed(index) if index < 16 => ANSI_PALETTE[index as usize],
+        Color::Indexed(index) if index < GREYSCALE_START => {
+            let cube = index - 16;
+            (
+                CUBE_LEVELS[(cube / 36) as usize],
+                CUBE_LEVELS[(cube / 6 % 6) as usize],
+                CUBE_LEVELS[(cube % 6) as usize],
+            )
+        }
+        Color::Indexed(index) => {
+            let level = 8 + (index - GREYSCALE_START) * 10;
+            (level, level, level)
+        }
+    }
+}
+
+/// The closest of the cube and greyscale colours. The first 16 are left out, as they depend
+/// on the terminal's palette.
+fn nearest_256(r: u8, g: u8, b: u8) -> u8 {
+    let nearest_level = |channel: u8| (0..CUBE_LEVELS.len())
+        .min_by_key(|i| (CUBE_LEVELS[*i] as i32 - channel as i32).abs())
+        .unwrap() as u8;
+    let cube = 16 + 36 * nearest_level(r) + 6 * nearest_level(g) + nearest_level(b);
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let grey = GREYSCALE_START + (average.saturating_sub(3) / 10).min(23) as u8;
+    let rgb = (r, g, b);
+    if distance(to_rgb(Color::Indexed(grey)), rgb) < distance(to_rgb(Color::Indexed(cube)), rgb) {
+        grey
+    } else {
+        cube
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let channel = |a: u8, b: u8| (a as i32 - b as i32).pow(2) as u32;
+    channel(a.0, b.0) + channel(a.1, b.1) + channel(a.2, b.2)
+}
@@ -0,0 +1,69 @@
+//! Retained drawing: each frame is drawn into a buffer of cells, and only the cells that
+//! differ from the previous frame are written to the terminal.
+
+mod backend;
+mod buffer;
+mod color;
+mod terminal;
+
+#[cfg(test)]
+pub use backend::MemoryBackend;
+pub use backend::{Backend, TermionBackend};
+pub use buffer::{Buffer, Cell, Style};
+pub use color::{Color, ColorSupport};
+pub use terminal::{Frame, Overlay, Terminal};
+
+/// A rectangle of cells, with `(0, 0)` at the top left of the terminal.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl Rect {
+    pub fn new(x: u16, y: u16, width: u16, height: u16) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    pub fn right(&self) -> u16 {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> u16 {
+        self.y + self.height
+    }
+
+    pub fn area(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+
+    pub fn contains(&self, x: u16, y: u16) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    /// The `i`th row of the rectangle.
+    pub fn row(&self, i: u16) -> Rect {
+        Rect::new(self.x, self.y + i, self.width, 1)
+    }
+
+    /// Splits off the bottom `height` rows, returning the top and bottom parts.
+    pub fn split_bottom(&self, height: u16) -> (Rect, Rect) {
+        let height = height.min(self.height);
+        let top = Rect::new(self.x, self.y, self.width, self.height - height);
+        (top, Rect::new(self.x, top.bottom(), self.width, height))
+    }
+
+    /// A rectangle of at most the given size in the middle of this one.
+    pub fn centered(&self, width: u16, height: u16) -> Rect {
+        let width = width.min(self.width);
+        let height = height.min(self.height);
+        Rect::new(
+            self.x + (self.width - width) / 2,
+            self.y + (self.height - height) / 2,
+            width,
+            height,
+        )
+    }
+}
@@ -0,0 +1,164 @@
+use crate::ui::{Backend, Buffer, Cell, Rect};
+
+/// Escape sequences drawn over an area after its cells, for images that are not made of
+/// text. They are sent again whenever a cell under them changes, since writing the cell
+/// erases them.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Overlay {
+    pub area: Rect,
+    pub data: String,
+}
+
+pub struct Frame {
+    pub buffer: Buffer,
+    pub overlays: Vec<Overlay>,
+    /// Sent before the overlays whenever they are redrawn, to remove those of the previous
+    /// frame that cells do not cover, such as Kitty placements.
+    pub overlay_reset: String,
+}
+
+impl Frame {
+    pub fn area(&self) -> Rect {
+        self.buffer.area
+    }
+}
+
+/// Draws frames to a backend, writing only the cells that changed since the last one.
+pub struct Terminal<B: Backend> {
+    backend: B,
+    previous: Buffer,
+    previous_overlays: Vec<Overlay>,
+    /// Whether the screen no longer matches the previous frame.
+    is_invalid: bool,
+}
+
+impl<B: Backend> Terminal<B> {
+    pub fn new(backend: B) -> Terminal<B> {
+        Terminal {
+            backend,
+            previous: Buffer::empty(Rect::default()),
+            previous_overlays: vec![],
+            is_invalid: true,
+        }
+    }
+
+    pub fn size(&self) -> Rect {
+        let (width, height) = self.backend.size();
+        Rect::new(0, 0, width, height)
+    }
+
+    #[cfg(test)]
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Writes escape sequences straight away, outside of any frame.
+    pub fn write_raw(&mut self, data: &str) {
+        self.backend.write_raw(data);
+        self.backend.flush();
+    }
+
+    /// Makes the next frame redraw everything, e.g. after another program used the screen.
+    pub fn invalidate(&mut self) {
+        self.is_invalid = true;
+    }
+
+    pub fn draw(&mut self, render: impl FnOnce(&mut Frame)) {
+        let area = self.size();
+        if area != self.previous.area {
+            self.is_invalid = true;
+        }
+        let mut frame = Frame {
+            buffer: Buffer::empty(area),
+            overlays: vec![],
+            overlay_reset: String::new(),
+        };
+        render(&mut frame);
+        let cells = frame.buffer.cells();
+        let mut is_changed: Vec<bool> = if self.is_invalid {
+            self.backend.clear();
+            let blank = Cell::default();
+            cells.iter().map(|cell| *cell != blank).collect()
+        } else {
+            cells.iter()
+                .zip(self.previous.cells())
+                .map(|(cell, previous)| cell != previous)
+                .collect()
+        };
+        let should_draw_overlays = self.is_invalid
+            || frame.overlays != self.previous_overlays
+            || is_changed.iter().enumerate().any(|(index, is_changed)| {
+                let (x, y) = frame.buffer.position(index);
+                *is_changed && frame.overlays.iter()
+                    .chain(&self.previous_overlays)
+                    .any(|overlay| overlay.area.contains(x, y))
+            });
+        if should_draw_overlays {
+            // Rewrite the cells under the old overlays, which may still show through.
+            for (index, is_changed) in is_changed.iter_mut().enumerate() {
+                let (x, y) = frame.buffer.position(index);
+                if self.previous_overlays.iter().any(|overlay| overlay.area.contains(x, y)) {
+                    *is_changed = true;
+                }
+            }
+        }
+        self.backend.draw(
+            cells.iter()
+                .enumerate()
+                .filter(|(index, _)| is_changed[*index])
+                .map(|(index, cell)| {
+                    let (x, y) = frame.buffer.position(index);
+                    (x, y, cell)
+                })
+        );
+        if should_draw_overlays
+            && !(frame.overlays.is_empty() && self.previous_overlays.is_empty()) {
+            self.backend.write_raw(&frame.overlay_reset);
+            for overlay in &frame.overlays {
+                self.backend.write_raw(&overlay.data);
+            }
+        }
+        self.backend.flush();
+        self.previous = frame.buffer;
+        self.previous_overlays = frame.overlays;
+        self.is_invalid = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ui::{MemoryBackend, Style};
+    use super::*;
+
+    fn draw_text(terminal: &mut Terminal<MemoryBackend>, lines: &[&str]) {
+        terminal.draw(|frame| {
+            for (y, line) in lines.iter().enumerate() {
+                frame.buffer.set_string(0, y as u16, line, &Style::default(), 10);
+            }
+        });
+    }
+
+    #[test]
+    fn writes_only_the_cells_that_changed() {
+        let mut terminal = Terminal::new(MemoryBackend::new(10, 3));
+        draw_text(&mut terminal, &["hello", "world"]);
+        // The first frame is drawn over a cleared screen, so blank cells are skipped.
+        assert_eq!(terminal.backend().cells_written, 10);
+        draw_text(&mut terminal, &["hello", "world"]);
+        assert_eq!(terminal.backend().cells_written, 10);
+        draw_text(&mut terminal, &["hello", "word"]);
+        // "l" becomes "d" and "d" becomes a space.
+        assert_eq!(terminal.backend().cells_written, 12);
+        assert_eq!(terminal.backend().lines(), ["hello", "word", ""]);
+    }
+
+    #[test]
+    fn redraws_everything_once_invalidated() {
+        let mut terminal = Terminal::new(MemoryBackend::new(10, 3));
+        draw_text(&mut terminal, &["hello"]);
+        terminal.invalidate();
+        draw_text(&mut terminal, &["hello"]);
+        assert_eq!(terminal.backend().cells_written, 10);
+        assert_eq!(terminal.backend().lines(), ["hello", "", ""]);
+    }
+}
@@ -0,0 +1,77 @@
+use serde::Deserialize;
+use api::mail::Message;
+use crate::filter::{deserialize_filter, Filter};
+use crate::state::MailboxTag;
+
+/// A list of unread messages: all of them, one account's, or those matching a filter.
+pub struct View {
+    pub name: String,
+    pub kind: ViewKind,
+    /// The selection and scroll to return to when the view is shown again.
+    pub selected_message_id: Option<String>,
+    pub list_scroll: usize,
+}
+
+#[derive(Clone, PartialEq)]
+pub enum ViewKind {
+    Unified,
+    /// The messages of the account with this id.
+    Account(String),
+    Smart(Filter),
+}
+
+/// A smart view as written in the config.
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ViewConfig {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_filter")]
+    pub filter: Filter,
+}
+
+impl View {
+    pub fn new(name: &str, kind: ViewKind) -> View {
+        View {
+            name: name.to_string(),
+            kind,
+            selected_message_id: None,
+            list_scroll: 0,
+        }
+    }
+
+    pub fn includes(&self, message: &Message, mailbox: Option<&MailboxTag>) -> bool {
+        match &self.kind {
+            ViewKind::Unified => true,
+            ViewKind::Account(id) => message.mailbox_id == *id,
+            ViewKind::Smart(filter) => {
+                let mut names = vec![];
+                if let Some(mailbox) = mailbox {
+                    names.extend([mailbox.name.as_str(), mailbox.id.as_str()]);
+                    names.extend(mailbox.address.as_deref());
+                }
+                filter.matches(message, &names)
+            }
+        }
+    }
+}
+
+/// The views there are for these mailboxes: every message, then each account, then the
+/// smart views of the config. Views that were already listed keep what they remember.
+pub fn list_views(
+    previous_views: Vec<View>,
+    mailboxes: &[MailboxTag],
+    smart_views: &[ViewConfig],
+) -> Vec<View> {
+    let mut views = vec![View::new("unread", ViewKind::Unified)];
+    views.extend(mailboxes.iter()
+        .map(|mailbox| View::new(&mailbox.name, ViewKind::Account(mailbox.id.clone()))));
+    views.extend(smart_views.iter()
+        .map(|view| View::new(&view.name, ViewKind::Smart(view.filter.clone()))));
+    for previous_view in previous_views {
+        if let Some(view) = views.iter_mut().find(|view| view.kind == previous_view.kind) {
+            view.selected_message_id = previous_view.selected_message_id;
+            view.list_scroll = previous_view.list_scroll;
+        }
+    }
+    views
+}
@@ -0,0 +1,235 @@
+use std::fmt;
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveTime, TimeZone, Weekday};
+
+/// The hour a day is taken to start at when no time is given, as in "tomorrow".
+const DEFAULT_HOUR: u32 = 9;
+/// How long "later" is.
+const LATER_HOURS: i64 = 3;
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+#[derive(Debug, PartialEq)]
+pub enum WhenError {
+    Empty,
+    /// Words that are not a day, time or duration.
+    Unknown(String),
+    /// A time that does not exist, such as `25:00`, or one skipped by a clock change.
+    InvalidTime(String),
+    Past,
+}
+
+impl fmt::Display for WhenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WhenError::Empty => write!(f, "no time given"),
+            WhenError::Unknown(text) => write!(f, "not a time: {}", text),
+            WhenError::InvalidTime(text) => write!(f, "no such time: {}", text),
+            WhenError::Past => write!(f, "that time has passed"),
+        }
+    }
+}
+
+impl std::error::Error for WhenError {}
+
+/// Reads a time after `now` written the way people say it: `tomorrow`, `tonight`,
+/// `monday 2pm`, `next week`, `in 2 hours`, `at 17:30`, `later` or `2026-03-01 8am`.
+/// Days without a time start at 9am, and a time alone is the next time the clock shows it.
+pub fn parse(text: &str, now: DateTime<Local>) -> Result<DateTime<Local>, WhenError> {
+    let text = text.trim().to_lowercase();
+    let words: Vec<&str> = text.split_whitespace()
+        .filter(|word| !["until", "on", "at"].contains(word))
+        .collect();
+    if words.is_empty() {
+        return Err(WhenError::Empty);
+    }
+    let time = match words.as_slice() {
+        ["later"] | ["later", "today"] => now + Duration::hours(LATER_HOURS),
+        ["in", amount, unit] => add_duration(now, amount, unit)?,
+        ["in", amount] => {
+            // "in 2h", "in 30m".
+            let split = amount.find(|c: char| !c.is_ascii_digit()).unwrap_or(amount.len());
+            add_duration(now, &amount[..split], &amount[split..])?
+        }
+        _ => {
+            let (date, rest) = parse_day(&words, now.date_naive())?;
+            let (time, is_default) = match rest {
+                [] => (NaiveTime::from_hms_opt(DEFAULT_HOUR, 0, 0).unwrap(), true),
+                rest => (parse_time(&rest.join(" "))?, false),
+            };
+            let date = match date {
+                Some(date) => date,
+                // A time alone means today, or tomorrow once it has passed.
+                None if time > now.time() => now.date_naive(),
+                None => now.date_naive() + Duration::days(1),
+            };
+            let time = if words[0] == "tonight" && is_default {
+                NaiveTime::from_hms_opt(20, 0, 0).unwrap()
+            } else {
+                time
+            };
+            Local.from_local_datetime(&date.and_time(time))
+                .earliest()
+                .ok_or_else(|| WhenError::InvalidTime(text.clone()))?
+        }
+    };
+    if time <= now {
+        return Err(WhenError::Past);
+    }
+    Ok(time)
+}
+
+/// Adds an amount of minutes, hours, days or weeks to `now`, failing rather than going past
+/// the last time there is, as in `in 100000000 weeks`.
+fn add_duration(
+    now: DateTime<Local>,
+    amount: &str,
+    unit: &str,
+) -> Result<DateTime<Local>, WhenError> {
+    let text = format!("{} {}", amount, unit);
+    let amount: i64 = amount.parse().map_err(|_| WhenError::Unknown(text.clone()))?;
+    let duration = match unit.trim_end_matches('s') {
+        "m" | "min" | "minute" => Duration::try_minutes(amount),
+        "h" | "hr" | "hour" => Duration::try_hours(amount),
+        "d" | "day" => Duration::try_days(amount),
+        "w" | "week" => Duration::try_weeks(amount),
+        _ => return Err(WhenError::Unknown(text)),
+    };
+    duration.and_then(|duration| now.checked_add_signed(duration))
+        .ok_or(WhenError::InvalidTime(text))
+}
+
+/// Reads the day the words start with, returning it, or `None` if they start with a time,
+/// and the words after it.
+fn parse_day<'a, 'b>(
+    words: &'a [&'b str],
+    today: NaiveDate,
+) -> Result<(Option<NaiveDate>, &'a [&'b str]), WhenError> {
+    let next_weekday = |weekday: Weekday| {
+        let days = (weekday.num_days_from_monday() + 7
+            - today.weekday().num_days_from_monday()) % 7;
+        // The same weekday as today means the one a week away.
+        today + Duration::days(if days == 0 { 7 } else { days as i64 })
+    };
+    let weekday = |word: &str| WEEKDAYS.iter()
+        // "mon", "tues" and "thurs" are as good as the whole name.
+        .find(|(name, _)| word.len() >= 3 && name.starts_with(word))
+        .map(|(_, weekday)| *weekday);
+    let (date, length) = match words {
+        ["today", ..] | ["tonight", ..] => (today, 1),
+        ["tomorrow", ..] => (today + Duration::days(1), 1),
+        ["next", "week", ..] => (next_weekday(Weekday::Mon), 2),
+        ["next", word, ..] if weekday(word).is_some() => (next_weekday(weekday(word).unwrap()), 2),
+        [word, ..] if weekday(word).is_some() => (next_weekday(weekday(word).unwrap()), 1),
+        [word, ..] => match NaiveDate::parse_from_str(word, "%Y-%m-%d") {
+            Ok(date) => (date, 1),
+            Err(_) => return Ok((None, words)),
+        },
+        [] => return Ok((None, words)),
+    };
+    Ok((Some(date), &words[length..]))
+}
+
+/// Reads a time of day such as `9`, `9am`, `9 pm`, `9:30`, `17:00`, `noon` or `evening`.
+fn parse_time(text: &str) -> Result<NaiveTime, WhenError> {
+    let unknown = || WhenError::Unknown(text.to_string());
+    let named_hour = match text {
+        "midnight" => Some(0),
+        "morning" => Some(DEFAULT_HOUR),
+        "noon" | "midday" => Some(12),
+        "afternoon" => Some(14),
+        "evening" => Some(18),
+        "night" => Some(20),
+        _ => None,
+    };
+    if let Some(hour) = named_hour {
+        return Ok(NaiveTime::from_hms_opt(hour, 0, 0).unwrap());
+    }
+    let text = text.replace(' ', "");
+    let (clock, offset) = if let Some(clock) = text.strip_suffix("am") {
+        (clock, Some(0))
+    } else if let Some(clock) = text.strip_suffix("pm") {
+        (clock, Some(12))
+    } else {
+        (text.as_str(), None)
+    };
+    let (hour, minute) = match clock.split_once(':') {
+        Some((hour, minute)) => (hour, minute),
+        None => (clock, "0"),
+    };
+    let hour: u32 = hour.parse().map_err(|_| unknown())?;
+    let minute: u32 = minute.parse().map_err(|_| unknown())?;
+    let hour = match offset {
+        Some(_) if hour == 0 || hour > 12 => return Err(WhenError::InvalidTime(text.clone())),
+        // 12am is midnight and 12pm is noon.
+        Some(offset) => hour % 12 + offset,
+        None => hour,
+    };
+    NaiveTime::from_hms_opt(hour, minute, 0).ok_or(WhenError::InvalidTime(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wednesday 4 March 2026, 10:15.
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 3, 4, 10, 15, 0).unwrap()
+    }
+
+    fn at(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(year, month, day, hour, minute, 0).unwrap()
+    }
+
+    #[test]
+    fn reads_durations() {
+        assert_eq!(parse("in 2 hours", now()), Ok(at(2026, 3, 4, 12, 15)));
+        assert_eq!(parse("in 30m", now()), Ok(at(2026, 3, 4, 10, 45)));
+        assert_eq!(parse("in 2 days", now()), Ok(at(2026, 3, 6, 10, 15)));
+        assert_eq!(parse("later", now()), Ok(at(2026, 3, 4, 13, 15)));
+        assert_eq!(parse("in 2 fortnights", now()), Err(WhenError::Unknown("2 fortnights".into())));
+    }
+
+    #[test]
+    fn rejects_durations_past_the_last_time_there_is() {
+        assert_eq!(
+            parse("in 100000000 d", now()),
+            Err(WhenError::InvalidTime("100000000 d".to_string()))
+        );
+        assert_eq!(
+            parse("in 9223372036854775807 weeks", now()),
+            Err(WhenError::InvalidTime("9223372036854775807 weeks".to_string()))
+        );
+        assert!(matches!(parse("in 99999999999999999999m", now()), Err(WhenError::Unknown(_))));
+    }
+
+    #[test]
+    fn reads_days_and_times() {
+        assert_eq!(parse("tomorrow", now()), Ok(at(2026, 3, 5, 9, 0)));
+        assert_eq!(parse("tonight", now()), Ok(at(2026, 3, 4, 20, 0)));
+        assert_eq!(parse("monday 2pm", now()), Ok(at(2026, 3, 9, 14, 0)));
+        // The same weekday as today is a week away.
+        assert_eq!(parse("wed", now()), Ok(at(2026, 3, 11, 9, 0)));
+        assert_eq!(parse("next week", now()), Ok(at(2026, 3, 9, 9, 0)));
+        assert_eq!(parse("at 17:30", now()), Ok(at(2026, 3, 4, 17, 30)));
+        // A time that has passed today is tomorrow's.
+        assert_eq!(parse("9am", now()), Ok(at(2026, 3, 5, 9, 0)));
+        assert_eq!(parse("2026-04-01 noon", now()), Ok(at(2026, 4, 1, 12, 0)));
+    }
+
+    #[test]
+    fn rejects_times_that_cannot_be() {
+        assert_eq!(parse(" ", now()), Err(WhenError::Empty));
+        assert_eq!(parse("25:00", now()), Err(WhenError::InvalidTime("25:00".to_string())));
+        assert_eq!(parse("13pm", now()), Err(WhenError::InvalidTime("13pm".to_string())));
+        assert_eq!(parse("today 8am", now()), Err(WhenError::Past));
+        assert_eq!(parse("someday", now()), Err(WhenError::Unknown("someday".to_string())));
+    }
+}
@@ -0,0 +1,30 @@
+//! Width of text in terminal columns, counted per grapheme as terminals draw it.
+
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthChar;
+
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{fe0f}';
+
+/// Columns taken by one grapheme. Joined emoji sequences and flags are drawn as a single
+/// wide character rather than the sum of their parts.
+pub fn grapheme_width(grapheme: &str) -> usize {
+    let mut chars = grapheme.chars();
+    let first = match chars.next() {
+        Some(first) => first,
+        None => return 0,
+    };
+    let is_regional_indicator = ('\u{1f1e6}'..='\u{1f1ff}').contains(&first);
+    if is_regional_indicator || grapheme.contains(EMOJI_PRESENTATION_SELECTOR) {
+        return 2;
+    }
+    let width = first.width().unwrap_or(0);
+    if width > 0 {
+        return width;
+    }
+    // A grapheme can start with a mark when it has nothing to combine with.
+    chars.map(|c| c.width().unwrap_or(0)).max().unwrap_or(0)
+}
+
+pub fn str_width(text: &str) -> usize {
+    text.graphemes(true).map(grapheme_width).sum()
+}